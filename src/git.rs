@@ -1,46 +1,194 @@
 #![allow(dead_code)]
 
+pub mod apply;
+pub mod archive;
+pub mod attributes;
+pub mod bisect;
+pub mod blame;
+pub mod branch;
+pub mod bundle;
+pub mod cat_file;
+pub mod checkout_index;
+pub mod cherry_pick;
+pub mod clean;
+pub mod clone;
+pub mod commit;
+pub mod commit_graph;
+pub mod config;
+pub mod count_objects;
+pub mod credential;
+pub mod date;
+pub mod describe;
+pub mod diff;
+pub mod error;
+pub mod fetch;
+pub mod fsck;
+pub mod gc;
+pub mod grep;
+pub mod hash;
+pub mod ignore;
+pub mod index;
+pub mod lockfile;
+pub mod log;
+pub mod ls_files;
+pub mod ls_tree;
+pub mod mailmap;
+pub mod merge;
+pub mod midx;
+pub mod mv;
+pub mod notes;
 pub mod pack;
+pub mod patch;
+pub mod platform;
+pub mod prune;
+pub mod pull;
+pub mod read_tree;
+pub mod rebase;
+pub mod reflog;
+pub mod refs;
+pub mod refspec;
 pub mod remote;
+pub mod repository;
+pub mod reset;
+pub mod rev_list;
+pub mod revert;
+pub mod revparse;
+pub mod revwalk;
+pub mod rm;
+pub mod shortlog;
+pub mod show;
+pub mod sign;
+pub mod sparse;
+pub mod stash;
+pub mod status;
+pub mod tag;
+pub mod update_index;
+pub mod worktree;
 
 use anyhow::{anyhow, bail, Context, Result};
+pub use error::Error;
 use flate2::{bufread::ZlibDecoder, write::ZlibEncoder, Compression};
-use sha1::{Digest, Sha1};
-use std::io::{prelude::*, stdout, BufReader};
-use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use hash::HashAlgo;
+pub use hash::ObjectId;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, prelude::*, stdout, BufReader, Cursor};
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
 use std::{env, fs};
 
-const HASH_SIZE: usize = 20; // hex string of SHA1
-const HASH_HEX_SIZE: usize = 40; // hex string of SHA1
 const DIRECTORY_MODE: u32 = 0o40000;
+const SYMLINK_MODE: u32 = 0o120000;
+const GITLINK_MODE: u32 = 0o160000;
+const MIN_ABBREVIATION: usize = 4;
 
 pub enum ParsedObject {
     Blob(Vec<u8>),
-    Commit(remote::Sha1),
-    Tag,
+    Commit(Commit),
+    Tag(tag::Tag),
     Tree(Vec<TreeEntry>),
 }
 
-impl ParsedObject {
-    pub fn print_tree_names(&self) -> Result<()> {
-        match &self {
-            ParsedObject::Tree(ref tree) => {
-                for entry in tree {
-                    println!("{}", entry.name);
-                }
-                Ok(())
-            }
-            _ => Err(anyhow!("Unsupported object")),
-        }
+pub struct Commit {
+    pub tree: remote::Sha1,
+    pub parents: Vec<remote::Sha1>,
+    pub author: Identity,
+    pub committer: Identity,
+    /// Character set of the message when it is not UTF-8.
+    pub encoding: Option<String>,
+    /// Signature over the commit without this header, as made by `commit -S`.
+    pub gpgsig: Option<String>,
+    pub message: String,
+}
+
+impl Commit {
+    /// The first paragraph of the message on one line, as git shows it.
+    pub fn subject(&self) -> String {
+        self.message
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| line.is_empty())
+            .take_while(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Committer timestamp in seconds since the epoch, used for ordering history.
+    pub fn time(&self) -> i64 {
+        self.committer.timestamp
+    }
+}
+
+/// Who made a commit or tag, and when.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    /// `+hhmm` or `-hhmm`, as recorded.
+    pub timezone: String,
+}
+
+impl Identity {
+    /// Parses a `Name <email> 1234567890 +0000` line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (identity, timestamp, timezone) = split_identity(line)?;
+        let (name, email) = identity.strip_suffix('>')?.split_once('<')?;
+        Some(Self {
+            name: name.strip_suffix(' ').unwrap_or(name).to_owned(),
+            email: email.to_owned(),
+            timestamp,
+            timezone: timezone.to_owned(),
+        })
+    }
+
+    /// `Name <email>`, without the date.
+    pub fn person(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
     }
 }
 
+impl std::fmt::Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.person(), self.timestamp, self.timezone)
+    }
+}
+
+/// Splits `Name <email> 1234567890 +0000` into the identity, timestamp and timezone.
+pub fn split_identity(line: &str) -> Option<(&str, i64, &str)> {
+    let (rest, timezone) = line.rsplit_once(' ')?;
+    let (identity, timestamp) = rest.rsplit_once(' ')?;
+    Some((identity, timestamp.parse().ok()?, timezone))
+}
+
 pub struct TreeEntry {
     mode: u32,
     name: String,
-    hash: Hash,
+    hash: ObjectId,
+}
+
+/// What a tree entry points at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryKind {
+    /// A regular file or a symlink.
+    Blob,
+    Tree,
+    /// A submodule, recorded as the commit checked out in it.
+    Gitlink,
+}
+
+impl EntryKind {
+    pub fn from_mode(mode: u32) -> Self {
+        match mode {
+            DIRECTORY_MODE => Self::Tree,
+            GITLINK_MODE => Self::Gitlink,
+            _ => Self::Blob,
+        }
+    }
+}
+
+impl TreeEntry {
+    pub fn kind(&self) -> EntryKind {
+        EntryKind::from_mode(self.mode)
+    }
 }
 
 pub struct Object {
@@ -51,9 +199,9 @@ pub struct Object {
 impl Object {
     fn new(kind: &[u8], content: &[u8]) -> Self {
         let mut header = vec![];
-        header.write(kind).unwrap();
-        header.write(b" ").unwrap();
-        header.write(content.len().to_string().as_bytes()).unwrap();
+        header.extend_from_slice(kind);
+        header.push(b' ');
+        header.extend_from_slice(content.len().to_string().as_bytes());
 
         Object {
             header,
@@ -61,259 +209,865 @@ impl Object {
         }
     }
 
-    pub fn from_hash(hash: &str) -> Result<Self> {
-        let filepath = object_path(hash)?;
-        let file = BufReader::new(fs::File::open(filepath)?);
-        let mut decoded_file = ZlibDecoder::new(file);
-        let mut data = vec![];
-        decoded_file.read_to_end(&mut data)?;
-        let header_end_index = data
-            .iter()
-            .position(|&b| b == 0)
-            .ok_or_else(|| anyhow!("Header not found"))?;
-        let content = data.split_off(header_end_index + 1);
-        let mut header = data;
-        let _ = header.pop(); // remove the separator byte
-
-        // TODO: verify header
-        Ok(Self { header, content })
-    }
-
     pub fn print(&self) -> Result<()> {
+        if let ParsedObject::Tag(tag) = self.parse()? {
+            print!("{tag}");
+            return Ok(());
+        }
         stdout()
             .write_all(&self.content)
             .with_context(|| "Failed to print object")
     }
 
-    pub fn parse(&self) -> Result<ParsedObject> {
-        let kind = self
-            .header
+    pub fn kind(&self) -> Result<&[u8]> {
+        self.header
             .split(|&b| b == b' ')
             .next()
-            .ok_or_else(|| anyhow!("Invalid object header"))?;
-        match kind {
+            .ok_or_else(|| anyhow!("Invalid object header"))
+    }
+
+    pub fn parse(&self) -> Result<ParsedObject> {
+        match self.kind()? {
             b"blob" => Ok(ParsedObject::Blob(self.content.clone())),
             b"commit" => Ok(ParsedObject::Commit(parse_commit(&self.content)?)),
-            b"tag" => Ok(ParsedObject::Tag),
+            b"tag" => Ok(ParsedObject::Tag(tag::parse(&self.content)?)),
             b"tree" => Ok(parse_tree(&self.content)?),
             _ => Err(anyhow!("Unsupported object type")),
         }
     }
-    pub fn serialize(&self) -> Result<Hash> {
-        // TODO: extract separator?
-        let separator = [b'\0'; 1];
-        let hash = self.hash();
-        let filepath = object_path(&hex::encode(&hash))?;
-        fs::create_dir_all(filepath.parent().unwrap())?;
-        let file = fs::File::create(filepath)?;
-        let mut encoder = ZlibEncoder::new(file, Compression::best());
-        encoder.write_all(&self.header)?;
-        encoder.write_all(&separator)?;
-        encoder.write_all(&self.content)?;
-        encoder.finish()?;
-        Ok(hash)
-    }
-
-    fn hash(&self) -> Hash {
-        let mut hasher = Sha1::new();
-        let separator = [b'\0'; 1];
+    fn hash(&self) -> ObjectId {
+        let mut hasher = HashAlgo::current().hasher();
         hasher.update(&self.header);
-        hasher.update(&separator);
+        hasher.update(b"\0");
         hasher.update(&self.content);
-        hasher.finalize().into_iter().collect::<Vec<_>>()
+        hasher.finalize()
+    }
+}
+
+/// An object whose header has been read while its content is still streamed,
+/// so the type and size are known without loading the whole object.
+pub struct ObjectReader {
+    kind: Vec<u8>,
+    size: usize,
+    content: Box<dyn Read>,
+}
+
+impl ObjectReader {
+    /// Starts decoding a zlib-compressed loose object, reading only its header.
+    fn from_loose(compressed: impl BufRead + 'static) -> Result<Self> {
+        let mut decoded = ZlibDecoder::new(compressed);
+        let mut header = vec![];
+        loop {
+            let mut byte = [0; 1];
+            if decoded.read(&mut byte)? == 0 {
+                bail!("Header not found");
+            }
+            if byte[0] == 0 {
+                break;
+            }
+            header.push(byte[0]);
+        }
+        let header = String::from_utf8(header).with_context(|| "Invalid object header")?;
+        let (kind, size) = header
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Invalid object header '{header}'"))?;
+        let size = size
+            .parse()
+            .with_context(|| format!("Invalid object size '{size}'"))?;
+        Ok(Self {
+            kind: kind.as_bytes().to_vec(),
+            size,
+            content: Box::new(decoded),
+        })
+    }
+
+    pub fn kind(&self) -> &[u8] {
+        &self.kind
+    }
+
+    /// Content size declared in the object header.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn into_object(mut self) -> Result<Object> {
+        let mut content = Vec::with_capacity(self.size);
+        self.content.read_to_end(&mut content)?;
+        if content.len() != self.size {
+            bail!(
+                "Object size mismatch (header says {}, got {})",
+                self.size,
+                content.len()
+            );
+        }
+        Ok(Object::new(&self.kind, &content))
+    }
+
+    /// Like `Object::print`, but blobs are copied to stdout without buffering them.
+    pub fn print(mut self) -> Result<()> {
+        if self.kind != b"blob" {
+            return self.into_object()?.print();
+        }
+        io::copy(&mut self.content, &mut stdout()).with_context(|| "Failed to print object")?;
+        Ok(())
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.content.read(buf)
     }
 }
 
 fn parse_tree(data: &[u8]) -> Result<ParsedObject> {
+    let hash_size = HashAlgo::current().size();
     let mut entries = vec![];
     let mut reader = BufReader::new(data);
     while !reader.fill_buf()?.is_empty() {
         let mode = u32::from_str_radix(&read_field(&mut reader, b' ')?, 8)
             .with_context(|| "Failed to read file mode")?;
         let name = read_field(&mut reader, 0)?;
-        let mut hash = vec![0; HASH_SIZE];
+        let mut hash = vec![0; hash_size];
         reader.read_exact(&mut hash)?;
+        let hash = ObjectId::from_bytes(&hash)?;
         entries.push(TreeEntry { mode, name, hash });
     }
     Ok(ParsedObject::Tree(entries))
 }
 
+/// Whether a tree entry name is safe to check out: not empty, `.`, `..` or
+/// `.git` in any case, and without a slash.
+pub fn is_valid_entry_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains('/')
+        && ![".", "..", ".git"].contains(&name.to_ascii_lowercase().as_str())
+}
+
+/// Fails for a path of the working tree that could lead outside of it or
+/// into the repository: an absolute path, or one with a component that is
+/// not a valid entry name, like `..` or `.git`.
+pub fn verify_path(path: &str) -> Result<()> {
+    if !path.split('/').all(is_valid_entry_name) {
+        bail!("invalid path '{path}'");
+    }
+    Ok(())
+}
+
+/// Whether a directory of the working tree leading to `name`, below
+/// `prefix`, is a symbolic link, through which writing could end up anywhere.
+pub fn has_symlink_leading_path(prefix: &str, name: &str) -> bool {
+    name.match_indices('/').any(|(end, _)| {
+        fs::symlink_metadata(format!("{prefix}{}", &name[..end]))
+            .is_ok_and(|meta| meta.is_symlink())
+    })
+}
+
 fn read_field<R: BufRead>(reader: &mut R, separator: u8) -> Result<String> {
     let mut field = vec![];
     reader.read_until(separator, &mut field)?;
     let _ = field.pop(); // remove separator
 
-    Ok(String::from_utf8(field).with_context(|| anyhow!("Failed to read field"))?)
+    String::from_utf8(field).with_context(|| anyhow!("Failed to read field"))
+}
+
+/// Hashes `content` as an object of `kind`, also storing it when `write` is
+/// set. Blobs read from `path` go through the conversion its attributes ask
+/// for; other kinds must parse, as git refuses malformed trees, commits and
+/// tags.
+pub fn hash_object(
+    kind: &str,
+    content: Vec<u8>,
+    path: Option<&Path>,
+    write: bool,
+) -> Result<ObjectId> {
+    let content = match (kind, path) {
+        ("blob", Some(path)) => attributes::Conversion::new()?.convert_to_git(path, content)?,
+        ("blob", None) => content,
+        ("tree" | "commit" | "tag", _) => {
+            Object::new(kind.as_bytes(), &content)
+                .parse()
+                .with_context(|| format!("Corrupt {kind}"))?;
+            content
+        }
+        _ => bail!("Invalid object type \"{kind}\""),
+    };
+    if write {
+        Ok(Odb::open()?.write(kind.as_bytes(), &content)?)
+    } else {
+        Ok(Object::new(kind.as_bytes(), &content).hash())
+    }
 }
 
-pub type Hash = Vec<u8>;
+/// A place objects are stored in, such as loose files or packs.
+pub trait Backend {
+    fn read(&self, hash: &[u8]) -> Result<Option<Object>>;
+
+    fn exists(&self, hash: &[u8]) -> Result<bool>;
 
-pub fn blobify(filepath: &Path) -> Result<Hash> {
-    let content_size: usize = filepath.metadata()?.len() as usize;
-    let mut content = Vec::with_capacity(content_size);
-    content.resize(content_size, 0);
-    fs::File::open(filepath)?.read_exact(&mut content)?;
-    Object::new(b"blob", &content).serialize()
+    /// Hex hashes of all stored objects starting with the given hex prefix.
+    fn find_abbreviated(&self, prefix: &str) -> Result<Vec<remote::Sha1>>;
+
+    /// Hex hashes of all stored objects.
+    fn list(&self) -> Result<Vec<remote::Sha1>>;
+
+    /// Opens an object for streaming. Backends that cannot stream read it whole.
+    fn open(&self, hash: &[u8]) -> Result<Option<ObjectReader>> {
+        Ok(self.read(hash)?.map(|object| ObjectReader {
+            kind: object.kind().map(<[u8]>::to_vec).unwrap_or_default(),
+            size: object.content.len(),
+            content: Box::new(Cursor::new(object.content)),
+        }))
+    }
+
+    /// Stores an object; only writable backends override this.
+    fn write(&self, object: &Object) -> Result<()> {
+        let _ = object;
+        bail!("Object store is read-only")
+    }
 }
 
-fn object_path(hash: &str) -> Result<PathBuf> {
-    if hash.len() != HASH_HEX_SIZE {
-        bail!("Invalid hash length {}", hash.len());
+/// Zlib-compressed objects stored one per file under `.git/objects/xx/`.
+struct Loose {
+    directory: PathBuf,
+}
+
+impl Loose {
+    fn object_path(&self, hash: &[u8]) -> PathBuf {
+        let hash = hex::encode(hash);
+        let (subdir, filename) = hash.split_at(2);
+        self.directory.join(subdir).join(filename)
     }
-    let (subdir, filename) = hash.split_at(2);
-    let mut filepath = PathBuf::new();
-    filepath.push(".git");
-    filepath.push("objects");
-    filepath.push(subdir);
-    filepath.push(filename);
-    Ok(filepath)
 }
 
-pub fn write_tree(directory: &Path) -> Result<Hash> {
-    let content = build_tree_content(directory)?;
-    Object::new(b"tree", &content).serialize()
+impl Backend for Loose {
+    fn read(&self, hash: &[u8]) -> Result<Option<Object>> {
+        let Some(reader) = self.open(hash)? else {
+            return Ok(None);
+        };
+        let object = reader
+            .into_object()
+            .map_err(|error| Error::corrupt(hash, error))?;
+        Ok(Some(object))
+    }
+
+    fn open(&self, hash: &[u8]) -> Result<Option<ObjectReader>> {
+        let filepath = self.object_path(hash);
+        if !filepath.exists() {
+            return Ok(None);
+        }
+        let file = BufReader::new(fs::File::open(filepath)?);
+        let reader = ObjectReader::from_loose(file).map_err(|error| Error::corrupt(hash, error))?;
+        Ok(Some(reader))
+    }
+
+    fn exists(&self, hash: &[u8]) -> Result<bool> {
+        Ok(self.object_path(hash).exists())
+    }
+
+    fn find_abbreviated(&self, prefix: &str) -> Result<Vec<remote::Sha1>> {
+        let (subdir, rest) = prefix.split_at(2);
+        let directory = self.directory.join(subdir);
+        let mut matches = vec![];
+        if directory.is_dir() {
+            for entry in directory.read_dir()? {
+                let filename = entry?.file_name().to_string_lossy().into_owned();
+                if filename.starts_with(rest) {
+                    matches.push(format!("{subdir}{filename}"));
+                }
+            }
+        }
+        Ok(matches)
+    }
+
+    fn list(&self) -> Result<Vec<remote::Sha1>> {
+        let name_size = HashAlgo::current().hex_size() - 2;
+        let mut hashes = vec![];
+        if !self.directory.is_dir() {
+            return Ok(hashes);
+        }
+        for entry in self.directory.read_dir()? {
+            let entry = entry?;
+            let subdir = entry.file_name().to_string_lossy().into_owned();
+            // other entries such as pack/ and info/ are not fan-out directories
+            if subdir.len() != 2 || !subdir.bytes().all(|b| b.is_ascii_hexdigit()) {
+                continue;
+            }
+            for file in entry.path().read_dir()? {
+                let filename = file?.file_name().to_string_lossy().into_owned();
+                if filename.len() == name_size {
+                    hashes.push(format!("{subdir}{filename}"));
+                }
+            }
+        }
+        Ok(hashes)
+    }
+
+    fn write(&self, object: &Object) -> Result<()> {
+        // TODO: extract separator?
+        let separator = [b'\0'; 1];
+        let filepath = self.object_path(&object.hash());
+        if filepath.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(filepath.parent().unwrap())?;
+        let file = fs::File::create(filepath)?;
+        let mut encoder = ZlibEncoder::new(file, Compression::best());
+        encoder.write_all(&object.header)?;
+        encoder.write_all(&separator)?;
+        encoder.write_all(&object.content)?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+/// The object database. Reads ask every backend in turn, writes go to loose objects.
+pub struct Odb {
+    backends: Vec<Box<dyn Backend>>,
 }
 
-fn build_tree_content(directory: &Path) -> Result<Vec<u8>> {
+impl Odb {
+    pub fn open() -> error::Result<Self> {
+        let directory = repository::path("objects");
+        Ok(Self {
+            backends: vec![
+                Box::new(Loose {
+                    directory: directory.clone(),
+                }),
+                Box::new(pack::Packs::open(&directory.join("pack"))?),
+            ],
+        })
+    }
+
+    /// Reads an object by its full or abbreviated hash.
+    pub fn read(&self, hash: &str) -> error::Result<Object> {
+        let hash = self.expand(hash)?;
+        let raw_hash = parse_hash(&hash)?;
+        for backend in &self.backends {
+            if let Some(object) = backend.read(&raw_hash)? {
+                return Ok(object);
+            }
+        }
+        Err(Error::ObjectNotFound(hash))
+    }
+
+    /// Opens an object by its full or abbreviated hash without reading its content yet.
+    pub fn stream(&self, hash: &str) -> error::Result<ObjectReader> {
+        let hash = self.expand(hash)?;
+        let raw_hash = parse_hash(&hash)?;
+        for backend in &self.backends {
+            if let Some(reader) = backend.open(&raw_hash)? {
+                return Ok(reader);
+            }
+        }
+        Err(Error::ObjectNotFound(hash))
+    }
+
+    pub fn write(&self, kind: &[u8], data: &[u8]) -> error::Result<ObjectId> {
+        let object = Object::new(kind, data);
+        self.backends[0].write(&object)?;
+        Ok(object.hash())
+    }
+
+    pub fn exists(&self, hash: &str) -> error::Result<bool> {
+        let hash = parse_hash(hash)?;
+        for backend in &self.backends {
+            if backend.exists(&hash)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Hex hashes of all objects, loose and packed, sorted and without duplicates.
+    pub fn list(&self) -> error::Result<Vec<remote::Sha1>> {
+        let mut hashes = vec![];
+        for backend in &self.backends {
+            hashes.extend(backend.list()?);
+        }
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    /// Hex hashes of the loose objects only.
+    pub fn list_loose(&self) -> error::Result<Vec<remote::Sha1>> {
+        Ok(self.backends[0].list()?)
+    }
+
+    /// The file a loose object is stored in, whether it exists or not.
+    pub fn loose_path(hash: &str) -> PathBuf {
+        let (subdir, filename) = hash.split_at(2);
+        repository::path("objects").join(subdir).join(filename)
+    }
+
+    /// Expands an abbreviated hash (at least 4 hex digits) to the full hash of the
+    /// single object it matches.
+    pub fn expand(&self, prefix: &str) -> error::Result<remote::Sha1> {
+        let hex_size = HashAlgo::current().hex_size();
+        if prefix.len() < MIN_ABBREVIATION || prefix.len() > hex_size {
+            return Err(anyhow!("Invalid hash length {}", prefix.len()).into());
+        }
+        if !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(anyhow!("Invalid hash {prefix}").into());
+        }
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() == hex_size {
+            return Ok(prefix);
+        }
+        let mut matches = vec![];
+        for backend in &self.backends {
+            matches.extend(backend.find_abbreviated(&prefix)?);
+        }
+        matches.sort();
+        matches.dedup();
+        match &matches[..] {
+            [hash] => Ok(hash.clone()),
+            [] => Err(Error::ObjectNotFound(prefix)),
+            _ => Err(Error::AmbiguousObject {
+                prefix,
+                candidates: matches,
+            }),
+        }
+    }
+
+    pub fn read_commit(&self, hash: &str) -> error::Result<Commit> {
+        if let ParsedObject::Commit(commit) = self.read(hash)?.parse()? {
+            Ok(commit)
+        } else {
+            Err(anyhow!("{hash} is not a commit").into())
+        }
+    }
+}
+
+/// Writes the tree of the staged files, or of `directory` when there is no
+/// index yet or `from_worktree` is set.
+pub fn write_tree(directory: &Path, from_worktree: bool) -> Result<ObjectId> {
+    let odb = Odb::open()?;
+    if !from_worktree && index::Index::exists() {
+        let index = index::Index::read()?;
+        if let Some(path) = index.conflicts().first() {
+            bail!("{path}: unmerged entries, cannot write a tree");
+        }
+        return write_tree_from_files(&odb, &index.files());
+    }
+    let content = build_tree_content(
+        &odb,
+        directory,
+        &mut ignore::Ignore::new()?,
+        &mut attributes::Conversion::new()?,
+    )?;
+    Ok(odb.write(b"tree", &content)?)
+}
+
+/// Writes the trees for a set of files keyed by path, returning the root tree hash.
+pub fn write_tree_from_files(odb: &Odb, files: &TreeFiles) -> Result<ObjectId> {
+    let mut entries = BTreeMap::new();
+    let mut subdirectories: BTreeMap<String, TreeFiles> = BTreeMap::new();
+    for (path, (mode, hash)) in files {
+        let mut components = path.components();
+        let name = components
+            .next()
+            .ok_or_else(|| anyhow!("Empty path in tree"))?
+            .as_os_str()
+            .to_string_lossy()
+            .into_owned();
+        let rest = components.as_path();
+        if rest.as_os_str().is_empty() {
+            entries.insert(name, (*mode, *hash));
+        } else {
+            subdirectories
+                .entry(name)
+                .or_default()
+                .insert(rest.to_path_buf(), (*mode, *hash));
+        }
+    }
+    for (name, files) in subdirectories {
+        entries.insert(name, (DIRECTORY_MODE, write_tree_from_files(odb, &files)?));
+    }
+    let mut entries = entries.into_iter().collect::<Vec<_>>();
+    // git orders directories as if their names ended with a slash
+    entries.sort_by_key(|(name, (mode, _))| {
+        let mut key = name.clone().into_bytes();
+        if *mode == DIRECTORY_MODE {
+            key.push(b'/');
+        }
+        key
+    });
+    let mut content = vec![];
+    for (name, (mode, hash)) in entries {
+        write!(&mut content, "{mode:o} {name}")?;
+        content.push(0);
+        content.extend_from_slice(&hash);
+    }
+    Ok(odb.write(b"tree", &content)?)
+}
+
+fn build_tree_content(
+    odb: &Odb,
+    directory: &Path,
+    ignore: &mut ignore::Ignore,
+    conversion: &mut attributes::Conversion,
+) -> Result<Vec<u8>> {
     assert!(directory.is_dir());
     let mut entries = directory
         .read_dir()?
-        .into_iter()
         .flatten()
-        .filter(|e| !(e.path().is_dir() && e.path().ends_with(".git")))
+        .filter(|e| e.file_name() != ".git")
         .collect::<Vec<_>>();
-    entries.sort_by_key(|e| e.file_name());
-    let content = entries
-        .into_iter()
-        .map(|entry| {
-            let meta = entry.metadata()?;
-            let (mode, hash) = if meta.is_dir() {
-                (DIRECTORY_MODE, write_tree(&entry.path())?)
-            } else if meta.is_file() {
-                (meta.permissions().mode(), blobify(&entry.path())?)
-            } else {
-                bail!("Unsupported file type: {}", entry.path().display());
-            };
-            let mut buffer = vec![];
-            write!(
-                &mut buffer,
-                "{:o} {}",
-                mode,
-                entry.file_name().to_string_lossy()
-            )?;
-            buffer.push(0);
-            buffer.extend(hash);
-            Ok(buffer)
-        })
-        .collect::<Result<Vec<_>>>()?
-        .concat();
+    // git orders directories as if their names ended with a slash
+    entries.sort_by_key(|e| {
+        let mut key = platform::path_bytes(Path::new(&e.file_name()));
+        if e.file_type().is_ok_and(|t| t.is_dir()) {
+            key.push(b'/');
+        }
+        key
+    });
+    let mut content = vec![];
+    for entry in entries {
+        let meta = entry.metadata()?;
+        if ignore.is_ignored(&entry.path(), meta.is_dir())? {
+            continue;
+        }
+        let (mode, hash) = if meta.is_dir() && entry.path().join(".git").exists() {
+            // a nested repository is recorded as the commit it has checked out
+            match gitlink_head(&entry.path())? {
+                Some(hash) => (GITLINK_MODE, hash),
+                None => bail!(
+                    "{} does not have a commit checked out",
+                    entry.path().display()
+                ),
+            }
+        } else if meta.is_dir() {
+            let subtree = build_tree_content(odb, &entry.path(), ignore, conversion)?;
+            // like git, directories without tracked content are left out
+            if subtree.is_empty() {
+                continue;
+            }
+            (DIRECTORY_MODE, odb.write(b"tree", &subtree)?)
+        } else if meta.is_file() {
+            let content = conversion.convert_to_git(&entry.path(), fs::read(entry.path())?)?;
+            (file_mode(&meta, None), odb.write(b"blob", &content)?)
+        } else if meta.is_symlink() {
+            // a symlink is stored as a blob holding its target
+            let target = fs::read_link(entry.path())?;
+            (
+                SYMLINK_MODE,
+                odb.write(b"blob", &platform::path_bytes(&target))?,
+            )
+        } else {
+            bail!("Unsupported file type: {}", entry.path().display());
+        };
+        write!(
+            &mut content,
+            "{:o} {}",
+            mode,
+            entry.file_name().to_string_lossy()
+        )?;
+        content.push(0);
+        content.extend_from_slice(&hash);
+    }
+    Ok(content)
+}
+
+/// Whose identity a signature line records.
+#[derive(Clone, Copy)]
+pub enum Role {
+    Author,
+    Committer,
+}
 
+/// Identity line used for authors, committers and taggers of new objects.
+/// `GIT_AUTHOR_*`/`GIT_COMMITTER_*` variables take precedence over `user.name` and `user.email`.
+fn signature(role: Role) -> Result<String> {
+    let prefix = match role {
+        Role::Author => "GIT_AUTHOR",
+        Role::Committer => "GIT_COMMITTER",
+    };
+    let config = config::Config::load()?;
+    let name = env::var(format!("{prefix}_NAME"))
+        .ok()
+        .or_else(|| config.get("user.name").map(str::to_owned))
+        .unwrap_or_else(|| "Anonymous".to_owned());
+    let email = env::var(format!("{prefix}_EMAIL"))
+        .ok()
+        .or_else(|| config.get("user.email").map(str::to_owned))
+        .unwrap_or_else(|| "anonymous@localhost".to_owned());
+    let (timestamp, timezone) = match env::var(format!("{prefix}_DATE")) {
+        Ok(value) => {
+            date::parse(&value).with_context(|| format!("Invalid {prefix}_DATE '{value}'"))?
+        }
+        Err(_) => (date::now()?, "+0000".to_owned()),
+    };
+    Ok(format!("{name} <{email}> {timestamp} {timezone}"))
+}
+
+/// Writes a commit object with one `parent` line per parent, without moving any ref.
+pub fn write_commit(tree: &ObjectId, parents: &[ObjectId], message: &str) -> Result<ObjectId> {
+    write_commit_as(tree, parents, &signature(Role::Author)?, message)
+}
+
+/// Like `write_commit`, but keeping the author of a change made elsewhere,
+/// e.g. one taken from a patch.
+pub fn write_commit_as(
+    tree: &ObjectId,
+    parents: &[ObjectId],
+    author: &str,
+    message: &str,
+) -> Result<ObjectId> {
+    let content = commit_content(tree, parents, author, message)?;
+    Ok(Odb::open()?.write(b"commit", content.as_bytes())?)
+}
+
+/// The content of a commit made now by the current committer.
+pub fn commit_content(
+    tree: &ObjectId,
+    parents: &[ObjectId],
+    author: &str,
+    message: &str,
+) -> Result<String> {
+    let committer = signature(Role::Committer)?;
+    let mut content = format!("tree {}\n", hex::encode(tree));
+    for parent in parents {
+        content.push_str(&format!("parent {}\n", hex::encode(parent)));
+    }
+    content.push_str(&format!(
+        "author {author}\ncommitter {committer}\n\n{message}\n"
+    ));
     Ok(content)
 }
 
-pub fn commit(tree: &Hash, parent: &Hash, message: &str) -> Result<Hash> {
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map_err(|_| anyhow!("Failed to read system time"))?
-        .as_secs();
-    let timestamp = format!("{timestamp} +0000");
-    let parent_hash = hex::encode(&parent);
-    let tree_hash = hex::encode(&tree);
-    let content = format!(
-        "tree {tree_hash}
-parent {parent_hash}
-author Anonymous {timestamp}
-committer Anonymous {timestamp}
-
-{message}
-"
-    );
-    let hash = Object::new(b"commit", content.as_bytes()).serialize()?;
-
-    let mut filepath = PathBuf::new();
-    filepath.push(".git");
-    filepath.push("refs");
-    filepath.push("heads");
-    filepath.push("master");
-    fs::write(filepath, format!("{}\n", hex::encode(&hash)))?;
-    Ok(hash)
+/// The first seven hex digits of `hash`, as git abbreviates object names;
+/// shorter input is returned whole.
+pub fn short_hash(hash: &str) -> &str {
+    hash.get(..7).unwrap_or(hash)
 }
 
-pub fn parse_hash(hash: &str) -> Result<Hash> {
-    if hash.len() != HASH_HEX_SIZE {
+pub fn parse_hash(hash: &str) -> Result<ObjectId> {
+    if hash.len() != HashAlgo::current().hex_size() {
         bail!("Invalid hash size {}", hash.len());
     }
-    hex::decode(hash).with_context(|| "Invalid hash")
+    hash.parse()
 }
 
-pub fn init<T>(path: T) -> Result<()>
+/// Creates a repository at `path` and moves into it.
+pub fn init<T>(path: T, algo: HashAlgo) -> Result<()>
 where
     T: AsRef<Path>,
 {
-    let path = path.as_ref();
+    init_git_dir(path.as_ref(), algo)?;
+    env::set_current_dir(path)?;
+    Ok(())
+}
+
+/// Creates the git directory of a repository at `path`. Repositories using
+/// another hash than SHA-1 record it as an extension, which requires
+/// repository format version 1.
+fn init_git_dir(path: &Path, algo: HashAlgo) -> Result<()> {
     if !path.exists() {
-        fs::create_dir_all(&path)?;
+        fs::create_dir_all(path)?;
+    }
+    let git_dir = path.join(".git");
+    fs::create_dir(&git_dir)?;
+    fs::create_dir(git_dir.join("objects"))?;
+    fs::create_dir(git_dir.join("refs"))?;
+    fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n")?;
+    if algo != HashAlgo::Sha1 {
+        repository::with_git_dir(&git_dir, || {
+            let mut config = config::Config::local()?;
+            config.set("core.repositoryformatversion", "1")?;
+            config.set("extensions.objectFormat", algo.name())?;
+            config.save()
+        })?;
     }
-    env::set_current_dir(&path)?;
-    fs::create_dir(".git")?;
-    fs::create_dir(".git/objects")?;
-    fs::create_dir(".git/refs")?;
-    fs::write(".git/HEAD", "ref: refs/heads/master\n")?;
     Ok(())
 }
 
-pub fn store_references(refs: &[remote::Reference]) -> Result<String> {
-    println!("Store references");
-    let mut refs = refs.iter();
-    let (head_hash, _) = refs.next().ok_or_else(|| anyhow!("No HEAD reference"))?;
-    let dot_git = Path::new(".git");
-    for (hash, path) in refs {
-        if hash == head_hash {
-            fs::write(dot_git.join("HEAD"), format!("ref: {path}"))?;
-        }
-        let ref_filepath = dot_git.join(path);
-        let parent_dir = ref_filepath.parent().unwrap();
-        if !parent_dir.exists() {
-            fs::create_dir_all(parent_dir)?;
+pub fn store_references(references: &[remote::Reference], url: &str) -> Result<String> {
+    let mut references = references.iter();
+    let (head_hash, _) = references
+        .next()
+        .ok_or_else(|| anyhow!("No HEAD reference"))?;
+    // HEAD may have been chosen from an annotated tag
+    let head_hash = &peel_to_commit(head_hash)?;
+    let message = format!("clone: from {url}");
+    let mut transaction = refs::Transaction::new();
+    let mut head_ref = None;
+    for (hash, name) in references {
+        let logged = hash == head_hash && head_ref.is_none() && name.starts_with("refs/heads/");
+        if logged {
+            head_ref = Some(name);
         }
-        fs::write(ref_filepath, format!("{hash}\n"))?;
+        let target = refs::Target::Direct(hash.clone());
+        transaction.update(name, target, None, logged.then_some(message.as_str()));
     }
-    println!("Stored all references");
-
+    // replace init's HEAD outright rather than following it; once HEAD
+    // points at the branch, the branch's log entry is recorded for it too
+    match head_ref {
+        Some(name) => transaction.update("HEAD", refs::Target::Symbolic(name.clone()), None, None),
+        None => transaction.update(
+            "HEAD",
+            refs::Target::Direct(head_hash.clone()),
+            None,
+            Some(&message),
+        ),
+    };
+    transaction.commit()?;
     Ok(head_hash.clone())
 }
 
+/// Checks out the tree of a commit into the current directory and writes
+/// the index for it. With sparse checkout, files outside the sparse cone are
+/// not written and are flagged with skip-worktree in the index.
 pub fn checkout(hash: &str) -> Result<()> {
-    println!("Checkout {hash}");
-    if let ParsedObject::Commit(commit) = Object::from_hash(hash)?.parse()? {
-        checkout_tree(&commit, &std::env::current_dir()?)
+    let odb = Odb::open()?;
+    let commit = odb.read_commit(hash)?;
+    let sparse = sparse::Sparse::load()?;
+    let mut index = index::Index::default();
+    for (path, (mode, hash)) in flatten_tree(&odb, &commit.tree)? {
+        let mut entry = index::Entry::staged(&path, mode, hash, 0)?;
+        entry.skip_worktree = sparse.as_ref().is_some_and(|s| !s.includes(&path));
+        index.entries.push(entry);
+    }
+    let names = index
+        .entries
+        .iter()
+        .filter(|entry| !entry.skip_worktree)
+        .map(|entry| entry.path.clone())
+        .collect();
+    checkout_index::write_entries(&odb, &mut index, &names, true, "")?;
+    index.write()
+}
+
+fn parse_commit(content: &[u8]) -> Result<Commit> {
+    let content = String::from_utf8_lossy(content);
+    let (headers, message) = content
+        .split_once("\n\n")
+        .ok_or_else(|| anyhow!("commit has no message separator"))?;
+    let mut tree = None;
+    let mut parents = vec![];
+    let mut author = None;
+    let mut committer = None;
+    let mut encoding = None;
+    let mut gpgsig: Option<String> = None;
+    let mut last_header = "";
+    for line in headers.lines() {
+        // continuation lines of multi-line headers start with a space
+        if let Some(continued) = line.strip_prefix(' ') {
+            if let ("gpgsig", Some(signature)) = (last_header, gpgsig.as_mut()) {
+                signature.push('\n');
+                signature.push_str(continued);
+            }
+            continue;
+        }
+        let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+        last_header = name;
+        let identity = |value: &str| {
+            Identity::parse(value).ok_or_else(|| anyhow!("invalid {name} line '{value}'"))
+        };
+        match name {
+            "tree" => tree = Some(value.to_owned()),
+            "parent" => parents.push(value.to_owned()),
+            "author" => author = Some(identity(value)?),
+            "committer" => committer = Some(identity(value)?),
+            "encoding" => encoding = Some(value.to_owned()),
+            "gpgsig" => gpgsig = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    Ok(Commit {
+        tree: tree.ok_or_else(|| anyhow!("commit does not have a tree line"))?,
+        parents,
+        author: author.ok_or_else(|| anyhow!("commit does not have an author line"))?,
+        committer: committer.ok_or_else(|| anyhow!("commit does not have a committer line"))?,
+        encoding,
+        gpgsig: gpgsig.map(|signature| signature + "\n"),
+        message: message.to_owned(),
+    })
+}
+
+/// Follows annotated tags until reaching the commit they point at.
+pub fn peel_to_commit(hash: &str) -> Result<remote::Sha1> {
+    let odb = Odb::open()?;
+    let mut hash = hash.to_owned();
+    loop {
+        match odb.read(&hash)?.parse()? {
+            ParsedObject::Commit(_) => return Ok(hash),
+            ParsedObject::Tag(tag) => hash = tag.object,
+            _ => bail!("{hash} is not a commit"),
+        }
+    }
+}
+
+fn write_file(
+    odb: &Odb,
+    filepath: &Path,
+    mode: u32,
+    hash: &ObjectId,
+    conversion: &mut attributes::Conversion,
+) -> Result<()> {
+    if mode == GITLINK_MODE {
+        fs::create_dir_all(filepath)?;
+        return Ok(());
+    }
+    let sha = hex::encode(hash);
+    // a partial clone may have been fetched without this blob
+    if !odb.exists(&sha)? {
+        eprintln!(
+            "warning: skipping {}: blob {sha} is missing",
+            filepath.display()
+        );
+        return Ok(());
+    }
+    if let ParsedObject::Blob(content) = odb.read(&sha)?.parse()? {
+        if mode == SYMLINK_MODE {
+            if fs::symlink_metadata(filepath).is_ok() {
+                fs::remove_file(filepath)?;
+            }
+            platform::symlink(&content, filepath)?;
+            return Ok(());
+        }
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filepath)?;
+        file.write_all(&conversion.convert_to_worktree(filepath, content)?)?;
+        // an existing file keeps its old permissions when truncated
+        platform::set_mode(filepath, mode)?;
+        conversion.written(filepath);
+        Ok(())
     } else {
-        bail!("{hash} is not a commit")
+        bail!("{sha} is not a blob")
     }
 }
 
-fn parse_commit(content: &[u8]) -> Result<remote::Sha1> {
-    Ok(String::from_utf8(
-        content
-            .strip_prefix(b"tree ")
-            .ok_or_else(|| anyhow!("commit does not start with the tree line"))?
-            .bytes()
-            .flatten()
-            .take_while(|b| *b != b'\n')
-            .collect(),
-    )?)
+/// Files of a tree keyed by their path relative to the tree root.
+pub type TreeFiles = BTreeMap<PathBuf, (u32, ObjectId)>;
+
+/// Recursively lists all non-tree entries reachable from a tree.
+pub fn flatten_tree(odb: &Odb, tree_hash: &str) -> Result<TreeFiles> {
+    let mut files = TreeFiles::new();
+    collect_tree_files(odb, tree_hash, Path::new(""), &mut files)?;
+    Ok(files)
 }
 
-fn checkout_tree(tree_hash: &str, target_path: &Path) -> Result<()> {
-    if let ParsedObject::Tree(entries) = Object::from_hash(tree_hash)?.parse()? {
-        // recurse trees and create objects from blobs
-        fs::create_dir_all(target_path)?;
+fn collect_tree_files(
+    odb: &Odb,
+    tree_hash: &str,
+    prefix: &Path,
+    files: &mut TreeFiles,
+) -> Result<()> {
+    if let ParsedObject::Tree(entries) = odb.read(tree_hash)?.parse()? {
         for entry in entries {
-            println!("entry {:o} {}", entry.mode, entry.name);
+            let path = prefix.join(&entry.name);
+            // names are checked before any file can be written for them
+            if !is_valid_entry_name(&entry.name) {
+                bail!("invalid path '{}'", path.display());
+            }
             if entry.mode == DIRECTORY_MODE {
-                checkout_tree(&hex::encode(&entry.hash), &target_path.join(entry.name))?
+                collect_tree_files(odb, &hex::encode(entry.hash), &path, files)?;
             } else {
-                checkout_file(entry, target_path)?
+                files.insert(path, (entry.mode, entry.hash));
             }
         }
         Ok(())
@@ -322,19 +1076,344 @@ fn checkout_tree(tree_hash: &str, target_path: &Path) -> Result<()> {
     }
 }
 
-fn checkout_file(file_entry: TreeEntry, parent_dir: &Path) -> Result<()> {
-    let sha = hex::encode(&file_entry.hash);
-    let filepath = parent_dir.join(file_entry.name);
-    if let ParsedObject::Blob(content) = Object::from_hash(&sha)?.parse()? {
-        fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .mode(file_entry.mode)
-            .open(filepath)?
-            .write_all(&content)?;
-        Ok(())
-    } else {
-        bail!("{sha} is not a blob")
+/// Hashes a working tree file as a blob without writing it to the object store.
+fn hash_file(filepath: &Path, conversion: &mut attributes::Conversion) -> Result<Option<ObjectId>> {
+    Ok(worktree_blob(filepath, None, conversion)?
+        .map(|(_, content)| Object::new(b"blob", &content).hash()))
+}
+
+/// The commit checked out in the repository at `path`, which has either a
+/// `.git` directory or a `.git` file pointing at one, as submodules do.
+pub fn gitlink_head(path: &Path) -> Result<Option<ObjectId>> {
+    let git_dir = repository::resolve(path)?;
+    let mut name = "HEAD".to_owned();
+    // guard against symbolic ref loops
+    for _ in 0..5 {
+        let loose = git_dir.join(&name);
+        let value = if loose.is_file() {
+            fs::read_to_string(loose)?.trim_end().to_owned()
+        } else {
+            let packed = fs::read_to_string(git_dir.join("packed-refs")).unwrap_or_default();
+            match packed
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .find(|(_, packed_name)| *packed_name == name)
+            {
+                Some((hash, _)) => hash.to_owned(),
+                None => return Ok(None),
+            }
+        };
+        match value.strip_prefix("ref: ") {
+            Some(target) => name = target.to_owned(),
+            None => return Ok(Some(parse_hash(&value)?)),
+        }
+    }
+    bail!("Too many levels of symbolic refs in {}", git_dir.display())
+}
+
+/// Whether a path tracked with `mode` is a submodule that was never cloned,
+/// which leaves just an empty directory and counts as unchanged.
+pub fn is_unpopulated_submodule(path: &Path, mode: u32) -> bool {
+    mode == GITLINK_MODE && path.is_dir() && !path.join(".git").exists()
+}
+
+/// Reads a regular file or a symlink of the working tree as the mode and
+/// content of the blob it would be stored as, or `None` if there is none.
+/// `recorded` is the mode the index or a tree has for the path.
+pub fn worktree_blob(
+    path: &Path,
+    recorded: Option<u32>,
+    conversion: &mut attributes::Conversion,
+) -> Result<Option<(u32, Vec<u8>)>> {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+    if meta.is_symlink() {
+        let target = fs::read_link(path)?;
+        return Ok(Some((SYMLINK_MODE, platform::path_bytes(&target))));
+    }
+    if !meta.is_file() {
+        return Ok(None);
+    }
+    let content = conversion.convert_to_git(path, fs::read(path)?)?;
+    Ok(Some((file_mode(&meta, recorded), content)))
+}
+
+/// The mode git records for a regular file: only whether its owner may
+/// execute it matters, other permission bits are dropped. File systems
+/// without execute bits keep the `recorded` mode, as do symlinks checked out
+/// as plain files there.
+pub fn file_mode(meta: &fs::Metadata, recorded: Option<u32>) -> u32 {
+    match platform::is_executable(meta) {
+        Some(true) => 0o100755,
+        Some(false) => 0o100644,
+        None => recorded
+            .filter(|mode| matches!(*mode, 0o100755 | SYMLINK_MODE))
+            .unwrap_or(0o100644),
+    }
+}
+
+fn head_files(odb: &Odb) -> Result<TreeFiles> {
+    match refs::peel("HEAD")? {
+        Some(hash) => flatten_tree(odb, &odb.read_commit(&hash)?.tree),
+        // unborn branch
+        None => Ok(TreeFiles::new()),
+    }
+}
+
+/// Switches to a local branch or, given any other commit or asked to
+/// `detach`, detaches HEAD at the commit, updating the working tree to match.
+pub fn checkout_revision(revision: &str, detach: bool) -> Result<()> {
+    if !detach && refs::read(&format!("refs/heads/{revision}"))?.is_some() {
+        return switch_branch(revision);
+    }
+    let hash = peel_to_commit(&revparse::resolve(revision)?)?;
+    let odb = Odb::open()?;
+    let target = flatten_tree(&odb, &odb.read_commit(&hash)?.tree)?;
+    move_checkout(&odb, &head_files(&odb)?, &target)?;
+    let old_head = refs::peel("HEAD")?;
+    let attached = refs::current_branch()?.is_some();
+    let from = checkout_origin(&hash)?;
+    refs::write("HEAD", &hash)?;
+    reflog::append(
+        "HEAD",
+        old_head.as_deref(),
+        &hash,
+        &format!("checkout: moving from {from} to {revision}"),
+    )?;
+    if attached && !detach {
+        println!(
+            "Note: switching to '{revision}'.\n\n\
+             You are in 'detached HEAD' state. You can look around, make experimental\n\
+             changes and commit them, and you can discard any commits you make in this\n\
+             state without impacting any branches by switching back to a branch.\n"
+        );
+    }
+    let subject = odb.read_commit(&hash)?.subject();
+    println!("HEAD is now at {} {subject}", short_hash(&hash));
+    Ok(())
+}
+
+/// Switches HEAD to a local branch and updates the working tree to match it.
+pub fn switch_branch(name: &str) -> Result<()> {
+    let branch_ref = format!("refs/heads/{name}");
+    let hash = refs::peel(&branch_ref)?
+        .ok_or_else(|| anyhow!("pathspec '{name}' did not match any branch"))?;
+    let odb = Odb::open()?;
+    let target = flatten_tree(&odb, &odb.read_commit(&hash)?.tree)?;
+    move_checkout(&odb, &head_files(&odb)?, &target)?;
+    let old_head = refs::peel("HEAD")?;
+    let from = checkout_origin(&hash)?;
+    refs::write_symbolic("HEAD", &branch_ref)?;
+    reflog::append(
+        "HEAD",
+        old_head.as_deref(),
+        &hash,
+        &format!("checkout: moving from {from} to {name}"),
+    )?;
+    println!("Switched to branch '{name}'");
+    Ok(())
+}
+
+/// What a checkout to `target` moves away from, as git names it in the
+/// reflog: the current branch or, when HEAD is detached, the commit, which
+/// is also reported if left so that it can be found again.
+fn checkout_origin(target: &str) -> Result<String> {
+    if let Some(branch) = refs::current_branch()? {
+        return Ok(branch);
+    }
+    let hash = refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD is unborn"))?;
+    if hash != target {
+        println!(
+            "Previous HEAD position was {} {}",
+            short_hash(&hash),
+            Odb::open()?.read_commit(&hash)?.subject()
+        );
+    }
+    Ok(hash)
+}
+
+/// Advances the current branch (or detached HEAD) to a descendant commit,
+/// updating the working tree to match.
+pub fn fast_forward(hash: &str, message: &str) -> Result<()> {
+    let odb = Odb::open()?;
+    let target = flatten_tree(&odb, &odb.read_commit(hash)?.tree)?;
+    move_checkout(&odb, &head_files(&odb)?, &target)?;
+    Ok(refs::update("HEAD", hash, message)?)
+}
+
+/// Detaches HEAD at a commit, updating the working tree and the index to match.
+pub fn detach_head(hash: &str, message: &str) -> Result<()> {
+    let odb = Odb::open()?;
+    let target = flatten_tree(&odb, &odb.read_commit(hash)?.tree)?;
+    move_checkout(&odb, &head_files(&odb)?, &target)?;
+    let old_head = refs::peel("HEAD")?;
+    refs::write("HEAD", hash)?;
+    reflog::append("HEAD", old_head.as_deref(), hash, message)
+}
+
+/// Makes the working tree and the index match a commit, discarding changes
+/// to tracked files, conflicted ones included. HEAD is left alone.
+pub fn reset_hard(odb: &Odb, hash: &str) -> Result<()> {
+    let target = flatten_tree(odb, &odb.read_commit(hash)?.tree)?;
+    let index = index::Index::read()?;
+    let tracked = head_files(odb)?
+        .into_iter()
+        .map(|(path, (mode, _))| (path, mode))
+        .chain(
+            index
+                .entries
+                .iter()
+                .map(|entry| (PathBuf::from(&entry.path), entry.mode)),
+        )
+        .collect::<BTreeMap<_, _>>();
+    // what is on disk is what gets replaced, so nothing counts as a local change
+    let mut conversion = attributes::Conversion::new()?;
+    let mut on_disk = TreeFiles::new();
+    for (path, mode) in tracked {
+        if let Some(version) = diff::Version::from_file(&path, Some(mode), &mut conversion)? {
+            on_disk.insert(path, (version.mode, version.hash));
+        }
+    }
+    update_worktree(odb, &on_disk, &target)?;
+    index::Index::from_files(&target)?.write()
+}
+
+/// Moves the working tree and the index from `current` to `target`. Index
+/// entries of paths the move leaves alone are kept, staged changes included.
+fn move_checkout(odb: &Odb, current: &TreeFiles, target: &TreeFiles) -> Result<()> {
+    update_worktree(odb, current, target)?;
+    let mut index = index::Index::read()?;
+    for path in current.keys().chain(target.keys()) {
+        if current.get(path) == target.get(path) {
+            continue;
+        }
+        index.remove(
+            &path
+                .to_string_lossy()
+                .replace(std::path::MAIN_SEPARATOR, "/"),
+        );
+        if let Some((mode, hash)) = target.get(path) {
+            let entry = index::Entry::from_file(path, *mode, *hash)
+                .or_else(|_| index::Entry::staged(path, *mode, *hash, 0))?;
+            index.add(entry);
+        }
+    }
+    index.write()
+}
+
+/// Moves the working tree from `current` to `target`, touching only files that differ.
+/// Refuses to proceed if any of those files has local modifications.
+fn update_worktree(odb: &Odb, current: &TreeFiles, target: &TreeFiles) -> Result<()> {
+    let mut conversion = attributes::Conversion::new()?;
+    let changed = current
+        .keys()
+        .chain(target.keys())
+        .filter(|path| current.get(*path) != target.get(*path))
+        .collect::<BTreeSet<_>>();
+    let conflicts = changed
+        .iter()
+        .filter(|path| {
+            // submodule contents are never touched
+            let is_gitlink = |files: &TreeFiles| {
+                files
+                    .get(**path)
+                    .is_some_and(|(mode, _)| *mode == GITLINK_MODE)
+            };
+            if is_gitlink(current) || is_gitlink(target) {
+                return false;
+            }
+            let on_disk = hash_file(path, &mut conversion).ok().flatten();
+            match (current.get(**path), target.get(**path)) {
+                (Some((_, hash)), _) => on_disk.as_ref() != Some(hash),
+                // untracked files are fine unless they differ from what we would write
+                (None, Some((_, hash))) => on_disk.is_some_and(|h| &h != hash),
+                (None, None) => false,
+            }
+        })
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+    if !conflicts.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by checkout:\n\t{}",
+            conflicts.join("\n\t")
+        );
+    }
+    for path in changed {
+        match target.get(path) {
+            Some((mode, hash)) => {
+                if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)?;
+                }
+                write_file(odb, path, *mode, hash, &mut conversion)?;
+            }
+            None => {
+                if current
+                    .get(path)
+                    .is_some_and(|(mode, _)| *mode == GITLINK_MODE)
+                {
+                    // like git, keep a submodule that is not empty
+                    if fs::remove_dir(path).is_err() {
+                        eprintln!("warning: unable to rmdir '{}'", path.display());
+                        continue;
+                    }
+                } else {
+                    fs::remove_file(path)?;
+                }
+                remove_empty_parents(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn remove_empty_parents(path: &Path) {
+    let mut parent = path.parent();
+    while let Some(directory) = parent.filter(|p| !p.as_os_str().is_empty()) {
+        if fs::remove_dir(directory).is_err() {
+            break;
+        }
+        parent = directory.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_names_that_cannot_be_checked_out() {
+        for name in ["", ".", "..", ".git", ".GIT", ".Git", "a/b", "/"] {
+            assert!(!is_valid_entry_name(name), "{name:?} is accepted");
+        }
+        for name in ["a", ".gitignore", "...", ".git2", "..a", "git"] {
+            assert!(is_valid_entry_name(name), "{name:?} is refused");
+        }
+    }
+
+    #[test]
+    fn paths_leading_out_of_the_worktree() {
+        for path in [
+            "/etc/passwd",
+            "../evil",
+            "a/../../evil",
+            ".git/config",
+            "sub/.git/hooks/post-checkout",
+            "a//b",
+            "a/",
+            "./a",
+        ] {
+            assert!(verify_path(path).is_err(), "{path:?} is accepted");
+        }
+        for path in ["a", "a/b/c", ".github/workflows/ci.yml", "a/.gitignore"] {
+            assert!(verify_path(path).is_ok(), "{path:?} is refused");
+        }
+    }
+
+    #[test]
+    fn short_hashes_never_panic() {
+        assert_eq!(short_hash("0123456789abcdef"), "0123456");
+        assert_eq!(short_hash("abc"), "abc");
+        assert_eq!(short_hash(""), "");
     }
 }
@@ -0,0 +1,503 @@
+use super::{
+    diff::lines,
+    file_mode, has_symlink_leading_path,
+    index::{Entry, Index},
+    platform, remove_empty_parents, verify_path, ObjectId, Odb, ParsedObject,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// How many context lines a hunk may lose at each end and still apply.
+const MAX_FUZZ: usize = 2;
+const DEFAULT_MODE: u32 = 0o100644;
+
+#[derive(Default)]
+pub struct ApplyOptions {
+    /// Apply to the index only, leaving the working tree alone (`--cached`).
+    pub cached: bool,
+    /// Apply to both the working tree and the index (`--index`).
+    pub index: bool,
+    /// Only check that the patch applies (`--check`).
+    pub check: bool,
+}
+
+/// The changes to one file, `None` paths standing for `/dev/null`.
+#[derive(Default)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_mode: Option<u32>,
+    pub new_mode: Option<u32>,
+    /// Whether differing old and new paths are a rename rather than two
+    /// names for one file, as plain diffs often have.
+    pub rename: bool,
+    pub hunks: Vec<Hunk>,
+}
+
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_start: usize,
+    pub new_count: usize,
+    /// Lines with their ` `, `-` or `+` prefix removed, newlines included.
+    pub lines: Vec<(u8, Vec<u8>)>,
+}
+
+impl Hunk {
+    /// The lines the hunk expects and the lines it leaves behind.
+    fn images(&self) -> (Vec<&[u8]>, Vec<&[u8]>) {
+        let (mut preimage, mut postimage) = (vec![], vec![]);
+        for (kind, line) in &self.lines {
+            if *kind != b'+' {
+                preimage.push(line.as_slice());
+            }
+            if *kind != b'-' {
+                postimage.push(line.as_slice());
+            }
+        }
+        (preimage, postimage)
+    }
+
+    /// Number of context lines before and after the changes.
+    fn context(&self) -> (usize, usize) {
+        let leading = self.lines.iter().take_while(|(k, _)| *k == b' ').count();
+        let trailing = self
+            .lines
+            .iter()
+            .rev()
+            .take_while(|(k, _)| *k == b' ')
+            .count();
+        (leading, trailing)
+    }
+}
+
+/// Parses a patch in unified format, with or without git's extended headers.
+pub fn parse(patch: &[u8]) -> Result<Vec<FilePatch>> {
+    let mut patches: Vec<FilePatch> = vec![];
+    let mut lines = lines(patch).into_iter().peekable();
+    while let Some(line) = lines.next() {
+        let line_str = String::from_utf8_lossy(line);
+        let line_str = line_str.trim_end_matches(['\n', '\r']);
+        if let Some(paths) = line_str.strip_prefix("diff --git ") {
+            // the ---/+++ lines override these for anything but pure mode changes
+            let (old, new) = paths
+                .split_once(" b/")
+                .ok_or_else(|| anyhow!("Malformed diff header: {line_str}"))?;
+            patches.push(FilePatch {
+                old_path: Some(strip_prefix(old)),
+                new_path: Some(strip_prefix(new)),
+                ..Default::default()
+            });
+            continue;
+        }
+        if line_str.starts_with("--- ") && patches.last().is_none_or(|p| !p.hunks.is_empty()) {
+            // a plain unified diff without git headers
+            patches.push(FilePatch::default());
+        }
+        let Some(current) = patches.last_mut() else {
+            // anything before the first file, such as a commit message
+            continue;
+        };
+        let mode = |value: &str| u32::from_str_radix(value, 8).context("Invalid file mode");
+        if let Some(value) = line_str.strip_prefix("new file mode ") {
+            current.old_path = None;
+            current.new_mode = Some(mode(value)?);
+        } else if let Some(value) = line_str.strip_prefix("deleted file mode ") {
+            current.new_path = None;
+            current.old_mode = Some(mode(value)?);
+        } else if let Some(value) = line_str.strip_prefix("old mode ") {
+            current.old_mode = Some(mode(value)?);
+        } else if let Some(value) = line_str.strip_prefix("new mode ") {
+            current.new_mode = Some(mode(value)?);
+        } else if let Some(path) = line_str.strip_prefix("rename from ") {
+            current.old_path = Some(path.to_owned());
+            current.rename = true;
+        } else if let Some(path) = line_str.strip_prefix("rename to ") {
+            current.new_path = Some(path.to_owned());
+            current.rename = true;
+        } else if let Some(path) = line_str.strip_prefix("--- ") {
+            current.old_path = patch_path(path);
+        } else if let Some(path) = line_str.strip_prefix("+++ ") {
+            current.new_path = patch_path(path);
+        } else if line_str.starts_with("GIT binary patch") || line_str.starts_with("Binary files ")
+        {
+            bail!("Cannot apply binary patches");
+        } else if line_str.starts_with("@@ ") {
+            current.hunks.push(parse_hunk(line_str, &mut lines)?);
+        }
+    }
+    for patch in &mut patches {
+        if patch.old_path.is_some() && patch.new_path.is_some() && !patch.rename {
+            patch.old_path = patch.new_path.clone();
+        }
+    }
+    Ok(patches)
+}
+
+/// Path of a ---/+++ line without its `a/` or `b/` prefix and any timestamp.
+fn patch_path(path: &str) -> Option<String> {
+    let path = path.split('\t').next().unwrap_or(path);
+    (path != "/dev/null").then(|| strip_prefix(path))
+}
+
+fn strip_prefix(path: &str) -> String {
+    // like -p1: drop the first path component
+    path.split_once('/')
+        .map_or(path, |(_, rest)| rest)
+        .to_owned()
+}
+
+fn parse_hunk<'a>(
+    header: &str,
+    lines: &mut std::iter::Peekable<impl Iterator<Item = &'a [u8]>>,
+) -> Result<Hunk> {
+    let malformed = || anyhow!("Malformed hunk header: {header}");
+    let mut ranges = header.split(' ').skip(1);
+    let mut range = |prefix: char| -> Result<(usize, usize)> {
+        let range = ranges
+            .next()
+            .and_then(|r| r.strip_prefix(prefix))
+            .ok_or_else(malformed)?;
+        Ok(match range.split_once(',') {
+            Some((start, count)) => (start.parse()?, count.parse()?),
+            None => (range.parse()?, 1),
+        })
+    };
+    let (old_start, old_count) = range('-')?;
+    let (new_start, new_count) = range('+')?;
+    let mut hunk = Hunk {
+        old_start,
+        old_count,
+        new_start,
+        new_count,
+        lines: vec![],
+    };
+    let (mut old_left, mut new_left) = (old_count, new_count);
+    while old_left > 0 || new_left > 0 || lines.peek().is_some_and(|l| l.starts_with(b"\\")) {
+        let line = lines
+            .next()
+            .ok_or_else(|| anyhow!("Patch ends in the middle of a hunk"))?;
+        let (kind, content) = match line.split_first() {
+            Some((b'\\', _)) => {
+                // "\ No newline at end of file" applies to the line before it
+                if let Some((_, previous)) = hunk.lines.last_mut() {
+                    if previous.ends_with(b"\n") {
+                        previous.pop();
+                    }
+                }
+                continue;
+            }
+            Some((&kind, content)) if matches!(kind, b' ' | b'-' | b'+') => (kind, content),
+            // some editors strip the space of empty context lines
+            Some((b'\n', _)) => (b' ', line),
+            _ => bail!("Corrupt patch line: {}", String::from_utf8_lossy(line)),
+        };
+        match kind {
+            b' ' => {
+                old_left = old_left.checked_sub(1).ok_or_else(malformed)?;
+                new_left = new_left.checked_sub(1).ok_or_else(malformed)?;
+            }
+            b'-' => old_left = old_left.checked_sub(1).ok_or_else(malformed)?,
+            _ => new_left = new_left.checked_sub(1).ok_or_else(malformed)?,
+        }
+        hunk.lines.push((kind, content.to_vec()));
+    }
+    Ok(hunk)
+}
+
+/// Applies hunks in order. A hunk that does not match where its header says is
+/// searched for nearby, then retried with up to `MAX_FUZZ` context lines
+/// dropped at each end; both are reported as they may hide a wrong result.
+pub fn apply_hunks(path: &str, content: &[u8], hunks: &[Hunk]) -> Result<Vec<u8>> {
+    let mut result = lines(content);
+    // how far hunks have moved from their positions in the header
+    let mut shift = 0isize;
+    for (number, hunk) in (1..).zip(hunks) {
+        let (preimage, postimage) = hunk.images();
+        let (leading, trailing) = hunk.context();
+        // an empty preimage is inserted after the line in its header
+        let expected = match hunk.old_count {
+            0 => hunk.old_start,
+            _ => hunk.old_start.saturating_sub(1),
+        };
+        let expected = (expected as isize + shift).max(0) as usize;
+        let mut applied = false;
+        for fuzz in 0..=MAX_FUZZ {
+            let (head, tail) = (fuzz.min(leading), fuzz.min(trailing));
+            if fuzz > 0 && head == 0 && tail == 0 {
+                break;
+            }
+            let preimage = &preimage[head..preimage.len() - tail];
+            let postimage = &postimage[head..postimage.len() - tail];
+            let Some(position) = find(&result, preimage, expected + head) else {
+                continue;
+            };
+            let offset = position as isize - (expected + head) as isize;
+            if offset != 0 || fuzz > 0 {
+                eprint!("Hunk #{number} succeeded at {}", position + 1);
+                if fuzz > 0 {
+                    eprint!(" with fuzz {fuzz}");
+                }
+                if offset != 0 {
+                    eprint!(" (offset {offset} lines)");
+                }
+                eprintln!(".");
+            }
+            result.splice(
+                position..position + preimage.len(),
+                postimage.iter().copied(),
+            );
+            shift += offset + postimage.len() as isize - preimage.len() as isize;
+            applied = true;
+            break;
+        }
+        if !applied {
+            bail!(
+                "patch failed: {path}:{}\n{path}: patch does not apply",
+                hunk.old_start
+            );
+        }
+    }
+    Ok(result.concat())
+}
+
+/// Finds the position of `image` in `lines` closest to `expected`.
+fn find(lines: &[&[u8]], image: &[&[u8]], expected: usize) -> Option<usize> {
+    let last = lines.len().checked_sub(image.len())?;
+    let matches = |position: usize| lines[position..position + image.len()] == *image;
+    let expected = expected.min(last);
+    (0..=last).find_map(|distance| {
+        [expected.checked_sub(distance), Some(expected + distance)]
+            .into_iter()
+            .flatten()
+            .find(|&position| position <= last && matches(position))
+    })
+}
+
+/// The new state of one path: its content and mode, or `None` for deletion.
+type Outcome = Option<(Vec<u8>, u32)>;
+
+/// Applies a patch file to the working tree and/or the index. Every file is
+/// patched in memory first so that a failing hunk leaves nothing half-applied.
+pub fn apply(patch_file: &Path, options: &ApplyOptions) -> Result<()> {
+    let patch = fs::read(patch_file)
+        .with_context(|| format!("Cannot read patch {}", patch_file.display()))?;
+    apply_patches(&parse(&patch)?, options)
+}
+
+/// Applies parsed patches as `apply` does. Their paths are checked before
+/// anything is read or written, so that no patch can reach outside of the
+/// working tree or into the repository.
+pub fn apply_patches(patches: &[FilePatch], options: &ApplyOptions) -> Result<()> {
+    for patch in patches {
+        for path in [&patch.old_path, &patch.new_path].into_iter().flatten() {
+            verify_path(path)?;
+            if !options.cached && has_symlink_leading_path("", path) {
+                bail!("{path}: affected file is beyond a symbolic link");
+            }
+        }
+    }
+    let odb = Odb::open()?;
+    let use_index = options.cached || options.index;
+    let mut index = if use_index {
+        Some(Index::read()?)
+    } else {
+        None
+    };
+    // paths that go away may be replaced, e.g. a directory by a file
+    let removed = patches
+        .iter()
+        .filter(|p| p.old_path != p.new_path)
+        .filter_map(|p| p.old_path.as_deref())
+        .collect::<HashSet<_>>();
+    let mut outcomes: BTreeMap<String, Outcome> = BTreeMap::new();
+    for patch in patches {
+        let old = match &patch.old_path {
+            Some(path) => Some(match outcomes.get(path) {
+                // a patch may touch the same file several times
+                Some(outcome) => outcome
+                    .clone()
+                    .ok_or_else(|| anyhow!("{path}: already deleted by this patch"))?,
+                None => read_current(&odb, index.as_ref(), path, options)?,
+            }),
+            None => None,
+        };
+        if let (None, Some(path)) = (&patch.old_path, &patch.new_path) {
+            let on_disk =
+                !options.cached && fs::symlink_metadata(path).is_ok_and(|meta| !meta.is_dir());
+            let in_index = index
+                .as_ref()
+                .is_some_and(|i| i.entries.iter().any(|e| e.path == *path));
+            if outcomes.get(path).is_some_and(Option::is_some)
+                || (!removed.contains(path.as_str()) && (on_disk || in_index))
+            {
+                bail!("{path}: already exists");
+            }
+        }
+        let (content, old_mode) = old.unwrap_or((vec![], DEFAULT_MODE));
+        let name = patch.new_path.as_ref().or(patch.old_path.as_ref()).unwrap();
+        let content = apply_hunks(name, &content, &patch.hunks)?;
+        if let Some(path) = &patch.old_path {
+            outcomes.insert(path.clone(), None);
+        }
+        if let Some(path) = &patch.new_path {
+            let mode = patch.new_mode.unwrap_or(old_mode);
+            outcomes.insert(path.clone(), Some((content, mode)));
+        } else if !content.is_empty() {
+            bail!("{name}: removal patch leaves file contents");
+        }
+    }
+    if options.check {
+        return Ok(());
+    }
+
+    let mut ordered = outcomes.iter().collect::<Vec<_>>();
+    // removals go first to make room for files replacing them
+    ordered.sort_by_key(|(_, outcome)| outcome.is_some());
+    for (path, outcome) in ordered {
+        if !options.cached {
+            write_worktree(Path::new(path), outcome.as_ref())?;
+        }
+        if let Some(index) = index.as_mut() {
+            match outcome {
+                Some((content, mode)) => {
+                    let hash = odb.write(b"blob", content)?;
+                    index.add(index_entry(path, *mode, hash, options.cached)?);
+                }
+                None => index.remove(path),
+            }
+        }
+    }
+    if let Some(index) = index {
+        index.write()?;
+    }
+    Ok(())
+}
+
+/// Reads the content and mode a patch applies to, from the index with
+/// `--cached` and from the working tree otherwise.
+fn read_current(
+    odb: &Odb,
+    index: Option<&Index>,
+    path: &str,
+    options: &ApplyOptions,
+) -> Result<(Vec<u8>, u32)> {
+    let entry = index.and_then(|i| i.entries.iter().find(|e| e.path == path && e.stage == 0));
+    if options.cached || options.index {
+        let entry = entry.ok_or_else(|| anyhow!("{path}: does not exist in index"))?;
+        let ParsedObject::Blob(content) = odb.read(&entry.hash.to_string())?.parse()? else {
+            bail!("{path}: index entry is not a blob");
+        };
+        if options.cached {
+            return Ok((content, entry.mode));
+        }
+        if fs::read(path).ok().as_ref() != Some(&content) {
+            bail!("{path}: does not match index");
+        }
+    }
+    let content = fs::read(path).with_context(|| format!("{path}: No such file or directory"))?;
+    let recorded = entry.map(|e| e.mode);
+    Ok((content, file_mode(&fs::metadata(path)?, recorded)))
+}
+
+fn write_worktree(path: &Path, outcome: Option<&(Vec<u8>, u32)>) -> Result<()> {
+    match outcome {
+        Some((content, mode)) => {
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, content)?;
+            platform::set_mode(path, *mode)?;
+        }
+        None => {
+            fs::remove_file(path)?;
+            remove_empty_parents(path);
+        }
+    }
+    Ok(())
+}
+
+fn index_entry(path: &str, mode: u32, hash: ObjectId, cached: bool) -> Result<Entry> {
+    if cached {
+        // the working tree file does not match, so no stat data is recorded
+        Entry::staged(Path::new(path), mode, hash, 0)
+    } else {
+        Entry::from_file(Path::new(path), mode, hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::{
+        diff::{file_diff, Version},
+        Object,
+    };
+
+    fn version(mode: u32, content: &str) -> Version {
+        Version {
+            mode,
+            hash: Object::new(b"blob", content.as_bytes()).hash(),
+            content: content.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn diffs_parse_and_apply_back() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n10\neleven";
+        let patch = file_diff(
+            "dir/f.txt",
+            Some(&version(0o100644, old)),
+            Some(&version(0o100755, new)),
+        );
+        let patches = parse(&patch).unwrap();
+        assert_eq!(patches.len(), 1);
+        let file = &patches[0];
+        assert_eq!(file.old_path.as_deref(), Some("dir/f.txt"));
+        assert_eq!(file.new_path.as_deref(), Some("dir/f.txt"));
+        assert_eq!(
+            (file.old_mode, file.new_mode),
+            (Some(0o100644), Some(0o100755))
+        );
+        assert!(!file.rename);
+        assert_eq!(file.hunks.len(), 2);
+        assert_eq!(
+            apply_hunks("dir/f.txt", old.as_bytes(), &file.hunks).unwrap(),
+            new.as_bytes()
+        );
+    }
+
+    #[test]
+    fn created_and_deleted_files_round_trip() {
+        let content = "a\nb\n";
+        let created = parse(&file_diff(
+            "new.txt",
+            None,
+            Some(&version(0o100644, content)),
+        ))
+        .unwrap();
+        assert_eq!(created[0].old_path, None);
+        assert_eq!(created[0].new_path.as_deref(), Some("new.txt"));
+        assert_eq!(created[0].new_mode, Some(0o100644));
+        assert_eq!(
+            apply_hunks("new.txt", b"", &created[0].hunks).unwrap(),
+            content.as_bytes()
+        );
+
+        let deleted = parse(&file_diff(
+            "old.txt",
+            Some(&version(0o100644, content)),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(deleted[0].old_path.as_deref(), Some("old.txt"));
+        assert_eq!(deleted[0].new_path, None);
+        assert_eq!(
+            apply_hunks("old.txt", content.as_bytes(), &deleted[0].hunks).unwrap(),
+            b""
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use super::{date, revparse, EntryKind, Odb, ParsedObject, SYMLINK_MODE};
+use anyhow::{anyhow, bail, Result};
+use flate2::{write::DeflateEncoder, Compression, Crc};
+use std::io::{stdout, BufWriter, Write};
+
+/// Block size of tar archives, which are padded to whole records.
+const BLOCK: usize = 512;
+const RECORD: usize = 20 * BLOCK;
+
+#[derive(Clone, Copy)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "tar" => Ok(Self::Tar),
+            "zip" => Ok(Self::Zip),
+            _ => bail!("Unknown archive format '{name}'"),
+        }
+    }
+}
+
+/// A file, symlink or directory to archive.
+struct Member {
+    path: String,
+    /// Permissions as git archives them, after `tar.umask` of 002.
+    mode: u32,
+    kind: MemberKind,
+    content: Vec<u8>,
+}
+
+#[derive(PartialEq)]
+enum MemberKind {
+    File,
+    Symlink,
+    Directory,
+}
+
+/// Writes the content of `tree_ish` to stdout as an archive whose paths start
+/// with `prefix`. Members are dated with the commit time, or now for a tree.
+pub fn archive(tree_ish: &str, format: ArchiveFormat, prefix: &str) -> Result<()> {
+    let odb = Odb::open()?;
+    let hash = revparse::resolve(tree_ish)?;
+    let commit = revparse::peel(&hash, "commit").ok();
+    let (time, timezone) = match &commit {
+        Some(commit) => {
+            let committer = odb.read_commit(commit)?.committer;
+            (committer.timestamp, committer.timezone)
+        }
+        None => (date::now()?, "+0000".to_owned()),
+    };
+    let mut members = vec![];
+    if prefix.ends_with('/') {
+        members.push(directory(prefix.to_owned()));
+    }
+    collect(&odb, &revparse::peel(&hash, "tree")?, prefix, &mut members)?;
+
+    let mut out = BufWriter::new(stdout().lock());
+    match format {
+        ArchiveFormat::Tar => write_tar(&mut out, &members, commit.as_deref(), time)?,
+        ArchiveFormat::Zip => write_zip(&mut out, &members, commit.as_deref(), (time, &timezone))?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn directory(path: String) -> Member {
+    Member {
+        path,
+        mode: 0o775,
+        kind: MemberKind::Directory,
+        content: vec![],
+    }
+}
+
+fn collect(odb: &Odb, tree: &str, prefix: &str, members: &mut Vec<Member>) -> Result<()> {
+    let ParsedObject::Tree(entries) = odb.read(tree)?.parse()? else {
+        bail!("{tree} is not a tree");
+    };
+    for entry in entries {
+        let path = format!("{prefix}{}", entry.name);
+        match entry.kind() {
+            EntryKind::Tree => {
+                members.push(directory(format!("{path}/")));
+                collect(odb, &entry.hash.to_string(), &format!("{path}/"), members)?;
+            }
+            // submodule content is not in this repository
+            EntryKind::Gitlink => members.push(directory(format!("{path}/"))),
+            EntryKind::Blob => {
+                let ParsedObject::Blob(content) = odb.read(&entry.hash.to_string())?.parse()?
+                else {
+                    bail!("{} is not a blob", entry.hash);
+                };
+                let (mode, kind) = match entry.mode {
+                    SYMLINK_MODE => (0o777, MemberKind::Symlink),
+                    0o100755 => (0o775, MemberKind::File),
+                    _ => (0o664, MemberKind::File),
+                };
+                members.push(Member {
+                    path,
+                    mode,
+                    kind,
+                    content,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_tar(
+    out: &mut impl Write,
+    members: &[Member],
+    commit: Option<&str>,
+    time: i64,
+) -> Result<()> {
+    let mut written = 0;
+    // the commit id, as read back by `git get-tar-commit-id`
+    if let Some(commit) = commit {
+        let records = pax_record("comment", commit.as_bytes());
+        let header = TarHeader::new("pax_global_header", 0o666, b'g', b"", time);
+        written += header.write(out, &records)?;
+    }
+    for member in members {
+        let (type_flag, link, content) = match member.kind {
+            MemberKind::File => (b'0', &b""[..], &member.content[..]),
+            MemberKind::Symlink => (b'2', &member.content[..], &b""[..]),
+            MemberKind::Directory => (b'5', &b""[..], &b""[..]),
+        };
+        let mut header = TarHeader::new(&member.path, member.mode, type_flag, link, time);
+        // names and link targets a header cannot hold go in a pax header
+        let mut records = vec![];
+        if let Some((prefix, name)) = ustar_split(&member.path) {
+            header.prefix = prefix;
+            header.name = name;
+        } else {
+            records.extend(pax_record("path", member.path.as_bytes()));
+        }
+        if link.len() > 100 {
+            records.extend(pax_record("linkpath", link));
+        }
+        if !records.is_empty() {
+            let pax = TarHeader::new("pax_extended_header", 0o666, b'x', b"", time);
+            written += pax.write(out, &records)?;
+        }
+        written += header.write(out, content)?;
+    }
+    // two zero blocks end the archive, which fills its last record
+    let end = 2 * BLOCK;
+    let padding = (RECORD - (written + end) % RECORD) % RECORD;
+    out.write_all(&vec![0; end + padding])?;
+    Ok(())
+}
+
+/// Splits a path into the ustar `prefix` and `name` fields, which hold 155
+/// and 100 bytes.
+fn ustar_split(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    // a trailing slash stays with the name
+    path[..path.len() - 1]
+        .match_indices('/')
+        .map(|(i, _)| i)
+        .find(|&i| i <= 155 && path.len() - i - 1 <= 100)
+        .map(|i| (&path[..i], &path[i + 1..]))
+}
+
+/// A pax `<length> <key>=<value>\n` record, whose length counts itself.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + 1;
+    while length.to_string().len() + rest != length {
+        length += 1;
+    }
+    let mut record = format!("{length} {key}=").into_bytes();
+    record.extend(value);
+    record.push(b'\n');
+    record
+}
+
+/// The fields of a ustar header; names and links too long for it are
+/// truncated, the full ones going in a pax header before it.
+struct TarHeader<'a> {
+    prefix: &'a str,
+    name: &'a str,
+    mode: u32,
+    type_flag: u8,
+    link: &'a [u8],
+    time: i64,
+}
+
+impl<'a> TarHeader<'a> {
+    fn new(name: &'a str, mode: u32, type_flag: u8, link: &'a [u8], time: i64) -> Self {
+        Self {
+            prefix: "",
+            name: &name[..name.len().min(100)],
+            mode,
+            type_flag,
+            link: &link[..link.len().min(100)],
+            time,
+        }
+    }
+
+    /// Writes the header and padded content, returning the bytes written.
+    fn write(&self, out: &mut impl Write, content: &[u8]) -> Result<usize> {
+        let mut header = [0u8; BLOCK];
+        header[..self.name.len()].copy_from_slice(self.name.as_bytes());
+        put_octal(&mut header[100..108], self.mode as u64);
+        put_octal(&mut header[108..116], 0);
+        put_octal(&mut header[116..124], 0);
+        put_octal(&mut header[124..136], content.len() as u64);
+        put_octal(&mut header[136..148], self.time.max(0) as u64);
+        header[156] = self.type_flag;
+        header[157..157 + self.link.len()].copy_from_slice(self.link);
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        header[265..269].copy_from_slice(b"root");
+        header[297..301].copy_from_slice(b"root");
+        put_octal(&mut header[329..337], 0);
+        put_octal(&mut header[337..345], 0);
+        header[345..345 + self.prefix.len()].copy_from_slice(self.prefix.as_bytes());
+        // the checksum is summed with its own field as spaces
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+        put_octal(&mut header[148..156], checksum as u64);
+        out.write_all(&header)?;
+        out.write_all(content)?;
+        let padding = (BLOCK - content.len() % BLOCK) % BLOCK;
+        out.write_all(&vec![0; padding])?;
+        Ok(BLOCK + content.len() + padding)
+    }
+}
+
+/// Fills a field with a zero-padded octal number and a terminating NUL.
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+/// Writes a zip archive, deflating files and storing symlink targets and
+/// directories as they are. The commit id, if any, is the archive comment.
+fn write_zip(
+    out: &mut impl Write,
+    members: &[Member],
+    commit: Option<&str>,
+    (time, timezone): (i64, &str),
+) -> Result<()> {
+    let (dos_time, dos_date) = date::dos_time(time, timezone);
+    let mut offset = 0usize;
+    let mut directory = vec![];
+    for member in members {
+        let mut crc = Crc::new();
+        crc.update(&member.content);
+        let (method, data) = match member.kind {
+            MemberKind::File => {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                encoder.write_all(&member.content)?;
+                (8u16, encoder.finish()?)
+            }
+            _ => (0, member.content.clone()),
+        };
+        let file_type = match member.kind {
+            MemberKind::File => 0o100000,
+            MemberKind::Symlink => 0o120000,
+            MemberKind::Directory => 0o040000,
+        };
+        // the low byte keeps the MS-DOS directory attribute
+        let dos_attribute = u32::from(member.kind == MemberKind::Directory) << 4;
+        let attributes = (file_type | member.mode) << 16 | dos_attribute;
+        // names are UTF-8 unless plain ASCII
+        let flags: u16 = if member.path.is_ascii() { 0 } else { 1 << 11 };
+        let version: u16 = if method == 8 { 20 } else { 10 };
+        let (size, compressed, local_offset) = (
+            zip32(member.content.len())?,
+            zip32(data.len())?,
+            zip32(offset)?,
+        );
+
+        let mut common = vec![];
+        common.extend(version.to_le_bytes());
+        common.extend(flags.to_le_bytes());
+        common.extend(method.to_le_bytes());
+        common.extend(dos_time.to_le_bytes());
+        common.extend(dos_date.to_le_bytes());
+        common.extend(crc.sum().to_le_bytes());
+        common.extend(compressed.to_le_bytes());
+        common.extend(size.to_le_bytes());
+        common.extend((member.path.len() as u16).to_le_bytes());
+        common.extend(0u16.to_le_bytes());
+
+        out.write_all(&0x04034b50u32.to_le_bytes())?;
+        out.write_all(&common)?;
+        out.write_all(member.path.as_bytes())?;
+        out.write_all(&data)?;
+        offset += 4 + common.len() + member.path.len() + data.len();
+
+        // made by unix, whose mode is in the external attributes
+        directory.extend(0x02014b50u32.to_le_bytes());
+        directory.extend((3u16 << 8 | 20).to_le_bytes());
+        directory.extend(common);
+        directory.extend([0; 6]);
+        directory.extend(attributes.to_le_bytes());
+        directory.extend(local_offset.to_le_bytes());
+        directory.extend(member.path.as_bytes());
+    }
+    let comment = commit.unwrap_or_default();
+    let count = u16::try_from(members.len()).map_err(|_| anyhow!("Too many files for zip"))?;
+    out.write_all(&directory)?;
+    out.write_all(&0x06054b50u32.to_le_bytes())?;
+    out.write_all(&[0; 4])?;
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&zip32(directory.len())?.to_le_bytes())?;
+    out.write_all(&zip32(offset)?.to_le_bytes())?;
+    out.write_all(&(comment.len() as u16).to_le_bytes())?;
+    out.write_all(comment.as_bytes())?;
+    Ok(())
+}
+
+/// Sizes and offsets of zip archives without the zip64 extension.
+fn zip32(value: usize) -> Result<u32> {
+    u32::try_from(value).map_err(|_| anyhow!("Archive too large for zip"))
+}
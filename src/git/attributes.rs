@@ -0,0 +1,329 @@
+use super::{config::Config, diff::is_binary, ignore::wildmatch, repository};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+const ATTRIBUTES_FILE: &str = ".gitattributes";
+const INFO_ATTRIBUTES: &str = "info/attributes";
+
+/// The state of one attribute for a path.
+#[derive(Clone, PartialEq, Debug)]
+pub enum State {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+}
+
+/// One line of an attributes file.
+struct Rule {
+    glob: String,
+    /// Matched against the whole path below `base` rather than the file name.
+    anchored: bool,
+    /// Directory of the attributes file, `""` or ending with a slash.
+    base: String,
+    /// Attributes in order, `None` for `!attr` which makes it unspecified.
+    attributes: Vec<(String, Option<State>)>,
+}
+
+impl Rule {
+    fn parse(line: &str, base: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let mut fields = line.split_whitespace();
+        let pattern = fields.next()?;
+        // negative patterns are forbidden in attribute files
+        if pattern.starts_with('!') {
+            return None;
+        }
+        let mut attributes = vec![];
+        for field in fields {
+            if field == "binary" {
+                // the built-in macro for files that are neither diffed nor converted
+                attributes.push(("text".to_owned(), Some(State::Unset)));
+                attributes.push(("diff".to_owned(), Some(State::Unset)));
+                attributes.push(("merge".to_owned(), Some(State::Unset)));
+            } else if let Some(name) = field.strip_prefix('-') {
+                attributes.push((name.to_owned(), Some(State::Unset)));
+            } else if let Some(name) = field.strip_prefix('!') {
+                attributes.push((name.to_owned(), None));
+            } else if let Some((name, value)) = field.split_once('=') {
+                attributes.push((name.to_owned(), Some(State::Value(value.to_owned()))));
+            } else {
+                attributes.push((field.to_owned(), Some(State::Set)));
+            }
+        }
+        Some(Self {
+            anchored: pattern.contains('/'),
+            glob: pattern.strip_prefix('/').unwrap_or(pattern).to_owned(),
+            base: base.to_owned(),
+            attributes,
+        })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let Some(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// Looks up the attributes of paths from the `.gitattributes` file of each
+/// directory, read on first use, and `.git/info/attributes`. Deeper files
+/// take precedence, `.git/info/attributes` over all of them, and within a
+/// file later lines win.
+pub struct Attributes {
+    root: PathBuf,
+    info: Vec<Rule>,
+    directories: HashMap<String, Vec<Rule>>,
+}
+
+impl Attributes {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            root: env::current_dir()?,
+            info: read_rules(&repository::path(INFO_ATTRIBUTES), ""),
+            directories: HashMap::new(),
+        })
+    }
+
+    /// The specified attributes of a path, relative to the repository root
+    /// or absolute within it.
+    pub fn get(&mut self, path: &Path) -> HashMap<String, State> {
+        let path = self.relative(path);
+        let mut directories = vec![String::new()];
+        for (i, _) in path.match_indices('/') {
+            directories.push(path[..=i].to_owned());
+        }
+        let mut states = HashMap::new();
+        let mut apply = |rule: &Rule| {
+            if rule.matches(&path) {
+                for (name, state) in &rule.attributes {
+                    match state {
+                        Some(state) => states.insert(name.clone(), state.clone()),
+                        None => states.remove(name),
+                    };
+                }
+            }
+        };
+        for directory in directories {
+            let rules = self
+                .directories
+                .entry(directory.clone())
+                .or_insert_with(|| {
+                    read_rules(&Path::new(&directory).join(ATTRIBUTES_FILE), &directory)
+                });
+            rules.iter().for_each(&mut apply);
+        }
+        self.info.iter().for_each(&mut apply);
+        states
+    }
+
+    /// A path relative to the repository root with `/` separators.
+    fn relative(&self, path: &Path) -> String {
+        normalize(path.strip_prefix(&self.root).unwrap_or(path))
+    }
+
+    /// Forgets the rules read for the directory of a `.gitattributes` file
+    /// that was just written, so that they are read again on next use.
+    pub fn reload(&mut self, path: &Path) {
+        if path.file_name().is_some_and(|name| name == ATTRIBUTES_FILE) {
+            let path = path.strip_prefix(&self.root).unwrap_or(path);
+            let mut directory = normalize(path.parent().unwrap_or(Path::new("")));
+            if !directory.is_empty() {
+                directory.push('/');
+            }
+            self.directories.remove(&directory);
+        }
+    }
+}
+
+/// A path with `/` separators and without `.` components.
+fn normalize(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn read_rules(file: &Path, base: &str) -> Vec<Rule> {
+    match fs::read_to_string(file) {
+        Ok(content) => content
+            .lines()
+            .filter_map(|line| Rule::parse(line, base))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Eol {
+    Lf,
+    Crlf,
+}
+
+/// Converts file contents between the repository and the working tree
+/// according to the `text` and `eol` attributes and `core.autocrlf`:
+/// text files are stored with LF line endings and may be checked out with
+/// CRLF ones. Files with a `filter` attribute are also piped through the
+/// `filter.<name>.clean` and `filter.<name>.smudge` commands.
+pub struct Conversion {
+    attributes: Attributes,
+    config: Config,
+    /// `core.autocrlf`: `None` when false, else the line ending to check out
+    /// (`true` for CRLF, `input` for LF).
+    autocrlf: Option<Eol>,
+    /// `core.eol`, used when `core.autocrlf` is false.
+    eol: Eol,
+}
+
+impl Conversion {
+    pub fn new() -> Result<Self> {
+        let config = Config::load()?;
+        let autocrlf = match config.get("core.autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => Some(Eol::Lf),
+            _ if config.get_bool("core.autocrlf") == Some(true) => Some(Eol::Crlf),
+            _ => None,
+        };
+        let eol = match config.get("core.eol") {
+            None | Some("lf") | Some("native") => Eol::Lf,
+            Some("crlf") => Eol::Crlf,
+            Some(value) => bail!("Invalid core.eol value {value}"),
+        };
+        Ok(Self {
+            attributes: Attributes::new()?,
+            config,
+            autocrlf,
+            eol,
+        })
+    }
+
+    /// The line ending to check a text file out with, or `None` when the
+    /// file is not converted at all.
+    fn text_eol(&mut self, path: &Path, content: &[u8]) -> Option<Eol> {
+        let attributes = self.attributes.get(path);
+        let eol = match attributes.get("eol") {
+            Some(State::Value(value)) if value == "crlf" => Some(Eol::Crlf),
+            Some(State::Value(value)) if value == "lf" => Some(Eol::Lf),
+            _ => None,
+        };
+        let text = match attributes.get("text") {
+            Some(State::Set) => true,
+            Some(State::Unset) => false,
+            Some(State::Value(value)) if value == "auto" => !is_binary(content),
+            // an eol attribute marks the file as text
+            _ if eol.is_some() => true,
+            _ => self.autocrlf.is_some() && !is_binary(content),
+        };
+        text.then(|| eol.or(self.autocrlf).unwrap_or(self.eol))
+    }
+
+    /// Normalizes the content of a working tree file for storing it as a blob.
+    pub fn convert_to_git(&mut self, path: &Path, content: Vec<u8>) -> Result<Vec<u8>> {
+        let content = self.filter(path, content, "clean")?;
+        if self.text_eol(path, &content).is_none() || !content.contains(&b'\r') {
+            return Ok(content);
+        }
+        let mut converted = Vec::with_capacity(content.len());
+        for (i, &byte) in content.iter().enumerate() {
+            if !(byte == b'\r' && content.get(i + 1) == Some(&b'\n')) {
+                converted.push(byte);
+            }
+        }
+        Ok(converted)
+    }
+
+    /// Converts the content of a blob for writing it to the working tree.
+    pub fn convert_to_worktree(&mut self, path: &Path, content: Vec<u8>) -> Result<Vec<u8>> {
+        let content = if self.text_eol(path, &content) == Some(Eol::Crlf) {
+            let mut converted = Vec::with_capacity(content.len() + content.len() / 16);
+            for (i, &byte) in content.iter().enumerate() {
+                if byte == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+                    converted.push(b'\r');
+                }
+                converted.push(byte);
+            }
+            converted
+        } else {
+            content
+        };
+        self.filter(path, content, "smudge")
+    }
+
+    /// Pipes content through the `clean` or `smudge` command of the filter
+    /// driver named by the `filter` attribute of a path. Without a command
+    /// the content passes unchanged; a failing command is an error only if
+    /// the driver is `required`, otherwise the content is used unfiltered.
+    fn filter(&mut self, path: &Path, content: Vec<u8>, action: &str) -> Result<Vec<u8>> {
+        let Some(State::Value(driver)) = self.attributes.get(path).remove("filter") else {
+            return Ok(content);
+        };
+        let Some(command) = self.config.get(&format!("filter.{driver}.{action}")) else {
+            return Ok(content);
+        };
+        let relative = self.attributes.relative(path);
+        // "%f" stands for the quoted path of the file being filtered
+        let command = command.replace("%f", &format!("'{}'", relative.replace('\'', "'\\''")));
+        match run_filter(&command, &content) {
+            Ok(filtered) => Ok(filtered),
+            Err(error)
+                if self.config.get_bool(&format!("filter.{driver}.required")) == Some(true) =>
+            {
+                Err(error.context(format!("{relative}: {action} filter '{driver}' failed")))
+            }
+            Err(error) => {
+                eprintln!("error: {relative}: {action} filter '{driver}' failed: {error:#}");
+                Ok(content)
+            }
+        }
+    }
+
+    /// Takes note of a file written to the working tree, which may change
+    /// the attributes of the files written after it.
+    pub fn written(&mut self, path: &Path) {
+        self.attributes.reload(path);
+    }
+}
+
+/// Runs a filter command through the shell, feeding it the content on stdin.
+fn run_filter(command: &str, content: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Cannot run {command}"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = content.to_vec();
+    // write from another thread so that a filter producing output before
+    // reading all of its input cannot block on a full pipe
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    let written = writer
+        .join()
+        .map_err(|_| anyhow!("Writing to {command} panicked"))?;
+    if !output.status.success() {
+        bail!("{command} exited with {}", output.status);
+    }
+    written.with_context(|| format!("Cannot write to {command}"))?;
+    Ok(output.stdout)
+}
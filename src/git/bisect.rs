@@ -0,0 +1,225 @@
+use super::{
+    detach_head, log, peel_to_commit, refs, remote::Sha1, repository, revparse, revwalk::RevWalk,
+    switch_branch, Odb,
+};
+use anyhow::{anyhow, bail, Result};
+use std::fs::{self, OpenOptions};
+use std::io::{stdout, Write};
+
+/// The branch, or detached commit, the bisection started from.
+const BISECT_START: &str = "BISECT_START";
+/// The commands of the bisection, as `git bisect log` would replay them.
+const BISECT_LOG: &str = "BISECT_LOG";
+const BAD_REF: &str = "refs/bisect/bad";
+const GOOD_PREFIX: &str = "refs/bisect/good-";
+
+/// Starts a bisection from the current HEAD, optionally marking a bad commit
+/// and good ones right away. A bisection in progress is started over.
+pub fn start(bad: Option<&str>, good: &[String]) -> Result<()> {
+    if is_bisecting() {
+        clear()?;
+    }
+    let start = match refs::current_branch()? {
+        Some(branch) => branch,
+        None => refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD is unborn"))?,
+    };
+    fs::write(repository::path(BISECT_START), format!("{start}\n"))?;
+    fs::write(repository::path(BISECT_LOG), "git bisect start\n")?;
+    if let Some(bad) = bad {
+        mark(bad, true)?;
+    }
+    for good in good {
+        mark(good, false)?;
+    }
+    next()
+}
+
+/// Marks a commit, HEAD by default, as bad and checks out the next one to test.
+pub fn bad(revision: &str) -> Result<()> {
+    check_bisecting()?;
+    mark(revision, true)?;
+    next()
+}
+
+/// Marks commits as good and checks out the next one to test.
+pub fn good(revisions: &[String]) -> Result<()> {
+    check_bisecting()?;
+    for revision in revisions {
+        mark(revision, false)?;
+    }
+    next()
+}
+
+/// Ends the bisection, going back to where it started.
+pub fn reset() -> Result<()> {
+    if !is_bisecting() {
+        println!("We are not bisecting.");
+        return Ok(());
+    }
+    let start = fs::read_to_string(repository::path(BISECT_START))?;
+    let start = start.trim_end();
+    if refs::peel(&format!("refs/heads/{start}"))?.is_some() {
+        switch_branch(start)?;
+    } else {
+        let from = refs::peel("HEAD")?.unwrap_or_default();
+        detach_head(start, &format!("checkout: moving from {from} to {start}"))?;
+    }
+    clear()
+}
+
+fn is_bisecting() -> bool {
+    repository::path(BISECT_START).is_file()
+}
+
+fn check_bisecting() -> Result<()> {
+    if !is_bisecting() {
+        bail!("You need to start by \"git bisect start\"");
+    }
+    Ok(())
+}
+
+fn clear() -> Result<()> {
+    for (name, _) in refs::list("refs/bisect/")? {
+        refs::delete(&name)?;
+    }
+    for file in [BISECT_START, BISECT_LOG] {
+        let _ = fs::remove_file(repository::path(file));
+    }
+    Ok(())
+}
+
+fn mark(revision: &str, bad: bool) -> Result<()> {
+    let hash = peel_to_commit(&revparse::resolve(revision)?)?;
+    let (term, name) = match bad {
+        true => ("bad", BAD_REF.to_owned()),
+        false => ("good", format!("{GOOD_PREFIX}{hash}")),
+    };
+    refs::write(&name, &hash)?;
+    let subject = Odb::open()?.read_commit(&hash)?.subject();
+    let mut log = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(repository::path(BISECT_LOG))?;
+    writeln!(log, "# {term}: [{hash}] {subject}")?;
+    writeln!(log, "git bisect {term} {hash}")?;
+    Ok(())
+}
+
+/// Checks out the commit halving the suspects: those reachable from the bad
+/// commit but not from any good one. When the bad commit is the only one
+/// left, it is reported as the first bad commit.
+fn next() -> Result<()> {
+    let bad = refs::peel(BAD_REF)?;
+    let good = refs::list("refs/bisect/")?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(GOOD_PREFIX))
+        .map(|(_, hash)| hash)
+        .collect::<Vec<_>>();
+    let bad = match (bad, good.is_empty()) {
+        (None, true) => {
+            println!("status: waiting for both good and bad commits");
+            return Ok(());
+        }
+        (None, false) => {
+            println!(
+                "status: waiting for bad commit, {} good commit(s) known",
+                good.len()
+            );
+            return Ok(());
+        }
+        (Some(_), true) => {
+            println!("status: waiting for good commit(s), bad commit known");
+            return Ok(());
+        }
+        (Some(bad), false) => bad,
+    };
+    let odb = Odb::open()?;
+    let suspects = suspects(&odb, &bad, &good)?;
+    if suspects.len() <= 1 {
+        println!("{bad} is the first bad commit");
+        log::print_commit(&mut stdout(), &bad, &odb.read_commit(&bad)?, None)?;
+        return Ok(());
+    }
+
+    let all = suspects.len();
+    let (next, reaches) = best_split(&odb, &suspects, &good)?;
+    let left = all - reaches - 1;
+    let steps = estimate_steps(all);
+    println!(
+        "Bisecting: {left} revision{} left to test after this (roughly {steps} step{})",
+        if left == 1 { "" } else { "s" },
+        if steps == 1 { "" } else { "s" },
+    );
+    let from = match refs::current_branch()? {
+        Some(branch) => branch,
+        None => refs::peel("HEAD")?.unwrap_or_default(),
+    };
+    detach_head(next, &format!("checkout: moving from {from} to {next}"))?;
+    let subject = odb.read_commit(next)?.subject();
+    println!("[{next}] {subject}");
+    Ok(())
+}
+
+/// Picks the suspect splitting the suspects most evenly into those it
+/// reaches and the others, returning it with the number it reaches. Like
+/// git, this goes through the suspects oldest first and stops at the first
+/// found halfway: merges, then other commits except the oldest suspects.
+fn best_split<'a>(odb: &Odb, suspects: &'a [Sha1], good: &[Sha1]) -> Result<(&'a Sha1, usize)> {
+    let all = suspects.len();
+    let mut weights = vec![];
+    for suspect in suspects {
+        let parents = odb
+            .read_commit(suspect)?
+            .parents
+            .iter()
+            .filter(|parent| suspects.contains(parent))
+            .count();
+        weights.push((suspect, parents, self::suspects(odb, suspect, good)?.len()));
+    }
+    let halfway = |reaches: usize| (2 * reaches).abs_diff(all) <= 1;
+    let merges = weights.iter().rev().filter(|(_, parents, _)| *parents > 1);
+    let others = weights.iter().rev().filter(|(_, parents, _)| *parents == 1);
+    if let Some((suspect, _, reaches)) = merges
+        .chain(others)
+        .find(|(_, _, reaches)| halfway(*reaches))
+    {
+        return Ok((suspect, *reaches));
+    }
+    // otherwise the oldest of the best splits
+    let distance = |reaches: usize| reaches.min(all - reaches);
+    let best = weights
+        .iter()
+        .map(|(_, _, reaches)| distance(*reaches))
+        .max()
+        .unwrap_or_default();
+    let (suspect, _, reaches) = weights
+        .iter()
+        .rev()
+        .find(|(_, _, reaches)| distance(*reaches) == best)
+        .expect("there are suspects");
+    Ok((suspect, *reaches))
+}
+
+/// Commits reachable from `bad` but not from any good commit, newest first.
+fn suspects(odb: &Odb, bad: &str, good: &[Sha1]) -> Result<Vec<Sha1>> {
+    let mut walk = RevWalk::from_tip(odb, bad)?;
+    for hash in good {
+        walk.hide(hash)?;
+    }
+    walk.hashes().collect()
+}
+
+/// The number of steps left after testing the next commit, as git estimates
+/// it for `all` suspects.
+fn estimate_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+    let n = (usize::BITS - 1 - all.leading_zeros()) as usize;
+    let power = 1 << n;
+    if power < 3 * (all - power) {
+        n
+    } else {
+        n - 1
+    }
+}
@@ -0,0 +1,158 @@
+use super::{
+    date, diff, mailmap::Mailmap, peel_to_commit, revparse, short_hash, Commit, Odb, ParsedObject,
+};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{stdout, Write};
+
+/// A version of the blamed file still to be searched for the lines it
+/// introduced.
+struct Candidate {
+    commit: Commit,
+    blob: String,
+    content: Vec<u8>,
+    /// Lines of the final file that may come from this version, as indexes
+    /// into the final file and into this version.
+    lines: Vec<(usize, usize)>,
+}
+
+/// Prints each line of `path` as of `revision` with the commit that last
+/// changed it. Lines are passed on to a parent as long as the diff against it
+/// leaves them unchanged; those a commit changed or added are its own.
+pub fn blame(revision: &str, path: &str) -> Result<()> {
+    let odb = Odb::open()?;
+    let start = peel_to_commit(&revparse::resolve(revision)?)?;
+    let commit = odb.read_commit(&start)?;
+    let Some(blob) = blob_at(&odb, &commit.tree, path)? else {
+        bail!("no such path {path} in {revision}");
+    };
+    let content = read_blob(&odb, &blob)?;
+    let final_lines = diff::lines(&content)
+        .into_iter()
+        .map(<[u8]>::to_vec)
+        .collect::<Vec<_>>();
+
+    let mut owners = vec![String::new(); final_lines.len()];
+    let mut owning_commits = HashMap::new();
+    // newest first, so that a commit gets the lines of all its children
+    let mut candidates = BTreeMap::new();
+    candidates.insert(
+        (commit.time(), start),
+        Candidate {
+            commit,
+            blob,
+            content,
+            lines: (0..final_lines.len()).map(|line| (line, line)).collect(),
+        },
+    );
+    while let Some(((_, hash), mut candidate)) = candidates.pop_last() {
+        for parent in &candidate.commit.parents {
+            if candidate.lines.is_empty() {
+                break;
+            }
+            let parent_commit = odb.read_commit(parent)?;
+            let Some(parent_blob) = blob_at(&odb, &parent_commit.tree, path)? else {
+                continue;
+            };
+            let key = (parent_commit.time(), parent.clone());
+            if !candidates.contains_key(&key) {
+                let content = read_blob(&odb, &parent_blob)?;
+                candidates.insert(
+                    key.clone(),
+                    Candidate {
+                        commit: parent_commit,
+                        blob: parent_blob,
+                        content,
+                        lines: vec![],
+                    },
+                );
+            }
+            let parent = candidates.get_mut(&key).unwrap();
+            if parent.blob == candidate.blob {
+                parent.lines.append(&mut candidate.lines);
+                continue;
+            }
+            let unchanged = diff::myers(
+                &diff::lines(&parent.content),
+                &diff::lines(&candidate.content),
+            )
+            .into_iter()
+            .filter_map(|edit| match edit {
+                diff::Edit::Equal { old, new } => Some((new, old)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+            let (passed, kept) = candidate
+                .lines
+                .into_iter()
+                .partition::<Vec<_>, _>(|(_, line)| unchanged.contains_key(line));
+            parent.lines.extend(
+                passed
+                    .into_iter()
+                    .map(|(final_line, line)| (final_line, unchanged[&line])),
+            );
+            candidate.lines = kept;
+        }
+        if !candidate.lines.is_empty() {
+            for (final_line, _) in &candidate.lines {
+                owners[*final_line] = hash.clone();
+            }
+            owning_commits.insert(hash, candidate.commit);
+        }
+    }
+
+    // authors are shown by their canonical names
+    let mailmap = Mailmap::load()?;
+    for commit in owning_commits.values_mut() {
+        commit.author = mailmap.map_identity(&commit.author);
+    }
+    let author_width = owning_commits
+        .values()
+        .map(|commit| commit.author.name.chars().count())
+        .max()
+        .unwrap_or_default();
+    let number_width = final_lines.len().to_string().len();
+    let mut out = stdout().lock();
+    for (number, (line, owner)) in (1..).zip(final_lines.iter().zip(&owners)) {
+        let commit = &owning_commits[owner];
+        // root commits are marked as the boundary of the search
+        let hash = match commit.parents.is_empty() {
+            true => format!("^{}", short_hash(owner)),
+            false => owner[..8].to_owned(),
+        };
+        let author = &commit.author;
+        write!(
+            out,
+            "{hash} ({:<author_width$} {} {number:>number_width$}) ",
+            author.name,
+            date::format_iso(author.timestamp, &author.timezone)
+        )?;
+        out.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// The hash of the blob at `path` in a tree, if there is one.
+fn blob_at(odb: &Odb, tree: &str, path: &str) -> Result<Option<String>> {
+    let mut hash = tree.to_owned();
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        let ParsedObject::Tree(entries) = odb.read(&hash)?.parse()? else {
+            return Ok(None);
+        };
+        match entries.into_iter().find(|entry| entry.name == component) {
+            Some(entry) => hash = entry.hash.to_string(),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(hash))
+}
+
+fn read_blob(odb: &Odb, hash: &str) -> Result<Vec<u8>> {
+    match odb.read(hash)?.parse()? {
+        ParsedObject::Blob(content) => Ok(content),
+        _ => bail!("{hash} is not a blob"),
+    }
+}
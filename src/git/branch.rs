@@ -0,0 +1,53 @@
+use super::{refs, revparse, short_hash};
+use anyhow::{bail, Result};
+
+const HEADS_PREFIX: &str = "refs/heads/";
+
+/// Prints local branches, marking the one HEAD points at with `*`.
+pub fn list() -> Result<()> {
+    let current = refs::current_branch()?;
+    for (name, _) in refs::list(HEADS_PREFIX)? {
+        let branch = &name[HEADS_PREFIX.len()..];
+        let marker = if Some(branch) == current.as_deref() {
+            '*'
+        } else {
+            ' '
+        };
+        println!("{marker} {branch}");
+    }
+    Ok(())
+}
+
+pub fn create(name: &str, start: &str) -> Result<()> {
+    verify_name(name)?;
+    let full_name = format!("{HEADS_PREFIX}{name}");
+    if refs::read(&full_name)?.is_some() {
+        bail!("A branch named '{name}' already exists");
+    }
+    Ok(refs::update(
+        &full_name,
+        &revparse::resolve(start)?,
+        &format!("branch: Created from {start}"),
+    )?)
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    verify_name(name)?;
+    let full_name = format!("{HEADS_PREFIX}{name}");
+    let Some(hash) = refs::peel(&full_name)? else {
+        bail!("Branch '{name}' not found");
+    };
+    if refs::current_branch()?.as_deref() == Some(name) {
+        bail!("Cannot delete branch '{name}' checked out at HEAD");
+    }
+    refs::delete(&full_name)?;
+    println!("Deleted branch {name} (was {}).", short_hash(&hash));
+    Ok(())
+}
+
+fn verify_name(name: &str) -> Result<()> {
+    if name.starts_with('-') || !refs::is_valid_name(&format!("{HEADS_PREFIX}{name}")) {
+        bail!("'{name}' is not a valid branch name");
+    }
+    Ok(())
+}
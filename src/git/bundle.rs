@@ -0,0 +1,136 @@
+use super::{
+    pack, peel_to_commit, refs,
+    remote::{Reference, Sha1},
+    revparse, revwalk, Odb,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+const SIGNATURE: &str = "# v2 git bundle\n";
+const SIGNATURE_V3: &str = "# v3 git bundle\n";
+
+/// A bundle file: refs with the pack of their history, minus that of the
+/// prerequisite commits the receiving repository must already have.
+pub struct Bundle {
+    pub prerequisites: Vec<Sha1>,
+    pub references: Vec<Reference>,
+    pack: Vec<u8>,
+}
+
+impl Bundle {
+    /// Reads the bundle at `path`, or `None` if it is not a bundle file.
+    pub fn open(path: &Path) -> Result<Option<Self>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read(path)?;
+        if !content.starts_with(SIGNATURE.as_bytes())
+            && !content.starts_with(SIGNATURE_V3.as_bytes())
+        {
+            return Ok(None);
+        }
+        let header_end = content
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .with_context(|| format!("{} has no pack", path.display()))?;
+        let header = std::str::from_utf8(&content[..header_end])?;
+        let mut bundle = Self {
+            prerequisites: vec![],
+            references: vec![],
+            pack: content[header_end + 2..].to_vec(),
+        };
+        for line in header.lines().skip(1) {
+            if let Some(capability) = line.strip_prefix('@') {
+                if !matches!(capability, "object-format=sha1" | "filter") {
+                    bail!("Unsupported bundle capability '{capability}'");
+                }
+            } else if let Some(prerequisite) = line.strip_prefix('-') {
+                // the commit is followed by its subject
+                let hash = prerequisite.split(' ').next().unwrap_or_default();
+                bundle.prerequisites.push(hash.to_owned());
+            } else {
+                let (hash, name) = line
+                    .split_once(' ')
+                    .ok_or_else(|| anyhow!("Invalid bundle header line '{line}'"))?;
+                bundle.references.push((hash.to_owned(), name.to_owned()));
+            }
+        }
+        Ok(Some(bundle))
+    }
+
+    /// Stores the pack of the bundle, which must not miss anything the
+    /// repository lacks.
+    pub fn unpack(&self) -> Result<()> {
+        let odb = Odb::open()?;
+        for prerequisite in &self.prerequisites {
+            if !odb.exists(prerequisite)? {
+                bail!("Repository lacks the prerequisite commit {prerequisite}");
+            }
+        }
+        pack::index_pack(self.pack.clone())?;
+        Ok(())
+    }
+}
+
+/// Writes a bundle of refs to `path`. Revisions are ref names, `--all`,
+/// `^<rev>` or `<rev>..<ref>`; excluded commits become prerequisites.
+pub fn create(path: &Path, revisions: &[String]) -> Result<()> {
+    let mut references = vec![];
+    let mut excluded = vec![];
+    for revision in revisions {
+        if revision == "--all" {
+            references.extend(refs::peel("HEAD")?.map(|hash| (hash, "HEAD".to_owned())));
+            references.extend(
+                refs::list("refs/")?
+                    .into_iter()
+                    .map(|(name, hash)| (hash, name)),
+            );
+        } else if let Some(excluded_revision) = revision.strip_prefix('^') {
+            excluded.push(peel_to_commit(&revparse::resolve(excluded_revision)?)?);
+        } else if let Some((old, new)) = revision.split_once("..") {
+            let old = if old.is_empty() { "HEAD" } else { old };
+            excluded.push(peel_to_commit(&revparse::resolve(old)?)?);
+            references.push(reference(if new.is_empty() { "HEAD" } else { new })?);
+        } else {
+            references.push(reference(revision)?);
+        }
+    }
+    references.dedup_by(|a, b| a.1 == b.1);
+
+    let odb = Odb::open()?;
+    let tips = references
+        .iter()
+        .map(|(hash, _)| hash.clone())
+        .collect::<Vec<_>>();
+    let objects = revwalk::reachable_objects(&odb, &tips, &excluded)?;
+    if objects.is_empty() {
+        bail!("Refusing to create empty bundle");
+    }
+    let objects = objects
+        .into_iter()
+        .map(|(hash, path)| Ok((odb.read(&hash)?, path)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut content = SIGNATURE.to_owned();
+    for hash in &excluded {
+        let commit = odb.read_commit(hash)?;
+        let subject = commit.message.lines().next().unwrap_or_default();
+        content.push_str(&format!("-{hash} {subject}\n"));
+    }
+    for (hash, name) in &references {
+        content.push_str(&format!("{hash} {name}\n"));
+    }
+    content.push('\n');
+    let mut content = content.into_bytes();
+    content.extend(pack::write_pack(&objects)?);
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// The full name of a ref along with what it points at.
+fn reference(name: &str) -> Result<Reference> {
+    let name = refs::expand(name)?.ok_or_else(|| anyhow!("{name} is not a ref"))?;
+    let hash = refs::peel(&name)?.ok_or_else(|| anyhow!("{name} does not point anywhere"))?;
+    Ok((hash, name))
+}
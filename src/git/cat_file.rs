@@ -0,0 +1,44 @@
+use super::{revparse, Error, Odb};
+use anyhow::Result;
+use std::io::{self, prelude::*};
+
+/// Answers the object names read from `input`, one per line, with
+/// `<hash> <type> <size>`, followed by the raw content and a newline when
+/// `contents` is set. Names that do not resolve to an object are reported as
+/// missing or ambiguous instead of ending the batch.
+pub fn batch(input: impl BufRead, contents: bool) -> Result<()> {
+    let odb = Odb::open()?;
+    let mut out = io::stdout().lock();
+    for line in input.lines() {
+        let line = line?;
+        let name = line.trim();
+        let object = match revparse::resolve(name) {
+            Ok(hash) => odb
+                .stream(&hash)
+                .map(|object| (hash, object))
+                .map_err(Into::into),
+            Err(error) => Err(error),
+        };
+        let (hash, mut object) = match object {
+            Ok(found) => found,
+            Err(error) => {
+                match error.downcast_ref::<Error>() {
+                    Some(Error::CorruptObject { .. }) => return Err(error),
+                    Some(Error::AmbiguousObject { .. }) => writeln!(out, "{name} ambiguous")?,
+                    _ => writeln!(out, "{name} missing")?,
+                }
+                out.flush()?;
+                continue;
+            }
+        };
+        let kind = String::from_utf8_lossy(object.kind()).into_owned();
+        writeln!(out, "{hash} {kind} {}", object.size())?;
+        if contents {
+            io::copy(&mut object, &mut out)?;
+            writeln!(out)?;
+        }
+        // callers may wait for each answer before sending the next name
+        out.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use super::{
+    attributes::Conversion,
+    has_symlink_leading_path,
+    index::{self, Entry, Index},
+    verify_path, write_file, Odb,
+};
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+pub struct CheckoutIndexOptions {
+    /// Check out every entry of the index (`-a`).
+    pub all: bool,
+    /// Replace files that already exist (`-f`).
+    pub force: bool,
+    /// Written before each path, e.g. `export/` to check out elsewhere (`--prefix`).
+    pub prefix: String,
+}
+
+/// Writes files of the index into the working tree: the given paths, or all
+/// of them except those left out by sparse checkout. Existing files are kept
+/// unless forced, with a warning for the paths named explicitly.
+pub fn checkout_index(paths: &[PathBuf], options: &CheckoutIndexOptions) -> Result<()> {
+    let odb = Odb::open()?;
+    let mut index = Index::read()?;
+    let mut names = BTreeSet::new();
+    if options.all {
+        names.extend(
+            index
+                .entries
+                .iter()
+                .filter(|entry| entry.stage == 0 && !entry.skip_worktree)
+                .map(|entry| entry.path.clone()),
+        );
+    }
+    for path in paths {
+        let name = index::pathspec_name(path)?;
+        let stages = index
+            .entries
+            .iter()
+            .filter(|entry| entry.path == name)
+            .map(|entry| entry.stage)
+            .collect::<Vec<_>>();
+        match stages[..] {
+            [] => bail!("{name} is not in the cache"),
+            [0] => {}
+            _ => bail!("{name} is unmerged"),
+        }
+        names.insert(name);
+    }
+
+    let existing = write_entries(&odb, &mut index, &names, options.force, &options.prefix)?;
+    for name in existing {
+        if !options.all || paths.iter().any(|path| path == Path::new(&name)) {
+            eprintln!("{}{name} already exists, no checkout", options.prefix);
+        }
+    }
+    // the stat data of files written in place is refreshed
+    if options.prefix.is_empty() {
+        index.write()?;
+    }
+    Ok(())
+}
+
+/// Writes the named stage 0 entries of the index into the working tree,
+/// below `prefix` if not empty. Files that already exist are only replaced
+/// when forced, and the names of those kept are returned. Entries written in
+/// place take the stat data of their new file. Paths that could lead out of
+/// the working tree, or into the repository, are refused.
+pub fn write_entries(
+    odb: &Odb,
+    index: &mut Index,
+    names: &BTreeSet<String>,
+    force: bool,
+    prefix: &str,
+) -> Result<Vec<String>> {
+    let mut conversion = Conversion::new()?;
+    let mut existing = vec![];
+    for entry in &mut index.entries {
+        if entry.stage != 0 || !names.contains(&entry.path) {
+            continue;
+        }
+        verify_path(&entry.path)?;
+        let path = PathBuf::from(format!("{prefix}{}", entry.path));
+        if has_symlink_leading_path(prefix, &entry.path) {
+            bail!("cannot check out '{}' beyond a symbolic link", entry.path);
+        }
+        if !force && fs::symlink_metadata(&path).is_ok() {
+            existing.push(entry.path.clone());
+            continue;
+        }
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        write_file(odb, &path, entry.mode, &entry.hash, &mut conversion)?;
+        if prefix.is_empty() {
+            *entry = Entry {
+                skip_worktree: entry.skip_worktree,
+                ..Entry::from_file(&path, entry.mode, entry.hash)?
+            };
+        }
+    }
+    Ok(existing)
+}
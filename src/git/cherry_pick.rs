@@ -0,0 +1,111 @@
+use super::{
+    flatten_tree, merge, parse_hash, peel_to_commit, refs, revparse, short_hash, write_commit_as,
+    write_tree_from_files, ObjectId, Odb, TreeFiles,
+};
+use anyhow::{anyhow, bail, Result};
+
+/// Applies the change a commit made to its parent on top of HEAD and
+/// commits the result with the commit's author and message.
+pub fn cherry_pick(revision: &str) -> Result<()> {
+    let hash = peel_to_commit(&revparse::resolve(revision)?)?;
+    let odb = Odb::open()?;
+    let commit = odb.read_commit(&hash)?;
+    if commit.parents.len() > 1 {
+        bail!("commit {hash} is a merge but no -m option was given.");
+    }
+    let subject = commit.subject();
+    let change = Change {
+        base: commit.parents.first().map(String::as_str),
+        target: Some(&hash),
+        label: format!("{} ({subject})", short_hash(&hash)),
+    };
+    let message = commit.message.trim_end();
+    let outcome = apply(
+        &odb,
+        &change,
+        &commit.author.to_string(),
+        message,
+        "cherry-pick",
+    )?;
+    report(
+        outcome,
+        message,
+        &format!("could not apply {}... {subject}", short_hash(&hash)),
+    )
+}
+
+/// A change to replay on HEAD: the difference from `base` to `target`,
+/// either of which may be missing for a change from or to nothing.
+pub struct Change<'a> {
+    pub base: Option<&'a str>,
+    pub target: Option<&'a str>,
+    /// Names the change in conflict markers and messages.
+    pub label: String,
+}
+
+/// What applying a change left behind.
+pub enum Outcome {
+    Committed(ObjectId),
+    /// HEAD already had the change, so nothing was committed.
+    Empty,
+    /// Conflicts are left in the working tree and the index.
+    Conflicted,
+}
+
+/// Three-way merges a change onto HEAD and commits the result on top of it,
+/// with `action` naming the operation in the reflog.
+pub fn apply(
+    odb: &Odb,
+    change: &Change,
+    author: &str,
+    message: &str,
+    action: &str,
+) -> Result<Outcome> {
+    let head = refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD does not point to a commit"))?;
+    let head_tree = odb.read_commit(&head)?.tree;
+    let tree_files = |commit: Option<&str>| match commit {
+        Some(commit) => flatten_tree(odb, &odb.read_commit(commit)?.tree),
+        None => Ok(TreeFiles::new()),
+    };
+    let (merged, conflicts) = merge::merge_trees(
+        odb,
+        &tree_files(change.base)?,
+        &flatten_tree(odb, &head_tree)?,
+        &tree_files(change.target)?,
+        &change.label,
+    )?;
+    if !conflicts.is_empty() {
+        return Ok(Outcome::Conflicted);
+    }
+
+    let tree = write_tree_from_files(odb, &merged)?;
+    if tree.to_string() == head_tree {
+        return Ok(Outcome::Empty);
+    }
+    let subject = message.lines().next().unwrap_or_default();
+    let hash = write_commit_as(&tree, &[parse_hash(&head)?], author, message)?;
+    refs::update_from(
+        "HEAD",
+        Some(&head),
+        &hash.to_string(),
+        &format!("{action}: {subject}"),
+    )?;
+    Ok(Outcome::Committed(hash))
+}
+
+/// Reports a single change applied on its own, `failure` being the error
+/// for a conflicted one.
+pub fn report(outcome: Outcome, message: &str, failure: &str) -> Result<()> {
+    match outcome {
+        Outcome::Committed(hash) => {
+            let branch = refs::current_branch()?.unwrap_or_else(|| "detached HEAD".to_owned());
+            let subject = message.lines().next().unwrap_or_default();
+            println!("[{branch} {}] {subject}", short_hash(&hash.to_string()));
+            Ok(())
+        }
+        Outcome::Empty => {
+            bail!("The previous cherry-pick is now empty, possibly due to conflict resolution.")
+        }
+        Outcome::Conflicted => bail!("{failure}"),
+    }
+}
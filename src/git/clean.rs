@@ -0,0 +1,99 @@
+use super::{
+    config::Config,
+    ignore::{self, Ignore},
+    index::Index,
+    status::untracked_name,
+};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+pub struct CleanOptions {
+    /// Only show what would be removed (`-n`).
+    pub dry_run: bool,
+    /// Remove files, as required by `clean.requireForce` (`-f`).
+    pub force: bool,
+    /// Remove untracked directories as well (`-d`).
+    pub directories: bool,
+}
+
+/// Removes the untracked files of the working tree, and with `directories`
+/// the directories holding nothing tracked. Ignored files are kept, and so
+/// are directories holding them, as well as nested repositories.
+pub fn clean(options: &CleanOptions) -> Result<()> {
+    let require_force = Config::load()?
+        .get_bool("clean.requireForce")
+        .unwrap_or(true);
+    if require_force && !options.force && !options.dry_run {
+        bail!("clean.requireForce defaults to true and neither -n nor -f given; refusing to clean");
+    }
+    let tracked = Index::read()?
+        .entries
+        .iter()
+        .map(|e| PathBuf::from(&e.path))
+        .collect::<BTreeSet<_>>();
+    // untracked files grouped by the outermost directory holding nothing tracked
+    let mut untracked = BTreeMap::<String, Vec<PathBuf>>::new();
+    for file in ignore::worktree_files(Some(&mut Ignore::new()?), Path::new("."))? {
+        if !tracked.contains(&file) && !file.join(".git").exists() {
+            untracked
+                .entry(untracked_name(&file, &tracked))
+                .or_default()
+                .push(file);
+        }
+    }
+
+    let verb = if options.dry_run {
+        "Would remove"
+    } else {
+        "Removing"
+    };
+    for (name, files) in untracked {
+        let Some(directory) = name.strip_suffix('/') else {
+            println!("{verb} {name}");
+            if !options.dry_run {
+                fs::remove_file(&name)?;
+            }
+            continue;
+        };
+        if !options.directories {
+            continue;
+        }
+        if count_files(Path::new(directory))? == files.len() {
+            println!("{verb} {name}");
+            if !options.dry_run {
+                fs::remove_dir_all(directory)?;
+            }
+            continue;
+        }
+        // ignored files are kept, and the directories holding them
+        for file in files {
+            println!("{verb} {}", file.display());
+            if !options.dry_run {
+                fs::remove_file(&file)?;
+                for parent in file.ancestors().skip(1) {
+                    if !parent.starts_with(directory) || fs::remove_dir(parent).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The number of files below a directory, at any depth.
+fn count_files(directory: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in directory.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            count += count_files(&entry.path())?;
+        } else {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,357 @@
+use super::{
+    bundle::Bundle, checkout, config::Config, flatten_tree, hash::HashAlgo, init, pack, refs,
+    remote, revwalk, sparse, store_references, EntryKind, Odb,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+const GITMODULES: &str = ".gitmodules";
+
+#[derive(Default)]
+pub struct CloneOptions {
+    pub fetch: remote::FetchOptions,
+    /// Branch or tag to check out instead of the remote HEAD (`--branch`).
+    pub branch: Option<String>,
+    /// Fetch and store only the checked out branch (`--single-branch`).
+    pub single_branch: bool,
+    /// Also clone the submodules of the checked out commit (`--recurse-submodules`).
+    pub recurse_submodules: bool,
+    /// Check out only the top-level files with sparse checkout (`--sparse`).
+    pub sparse: bool,
+}
+
+/// Clones a repository given by URL or by a local path into `path` and checks out its HEAD.
+pub fn clone(url: &str, path: &Path, options: &CloneOptions) -> Result<()> {
+    let head_hash = fetch_repository(url, path, options)?;
+    if options.sparse {
+        sparse::enable_for_checkout()?;
+    }
+    checkout(&head_hash)?;
+    if options.recurse_submodules {
+        clone_submodules(&head_hash)?;
+    }
+    Ok(())
+}
+
+/// Creates the repository at `path` with the objects and refs of `url`,
+/// returning the commit of its HEAD. The new repository becomes the current
+/// directory.
+fn fetch_repository(url: &str, path: &Path, options: &CloneOptions) -> Result<String> {
+    if options.fetch.depth == Some(0) {
+        bail!("depth 0 is not a positive number");
+    }
+    if let Some(filter) = &options.fetch.filter {
+        remote::check_filter(filter)?;
+    }
+    if let Some(bundle) = Bundle::open(Path::new(url))? {
+        return clone_bundle(&bundle, url, path, options);
+    }
+    let head_hash = match local_repository(url)? {
+        Some(git_dir) => {
+            if options.fetch.depth.is_some() {
+                eprintln!("warning: --depth is ignored in local clones");
+            }
+            if options.fetch.filter.is_some() {
+                eprintln!("warning: --filter is ignored in local clones");
+            }
+            clone_local(&git_dir, url, path, options)?
+        }
+        None => clone_remote(url, path, options)?,
+    };
+    Ok(head_hash)
+}
+
+/// Clones each submodule listed in `.gitmodules` into its path and checks
+/// out, with a detached HEAD, the commit the superproject records for it.
+/// Submodules of submodules are cloned as well.
+fn clone_submodules(head_hash: &str) -> Result<()> {
+    let root = env::current_dir()?;
+    let modules = Config::read(Path::new(GITMODULES))?;
+    let odb = Odb::open()?;
+    let files = flatten_tree(&odb, &odb.read_commit(head_hash)?.tree)?;
+    let superproject_url = Config::local()?
+        .get("remote.origin.url")
+        .map(str::to_owned)
+        .ok_or_else(|| anyhow!("The superproject has no origin"))?;
+    let mut config = Config::local()?;
+    for name in modules.subsections("submodule") {
+        let (Some(path), Some(url)) = (
+            modules.get(&format!("submodule.{name}.path")),
+            modules.get(&format!("submodule.{name}.url")),
+        ) else {
+            continue;
+        };
+        let Some((_, hash)) = files
+            .get(Path::new(path))
+            .filter(|(mode, _)| EntryKind::from_mode(*mode) == EntryKind::Gitlink)
+        else {
+            eprintln!("warning: {path} is not a submodule of {head_hash}");
+            continue;
+        };
+        let url = resolve_submodule_url(&superproject_url, url)?;
+        config.set(&format!("submodule.{name}.url"), &url)?;
+        config.set(&format!("submodule.{name}.active"), "true")?;
+        println!("Cloning into '{}'...", root.join(path).display());
+
+        let commit = hex::encode(hash);
+        // checkout left an empty directory for the submodule
+        let result = fetch_repository(&url, &root.join(path), &CloneOptions::default())
+            .and_then(|_| Ok(refs::write("HEAD", &commit)?))
+            .and_then(|_| checkout(&commit))
+            .and_then(|_| clone_submodules(&commit));
+        env::set_current_dir(&root)?;
+        result.with_context(|| format!("Clone of '{url}' into submodule path '{path}' failed"))?;
+    }
+    config.save()
+}
+
+/// Resolves a submodule URL relative to the superproject's (`./` or `../`).
+fn resolve_submodule_url(base: &str, url: &str) -> Result<String> {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return Ok(url.to_owned());
+    }
+    let mut base = base.trim_end_matches('/').to_owned();
+    let mut rest = url;
+    loop {
+        if let Some(after) = rest.strip_prefix("./") {
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("../") {
+            let (parent, _) = base
+                .rsplit_once('/')
+                .ok_or_else(|| anyhow!("Cannot resolve {url} relative to {base}"))?;
+            base = parent.to_owned();
+            rest = after;
+        } else {
+            return Ok(format!("{base}/{rest}"));
+        }
+    }
+}
+
+fn clone_remote(url: &str, path: &Path, options: &CloneOptions) -> Result<String> {
+    let remote_url = remote::parse_url(url)?;
+    let mut advertisement = remote::discover_references(&remote_url)?;
+    // narrowing the refs before fetching keeps the wants to the selected branch
+    advertisement.refs = select_references(advertisement.refs, options)?;
+    let fetched = remote::fetch_pack(&remote_url, &advertisement, &options.fetch)?;
+    init(path, HashAlgo::Sha1)?;
+    pack::index_pack(fetched.pack)?;
+    revwalk::write_shallow(&fetched.shallow)?;
+    let head_hash = store_references(&advertisement.refs, remote_url.as_str())?;
+    configure_remote(url, &advertisement.refs, options)?;
+    Ok(head_hash)
+}
+
+/// Clones from a bundle file, which must hold complete history. Without a
+/// HEAD in the bundle its first branch is checked out.
+fn clone_bundle(bundle: &Bundle, url: &str, path: &Path, options: &CloneOptions) -> Result<String> {
+    if !bundle.prerequisites.is_empty() {
+        bail!("Cannot clone from a bundle with prerequisite commits");
+    }
+    let mut references = bundle.references.clone();
+    if !references.iter().any(|(_, name)| name == "HEAD") {
+        let (hash, _) = references
+            .iter()
+            .find(|(_, name)| name.starts_with("refs/heads/"))
+            .ok_or_else(|| anyhow!("The bundle has no branch to check out"))?;
+        references.insert(0, (hash.clone(), "HEAD".to_owned()));
+    }
+    // store_references expects HEAD first
+    references.sort_by_key(|(_, name)| name != "HEAD");
+    let references = select_references(references, options)?;
+    let location = fs::canonicalize(url)?.display().to_string();
+    init(path, HashAlgo::Sha1)?;
+    bundle.unpack()?;
+    let head_hash = store_references(&references, &location)?;
+    configure_remote(&location, &references, options)?;
+    Ok(head_hash)
+}
+
+/// Records the cloned repository as `origin`, with remote-tracking refs for
+/// its branches and the checked out branch tracking its remote counterpart,
+/// so that fetch and pull know where to go.
+fn configure_remote(
+    url: &str,
+    references: &[remote::Reference],
+    options: &CloneOptions,
+) -> Result<()> {
+    let branch = refs::current_branch()?;
+    let refspec = match (&branch, options.single_branch) {
+        (Some(branch), true) => format!("+refs/heads/{branch}:refs/remotes/origin/{branch}"),
+        _ => "+refs/heads/*:refs/remotes/origin/*".to_owned(),
+    };
+    let mut config = Config::local()?;
+    config.set("remote.origin.url", url)?;
+    config.set("remote.origin.fetch", &refspec)?;
+    if let Some(branch) = &branch {
+        config.set(&format!("branch.{branch}.remote"), "origin")?;
+        config.set(
+            &format!("branch.{branch}.merge"),
+            &format!("refs/heads/{branch}"),
+        )?;
+    }
+    config.save()?;
+
+    for (hash, name) in references {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            refs::write(&format!("refs/remotes/origin/{branch}"), hash)?;
+        }
+    }
+    if let Some(branch) = &branch {
+        refs::write_symbolic(
+            "refs/remotes/origin/HEAD",
+            &format!("refs/remotes/origin/{branch}"),
+        )?;
+    }
+    Ok(())
+}
+
+/// Applies `--branch` and `--single-branch` to advertised refs (HEAD first):
+/// the selected branch or tag takes the place of HEAD, and with
+/// `single_branch` no other ref is kept.
+fn select_references(
+    mut refs: Vec<remote::Reference>,
+    options: &CloneOptions,
+) -> Result<Vec<remote::Reference>> {
+    let selected = match &options.branch {
+        Some(branch) => Some(
+            [
+                format!("refs/heads/{branch}"),
+                format!("refs/tags/{branch}"),
+            ]
+            .iter()
+            .find_map(|name| refs.iter().find(|(_, n)| n == name))
+            .cloned()
+            .ok_or_else(|| anyhow!("Remote branch {branch} not found in upstream"))?,
+        ),
+        None if options.single_branch => refs.first().and_then(|(head_hash, _)| {
+            refs.iter()
+                .find(|(hash, name)| hash == head_hash && name.starts_with("refs/heads/"))
+                .cloned()
+        }),
+        None => None,
+    };
+    let Some(selected) = selected else {
+        if options.single_branch {
+            // a detached remote HEAD is all there is to take
+            refs.truncate(1);
+        }
+        return Ok(refs);
+    };
+    let head = (selected.0.clone(), "HEAD".to_owned());
+    if options.single_branch {
+        return Ok(vec![head, selected]);
+    }
+    refs.retain(|(_, name)| name != "HEAD" && *name != selected.1);
+    // store_references attaches HEAD to the first branch matching its hash
+    Ok([head, selected].into_iter().chain(refs).collect())
+}
+
+/// Finds the git directory behind a `file://` URL or a path to an existing
+/// repository, bare or not. Anything else is left to the network transports.
+pub fn local_repository(url: &str) -> Result<Option<PathBuf>> {
+    let path = match url.strip_prefix("file://") {
+        Some(path) => PathBuf::from(path),
+        None if Path::new(url).exists() => PathBuf::from(url),
+        None => return Ok(None),
+    };
+    let path = path
+        .canonicalize()
+        .map_err(|e| anyhow!("Cannot access {}: {e}", path.display()))?;
+    if path.join(".git").is_dir() {
+        Ok(Some(path.join(".git")))
+    } else if path.join("objects").is_dir() && path.join("HEAD").is_file() {
+        Ok(Some(path))
+    } else {
+        bail!("{} is not a git repository", path.display())
+    }
+}
+
+fn clone_local(git_dir: &Path, url: &str, path: &Path, options: &CloneOptions) -> Result<String> {
+    // read everything before init moves into the new repository
+    let references = select_references(local_references(git_dir)?, options)?;
+    let location = match url.strip_prefix("file://") {
+        Some(_) => url.to_owned(),
+        None => fs::canonicalize(url)?.display().to_string(),
+    };
+    init(path, HashAlgo::Sha1)?;
+    link_objects(&git_dir.join("objects"), Path::new(".git/objects"))?;
+    let head_hash = store_references(&references, url)?;
+    configure_remote(&location, &references, options)?;
+    Ok(head_hash)
+}
+
+/// Hard-links every file of an object directory, copying when linking is not
+/// possible (e.g. across filesystems). Files already in `target` are kept, as
+/// objects and packs never change under their name.
+pub fn link_objects(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in source.read_dir()? {
+        let entry = entry?;
+        let target = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_objects(&entry.path(), &target)?;
+        } else if target.exists() {
+            continue;
+        } else if fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the refs of a repository on disk the way a server advertises them, HEAD first.
+pub fn local_references(git_dir: &Path) -> Result<Vec<remote::Reference>> {
+    let mut references = BTreeMap::new();
+    // loose refs are read last so that they take precedence
+    let packed_refs = git_dir.join("packed-refs");
+    if packed_refs.is_file() {
+        for line in fs::read_to_string(packed_refs)?.lines() {
+            if line.starts_with(['#', '^']) {
+                continue;
+            }
+            if let Some((hash, name)) = line.split_once(' ') {
+                references.insert(name.to_owned(), hash.to_owned());
+            }
+        }
+    }
+    collect_loose_references(git_dir, "refs", &mut references)?;
+    let head = fs::read_to_string(git_dir.join("HEAD"))?;
+    let head = head.trim_end();
+    let head_hash = match head.strip_prefix("ref: ") {
+        Some(target) => references
+            .get(target)
+            .cloned()
+            .ok_or_else(|| anyhow!("Cannot clone an empty repository"))?,
+        None => head.to_owned(),
+    };
+    Ok(std::iter::once((head_hash, "HEAD".to_owned()))
+        .chain(references.into_iter().map(|(name, hash)| (hash, name)))
+        .collect())
+}
+
+fn collect_loose_references(
+    git_dir: &Path,
+    prefix: &str,
+    references: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    let directory = git_dir.join(prefix);
+    if !directory.is_dir() {
+        return Ok(());
+    }
+    for entry in directory.read_dir()? {
+        let entry = entry?;
+        let name = format!("{prefix}/{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_loose_references(git_dir, &name, references)?;
+            continue;
+        }
+        let content = fs::read_to_string(entry.path())?;
+        // symbolic refs such as refs/remotes/origin/HEAD are not advertised
+        if !content.starts_with("ref: ") {
+            references.insert(name, content.trim_end().to_owned());
+        }
+    }
+    Ok(())
+}
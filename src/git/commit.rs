@@ -0,0 +1,104 @@
+use super::{
+    commit_content, index::Index, merge, parse_hash, refs, short_hash, sign, signature,
+    write_tree_from_files, ObjectId, Odb, Role,
+};
+use anyhow::{anyhow, bail, Result};
+
+#[derive(Default)]
+pub struct CommitOptions {
+    /// The commit message; amending without one keeps the old message.
+    pub message: Option<String>,
+    /// Replace the tip commit instead of adding one on top of it (`--amend`).
+    pub amend: bool,
+    /// Sign the commit (`-S`).
+    pub sign: bool,
+}
+
+/// Records the staged files as a commit on top of HEAD and moves the branch
+/// HEAD points at, or HEAD itself when detached. On an unborn branch the
+/// commit has no parent. Amending takes the parents and author of the
+/// replaced commit instead, and concluding a merge adds `MERGE_HEAD` as a
+/// second parent.
+pub fn commit(options: &CommitOptions) -> Result<ObjectId> {
+    let odb = Odb::open()?;
+    let index = Index::read()?;
+    if !index.conflicts().is_empty() {
+        bail!("Committing is not possible because you have unmerged files");
+    }
+    let head = refs::peel("HEAD")?;
+    let merge_head = merge::merge_head()?;
+    let amended = match (&head, options.amend) {
+        (_, true) if merge_head.is_some() => {
+            bail!("You are in the middle of a merge -- cannot amend.")
+        }
+        (Some(head), true) => Some(odb.read_commit(head)?),
+        (None, true) => bail!("You have nothing to amend"),
+        (_, false) => None,
+    };
+    let message = match (&options.message, &amended) {
+        (Some(message), _) => message.clone(),
+        (None, Some(amended)) => amended.message.clone(),
+        (None, None) => {
+            merge::merge_message()?.ok_or_else(|| anyhow!("A commit message is required"))?
+        }
+    };
+    let message = message.trim_end();
+    if message.trim().is_empty() {
+        bail!("Aborting commit due to empty commit message");
+    }
+    if head.is_none() && index.entries.is_empty() {
+        bail!("nothing to commit");
+    }
+    let tree = write_tree_from_files(&odb, &index.files())?;
+
+    let (parents, author) = match amended {
+        Some(amended) => (amended.parents, amended.author.to_string()),
+        None => (
+            head.iter().chain(&merge_head).cloned().collect(),
+            signature(Role::Author)?,
+        ),
+    };
+    // amending may keep the tree and only reword the message
+    if let (false, [parent]) = (options.amend, parents.as_slice()) {
+        if odb.read_commit(parent)?.tree == tree.to_string() {
+            bail!("nothing to commit");
+        }
+    }
+    let parents = parents
+        .iter()
+        .map(|parent| parse_hash(parent))
+        .collect::<Result<Vec<_>>>()?;
+    let mut content = commit_content(&tree, &parents, &author, message)?;
+    if options.sign {
+        content = sign::sign_commit(&content)?;
+    }
+    let hash = odb.write(b"commit", content.as_bytes())?;
+
+    let subject = message.lines().next().unwrap_or_default();
+    let action = match (options.amend, parents.len()) {
+        (true, _) => "commit (amend)",
+        (false, 0) => "commit (initial)",
+        (false, 1) => "commit",
+        (false, _) => "commit (merge)",
+    };
+    refs::update_from(
+        "HEAD",
+        head.as_deref(),
+        &hash.to_string(),
+        &format!("{action}: {subject}"),
+    )?;
+    if merge_head.is_some() {
+        merge::clear_state()?;
+    }
+    let branch = refs::current_branch()?.unwrap_or_else(|| "detached HEAD".to_owned());
+    let root = if parents.is_empty() {
+        " (root-commit)"
+    } else {
+        ""
+    };
+    println!(
+        "[{branch}{root} {}] {subject}",
+        short_hash(&hash.to_string())
+    );
+    Ok(hash)
+}
@@ -0,0 +1,337 @@
+use super::{
+    config::Config, hash::HashAlgo, peel_to_commit, refs, remote::Sha1, repository, revwalk, Odb,
+};
+use anyhow::{anyhow, bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+const COMMIT_GRAPH: &str = "objects/info/commit-graph";
+const SIGNATURE: &[u8; 4] = b"CGPH";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 8;
+const CHUNK_ENTRY_SIZE: usize = 12;
+const OID_FANOUT: &[u8; 4] = b"OIDF";
+const OID_LOOKUP: &[u8; 4] = b"OIDL";
+const COMMIT_DATA: &[u8; 4] = b"CDAT";
+const EXTRA_EDGES: &[u8; 4] = b"EDGE";
+const FANOUT_SIZE: usize = 256 * 4;
+/// Parent positions, generation and commit time following the tree hash.
+const COMMIT_FIELDS_SIZE: usize = 16;
+const NO_PARENT: u32 = 0x7000_0000;
+/// Marks a second parent field pointing into the extra edges of an octopus
+/// merge, and the last of those edges.
+const EDGE_FLAG: u32 = 0x8000_0000;
+
+/// What the graph records about a commit, enough to walk history without
+/// inflating commit objects.
+pub struct GraphCommit {
+    pub parents: Vec<Sha1>,
+    /// Committer timestamp.
+    pub time: i64,
+    /// Topological level: 1 for root commits, one more than the highest
+    /// parent otherwise. An ancestor always has a lower generation.
+    pub generation: u32,
+}
+
+/// The `objects/info/commit-graph` file of the repository.
+pub struct CommitGraph {
+    data: Vec<u8>,
+    count: usize,
+    fanout: usize,
+    lookup: usize,
+    commits: usize,
+    edges: Option<usize>,
+    hash_size: usize,
+}
+
+impl CommitGraph {
+    /// Loads the commit graph, `None` when there is none, it is disabled by
+    /// `core.commitGraph` or it cannot be read.
+    pub fn load() -> Option<Self> {
+        let enabled = Config::load()
+            .ok()
+            .and_then(|config| config.get_bool("core.commitGraph"))
+            .unwrap_or(true);
+        if !enabled {
+            return None;
+        }
+        let data = fs::read(repository::path(COMMIT_GRAPH)).ok()?;
+        Self::parse(data).ok()
+    }
+
+    fn parse(data: Vec<u8>) -> Result<Self> {
+        let algo = HashAlgo::current();
+        let hash_size = algo.size();
+        if data.len() < HEADER_SIZE + CHUNK_ENTRY_SIZE + hash_size
+            || &data[..4] != SIGNATURE
+            || data[4] != VERSION
+            || data[5] != algo.format_version()
+        {
+            bail!("Unsupported commit-graph file");
+        }
+        let mut chunks = HashMap::new();
+        for i in 0..data[6] as usize {
+            let entry = HEADER_SIZE + i * CHUNK_ENTRY_SIZE;
+            let id: [u8; 4] = data[entry..entry + 4].try_into()?;
+            let offset = u64::from_be_bytes(data[entry + 4..entry + 12].try_into()?) as usize;
+            if offset >= data.len() {
+                bail!("Commit-graph chunk out of bounds");
+            }
+            chunks.insert(id, offset);
+        }
+        let chunk = |id: &[u8; 4]| {
+            chunks
+                .get(id)
+                .copied()
+                .ok_or_else(|| anyhow!("Commit-graph lacks {}", String::from_utf8_lossy(id)))
+        };
+        let fanout = chunk(OID_FANOUT)?;
+        let mut graph = Self {
+            count: 0,
+            fanout,
+            lookup: chunk(OID_LOOKUP)?,
+            commits: chunk(COMMIT_DATA)?,
+            edges: chunk(EXTRA_EDGES).ok(),
+            hash_size,
+            data,
+        };
+        if graph.fanout + FANOUT_SIZE > graph.data.len() {
+            bail!("Commit-graph is truncated");
+        }
+        graph.count = graph.fanout(u8::MAX);
+        if graph.lookup + graph.count * hash_size > graph.data.len()
+            || graph.commits + graph.count * graph.commit_data_size() > graph.data.len()
+        {
+            bail!("Commit-graph is truncated");
+        }
+        Ok(graph)
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Number of commits whose hash starts with a byte not greater than `byte`.
+    fn fanout(&self, byte: u8) -> usize {
+        self.read_u32(self.fanout + byte as usize * 4) as usize
+    }
+
+    fn hash(&self, position: usize) -> &[u8] {
+        let start = self.lookup + position * self.hash_size;
+        &self.data[start..start + self.hash_size]
+    }
+
+    fn commit_data_size(&self) -> usize {
+        self.hash_size + COMMIT_FIELDS_SIZE
+    }
+
+    fn position(&self, hash: &[u8]) -> Option<usize> {
+        let first = *hash.first()?;
+        let start = if first == 0 {
+            0
+        } else {
+            self.fanout(first - 1)
+        };
+        let end = self.fanout(first);
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.hash(middle).cmp(hash) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    /// The recorded data of a commit, `None` if the graph does not have it.
+    pub fn get(&self, hash: &str) -> Option<GraphCommit> {
+        let position = self.position(&hex::decode(hash).ok()?)?;
+        let start = self.commits + position * self.commit_data_size() + self.hash_size;
+        let mut parents = vec![];
+        match self.read_u32(start) {
+            NO_PARENT => {}
+            parent => parents.push(self.parent_hash(parent)?),
+        }
+        match self.read_u32(start + 4) {
+            NO_PARENT => {}
+            edge if edge & EDGE_FLAG != 0 => {
+                let mut offset = self.edges? + (edge & !EDGE_FLAG) as usize * 4;
+                loop {
+                    self.data.get(offset..offset + 4)?;
+                    let value = self.read_u32(offset);
+                    parents.push(self.parent_hash(value & !EDGE_FLAG)?);
+                    if value & EDGE_FLAG != 0 {
+                        break;
+                    }
+                    offset += 4;
+                }
+            }
+            parent => parents.push(self.parent_hash(parent)?),
+        }
+        let generation_and_time = self.read_u32(start + 8);
+        let time = ((generation_and_time as i64 & 0b11) << 32) | self.read_u32(start + 12) as i64;
+        Some(GraphCommit {
+            parents,
+            time,
+            generation: generation_and_time >> 2,
+        })
+    }
+
+    fn parent_hash(&self, position: u32) -> Option<Sha1> {
+        let position = position as usize;
+        (position < self.count).then(|| hex::encode(self.hash(position)))
+    }
+
+    /// Whether `ancestor` is reachable from `descendant`, walking only
+    /// commits whose generation is high enough to lead to it. `None` when
+    /// either commit is not in the graph.
+    pub fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Option<bool> {
+        let target = self.get(ancestor)?.generation;
+        self.get(descendant)?;
+        let mut seen = HashSet::new();
+        let mut stack = vec![descendant.to_owned()];
+        while let Some(hash) = stack.pop() {
+            if hash == ancestor {
+                return Some(true);
+            }
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            let commit = self.get(&hash)?;
+            if commit.generation <= target {
+                continue;
+            }
+            stack.extend(commit.parents);
+        }
+        Some(false)
+    }
+}
+
+/// Writes the commit graph of all commits reachable from refs and HEAD,
+/// replacing any previous one.
+pub fn write() -> Result<()> {
+    if !revwalk::read_shallow().is_empty() {
+        bail!("Commit graphs are not supported in shallow repositories");
+    }
+    let odb = Odb::open()?;
+    let mut tips = vec![];
+    let heads = refs::list("refs/")?.into_iter().map(|(_, hash)| hash);
+    for hash in heads.chain(refs::peel("HEAD")?) {
+        // refs may also point at trees and blobs through tags
+        if let Ok(commit) = peel_to_commit(&hash) {
+            tips.push(commit);
+        }
+    }
+
+    // read every commit once, then number them by hash
+    let mut commits = HashMap::new();
+    let mut stack = tips;
+    while let Some(hash) = stack.pop() {
+        if commits.contains_key(&hash) {
+            continue;
+        }
+        let commit = odb.read_commit(&hash)?;
+        stack.extend(commit.parents.iter().cloned());
+        commits.insert(hash, commit);
+    }
+    let mut hashes = commits.keys().cloned().collect::<Vec<_>>();
+    hashes.sort();
+    let positions = hashes
+        .iter()
+        .enumerate()
+        .map(|(position, hash)| (hash.as_str(), position as u32))
+        .collect::<HashMap<_, _>>();
+
+    // topological levels, computed parents first
+    let mut generations: HashMap<&str, u32> = HashMap::new();
+    for hash in &hashes {
+        let mut stack = vec![hash.as_str()];
+        while let Some(&hash) = stack.last() {
+            if generations.contains_key(hash) {
+                stack.pop();
+                continue;
+            }
+            let parents = &commits[hash].parents;
+            let pending = parents
+                .iter()
+                .filter(|p| !generations.contains_key(p.as_str()))
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if pending.is_empty() {
+                let generation = parents.iter().map(|p| generations[p.as_str()]).max();
+                generations.insert(hash, generation.unwrap_or(0) + 1);
+                stack.pop();
+            } else {
+                stack.extend(pending);
+            }
+        }
+    }
+
+    let mut fanout = vec![];
+    for byte in 0..=u8::MAX {
+        let prefix = format!("{byte:02x}");
+        let count = hashes.partition_point(|hash| hash[..2] <= *prefix);
+        fanout.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    let mut lookup = vec![];
+    let mut data = vec![];
+    let mut edges: Vec<u8> = vec![];
+    for hash in &hashes {
+        let commit = &commits[hash];
+        lookup.extend(hex::decode(hash)?);
+        data.extend(hex::decode(&commit.tree)?);
+        let parent = |index: usize| commit.parents.get(index).map(|p| positions[p.as_str()]);
+        data.extend_from_slice(&parent(0).unwrap_or(NO_PARENT).to_be_bytes());
+        let second = match commit.parents.len() {
+            0 | 1 => NO_PARENT,
+            2 => positions[commit.parents[1].as_str()],
+            _ => {
+                let edge = EDGE_FLAG | (edges.len() / 4) as u32;
+                let extra = &commit.parents[1..];
+                for (i, parent) in extra.iter().enumerate() {
+                    let last = if i + 1 == extra.len() { EDGE_FLAG } else { 0 };
+                    edges.extend_from_slice(&(positions[parent.as_str()] | last).to_be_bytes());
+                }
+                edge
+            }
+        };
+        data.extend_from_slice(&second.to_be_bytes());
+        let time = commit.time().max(0) as u64;
+        let generation = generations[hash.as_str()];
+        data.extend_from_slice(&((generation << 2) | (time >> 32) as u32 & 0b11).to_be_bytes());
+        data.extend_from_slice(&(time as u32).to_be_bytes());
+    }
+
+    let mut chunks = vec![
+        (OID_FANOUT, fanout),
+        (OID_LOOKUP, lookup),
+        (COMMIT_DATA, data),
+    ];
+    if !edges.is_empty() {
+        chunks.push((EXTRA_EDGES, edges));
+    }
+    let algo = HashAlgo::current();
+    let mut graph = vec![];
+    graph.extend_from_slice(SIGNATURE);
+    graph.extend_from_slice(&[VERSION, algo.format_version(), chunks.len() as u8, 0]);
+    let mut offset = HEADER_SIZE + (chunks.len() + 1) * CHUNK_ENTRY_SIZE;
+    for (id, chunk) in &chunks {
+        graph.extend_from_slice(*id);
+        graph.extend_from_slice(&(offset as u64).to_be_bytes());
+        offset += chunk.len();
+    }
+    graph.extend_from_slice(&[0; 4]);
+    graph.extend_from_slice(&(offset as u64).to_be_bytes());
+    for (_, chunk) in chunks {
+        graph.extend(chunk);
+    }
+    let checksum = algo.digest(&graph);
+    graph.extend_from_slice(&checksum);
+
+    let path = repository::path(COMMIT_GRAPH);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, graph)?;
+    Ok(())
+}
@@ -0,0 +1,489 @@
+use super::{lockfile::LockFile, repository};
+use anyhow::{anyhow, bail, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const LOCAL_CONFIG: &str = "config";
+
+struct Section {
+    name: String,
+    subsection: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    fn matches(&self, name: &str, subsection: Option<&str>) -> bool {
+        self.name.eq_ignore_ascii_case(name) && self.subsection.as_deref() == subsection
+    }
+}
+
+/// A change made through `set`, `add` or `unset`, replayed on the file by
+/// `save`.
+enum Edit {
+    Set(String, String),
+    Add(String, String),
+    Unset(String),
+}
+
+/// INI-style git configuration, e.g. `.git/config`.
+#[derive(Default)]
+pub struct Config {
+    sections: Vec<Section>,
+    edits: Vec<Edit>,
+}
+
+impl Config {
+    /// Loads the global and repository configuration, the latter taking precedence.
+    pub fn load() -> Result<Self> {
+        let mut config = Config::default();
+        for path in global_paths()
+            .into_iter()
+            .chain([repository::path(LOCAL_CONFIG)])
+        {
+            config.sections.extend(Self::read(&path)?.sections);
+        }
+        Ok(config)
+    }
+
+    /// Reads the repository configuration only, suitable for modifying and saving back.
+    pub fn local() -> Result<Self> {
+        Self::read(&repository::path(LOCAL_CONFIG))
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            return Ok(Config::default());
+        }
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut sections: Vec<Section> = vec![];
+        for (lines, line) in logical_lines(text) {
+            let number = lines.start;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .split_once(']')
+                    .map(|(header, _)| header)
+                    .ok_or_else(|| anyhow!("Bad config section at line {}", number + 1))?;
+                let (name, subsection) = match header.split_once(' ') {
+                    Some((name, subsection)) => (name, Some(unquote(subsection.trim()))),
+                    // deprecated [section.subsection] syntax
+                    None => match header.split_once('.') {
+                        Some((name, subsection)) => (name, Some(subsection.to_owned())),
+                        None => (header, None),
+                    },
+                };
+                sections.push(Section {
+                    name: name.to_ascii_lowercase(),
+                    subsection,
+                    entries: vec![],
+                });
+                continue;
+            }
+            let Some(section) = sections.last_mut() else {
+                bail!("Config entry outside of a section at line {}", number + 1);
+            };
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), unquote(value.trim())),
+                // a key without a value is a boolean flag
+                None => (line, "true".to_owned()),
+            };
+            section.entries.push((key.to_ascii_lowercase(), value));
+        }
+        Ok(Self {
+            sections,
+            edits: vec![],
+        })
+    }
+
+    /// Returns the last value of a `section[.subsection].key` variable.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).pop()
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let Some((name, subsection, variable)) = split_key(key) else {
+            return vec![];
+        };
+        self.sections
+            .iter()
+            .filter(|s| s.matches(name, subsection))
+            .flat_map(|s| s.entries.iter())
+            .filter(|(k, _)| k.eq_ignore_ascii_case(variable))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Names of the subsections of a section, e.g. the remotes of `remote`.
+    pub fn subsections(&self, name: &str) -> Vec<&str> {
+        let mut names = vec![];
+        for section in &self.sections {
+            if let Some(subsection) = section.subsection.as_deref() {
+                if section.name.eq_ignore_ascii_case(name) && !names.contains(&subsection) {
+                    names.push(subsection);
+                }
+            }
+        }
+        names
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key).map(|value| {
+            matches!(
+                value.to_ascii_lowercase().as_str(),
+                "true" | "yes" | "on" | "1"
+            )
+        })
+    }
+
+    /// Sets a variable, replacing any existing values.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        self.remove(key)?;
+        self.append(key, value)?;
+        self.edits.push(Edit::Set(key.to_owned(), value.to_owned()));
+        Ok(())
+    }
+
+    /// Appends a value to a (possibly multi-valued) variable.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<()> {
+        self.append(key, value)?;
+        self.edits.push(Edit::Add(key.to_owned(), value.to_owned()));
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        self.remove(key)?;
+        self.edits.push(Edit::Unset(key.to_owned()));
+        Ok(())
+    }
+
+    fn append(&mut self, key: &str, value: &str) -> Result<()> {
+        let Some((name, subsection, variable)) = split_key(key) else {
+            bail!("Invalid config key {key}");
+        };
+        let index = match self
+            .sections
+            .iter()
+            .position(|s| s.matches(name, subsection))
+        {
+            Some(index) => index,
+            None => {
+                self.sections.push(Section {
+                    name: name.to_ascii_lowercase(),
+                    subsection: subsection.map(str::to_owned),
+                    entries: vec![],
+                });
+                self.sections.len() - 1
+            }
+        };
+        self.sections[index]
+            .entries
+            .push((variable.to_ascii_lowercase(), value.to_owned()));
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<()> {
+        let Some((name, subsection, variable)) = split_key(key) else {
+            bail!("Invalid config key {key}");
+        };
+        for section in self
+            .sections
+            .iter_mut()
+            .filter(|s| s.matches(name, subsection))
+        {
+            section
+                .entries
+                .retain(|(k, _)| !k.eq_ignore_ascii_case(variable));
+        }
+        Ok(())
+    }
+
+    /// Writes the changes made since loading to the repository configuration.
+    /// Only the lines of changed variables are touched; comments, layout and
+    /// everything else in the file stay as they are.
+    pub fn save(&mut self) -> Result<()> {
+        let path = repository::path(LOCAL_CONFIG);
+        let text = match path.is_file() {
+            true => fs::read_to_string(&path)?,
+            false => String::new(),
+        };
+        let mut lines = text.lines().map(str::to_owned).collect::<Vec<_>>();
+        for edit in self.edits.drain(..) {
+            match edit {
+                Edit::Set(key, value) => edit_lines(&mut lines, &key, Some(&value), true)?,
+                Edit::Add(key, value) => edit_lines(&mut lines, &key, Some(&value), false)?,
+                Edit::Unset(key) => edit_lines(&mut lines, &key, None, true)?,
+            }
+        }
+        let mut text = lines.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        let mut lock = LockFile::acquire(&path)?;
+        lock.write(text.as_bytes())?;
+        lock.commit()
+    }
+}
+
+/// Joins lines continued with a trailing backslash, giving each logical line
+/// with the range of file lines it spans.
+fn logical_lines(text: &str) -> Vec<(Range<usize>, String)> {
+    let mut logical: Vec<(Range<usize>, String)> = vec![];
+    let mut continued = false;
+    for (number, line) in text.lines().enumerate() {
+        let (line, continues) = match line.strip_suffix('\\') {
+            // an escaped backslash does not continue the line
+            Some(stripped)
+                if !is_comment(line)
+                    && (stripped.len() - stripped.trim_end_matches('\\').len()) % 2 == 0 =>
+            {
+                (stripped, true)
+            }
+            _ => (line, false),
+        };
+        match logical.last_mut() {
+            Some((lines, joined)) if continued => {
+                lines.end = number + 1;
+                joined.push_str(line);
+            }
+            _ => logical.push((number..number + 1, line.to_owned())),
+        }
+        continued = continues;
+    }
+    logical
+}
+
+fn is_comment(line: &str) -> bool {
+    line.trim_start().starts_with(['#', ';'])
+}
+
+/// Sets (`replace`), adds or, without a value, removes a variable in the
+/// lines of a config file. A value set replaces the first line defining the
+/// variable and drops the others; added values go after the last line of the
+/// section, which is appended to the file if missing.
+fn edit_lines(
+    lines: &mut Vec<String>,
+    key: &str,
+    value: Option<&str>,
+    replace: bool,
+) -> Result<()> {
+    let Some((name, subsection, variable)) = split_key(key) else {
+        bail!("Invalid config key {key}");
+    };
+    let mut current = None;
+    let mut matching = vec![];
+    let mut section_end = None;
+    for (range, line) in logical_lines(&lines.join("\n")) {
+        let line = line.trim();
+        if line.is_empty() || is_comment(line) {
+            continue;
+        }
+        if line.starts_with('[') {
+            current = Some(Config::parse(line)?.sections.remove(0));
+        } else if let Some(section) = &current {
+            let entry = line.split_once('=').map_or(line, |(key, _)| key).trim();
+            if section.matches(name, subsection) && entry.eq_ignore_ascii_case(variable) {
+                matching.push(range.clone());
+            }
+        }
+        if current
+            .as_ref()
+            .is_some_and(|s| s.matches(name, subsection))
+        {
+            section_end = Some(range.end);
+        }
+    }
+    let entry = value.map(|value| format!("\t{variable} = {}", quote(value)));
+    if replace {
+        for range in matching.iter().rev() {
+            lines.drain(range.clone());
+        }
+        if let (Some(entry), Some(first)) = (&entry, matching.first()) {
+            lines.insert(first.start, entry.clone());
+            return Ok(());
+        }
+    }
+    let Some(entry) = entry else {
+        return Ok(());
+    };
+    match section_end {
+        Some(end) => lines.insert(end, entry),
+        None => {
+            lines.push(match subsection {
+                Some(subsection) => format!("[{name} \"{subsection}\"]"),
+                None => format!("[{name}]"),
+            });
+            lines.push(entry);
+        }
+    }
+    Ok(())
+}
+
+fn global_paths() -> Vec<PathBuf> {
+    let mut paths = vec![];
+    if let Some(config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+        paths.push(PathBuf::from(config_home).join("git/config"));
+    } else if let Some(home) = std::env::var_os("HOME") {
+        paths.push(PathBuf::from(&home).join(".config/git/config"));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        paths.push(PathBuf::from(home).join(".gitconfig"));
+    }
+    paths
+}
+
+/// Splits `section.key` or `section.sub.section.key` into its parts.
+fn split_key(key: &str) -> Option<(&str, Option<&str>, &str)> {
+    let (section, variable) = key.rsplit_once('.')?;
+    Some(match section.split_once('.') {
+        Some((name, subsection)) => (name, Some(subsection), variable),
+        None => (section, None, variable),
+    })
+}
+
+/// Strips comments, surrounding quotes and escapes from a raw value.
+fn unquote(raw: &str) -> String {
+    let mut value = String::new();
+    let mut quoted = false;
+    // length without the unquoted whitespace at the end
+    let mut kept = 0;
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => break,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(escaped) => value.push(escaped),
+                None => {}
+            },
+            _ => value.push(c),
+        }
+        if quoted || c == '\\' || !c.is_whitespace() {
+            kept = value.len();
+        }
+    }
+    value.truncate(kept);
+    value
+}
+
+fn quote(value: &str) -> String {
+    let needs_quotes = value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']);
+    let mut quoted = String::new();
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                let _ = write!(quoted, "\\{c}");
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    if needs_quotes {
+        format!("\"{quoted}\"")
+    } else {
+        quoted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "\
+# kept as written
+[core]
+\tbare = false ; no worktree otherwise
+\tlogAllRefUpdates = true
+[remote \"origin\"]
+\turl = https://example.com/repo.git
+\tfetch = +refs/heads/*:refs/remotes/origin/*
+\tpushurl = https://example.com/\\
+very/long.git
+";
+
+    fn edited(key: &str, value: Option<&str>, replace: bool) -> String {
+        let mut lines = TEXT.lines().map(str::to_owned).collect::<Vec<_>>();
+        edit_lines(&mut lines, key, value, replace).unwrap();
+        lines.join("\n") + "\n"
+    }
+
+    #[test]
+    fn continued_lines_are_joined() {
+        let config = Config::parse(TEXT).unwrap();
+        assert_eq!(
+            config.get("remote.origin.pushurl"),
+            Some("https://example.com/very/long.git")
+        );
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(config.get("core.logallrefupdates"), Some("true"));
+        let escaped = Config::parse("[a]\n\tb = c\\\\\n\td = e\n").unwrap();
+        assert_eq!(escaped.get("a.b"), Some("c\\"));
+        assert_eq!(escaped.get("a.d"), Some("e"));
+    }
+
+    #[test]
+    fn set_replaces_the_line_in_place() {
+        assert_eq!(
+            edited("core.bare", Some("true"), true),
+            TEXT.replace("\tbare = false ; no worktree otherwise", "\tbare = true")
+        );
+        assert_eq!(
+            edited("remote.origin.pushurl", Some("x"), true),
+            TEXT.replace(
+                "\tpushurl = https://example.com/\\\nvery/long.git",
+                "\tpushurl = x"
+            )
+        );
+    }
+
+    #[test]
+    fn add_appends_to_the_section() {
+        assert_eq!(
+            edited("core.ignoreCase", Some("true"), false),
+            TEXT.replace(
+                "\tlogAllRefUpdates = true\n",
+                "\tlogAllRefUpdates = true\n\tignoreCase = true\n"
+            )
+        );
+        assert_eq!(
+            edited("branch.main.remote", Some("origin"), true),
+            format!("{TEXT}[branch \"main\"]\n\tremote = origin\n")
+        );
+        assert_eq!(
+            edited("User.Name", Some("A U Thor"), true),
+            format!("{TEXT}[User]\n\tName = A U Thor\n")
+        );
+    }
+
+    #[test]
+    fn unset_removes_only_the_variable() {
+        assert_eq!(
+            edited("remote.origin.pushurl", None, true),
+            TEXT.replace("\tpushurl = https://example.com/\\\nvery/long.git\n", "")
+        );
+        assert_eq!(edited("core.missing", None, true), TEXT);
+    }
+
+    #[test]
+    fn quoted_values_round_trip() {
+        for value in [
+            "plain",
+            " leading and trailing ",
+            "# not a comment ; either",
+            "tab\tnewline\nquote\"backslash\\",
+        ] {
+            let config = Config::parse(&format!("[a]\n\tb = {}\n", quote(value))).unwrap();
+            assert_eq!(config.get("a.b"), Some(value));
+        }
+    }
+}
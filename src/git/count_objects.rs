@@ -0,0 +1,110 @@
+use super::{
+    hash::HashAlgo, midx::MULTI_PACK_INDEX, pack::Packs, parse_hash, platform, repository, Backend,
+    Odb,
+};
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+/// Extensions of files that may accompany a pack and its index.
+const PACK_EXTRAS: &[&str] = &["keep", "promisor", "rev", "bitmap", "mtimes"];
+
+#[derive(Default)]
+struct Counts {
+    loose: usize,
+    /// Disk space used by loose objects, in bytes.
+    loose_size: u64,
+    packs: usize,
+    /// Size of the packs and their indexes, in bytes.
+    pack_size: u64,
+    garbage: usize,
+    garbage_size: u64,
+}
+
+impl Counts {
+    fn add_garbage(&mut self, path: &Path, size: u64) {
+        eprintln!("warning: garbage found: {}", path.display());
+        self.garbage += 1;
+        self.garbage_size += size;
+    }
+}
+
+/// Prints the number of loose objects and the disk space they use; verbose
+/// output adds packed objects, loose objects also found in packs and stray
+/// files in the object directory.
+pub fn count_objects(verbose: bool) -> Result<()> {
+    let objects = repository::path("objects");
+    let mut counts = Counts::default();
+    let name_size = HashAlgo::current().hex_size() - 2;
+    if objects.is_dir() {
+        for entry in objects.read_dir()? {
+            let entry = entry?;
+            let subdir = entry.file_name().to_string_lossy().into_owned();
+            if subdir.len() != 2 || !subdir.bytes().all(|b| b.is_ascii_hexdigit()) {
+                continue;
+            }
+            for file in entry.path().read_dir()? {
+                let file = file?;
+                let metadata = file.metadata()?;
+                let name = file.file_name().to_string_lossy().into_owned();
+                if name.len() == name_size && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    counts.loose += 1;
+                    // allocated space, as du would count it
+                    counts.loose_size += platform::allocated_size(&metadata);
+                } else {
+                    counts.add_garbage(&file.path(), metadata.len());
+                }
+            }
+        }
+    }
+    if !verbose {
+        println!(
+            "{} objects, {} kilobytes",
+            counts.loose,
+            counts.loose_size / 1024
+        );
+        return Ok(());
+    }
+
+    let pack_directory = objects.join("pack");
+    if pack_directory.is_dir() {
+        for entry in pack_directory.read_dir()? {
+            let path = entry?.path();
+            let size = fs::metadata(&path)?.len();
+            let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+            if path
+                .file_name()
+                .is_some_and(|name| name == MULTI_PACK_INDEX)
+            {
+                continue;
+            }
+            match extension.as_deref() {
+                Some("pack") if path.with_extension("idx").is_file() => {
+                    counts.packs += 1;
+                    counts.pack_size += size;
+                }
+                Some("idx") if path.with_extension("pack").is_file() => counts.pack_size += size,
+                Some(extension)
+                    if PACK_EXTRAS.contains(&extension)
+                        && path.with_extension("pack").is_file() => {}
+                _ => counts.add_garbage(&path, size),
+            }
+        }
+    }
+    let packs = Packs::open(&pack_directory)?;
+    let mut prune_packable = 0;
+    for hash in Odb::open()?.list_loose()? {
+        if packs.exists(&parse_hash(&hash)?)? {
+            prune_packable += 1;
+        }
+    }
+    println!("count: {}", counts.loose);
+    println!("size: {}", counts.loose_size / 1024);
+    println!("in-pack: {}", packs.list()?.len());
+    println!("packs: {}", counts.packs);
+    println!("size-pack: {}", counts.pack_size / 1024);
+    println!("prune-packable: {prune_packable}");
+    println!("garbage: {}", counts.garbage);
+    println!("size-garbage: {}", counts.garbage_size / 1024);
+    Ok(())
+}
@@ -0,0 +1,246 @@
+use super::config::Config;
+use anyhow::{bail, Result};
+use reqwest::{blocking::RequestBuilder, Url};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::{env, fs, path::PathBuf};
+
+/// Credentials sent to an HTTP remote in the `Authorization` header.
+#[derive(Clone)]
+pub enum Credentials {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+impl Credentials {
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Self::Basic { username, password } => request.basic_auth(username, password.as_ref()),
+            Self::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+/// Credentials embedded in a URL as `user[:password]@host`.
+pub fn from_url(url: &Url) -> Option<Credentials> {
+    if url.username().is_empty() {
+        return None;
+    }
+    Some(Credentials::Basic {
+        username: percent_decode(url.username()),
+        password: url.password().map(percent_decode),
+    })
+}
+
+/// Looks for credentials outside the URL: `GIT_TOKEN` (sent as a bearer token),
+/// `GIT_USERNAME` with `GIT_PASSWORD`, the `~/.netrc` entry for the host, then
+/// the configured credential helpers.
+pub fn lookup(url: &Url) -> Result<Option<Credentials>> {
+    if let Ok(token) = env::var("GIT_TOKEN") {
+        return Ok(Some(Credentials::Bearer(token)));
+    }
+    if let Ok(username) = env::var("GIT_USERNAME") {
+        return Ok(Some(Credentials::Basic {
+            username,
+            password: env::var("GIT_PASSWORD").ok(),
+        }));
+    }
+    if let Some(credentials) = url.host_str().map(netrc).transpose()?.flatten() {
+        return Ok(Some(credentials));
+    }
+    fill(url)
+}
+
+/// Asks each `credential.helper` in turn for a username and password, like
+/// `git credential fill`.
+pub fn fill(url: &Url) -> Result<Option<Credentials>> {
+    let mut username = from_url(url).and_then(|credentials| match credentials {
+        Credentials::Basic { username, .. } => Some(username),
+        Credentials::Bearer(_) => None,
+    });
+    for helper in helpers()? {
+        let mut fields = describe(url);
+        if let Some(username) = &username {
+            fields.push(("username".to_owned(), username.clone()));
+        }
+        let mut password = None;
+        let mut quit = false;
+        for (key, value) in run_helper(&helper, "get", &fields)? {
+            match key.as_str() {
+                "username" => username = Some(value),
+                "password" => password = Some(value),
+                "quit" => quit = matches!(value.as_str(), "1" | "true"),
+                _ => {}
+            }
+        }
+        if let (Some(username), Some(password)) = (&username, password) {
+            return Ok(Some(Credentials::Basic {
+                username: username.clone(),
+                password: Some(password),
+            }));
+        }
+        if quit {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Tells the helpers that the credentials were accepted so they can store them.
+pub fn approve(url: &Url, credentials: &Credentials) -> Result<()> {
+    notify(url, credentials, "store")
+}
+
+/// Tells the helpers that the credentials were rejected so they can forget them.
+pub fn reject(url: &Url, credentials: &Credentials) -> Result<()> {
+    notify(url, credentials, "erase")
+}
+
+fn notify(url: &Url, credentials: &Credentials, action: &str) -> Result<()> {
+    // helpers only deal in usernames and passwords
+    let Credentials::Basic {
+        username,
+        password: Some(password),
+    } = credentials
+    else {
+        return Ok(());
+    };
+    let mut fields = describe(url);
+    fields.push(("username".to_owned(), username.clone()));
+    fields.push(("password".to_owned(), password.clone()));
+    for helper in helpers()? {
+        run_helper(&helper, action, &fields)?;
+    }
+    Ok(())
+}
+
+/// Returns the configured helpers; an empty value clears those listed before it.
+fn helpers() -> Result<Vec<String>> {
+    let config = Config::load()?;
+    let mut helpers = vec![];
+    for helper in config.get_all("credential.helper") {
+        if helper.is_empty() {
+            helpers.clear();
+        } else {
+            helpers.push(helper.to_owned());
+        }
+    }
+    Ok(helpers)
+}
+
+/// The `protocol` and `host` attributes describing a URL to a helper.
+fn describe(url: &Url) -> Vec<(String, String)> {
+    let mut host = url.host_str().unwrap_or_default().to_owned();
+    if let Some(port) = url.port() {
+        host = format!("{host}:{port}");
+    }
+    vec![
+        ("protocol".to_owned(), url.scheme().to_owned()),
+        ("host".to_owned(), host),
+    ]
+}
+
+/// Runs a helper the way git does: `!command` is a shell snippet, an absolute
+/// path is run as is, and anything else names a `git credential-<name>` helper.
+fn run_helper(
+    helper: &str,
+    action: &str,
+    fields: &[(String, String)],
+) -> Result<Vec<(String, String)>> {
+    let command = if let Some(command) = helper.strip_prefix('!') {
+        command.to_owned()
+    } else if helper.starts_with('/') {
+        helper.to_owned()
+    } else {
+        format!("git credential-{helper}")
+    };
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} {action}"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut input = String::new();
+    for (key, value) in fields {
+        if value.contains(['\n', '\0']) {
+            bail!("Credential {key} contains a newline or NUL");
+        }
+        input.push_str(&format!("{key}={value}\n"));
+    }
+    input.push('\n');
+    if let Some(mut stdin) = child.stdin.take() {
+        // helpers may exit without reading their input
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    // like git, ignore the exit status and use whatever the helper printed
+    let output = child.wait_with_output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect())
+}
+
+/// Finds the `machine` (or `default`) entry for a host in `~/.netrc`.
+fn netrc(host: &str) -> Result<Option<Credentials>> {
+    let Some(home) = env::var_os("HOME") else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(home).join(".netrc");
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    let mut tokens = content.split_whitespace();
+    let mut matching = false;
+    let mut username = None;
+    let mut password = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                if matching && username.is_some() {
+                    break;
+                }
+                matching = token == "default" || tokens.next() == Some(host);
+                username = None;
+                password = None;
+            }
+            "login" => username = tokens.next().filter(|_| matching),
+            "password" => password = tokens.next().filter(|_| matching),
+            _ => {}
+        }
+    }
+    Ok(username
+        .filter(|_| matching)
+        .map(|username| Credentials::Basic {
+            username: username.to_owned(),
+            password: password.map(str::to_owned),
+        }))
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
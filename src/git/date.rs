@@ -0,0 +1,225 @@
+use anyhow::{anyhow, Result};
+use std::time::SystemTime;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Units of relative dates like `2.weeks.ago`, in seconds.
+const UNITS: [(&str, i64); 7] = [
+    ("second", 1),
+    ("minute", 60),
+    ("hour", 60 * 60),
+    ("day", SECONDS_PER_DAY),
+    ("week", 7 * SECONDS_PER_DAY),
+    ("month", 30 * SECONDS_PER_DAY),
+    ("year", 365 * SECONDS_PER_DAY),
+];
+
+/// The current time in seconds since the epoch.
+pub fn now() -> Result<i64> {
+    let elapsed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| anyhow!("Failed to read system time"))?;
+    Ok(elapsed.as_secs() as i64)
+}
+
+/// Parses an expiry setting such as `gc.pruneExpire` into the timestamp
+/// before which things expire: `now`, `never` (`None`), a relative date like
+/// `2.weeks.ago` or an absolute date.
+pub fn parse_expiry(value: &str) -> Result<Option<i64>> {
+    match value {
+        "never" | "false" => return Ok(None),
+        "now" | "all" => return Ok(Some(now()?)),
+        _ => {}
+    }
+    let fields = value.split(['.', ' ']).collect::<Vec<_>>();
+    if let [count, unit, "ago"] = fields[..] {
+        let unit = unit.strip_suffix('s').unwrap_or(unit);
+        let (_, seconds) = UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .ok_or_else(|| anyhow!("Unknown time unit in '{value}'"))?;
+        let count: i64 = count
+            .parse()
+            .map_err(|_| anyhow!("Invalid expiry date '{value}'"))?;
+        return Ok(Some(now()? - count * seconds));
+    }
+    Ok(Some(parse(value)?.0))
+}
+
+/// Parses a `+hhmm`/`-hhmm` timezone into an offset in seconds.
+pub fn parse_timezone(timezone: &str) -> Result<i64> {
+    let (sign, digits) = match timezone.as_bytes().first() {
+        Some(b'+') => (1, &timezone[1..]),
+        Some(b'-') => (-1, &timezone[1..]),
+        _ => (1, timezone),
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(anyhow!("Invalid timezone {timezone}"));
+    }
+    let hours: i64 = digits[..2].parse()?;
+    let minutes: i64 = digits[2..].parse()?;
+    Ok(sign * (hours * 60 + minutes) * 60)
+}
+
+/// Formats a timestamp the way `git log` does by default: `Thu Jan 1 00:00:00 1970 +0000`.
+pub fn format(timestamp: i64, timezone: &str) -> String {
+    let (weekday, year, month, day, time) = local_fields(timestamp, timezone);
+    format!("{weekday} {month} {day} {time} {year} {timezone}")
+}
+
+/// Formats a timestamp for email headers: `Thu, 1 Jan 1970 00:00:00 +0000`.
+pub fn format_rfc2822(timestamp: i64, timezone: &str) -> String {
+    let (weekday, year, month, day, time) = local_fields(timestamp, timezone);
+    format!("{weekday}, {day} {month} {year} {time} {timezone}")
+}
+
+/// Formats a timestamp as `1970-01-01 00:00:00 +0000`, as `blame` shows it.
+pub fn format_iso(timestamp: i64, timezone: &str) -> String {
+    let (_, year, month, day, time) = local_fields(timestamp, timezone);
+    let month = MONTHS.iter().position(|m| *m == month).unwrap_or_default() + 1;
+    format!("{year}-{month:02}-{day:02} {time} {timezone}")
+}
+
+/// Packs a timestamp into the MS-DOS `(time, date)` fields of zip archives,
+/// which count years from 1980 and seconds in pairs.
+pub fn dos_time(timestamp: i64, timezone: &str) -> (u16, u16) {
+    let local = timestamp + parse_timezone(timezone).unwrap_or_default();
+    let (year, month, day) = civil_from_days(local.div_euclid(SECONDS_PER_DAY));
+    let seconds = local.rem_euclid(SECONDS_PER_DAY);
+    let time = ((seconds / 3600) << 11) | ((seconds / 60 % 60) << 5) | (seconds % 60 / 2);
+    let date = ((year - 1980).clamp(0, 127) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+/// Weekday, year, month name, day and `hh:mm:ss` in the given timezone.
+fn local_fields(timestamp: i64, timezone: &str) -> (&'static str, i64, &'static str, i64, String) {
+    let local = timestamp + parse_timezone(timezone).unwrap_or_default();
+    let days = local.div_euclid(SECONDS_PER_DAY);
+    let seconds = local.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    (
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        year,
+        MONTHS[month as usize - 1],
+        day,
+        time,
+    )
+}
+
+/// Parses a date as accepted in `GIT_AUTHOR_DATE`/`GIT_COMMITTER_DATE`
+/// into a timestamp and a `+hhmm` timezone. Supported forms are git's internal
+/// `<timestamp> <timezone>` (optionally prefixed with `@`), RFC 2822
+/// (`Thu, 07 Apr 2005 22:13:13 +0200`) and ISO 8601 (`2005-04-07T22:13:13+02:00`).
+pub fn parse(value: &str) -> Result<(i64, String)> {
+    let value = value.trim();
+    let raw = value.strip_prefix('@').unwrap_or(value);
+    let (timestamp, timezone) = raw.split_once(' ').unwrap_or((raw, "+0000"));
+    if let Ok(timestamp) = timestamp.parse::<i64>() {
+        parse_timezone(timezone)?;
+        return Ok((timestamp, timezone.to_owned()));
+    }
+    parse_rfc2822(value).or_else(|_| parse_iso8601(value))
+}
+
+fn parse_rfc2822(value: &str) -> Result<(i64, String)> {
+    let value = value.split_once(", ").map_or(value, |(_, rest)| rest);
+    let fields = value.split_whitespace().collect::<Vec<_>>();
+    let [day, month, year, time, timezone] = fields[..] else {
+        return Err(anyhow!("Unrecognized date {value}"));
+    };
+    let month = MONTHS
+        .iter()
+        .position(|m| m.eq_ignore_ascii_case(month))
+        .ok_or_else(|| anyhow!("Unknown month {month}"))? as i64
+        + 1;
+    to_timestamp(year.parse()?, month, day.parse()?, time, timezone)
+}
+
+fn parse_iso8601(value: &str) -> Result<(i64, String)> {
+    let (date, rest) = value
+        .split_once(['T', ' '])
+        .ok_or_else(|| anyhow!("Unrecognized date {value}"))?;
+    let rest = rest.trim();
+    let (time, timezone) = match rest.find(['+', '-', 'Z', ' ']) {
+        Some(index) => (&rest[..index], rest[index..].trim()),
+        None => (rest, ""),
+    };
+    let timezone = match timezone {
+        "" | "Z" => "+0000".to_owned(),
+        timezone => timezone.replace(':', ""),
+    };
+    let mut date_fields = date.splitn(3, '-');
+    let mut next_field = || -> Result<i64> {
+        Ok(date_fields
+            .next()
+            .ok_or_else(|| anyhow!("Unrecognized date {value}"))?
+            .parse()?)
+    };
+    let (year, month, day) = (next_field()?, next_field()?, next_field()?);
+    to_timestamp(year, month, day, time, &timezone)
+}
+
+fn to_timestamp(
+    year: i64,
+    month: i64,
+    day: i64,
+    time: &str,
+    timezone: &str,
+) -> Result<(i64, String)> {
+    let time = time
+        .split(':')
+        .map(|field| field.parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let (hours, minutes, seconds) = match time[..] {
+        [hours, minutes] => (hours, minutes, 0),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return Err(anyhow!("Unrecognized time")),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(anyhow!("Date out of range"));
+    }
+    let local =
+        days_from_civil(year, month, day) * SECONDS_PER_DAY + hours * 3600 + minutes * 60 + seconds;
+    Ok((local - parse_timezone(timezone)?, timezone.to_owned()))
+}
+
+/// Inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = (month + 9) % 12;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Converts days since the epoch into a (year, month, day) triple.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,108 @@
+use super::{
+    peel_to_commit, refs, remote::Sha1, revparse, revwalk::RevWalk, short_hash, Identity, Odb,
+    ParsedObject,
+};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// How many tagged commits are weighed against each other, as git does.
+const MAX_CANDIDATES: usize = 10;
+
+#[derive(Default)]
+pub struct DescribeOptions {
+    /// Describe with lightweight tags as well as annotated ones (`--tags`).
+    pub tags: bool,
+}
+
+/// A tag naming a commit; annotated tags win over lightweight ones, and
+/// newer over older.
+struct Name {
+    tag: String,
+    annotated: bool,
+    time: i64,
+}
+
+/// Names a commit after the nearest tag it descends from:
+/// `<tag>-<count>-g<abbreviated hash>`, or just the tag when it is tagged.
+/// The nearest tag is the one with the fewest commits on top of it.
+pub fn describe(revision: &str, options: &DescribeOptions) -> Result<String> {
+    let target = peel_to_commit(&revparse::resolve(revision)?)?;
+    let names = tag_names(options)?;
+    if names.is_empty() {
+        bail!("No names found, cannot describe anything.");
+    }
+    if let Some(name) = names.get(&target) {
+        return Ok(name.tag.clone());
+    }
+
+    let odb = Odb::open()?;
+    let mut candidates = vec![];
+    for hash in RevWalk::from_tip(&odb, &target)?.hashes() {
+        let hash = hash?;
+        if let Some(name) = names.get(&hash) {
+            candidates.push((hash, name));
+            if candidates.len() == MAX_CANDIDATES {
+                break;
+            }
+        }
+    }
+    // the first found wins among equally distant tags
+    let mut best: Option<(usize, &Name)> = None;
+    for (hash, name) in candidates {
+        let mut walk = RevWalk::from_tip(&odb, &target)?;
+        walk.hide(&hash)?;
+        let count = walk.hashes().count();
+        if best.is_none_or(|(best_count, _)| count < best_count) {
+            best = Some((count, name));
+        }
+    }
+    let Some((count, name)) = best else {
+        bail!("No tags can describe '{target}'.\nTry --always, or create some tags.");
+    };
+    Ok(format!("{}-{count}-g{}", name.tag, short_hash(&target)))
+}
+
+/// The best tag name of each tagged commit. Without `--tags` only annotated
+/// tags count.
+fn tag_names(options: &DescribeOptions) -> Result<HashMap<Sha1, Name>> {
+    let odb = Odb::open()?;
+    let mut names: HashMap<Sha1, Name> = HashMap::new();
+    let mut lightweight = false;
+    for (reference, hash) in refs::list("refs/tags/")? {
+        let tag = reference.trim_start_matches("refs/tags/").to_owned();
+        let name = match odb.read(&hash)?.parse()? {
+            ParsedObject::Tag(object) => Name {
+                tag,
+                annotated: true,
+                time: object
+                    .tagger
+                    .as_deref()
+                    .and_then(Identity::parse)
+                    .map_or(0, |tagger| tagger.timestamp),
+            },
+            _ if options.tags => Name {
+                tag,
+                annotated: false,
+                time: 0,
+            },
+            _ => {
+                lightweight = true;
+                continue;
+            }
+        };
+        // tags of trees and blobs describe nothing
+        let Ok(commit) = peel_to_commit(&hash) else {
+            continue;
+        };
+        let better = names
+            .get(&commit)
+            .is_none_or(|current| (name.annotated, name.time) > (current.annotated, current.time));
+        if better {
+            names.insert(commit, name);
+        }
+    }
+    if names.is_empty() && lightweight {
+        bail!("No annotated tags can describe anything.\nHowever, there were unannotated tags: try --tags.");
+    }
+    Ok(names)
+}
@@ -0,0 +1,446 @@
+use super::{
+    attributes::Conversion, flatten_tree, gitlink_head, head_files, index::Index,
+    is_unpopulated_submodule, parse_hash, revparse, worktree_blob, EntryKind, Object, ObjectId,
+    Odb, ParsedObject, TreeEntry, TreeFiles, DIRECTORY_MODE, GITLINK_MODE,
+};
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::io::{stdout, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Lines of unchanged context around each hunk.
+const CONTEXT: usize = 3;
+/// How much of a file is inspected for NUL bytes to tell binary files apart.
+const BINARY_PROBE: usize = 8000;
+const ABBREVIATION: usize = 7;
+
+/// A step of an edit script, holding line indexes into the old and new sides.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edit {
+    Equal { old: usize, new: usize },
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Computes a shortest edit script turning `a` into `b` with Myers' O(ND)
+/// algorithm. Only the diagonals reached in each round are kept for the
+/// backtracking pass.
+pub fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0; 2 * offset as usize + 1];
+    let mut trace: Vec<Vec<isize>> = vec![];
+    'search: for d in 0..=n + m {
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+
+    let (mut x, mut y) = (n, m);
+    let mut edits = vec![];
+    for d in (1..=trace.len() as isize).rev() {
+        // diagonals reached in the previous round, indexed from k = -(d - 1)
+        let previous = &trace[d as usize - 1];
+        let reached = |k: isize| previous[(k + d - 1) as usize];
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && reached(k - 1) < reached(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = reached(previous_k);
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal {
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+        if x == previous_x {
+            edits.push(Edit::Insert(previous_y as usize));
+        } else {
+            edits.push(Edit::Delete(previous_x as usize));
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        edits.push(Edit::Equal {
+            old: x as usize,
+            new: y as usize,
+        });
+    }
+    edits.reverse();
+    edits
+}
+
+/// Groups changes into hunks with `context` lines around them, merging hunks
+/// whose context would overlap. Returns ranges into `edits`.
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Range<usize>> {
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal { .. }))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let mut hunks: Vec<Range<usize>> = vec![];
+    for i in changes {
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(edits.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.end => hunk.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    hunks
+}
+
+/// Splits content into lines, each keeping its newline.
+pub fn lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Renders the hunks turning `old` into `new` in unified format.
+pub fn unified(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let (old, new) = (lines(old), lines(new));
+    let edits = myers(&old, &new);
+    let mut out = vec![];
+    for hunk in hunks(&edits, CONTEXT) {
+        // line counts before the hunk give its start positions
+        let (mut old_start, mut new_start) = (0, 0);
+        for edit in &edits[..hunk.start] {
+            match edit {
+                Edit::Equal { .. } => {
+                    old_start += 1;
+                    new_start += 1;
+                }
+                Edit::Delete(_) => old_start += 1,
+                Edit::Insert(_) => new_start += 1,
+            }
+        }
+        let edits = &edits[hunk];
+        let old_count = edits
+            .iter()
+            .filter(|e| !matches!(e, Edit::Insert(_)))
+            .count();
+        let new_count = edits
+            .iter()
+            .filter(|e| !matches!(e, Edit::Delete(_)))
+            .count();
+        out.extend(
+            format!(
+                "@@ -{} +{} @@\n",
+                hunk_range(old_start, old_count),
+                hunk_range(new_start, new_count)
+            )
+            .into_bytes(),
+        );
+        for edit in edits {
+            let (prefix, line) = match *edit {
+                Edit::Equal { old: i, .. } => (b' ', old[i]),
+                Edit::Delete(i) => (b'-', old[i]),
+                Edit::Insert(i) => (b'+', new[i]),
+            };
+            out.push(prefix);
+            out.extend(line);
+            if !line.ends_with(b"\n") {
+                out.extend(b"\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// `start,count` of a hunk, where an empty range starts at the line before it.
+fn hunk_range(lines_before: usize, count: usize) -> String {
+    match count {
+        0 => format!("{lines_before},0"),
+        1 => format!("{}", lines_before + 1),
+        _ => format!("{},{count}", lines_before + 1),
+    }
+}
+
+/// One side of a file diff.
+pub struct Version {
+    pub mode: u32,
+    pub hash: ObjectId,
+    pub content: Vec<u8>,
+}
+
+impl Version {
+    pub fn from_blob(odb: &Odb, mode: u32, hash: &ObjectId) -> Result<Self> {
+        if EntryKind::from_mode(mode) == EntryKind::Gitlink {
+            return Ok(Self::gitlink(*hash));
+        }
+        let ParsedObject::Blob(content) = odb.read(&hex::encode(hash))?.parse()? else {
+            bail!("{} is not a blob", hex::encode(hash));
+        };
+        Ok(Self {
+            mode,
+            hash: *hash,
+            content,
+        })
+    }
+
+    /// A submodule, shown in diffs as the commit it has checked out.
+    fn gitlink(hash: ObjectId) -> Self {
+        Self {
+            mode: GITLINK_MODE,
+            content: format!("Subproject commit {hash}\n").into_bytes(),
+            hash,
+        }
+    }
+
+    /// Reads a file from the working tree, if it exists, as it would be stored.
+    /// `recorded` is the mode it is tracked with, if any.
+    pub fn from_file(
+        path: &Path,
+        recorded: Option<u32>,
+        conversion: &mut Conversion,
+    ) -> Result<Option<Self>> {
+        if path.join(".git").exists() {
+            return Ok(gitlink_head(path)?.map(Self::gitlink));
+        }
+        Ok(
+            worktree_blob(path, recorded, conversion)?.map(|(mode, content)| Self {
+                mode,
+                hash: Object::new(b"blob", &content).hash(),
+                content,
+            }),
+        )
+    }
+}
+
+/// Renders the `diff --git` section for a path, `None` marking a missing side.
+pub fn file_diff(path: &str, old: Option<&Version>, new: Option<&Version>) -> Vec<u8> {
+    let mut header = format!("diff --git a/{path} b/{path}\n");
+    let abbreviate = |version: Option<&Version>| match version {
+        Some(version) => version.hash.to_string()[..ABBREVIATION].to_owned(),
+        None => "0".repeat(ABBREVIATION),
+    };
+    let (old_hash, new_hash) = (abbreviate(old), abbreviate(new));
+    match (old, new) {
+        (None, Some(new)) => header.push_str(&format!(
+            "new file mode {:o}\nindex {old_hash}..{new_hash}\n",
+            new.mode
+        )),
+        (Some(old), None) => header.push_str(&format!(
+            "deleted file mode {:o}\nindex {old_hash}..{new_hash}\n",
+            old.mode
+        )),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header.push_str(&format!(
+                "old mode {:o}\nnew mode {:o}\n",
+                old.mode, new.mode
+            ));
+            if old.hash != new.hash {
+                header.push_str(&format!("index {old_hash}..{new_hash}\n"));
+            }
+        }
+        (Some(old), Some(_)) => {
+            header.push_str(&format!("index {old_hash}..{new_hash} {:o}\n", old.mode))
+        }
+        (None, None) => {}
+    }
+    let old_content = old.map(|v| v.content.as_slice()).unwrap_or_default();
+    let new_content = new.map(|v| v.content.as_slice()).unwrap_or_default();
+    let old_name = old.map_or("/dev/null".to_owned(), |_| format!("a/{path}"));
+    let new_name = new.map_or("/dev/null".to_owned(), |_| format!("b/{path}"));
+    let mut out = header.into_bytes();
+    if old.map(|v| &v.hash) == new.map(|v| &v.hash) {
+        // a pure mode change has no content to show
+        return out;
+    }
+    if is_binary(old_content) || is_binary(new_content) {
+        out.extend(format!("Binary files {old_name} and {new_name} differ\n").into_bytes());
+        return out;
+    }
+    out.extend(format!("--- {old_name}\n+++ {new_name}\n").into_bytes());
+    out.extend(unified(old_content, new_content));
+    out
+}
+
+pub fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_PROBE)].contains(&0)
+}
+
+/// Prints the changes of tracked files in the working tree relative to a
+/// commit, or else to the index, or to HEAD when there is no index. Files
+/// added to the index but missing from the commit show up as new.
+pub fn print_worktree_diff(revision: Option<&str>) -> Result<()> {
+    let odb = Odb::open()?;
+    let (base, tracked): (TreeFiles, TreeFiles) = match revision {
+        Some(revision) => (
+            flatten_tree(
+                &odb,
+                &revparse::peel(&revparse::resolve(revision)?, "tree")?,
+            )?,
+            Index::read()?.files(),
+        ),
+        None if Index::exists() => (Index::read()?.files(), TreeFiles::new()),
+        None => (head_files(&odb)?, TreeFiles::new()),
+    };
+    let paths = base.keys().chain(tracked.keys()).collect::<BTreeSet<_>>();
+    // files left out by sparse checkout are not missing
+    let skipped = Index::read()?
+        .entries
+        .into_iter()
+        .filter(|e| e.skip_worktree)
+        .map(|e| PathBuf::from(e.path))
+        .collect::<BTreeSet<_>>();
+    let mut conversion = Conversion::new()?;
+    let mut out = stdout().lock();
+    for path in paths {
+        let old = base.get(path);
+        if skipped.contains(path)
+            || old.is_some_and(|(mode, _)| is_unpopulated_submodule(path, *mode))
+        {
+            continue;
+        }
+        let current = Version::from_file(path, old.map(|(mode, _)| *mode), &mut conversion)?;
+        if old.map(|(mode, hash)| (*mode, hash)) == current.as_ref().map(|c| (c.mode, &c.hash)) {
+            continue;
+        }
+        let old = old
+            .map(|(mode, hash)| Version::from_blob(&odb, *mode, hash))
+            .transpose()?;
+        let path = path.to_string_lossy();
+        out.write_all(&file_diff(&path, old.as_ref(), current.as_ref()))?;
+    }
+    Ok(())
+}
+
+/// A path whose entry differs between two trees, `None` marking absence.
+pub struct Change {
+    pub path: String,
+    pub old: Option<(u32, ObjectId)>,
+    pub new: Option<(u32, ObjectId)>,
+}
+
+/// Lists the files that differ between two trees. Both trees are walked side
+/// by side, and subtrees with the same hash are skipped without reading them.
+pub fn diff_trees(
+    odb: &Odb,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+) -> Result<Vec<Change>> {
+    let mut changes = vec![];
+    walk_trees(odb, old, new, "", &mut changes)?;
+    Ok(changes)
+}
+
+fn walk_trees(
+    odb: &Odb,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+    prefix: &str,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let (old, new) = (tree_entries(odb, old)?, tree_entries(odb, new)?);
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+    loop {
+        // entries are sorted by name, with directories compared as "name/"
+        let (old_entry, new_entry) = match (old.peek(), new.peek()) {
+            (None, None) => return Ok(()),
+            (Some(_), None) => (old.next(), None),
+            (None, Some(_)) => (None, new.next()),
+            (Some(a), Some(b)) => match sort_key(a).cmp(&sort_key(b)) {
+                Ordering::Less => (old.next(), None),
+                Ordering::Greater => (None, new.next()),
+                Ordering::Equal => (old.next(), new.next()),
+            },
+        };
+        let name = old_entry
+            .or(new_entry)
+            .map(|e| e.name.as_str())
+            .unwrap_or_default();
+        let path = format!("{prefix}{name}");
+        if let (Some(a), Some(b)) = (old_entry, new_entry) {
+            if a.mode == b.mode && a.hash == b.hash {
+                continue;
+            }
+        }
+        let is_tree = |entry: Option<&TreeEntry>| entry.is_some_and(|e| e.mode == DIRECTORY_MODE);
+        if is_tree(old_entry) || is_tree(new_entry) {
+            // directories only ever pair up with directories
+            walk_trees(
+                odb,
+                old_entry.map(|e| &e.hash),
+                new_entry.map(|e| &e.hash),
+                &format!("{path}/"),
+                changes,
+            )?;
+        } else {
+            changes.push(Change {
+                path,
+                old: old_entry.map(|e| (e.mode, e.hash)),
+                new: new_entry.map(|e| (e.mode, e.hash)),
+            });
+        }
+    }
+}
+
+fn tree_entries(odb: &Odb, tree: Option<&ObjectId>) -> Result<Vec<TreeEntry>> {
+    let Some(tree) = tree else {
+        return Ok(vec![]);
+    };
+    match odb.read(&hex::encode(tree))?.parse()? {
+        ParsedObject::Tree(entries) => Ok(entries),
+        _ => bail!("{} is not a tree", hex::encode(tree)),
+    }
+}
+
+fn sort_key(entry: &TreeEntry) -> Vec<u8> {
+    let mut key = entry.name.as_bytes().to_vec();
+    if entry.mode == DIRECTORY_MODE {
+        key.push(b'/');
+    }
+    key
+}
+
+/// Renders the differences between two trees as a patch.
+pub fn tree_patch(odb: &Odb, old: Option<&ObjectId>, new: Option<&ObjectId>) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    for change in diff_trees(odb, old, new)? {
+        let version = |side: &Option<(u32, ObjectId)>| {
+            side.as_ref()
+                .map(|(mode, hash)| Version::from_blob(odb, *mode, hash))
+                .transpose()
+        };
+        let (old, new) = (version(&change.old)?, version(&change.new)?);
+        out.extend(file_diff(&change.path, old.as_ref(), new.as_ref()));
+    }
+    Ok(out)
+}
+
+/// Prints the differences between two commits or trees.
+pub fn print_tree_diff(old: &str, new: &str) -> Result<()> {
+    let odb = Odb::open()?;
+    let tree = |revision: &str| -> Result<ObjectId> {
+        parse_hash(&revparse::peel(&revparse::resolve(revision)?, "tree")?)
+    };
+    let (old, new) = (tree(old)?, tree(new)?);
+    stdout().write_all(&tree_patch(&odb, Some(&old), Some(&new))?)?;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::io;
+use thiserror::Error as ThisError;
+
+/// Failures of the public API — `Repository`, `Odb` and refs — by category,
+/// for callers to match on. Everything else in the library returns
+/// `anyhow::Result`; an `Error` passed through it is recovered with
+/// `downcast_ref`.
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("Object {0} not found")]
+    ObjectNotFound(String),
+    #[error("Short object ID {prefix} is ambiguous, candidates are:\n  {}", candidates.join("\n  "))]
+    AmbiguousObject {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+    #[error("Corrupt object {hash}: {reason}")]
+    CorruptObject { hash: String, reason: String },
+    #[error("Unknown revision {0}")]
+    RefNotFound(String),
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Any other failure, with the context added along the way.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+/// The result of the public API.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn corrupt(hash: &[u8], error: anyhow::Error) -> Self {
+        Self::CorruptObject {
+            hash: hex::encode(hash),
+            reason: format!("{error:#}"),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    /// Keeps the category of an `Error` or I/O error raised further down,
+    /// unless context was added to it.
+    fn from(error: anyhow::Error) -> Self {
+        if error.chain().count() > 1 {
+            return Self::Other(error);
+        }
+        match error.downcast::<Self>() {
+            Ok(error) => error,
+            Err(error) => match error.downcast::<io::Error>() {
+                Ok(error) => Self::Io(error),
+                Err(error) => Self::Other(error),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn categories_survive_anyhow() {
+        let error = Error::from(anyhow::Error::from(Error::RefNotFound("x".to_owned())));
+        assert!(matches!(error, Error::RefNotFound(name) if name == "x"));
+        let io_error = io::Error::new(io::ErrorKind::NotFound, "gone");
+        assert!(matches!(
+            Error::from(anyhow::Error::from(io_error)),
+            Error::Io(_)
+        ));
+        assert!(matches!(Error::from(anyhow!("plain")), Error::Other(_)));
+    }
+
+    #[test]
+    fn context_is_kept() {
+        let error = Err::<(), _>(Error::ObjectNotFound("abcd".to_owned()))
+            .context("reading HEAD")
+            .unwrap_err();
+        let error = Error::from(error);
+        assert!(matches!(error, Error::Other(_)));
+        assert_eq!(
+            format!("{:#}", anyhow::Error::from(error)),
+            "reading HEAD: Object abcd not found"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use super::{
+    bundle::Bundle,
+    clone,
+    config::Config,
+    lockfile::LockFile,
+    pack, refs,
+    refspec::Refspec,
+    remote::{self, Advertisement, Reference, Sha1},
+    repository,
+    revwalk::{self, RevWalk},
+    short_hash, Odb,
+};
+use anyhow::{bail, Result};
+use reqwest::Url;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FETCH_HEAD: &str = "FETCH_HEAD";
+/// Number of local commits offered to the server during negotiation.
+const MAX_HAVES: usize = 256;
+
+/// A remote ref selected by a refspec together with its local destination.
+struct Update {
+    source: String,
+    destination: String,
+    hash: Sha1,
+    force: bool,
+}
+
+/// Where a fetch gets refs and objects from.
+enum Source {
+    Remote(Url, Advertisement),
+    Bundle(Bundle),
+    /// A repository on disk, by its git directory.
+    Local(PathBuf),
+}
+
+/// Downloads what is missing for the refs of `remote_name` and updates the
+/// matching remote-tracking refs and `FETCH_HEAD`, leaving the worktree alone.
+/// The remote may be a bundle file or a repository on disk, by path or
+/// `file://` URL.
+pub fn fetch(remote_name: &str) -> Result<()> {
+    remote::check_object_format()?;
+    let config = Config::load()?;
+    let mut refspecs = config
+        .get_all(&format!("remote.{remote_name}.fetch"))
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<Result<Vec<_>>>()?;
+    if refspecs.is_empty() {
+        refspecs.push(Refspec::parse(&format!(
+            "+refs/heads/*:refs/remotes/{remote_name}/*"
+        ))?);
+    }
+    let location = config
+        .get(&format!("remote.{remote_name}.url"))
+        .unwrap_or(remote_name);
+    let mut source = match Bundle::open(Path::new(location))? {
+        Some(bundle) => Source::Bundle(bundle),
+        None => match clone::local_repository(location)? {
+            Some(git_dir) => Source::Local(git_dir),
+            None => {
+                let git_url = remote::remote_url(remote_name)?;
+                let advertisement = remote::discover_references(&git_url)?;
+                Source::Remote(git_url, advertisement)
+            }
+        },
+    };
+    let references = match &source {
+        Source::Remote(_, advertisement) => advertisement.refs.clone(),
+        Source::Bundle(bundle) => bundle.references.clone(),
+        Source::Local(git_dir) => clone::local_references(git_dir)?,
+    };
+    let mut updates = vec![];
+    for (hash, name) in &references {
+        if let Some((refspec, destination)) = refspecs
+            .iter()
+            .find_map(|refspec| Some((refspec, refspec.map(name)?)))
+        {
+            updates.push(Update {
+                source: name.clone(),
+                destination,
+                hash: hash.clone(),
+                force: refspec.force,
+            });
+        }
+    }
+    let tags = references
+        .iter()
+        .filter(|(_, name)| name.starts_with("refs/tags/"))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let odb = Odb::open()?;
+    let mut wants = vec![];
+    for update in &updates {
+        if !odb.exists(&update.hash)? {
+            wants.push((update.hash.clone(), update.source.clone()));
+        }
+    }
+    if !wants.is_empty() {
+        match &mut source {
+            Source::Remote(git_url, advertisement) => {
+                advertisement.refs = wants;
+                let options = remote::FetchOptions {
+                    haves: local_haves()?,
+                    shallow: revwalk::read_shallow().into_iter().collect(),
+                    ..Default::default()
+                };
+                let fetched = remote::fetch_pack(git_url, advertisement, &options)?;
+                pack::index_pack(fetched.pack)?;
+            }
+            Source::Bundle(bundle) => bundle.unpack()?,
+            Source::Local(git_dir) => {
+                clone::link_objects(&git_dir.join("objects"), &repository::path("objects"))?
+            }
+        }
+    }
+
+    let url = match &source {
+        Source::Remote(git_url, _) => git_url.as_str().trim_end_matches('/'),
+        Source::Bundle(_) | Source::Local(_) => location,
+    };
+    println!("From {url}");
+    let mut rejected = false;
+    for update in &updates {
+        rejected |= !update_ref(&odb, update)?;
+    }
+    follow_tags(&tags)?;
+    write_fetch_head(remote_name, url, &updates)?;
+    if rejected {
+        bail!("Some refs could not be updated");
+    }
+    Ok(())
+}
+
+/// Commits of local branches and remote-tracking refs, newest first.
+fn local_haves() -> Result<Vec<Sha1>> {
+    let odb = Odb::open()?;
+    let mut walk = RevWalk::new(&odb);
+    for prefix in ["refs/heads/", "refs/remotes/"] {
+        for (_, hash) in refs::list(prefix)? {
+            walk.push(&hash)?;
+        }
+    }
+    walk.take(MAX_HAVES)
+        .map(|entry| entry.map(|(hash, _)| hash))
+        .collect()
+}
+
+/// Moves a remote-tracking ref, refusing non-fast-forward updates unless
+/// forced. Returns whether the ref is up to date afterwards.
+fn update_ref(odb: &Odb, update: &Update) -> Result<bool> {
+    let source = short_name(&update.source);
+    let destination = short_name(&update.destination);
+    let old = refs::peel(&update.destination)?;
+    let (summary, message) = match &old {
+        Some(old) if *old == update.hash => return Ok(true),
+        None => ("* [new branch]".to_owned(), "storing head"),
+        Some(old) if revwalk::is_ancestor(odb, old, &update.hash)? => (
+            format!("  {}..{}", short_hash(old), short_hash(&update.hash)),
+            "fast-forward",
+        ),
+        Some(_) if !update.force => {
+            report("! [rejected]", source, destination, " (non-fast-forward)");
+            return Ok(false);
+        }
+        Some(old) => (
+            format!("+ {}...{}", short_hash(old), short_hash(&update.hash)),
+            "forced-update",
+        ),
+    };
+    refs::update(
+        &update.destination,
+        &update.hash,
+        &format!("fetch: {message}"),
+    )?;
+    report(&summary, source, destination, "");
+    Ok(true)
+}
+
+/// Stores advertised tags that are new locally and whose objects were fetched.
+fn follow_tags(tags: &[Reference]) -> Result<()> {
+    let odb = Odb::open()?;
+    for (hash, name) in tags {
+        if refs::peel(name)?.is_none() && odb.exists(hash)? {
+            refs::write(name, hash)?;
+            let name = short_name(name);
+            report("* [new tag]", name, name, "");
+        }
+    }
+    Ok(())
+}
+
+/// Writes `FETCH_HEAD`, marking for merge the upstream of the current branch
+/// (from `branch.<name>.merge`, or the branch of the same name).
+fn write_fetch_head(remote_name: &str, url: &str, updates: &[Update]) -> Result<()> {
+    let config = Config::load()?;
+    let merge = refs::current_branch()?.and_then(|branch| {
+        match config.get(&format!("branch.{branch}.merge")) {
+            Some(merge) => (config.get(&format!("branch.{branch}.remote")) == Some(remote_name))
+                .then(|| merge.to_owned()),
+            None => Some(format!("refs/heads/{branch}")),
+        }
+    });
+    // merge candidates come first, as `pull` reads them from the top
+    let (mut merged, not_merged): (Vec<_>, Vec<_>) = updates
+        .iter()
+        .partition(|update| merge.as_deref() == Some(update.source.as_str()));
+    merged.extend(not_merged);
+    let mut content = String::new();
+    for update in merged {
+        let kind = if update.source.starts_with("refs/tags/") {
+            "tag"
+        } else {
+            "branch"
+        };
+        let marker = if merge.as_deref() == Some(update.source.as_str()) {
+            ""
+        } else {
+            "not-for-merge"
+        };
+        content.push_str(&format!(
+            "{}\t{marker}\t{kind} '{}' of {url}\n",
+            update.hash,
+            short_name(&update.source)
+        ));
+    }
+    let mut lock = LockFile::acquire(&repository::path(FETCH_HEAD))?;
+    lock.write(content.as_bytes())?;
+    lock.commit()
+}
+
+/// Returns the first commit `FETCH_HEAD` marks for merge, if any.
+pub fn merge_head() -> Result<Option<Sha1>> {
+    if !repository::path(FETCH_HEAD).is_file() {
+        return Ok(None);
+    }
+    Ok(fs::read_to_string(repository::path(FETCH_HEAD))?
+        .lines()
+        .find_map(|line| match line.split('\t').collect::<Vec<_>>()[..] {
+            [hash, "", ..] => Some(hash.to_owned()),
+            _ => None,
+        }))
+}
+
+fn report(summary: &str, source: &str, destination: &str, note: &str) {
+    println!(" {summary:<17} {source:<10} -> {destination}{note}");
+}
+
+fn short_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
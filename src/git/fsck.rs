@@ -0,0 +1,243 @@
+use super::{
+    hash::HashAlgo,
+    index::Index,
+    parse_hash, reflog,
+    refs::{self, Target},
+    remote::Sha1,
+    revwalk, split_identity, worktree, EntryKind, Object, Odb, ParsedObject, DIRECTORY_MODE,
+    GITLINK_MODE, SYMLINK_MODE,
+};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashSet};
+
+const FILE_MODES: &[u32] = &[
+    0o100644,
+    0o100755,
+    SYMLINK_MODE,
+    DIRECTORY_MODE,
+    GITLINK_MODE,
+];
+
+/// A stored object that could be read and checked.
+struct Node {
+    kind: String,
+    /// Objects it points at with the type they should have.
+    links: Vec<(Sha1, &'static str)>,
+}
+
+/// The objects everything else is reached from: refs, the HEAD and index of
+/// every worktree and the reflogs, which keep old commits alive.
+pub fn roots() -> Result<Vec<Sha1>> {
+    let mut roots = refs::list("refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect::<Vec<_>>();
+    for git_dir in worktree::git_dirs()? {
+        match worktree::read_head(&git_dir)? {
+            Some(Target::Direct(hash)) => roots.push(hash),
+            Some(Target::Symbolic(name)) => roots.extend(refs::peel(&name)?),
+            None => {}
+        }
+        if let Ok(data) = std::fs::read(git_dir.join("index")) {
+            let index = Index::parse(&data)?;
+            roots.extend(
+                index
+                    .entries
+                    .iter()
+                    // submodule commits live in another repository
+                    .filter(|e| EntryKind::from_mode(e.mode) != EntryKind::Gitlink)
+                    .map(|e| e.hash.to_string()),
+            );
+        }
+    }
+    let null = HashAlgo::current().null_hex();
+    for name in reflog::list()? {
+        for entry in reflog::read(&name)? {
+            roots.extend([entry.old, entry.new].into_iter().filter(|h| *h != null));
+        }
+    }
+    roots.sort();
+    roots.dedup();
+    Ok(roots)
+}
+
+/// Every object reachable from the roots, with the path it was reached
+/// through as `revwalk::reachable_objects` gives it. Roots whose object is
+/// gone, like commits of expired reflog entries, are skipped.
+pub fn reachable(odb: &Odb) -> Result<Vec<(Sha1, String)>> {
+    let mut existing = vec![];
+    for root in roots()? {
+        if odb.exists(&root)? {
+            existing.push(root);
+        }
+    }
+    revwalk::reachable_objects(odb, &existing, &[])
+}
+
+/// Re-hashes and validates every object, then reports objects that are
+/// missing from the history reachable from the roots and unreachable ones
+/// nothing points at.
+pub fn fsck() -> Result<()> {
+    let odb = Odb::open()?;
+    let mut errors = 0;
+    let mut nodes = BTreeMap::new();
+    let stored = odb.list()?;
+    for hash in &stored {
+        let object = match odb.read(hash) {
+            Ok(object) => object,
+            Err(error) => {
+                eprintln!("error: {hash}: object corrupt or missing: {error:#}");
+                errors += 1;
+                continue;
+            }
+        };
+        let actual = hex::encode(object.hash());
+        if &actual != hash {
+            eprintln!("error: {actual}: hash-path mismatch, found at: {hash}");
+            errors += 1;
+            continue;
+        }
+        let kind = String::from_utf8_lossy(object.kind()?).into_owned();
+        match check(&object) {
+            Ok(links) => {
+                nodes.insert(hash.clone(), Node { kind, links });
+            }
+            Err(error) => {
+                eprintln!("error in {kind} {hash}: {error:#}");
+                errors += 1;
+            }
+        }
+    }
+
+    let shallow = revwalk::read_shallow();
+    let mut reachable = HashSet::new();
+    let mut stack = roots()?
+        .into_iter()
+        .map(|hash| (hash, "object"))
+        .collect::<Vec<_>>();
+    while let Some((hash, kind)) = stack.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+        match nodes.get(&hash) {
+            Some(node) => stack.extend(
+                node.links
+                    .iter()
+                    .filter(|(_, kind)| *kind != "commit" || !shallow.contains(&hash))
+                    .cloned(),
+            ),
+            None if stored.binary_search(&hash).is_ok() => {} // reported as corrupt already
+            None => {
+                println!("missing {kind} {hash}");
+                errors += 1;
+            }
+        }
+    }
+
+    let referenced = nodes
+        .values()
+        .flat_map(|node| node.links.iter().map(|(hash, _)| hash))
+        .collect::<HashSet<_>>();
+    for (hash, node) in &nodes {
+        if !reachable.contains(hash) && !referenced.contains(hash) {
+            println!("dangling {} {hash}", node.kind);
+        }
+    }
+    if errors > 0 {
+        bail!("fsck found {errors} problem(s)");
+    }
+    Ok(())
+}
+
+/// Validates the syntax of an object and returns what it points at.
+fn check(object: &Object) -> Result<Vec<(Sha1, &'static str)>> {
+    let parsed = object.parse()?;
+    match &parsed {
+        ParsedObject::Commit(commit) => {
+            for hash in std::iter::once(&commit.tree).chain(&commit.parents) {
+                parse_hash(hash)?;
+            }
+            check_identity("author", &commit.author.to_string())?;
+            check_identity("committer", &commit.committer.to_string())?;
+        }
+        ParsedObject::Tag(tag) => {
+            parse_hash(&tag.object)?;
+            if !["blob", "commit", "tag", "tree"].contains(&tag.kind.as_str()) {
+                bail!("badType: invalid 'type' value '{}'", tag.kind);
+            }
+            if let Some(tagger) = &tag.tagger {
+                check_identity("tagger", tagger)?;
+            }
+        }
+        ParsedObject::Tree(entries) => {
+            let mut previous: Option<String> = None;
+            for entry in entries {
+                if !FILE_MODES.contains(&entry.mode) {
+                    bail!("badFilemode: contains bad file modes");
+                }
+                if entry.name.is_empty() || entry.name.contains('/') {
+                    bail!("badTree: contains an invalid entry name '{}'", entry.name);
+                }
+                if [".", "..", ".git"].contains(&entry.name.to_ascii_lowercase().as_str()) {
+                    bail!("hasDot: contains '{}'", entry.name);
+                }
+                // directories sort as if their name ended with a slash
+                let key = match entry.kind() {
+                    EntryKind::Tree => format!("{}/", entry.name),
+                    _ => entry.name.clone(),
+                };
+                if let Some(previous) = &previous {
+                    let bare = |name: &str| name.trim_end_matches('/').to_owned();
+                    if bare(previous) == entry.name {
+                        bail!("duplicateEntries: contains duplicate file entries");
+                    }
+                    if previous.as_str() > key.as_str() {
+                        bail!("treeNotSorted: not properly sorted");
+                    }
+                }
+                previous = Some(key);
+            }
+        }
+        ParsedObject::Blob(_) => {}
+    }
+    Ok(links(&parsed))
+}
+
+fn check_identity(header: &str, identity: &str) -> Result<()> {
+    let valid = split_identity(identity).is_some_and(|(name, _, timezone)| {
+        name.ends_with('>') && name.contains('<') && timezone.len() == 5
+    });
+    if !valid {
+        bail!("invalid {header} line '{identity}'");
+    }
+    Ok(())
+}
+
+/// The objects a parsed object points at, with the type each should have.
+/// Submodule commits are left out as they belong to another repository.
+fn links(parsed: &ParsedObject) -> Vec<(Sha1, &'static str)> {
+    match parsed {
+        ParsedObject::Commit(commit) => std::iter::once((commit.tree.clone(), "tree"))
+            .chain(commit.parents.iter().map(|p| (p.clone(), "commit")))
+            .collect(),
+        ParsedObject::Tag(tag) => {
+            let kind = match tag.kind.as_str() {
+                "blob" => "blob",
+                "commit" => "commit",
+                "tree" => "tree",
+                "tag" => "tag",
+                _ => "object",
+            };
+            vec![(tag.object.clone(), kind)]
+        }
+        ParsedObject::Tree(entries) => entries
+            .iter()
+            .filter_map(|entry| match entry.kind() {
+                EntryKind::Blob => Some((entry.hash.to_string(), "blob")),
+                EntryKind::Tree => Some((entry.hash.to_string(), "tree")),
+                EntryKind::Gitlink => None,
+            })
+            .collect(),
+        ParsedObject::Blob(_) => vec![],
+    }
+}
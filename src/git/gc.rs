@@ -0,0 +1,94 @@
+use super::{
+    commit_graph,
+    config::Config,
+    date, fsck, midx,
+    pack::{self, Packs},
+    prune, reflog, repository, revwalk, Backend, Odb,
+};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs;
+
+const PRUNE_EXPIRE: &str = "2.weeks.ago";
+const REFLOG_EXPIRE: &str = "90.days.ago";
+const REFLOG_EXPIRE_UNREACHABLE: &str = "30.days.ago";
+
+/// Expires old reflog entries, packs every reachable object into a single
+/// pack and deletes unreachable loose objects older than `gc.pruneExpire`.
+pub fn gc() -> Result<()> {
+    let config = Config::load()?;
+    let expiry = |key: &str, default: &str| date::parse_expiry(config.get(key).unwrap_or(default));
+    let expire = expiry("gc.reflogExpire", REFLOG_EXPIRE)?;
+    let expire_unreachable = expiry("gc.reflogExpireUnreachable", REFLOG_EXPIRE_UNREACHABLE)?;
+    for name in reflog::list()? {
+        reflog::expire(&name, expire, expire_unreachable)?;
+    }
+    let reachable = repack()?;
+    if config.get_bool("gc.writeCommitGraph").unwrap_or(true) && revwalk::read_shallow().is_empty()
+    {
+        commit_graph::write()?;
+    }
+    if let Some(expire) = expiry("gc.pruneExpire", PRUNE_EXPIRE)? {
+        for hash in prune::unreachable_loose(&reachable, expire)? {
+            prune::remove_loose(&hash)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces all packs with one holding the reachable objects and removes the
+/// loose copies of them. Unreachable objects of the old packs are kept as
+/// loose objects, leaving it to pruning to decide whether they are old enough
+/// to go. Returns the reachable objects.
+fn repack() -> Result<HashSet<String>> {
+    let odb = Odb::open()?;
+    let objects = fsck::reachable(&odb)?;
+    let reachable = objects
+        .iter()
+        .map(|(hash, _)| hash.clone())
+        .collect::<HashSet<_>>();
+
+    let pack_directory = repository::path("objects/pack");
+    for hash in Packs::open(&pack_directory)?.list()? {
+        if !reachable.contains(&hash) {
+            let object = odb.read(&hash)?;
+            odb.write(object.kind()?, &object.content)?;
+        }
+    }
+    let name = if objects.is_empty() {
+        None
+    } else {
+        let objects = objects
+            .into_iter()
+            .map(|(hash, path)| Ok((odb.read(&hash)?, path)))
+            .collect::<Result<Vec<_>>>()?;
+        Some(pack::index_pack(pack::write_pack(&objects)?)?)
+    };
+    if pack_directory.is_dir() {
+        let current = name.map(|name| format!("pack-{name}"));
+        for entry in pack_directory.read_dir()? {
+            let path = entry?.path();
+            let is_pack = path
+                .extension()
+                .is_some_and(|extension| extension == "pack" || extension == "idx");
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned());
+            if is_pack && stem != current {
+                fs::remove_file(path)?;
+            }
+        }
+        // it indexed the packs just removed
+        let multi_pack_index = pack_directory.join(midx::MULTI_PACK_INDEX);
+        if multi_pack_index.is_file() {
+            fs::remove_file(multi_pack_index)?;
+        }
+    }
+
+    for hash in odb.list_loose()? {
+        if reachable.contains(&hash) {
+            prune::remove_loose(&hash)?;
+        }
+    }
+    Ok(reachable)
+}
@@ -0,0 +1,205 @@
+use super::{diff, flatten_tree, index::Index, revparse, ObjectId, Odb, ParsedObject};
+use anyhow::{bail, Result};
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+
+#[derive(Default)]
+pub struct GrepOptions {
+    /// Prefix matches with their line number (`-n`).
+    pub line_numbers: bool,
+    /// Print only the names of files with matches (`-l`).
+    pub files_with_matches: bool,
+}
+
+/// Searches the staged files, or those of `tree_ish`, for lines matching a
+/// basic regular expression. Returns whether anything matched.
+pub fn grep(pattern: &str, tree_ish: Option<&str>, options: &GrepOptions) -> Result<bool> {
+    let pattern = Pattern::parse(pattern)?;
+    let odb = Odb::open()?;
+    // blobs to search, named as git prints them
+    let files: Vec<(String, ObjectId)> = match tree_ish {
+        Some(tree_ish) => {
+            let tree = revparse::peel(&revparse::resolve(tree_ish)?, "tree")?;
+            flatten_tree(&odb, &tree)?
+                .into_iter()
+                .filter(|(_, (mode, _))| is_regular(*mode))
+                .map(|(path, (_, hash))| (format!("{tree_ish}:{}", path.display()), hash))
+                .collect()
+        }
+        None => Index::read()?
+            .entries
+            .into_iter()
+            .filter(|e| e.stage == 0 && is_regular(e.mode))
+            .map(|e| (PathBuf::from(e.path).display().to_string(), e.hash))
+            .collect(),
+    };
+    let mut out = stdout().lock();
+    let mut matched = false;
+    for (name, hash) in files {
+        let ParsedObject::Blob(content) = odb.read(&hash.to_string())?.parse()? else {
+            bail!("{hash} is not a blob");
+        };
+        let mut lines = diff::lines(&content)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, line)| pattern.is_match(line.strip_suffix(b"\n").unwrap_or(line)))
+            .peekable();
+        if lines.peek().is_none() {
+            continue;
+        }
+        matched = true;
+        if options.files_with_matches {
+            writeln!(out, "{name}")?;
+        } else if diff::is_binary(&content) {
+            writeln!(out, "Binary file {name} matches")?;
+        } else {
+            for (number, line) in lines {
+                write!(out, "{name}:")?;
+                if options.line_numbers {
+                    write!(out, "{}:", number + 1)?;
+                }
+                out.write_all(line)?;
+                if !line.ends_with(b"\n") {
+                    writeln!(out)?;
+                }
+            }
+        }
+    }
+    Ok(matched)
+}
+
+/// Whether a mode is that of a regular file, leaving out symlinks and
+/// submodules.
+fn is_regular(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+enum Atom {
+    Byte(u8),
+    Any,
+    /// A bracket expression: byte ranges, possibly negated.
+    Class(bool, Vec<(u8, u8)>),
+}
+
+impl Atom {
+    fn matches(&self, byte: u8) -> bool {
+        match self {
+            Self::Byte(expected) => byte == *expected,
+            Self::Any => true,
+            Self::Class(negated, ranges) => {
+                ranges
+                    .iter()
+                    .any(|(low, high)| (*low..=*high).contains(&byte))
+                    != *negated
+            }
+        }
+    }
+}
+
+/// A POSIX basic regular expression of bytes, `.`, bracket expressions,
+/// `*`, the `^` and `$` anchors and backslash escapes.
+struct Pattern {
+    /// Atoms with whether each may repeat.
+    atoms: Vec<(Atom, bool)>,
+    start_anchored: bool,
+    end_anchored: bool,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        let mut bytes = pattern.as_bytes();
+        let start_anchored = bytes.first() == Some(&b'^');
+        if start_anchored {
+            bytes = &bytes[1..];
+        }
+        let end_anchored = bytes.last() == Some(&b'$') && !bytes.ends_with(b"\\$");
+        if end_anchored {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+        let mut atoms: Vec<(Atom, bool)> = vec![];
+        let mut i = 0;
+        while i < bytes.len() {
+            let atom = match bytes[i] {
+                // a leading star has nothing to repeat and is literal
+                b'*' if !atoms.is_empty() => {
+                    atoms.last_mut().unwrap().1 = true;
+                    i += 1;
+                    continue;
+                }
+                b'.' => Atom::Any,
+                b'\\' => {
+                    i += 1;
+                    match bytes.get(i) {
+                        Some(&byte) => Atom::Byte(byte),
+                        None => bail!("Trailing backslash in pattern '{pattern}'"),
+                    }
+                }
+                b'[' => {
+                    let (atom, end) = parse_class(bytes, i + 1)
+                        .ok_or_else(|| anyhow::anyhow!("Unmatched [ in pattern '{pattern}'"))?;
+                    i = end;
+                    atom
+                }
+                byte => Atom::Byte(byte),
+            };
+            atoms.push((atom, false));
+            i += 1;
+        }
+        Ok(Self {
+            atoms,
+            start_anchored,
+            end_anchored,
+        })
+    }
+
+    fn is_match(&self, line: &[u8]) -> bool {
+        if self.start_anchored {
+            return self.match_here(&self.atoms, line);
+        }
+        (0..=line.len()).any(|start| self.match_here(&self.atoms, &line[start..]))
+    }
+
+    fn match_here(&self, atoms: &[(Atom, bool)], text: &[u8]) -> bool {
+        match atoms.split_first() {
+            None => !self.end_anchored || text.is_empty(),
+            Some(((atom, true), rest)) => {
+                let count = text.iter().take_while(|&&byte| atom.matches(byte)).count();
+                (0..=count)
+                    .rev()
+                    .any(|taken| self.match_here(rest, &text[taken..]))
+            }
+            Some(((atom, false), rest)) => {
+                text.first().is_some_and(|&byte| atom.matches(byte))
+                    && self.match_here(rest, &text[1..])
+            }
+        }
+    }
+}
+
+/// Parses a bracket expression starting after its `[`, returning it and the
+/// index of its closing `]`.
+fn parse_class(bytes: &[u8], mut i: usize) -> Option<(Atom, usize)> {
+    let negated = bytes.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+    let mut ranges = vec![];
+    // a `]` right after the opening bracket is a member
+    let first = i;
+    loop {
+        let byte = *bytes.get(i)?;
+        if byte == b']' && i != first {
+            return Some((Atom::Class(negated, ranges), i));
+        }
+        match (bytes.get(i + 1), bytes.get(i + 2)) {
+            (Some(b'-'), Some(&high)) if high != b']' => {
+                ranges.push((byte, high));
+                i += 3;
+            }
+            _ => {
+                ranges.push((byte, byte));
+                i += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use super::{config::Config, repository};
+use anyhow::{anyhow, bail, Result};
+use sha1::{Digest, Sha1};
+use std::env;
+use std::fmt;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// The hash function naming the objects of a repository, chosen at `init`
+/// and recorded as `extensions.objectFormat`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum HashAlgo {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+/// The algorithm of the repository in the git directory it was read for.
+static CURRENT: Mutex<Option<(PathBuf, HashAlgo)>> = Mutex::new(None);
+
+impl HashAlgo {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(Self::Sha1),
+            "sha256" => Ok(Self::Sha256),
+            _ => bail!("Unknown hash algorithm '{name}'"),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// The hash function of the current repository. It is read once per
+    /// git directory, as commands like `clone` move into other ones.
+    pub fn current() -> Self {
+        let directory = env::current_dir()
+            .unwrap_or_default()
+            .join(repository::git_dir());
+        let mut current = CURRENT.lock().unwrap();
+        match &*current {
+            Some((cached, algo)) if *cached == directory => *algo,
+            _ => {
+                let algo = Self::configured().unwrap_or_default();
+                *current = Some((directory, algo));
+                algo
+            }
+        }
+    }
+
+    fn configured() -> Result<Self> {
+        let config = Config::local()?;
+        match config.get("extensions.objectFormat") {
+            Some(name) => Self::from_name(name),
+            None => Ok(Self::Sha1),
+        }
+    }
+
+    /// Size of a raw hash in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+        }
+    }
+
+    pub fn hex_size(self) -> usize {
+        self.size() * 2
+    }
+
+    /// The hash version stored in commit-graph and multi-pack-index headers.
+    pub fn format_version(self) -> u8 {
+        match self {
+            Self::Sha1 => 1,
+            Self::Sha256 => 2,
+        }
+    }
+
+    /// The all-zero hash standing for "no object", in hex.
+    pub fn null_hex(self) -> String {
+        "0".repeat(self.hex_size())
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::default()),
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> ObjectId {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+const MAX_HASH_SIZE: usize = 32;
+
+/// The name of an object, the raw hash of its header and content. Its
+/// storage fits the largest hash so that it can be `Copy`; SHA-1 hashes use
+/// the first 20 bytes.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ObjectId {
+    bytes: [u8; MAX_HASH_SIZE],
+    size: u8,
+}
+
+impl ObjectId {
+    /// Takes a raw hash of either algorithm.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != HashAlgo::Sha1.size() && bytes.len() != HashAlgo::Sha256.size() {
+            bail!("Invalid hash size {}", bytes.len());
+        }
+        Ok(Self::copy_from(bytes))
+    }
+
+    fn copy_from(bytes: &[u8]) -> Self {
+        let mut id = Self {
+            bytes: [0; MAX_HASH_SIZE],
+            size: bytes.len() as u8,
+        };
+        id.bytes[..bytes.len()].copy_from_slice(bytes);
+        id
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.size as usize]
+    }
+}
+
+impl Deref for ObjectId {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for ObjectId {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = anyhow::Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        let bytes = hex::decode(hex).map_err(|_| anyhow!("Invalid hash {hex}"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.as_bytes()))
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({self})")
+    }
+}
+
+/// An incremental hash computation with either algorithm.
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> ObjectId {
+        match self {
+            Self::Sha1(hasher) => ObjectId::copy_from(&hasher.finalize()),
+            Self::Sha256(hasher) => hasher.finalize(),
+        }
+    }
+}
+
+const SHA256_INITIAL: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+const SHA256_ROUNDS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+const SHA256_BLOCK_SIZE: usize = 64;
+
+/// SHA-256 as specified in FIPS 180-4.
+pub struct Sha256 {
+    state: [u32; 8],
+    /// Input not yet making up a full block.
+    pending: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            state: SHA256_INITIAL,
+            pending: Vec::with_capacity(SHA256_BLOCK_SIZE),
+            length: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.pending.is_empty() {
+            let taken = data.len().min(SHA256_BLOCK_SIZE - self.pending.len());
+            self.pending.extend_from_slice(&data[..taken]);
+            data = &data[taken..];
+            if self.pending.len() < SHA256_BLOCK_SIZE {
+                return;
+            }
+            let block = std::mem::take(&mut self.pending);
+            self.compress(&block);
+        }
+        let mut blocks = data.chunks_exact(SHA256_BLOCK_SIZE);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finalize(mut self) -> ObjectId {
+        let bits = self.length * 8;
+        let mut padding = vec![0x80];
+        let padded = (self.pending.len() + 1 + 8).next_multiple_of(SHA256_BLOCK_SIZE);
+        padding.resize(padded - self.pending.len() - 8, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        self.length = 0;
+        self.update(&padding);
+        let digest = self.state.map(u32::to_be_bytes);
+        ObjectId::copy_from(digest.as_flattened())
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut schedule = [0u32; 64];
+        for (word, bytes) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let (w15, w2) = (schedule[i - 15], schedule[i - 2]);
+            let s0 = w15.rotate_right(7) ^ w15.rotate_right(18) ^ (w15 >> 3);
+            let s1 = w2.rotate_right(17) ^ w2.rotate_right(19) ^ (w2 >> 10);
+            schedule[i] = schedule[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (round, word) in SHA256_ROUNDS.iter().zip(schedule) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(*round)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
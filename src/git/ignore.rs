@@ -0,0 +1,453 @@
+use super::{config::Config, index::Index, repository};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const IGNORE_FILE: &str = ".gitignore";
+const INFO_EXCLUDE: &str = "info/exclude";
+
+/// One line of an ignore file.
+struct Pattern {
+    /// The pattern as written, for reporting which one matched.
+    text: String,
+    /// Where the pattern was read from, as a file name and line number.
+    source: String,
+    line: usize,
+    glob: String,
+    negated: bool,
+    directory_only: bool,
+    /// Matched against the whole path below `base` rather than the file name.
+    anchored: bool,
+    /// Directory of the ignore file, `""` or ending with a slash.
+    base: String,
+}
+
+impl Pattern {
+    fn parse(line: &str, base: &str, source: &str, number: usize) -> Option<Self> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let text = line.to_owned();
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // a leading backslash escapes "#" and "!"
+        let line = line
+            .strip_prefix("\\#")
+            .map(|rest| format!("#{rest}"))
+            .or_else(|| line.strip_prefix("\\!").map(|rest| format!("!{rest}")))
+            .unwrap_or_else(|| line.to_owned());
+        let (directory_only, glob) = match line.strip_suffix('/') {
+            Some(glob) => (true, glob),
+            None => (false, line.as_str()),
+        };
+        if glob.is_empty() {
+            return None;
+        }
+        Some(Self {
+            text,
+            source: source.to_owned(),
+            line: number,
+            anchored: glob.contains('/'),
+            glob: glob.strip_prefix('/').unwrap_or(glob).to_owned(),
+            negated,
+            directory_only,
+            base: base.to_owned(),
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        let Some(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// Trailing spaces are dropped unless escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+/// Matches a path against a gitignore glob: `*` and `?` stop at slashes,
+/// `**` crosses them, and `[...]` is a character class.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) if rest.first() == Some(&b'*') => {
+            let rest = &rest[1..];
+            match rest.strip_prefix(b"/") {
+                // "**/" matches zero or more whole directories
+                Some(after) => {
+                    wildmatch(after, text)
+                        || text
+                            .iter()
+                            .enumerate()
+                            .any(|(i, &c)| c == b'/' && wildmatch(after, &text[i + 1..]))
+                }
+                None => (0..=text.len()).any(|i| wildmatch(rest, &text[i..])),
+            }
+        }
+        Some((b'*', rest)) => {
+            for i in 0..=text.len() {
+                if wildmatch(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some((b'?', rest)) => {
+            text.first().is_some_and(|&c| c != b'/') && wildmatch(rest, &text[1..])
+        }
+        Some((b'[', rest)) => match match_class(rest, text.first().copied()) {
+            Some((matched, after)) => matched && wildmatch(after, &text[1..]),
+            // an unterminated class is a literal bracket
+            None => text.first() == Some(&b'[') && wildmatch(rest, &text[1..]),
+        },
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            text.first() == rest.first() && wildmatch(&rest[1..], &text[1..])
+        }
+        Some((&c, rest)) => text.first() == Some(&c) && wildmatch(rest, &text[1..]),
+    }
+}
+
+/// Matches one character against the class following a `[`, returning
+/// whether it matched and the pattern after the closing `]`.
+fn match_class(class: &[u8], c: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negated, class) = match class.first() {
+        Some(b'!' | b'^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    // a "]" right after the opening bracket is part of the class
+    let end = 1 + class.get(1..)?.iter().position(|&b| b == b']')?;
+    let (members, after) = (&class[..end], &class[end + 1..]);
+    let Some(c) = c.filter(|&c| c != b'/') else {
+        return Some((false, after));
+    };
+    let mut matched = false;
+    let mut i = 0;
+    while i < members.len() {
+        if i + 2 < members.len() && members[i + 1] == b'-' {
+            matched |= (members[i]..=members[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= members[i] == c;
+            i += 1;
+        }
+    }
+    Some((matched != negated, after))
+}
+
+/// The pattern that decided whether a path is ignored.
+pub struct Match {
+    /// The ignore file holding the pattern.
+    pub source: String,
+    /// The line of the pattern in its file, counting from 1.
+    pub line: usize,
+    pub pattern: String,
+    /// A `!` pattern, which keeps the path from being ignored.
+    pub negated: bool,
+}
+
+impl From<&Pattern> for Match {
+    fn from(pattern: &Pattern) -> Self {
+        Self {
+            source: pattern.source.clone(),
+            line: pattern.line,
+            pattern: pattern.text.clone(),
+            negated: pattern.negated,
+        }
+    }
+}
+
+/// Decides which paths of the working tree are ignored, reading the
+/// `.gitignore` file of each directory on first use. From lowest to highest
+/// precedence the patterns come from `core.excludesFile`, `.git/info/exclude`
+/// and the `.gitignore` files from the root down; the last match wins.
+pub struct Ignore {
+    excludes: Vec<Pattern>,
+    directories: HashMap<String, Vec<Pattern>>,
+}
+
+impl Ignore {
+    pub fn new() -> Result<Self> {
+        let mut excludes = vec![];
+        let files = [excludes_file()?, Some(repository::path(INFO_EXCLUDE))];
+        for file in files.into_iter().flatten() {
+            excludes.extend(read_patterns(&file, ""));
+        }
+        Ok(Self {
+            excludes,
+            directories: HashMap::new(),
+        })
+    }
+
+    /// Whether a path relative to the repository root is ignored, either
+    /// itself or because a directory containing it is.
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> Result<bool> {
+        Ok(self
+            .matching_pattern(path, is_dir)?
+            .is_some_and(|found| !found.negated))
+    }
+
+    /// The pattern deciding whether a path is ignored: the one ignoring a
+    /// directory containing it, or else the last one matching the path
+    /// itself, which may be a negated one.
+    pub fn matching_pattern(&mut self, path: &Path, is_dir: bool) -> Result<Option<Match>> {
+        let path = normalize(path);
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let mut parent = String::new();
+        for component in path.split('/') {
+            let current = format!("{parent}{component}");
+            let is_path = current.len() == path.len();
+            let found = self.last_match(&current, !is_path || is_dir)?;
+            match found {
+                Some(found) if is_path || !found.negated => return Ok(Some(found)),
+                _ => {}
+            }
+            parent = format!("{current}/");
+        }
+        Ok(None)
+    }
+
+    /// The last of the patterns visible from a path matching it, without
+    /// looking at the directories above it.
+    fn last_match(&mut self, path: &str, is_dir: bool) -> Result<Option<Match>> {
+        let mut directories = vec![String::new()];
+        for (i, _) in path.match_indices('/') {
+            directories.push(path[..=i].to_owned());
+        }
+        let mut found = self
+            .excludes
+            .iter()
+            .rfind(|pattern| pattern.matches(path, is_dir))
+            .map(Match::from);
+        for directory in directories {
+            if let Some(pattern) = self
+                .patterns(&directory)?
+                .iter()
+                .rfind(|pattern| pattern.matches(path, is_dir))
+            {
+                found = Some(Match::from(pattern));
+            }
+        }
+        Ok(found)
+    }
+
+    fn patterns(&mut self, directory: &str) -> Result<&[Pattern]> {
+        if !self.directories.contains_key(directory) {
+            let patterns = read_patterns(&Path::new(directory).join(IGNORE_FILE), directory);
+            self.directories.insert(directory.to_owned(), patterns);
+        }
+        Ok(&self.directories[directory])
+    }
+}
+
+/// Prints the paths that are ignored, or with `verbose` those matching a
+/// pattern along with it, negated ones included. Tracked paths are never
+/// ignored. Returns whether anything was printed.
+pub fn check_ignore(paths: &[PathBuf], verbose: bool) -> Result<bool> {
+    let tracked = if Index::exists() {
+        Index::read()?.entries
+    } else {
+        vec![]
+    };
+    let mut ignore = Ignore::new()?;
+    let mut matched = false;
+    for path in paths {
+        let name = normalize(path);
+        if tracked.iter().any(|entry| entry.path == name) {
+            continue;
+        }
+        let found = ignore
+            .matching_pattern(path, path.is_dir())?
+            .filter(|found| verbose || !found.negated);
+        let Some(found) = found else {
+            continue;
+        };
+        matched = true;
+        if verbose {
+            println!(
+                "{}:{}:{}\t{}",
+                found.source,
+                found.line,
+                found.pattern,
+                path.display()
+            );
+        } else {
+            println!("{}", path.display());
+        }
+    }
+    Ok(matched)
+}
+
+/// Reads the patterns of an ignore file; a missing file has none.
+fn read_patterns(file: &Path, base: &str) -> Vec<Pattern> {
+    let source = file.display().to_string();
+    match fs::read_to_string(file) {
+        Ok(content) => content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| Pattern::parse(line, base, &source, i + 1))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// The user's global ignore file: `core.excludesFile`, by default
+/// `$XDG_CONFIG_HOME/git/ignore` or `~/.config/git/ignore`.
+fn excludes_file() -> Result<Option<PathBuf>> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    if let Some(file) = Config::load()?.get("core.excludesFile") {
+        return Ok(match (file.strip_prefix("~/"), home) {
+            (Some(rest), Some(home)) => Some(home.join(rest)),
+            (Some(_), None) => None,
+            (None, _) => Some(PathBuf::from(file)),
+        });
+    }
+    Ok(match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) => Some(PathBuf::from(config_home).join("git/ignore")),
+        None => home.map(|home| home.join(".config/git/ignore")),
+    })
+}
+
+/// A path relative to the repository root with `/` separators and without
+/// `.` components.
+fn normalize(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Lists the files of the working tree below `directory` that are not
+/// ignored, or all of them without an `ignore`, skipping the `.git`
+/// directory and the contents of nested repositories.
+pub fn worktree_files(ignore: Option<&mut Ignore>, directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    collect_files(ignore, directory, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files(
+    mut ignore: Option<&mut Ignore>,
+    directory: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in directory.read_dir()? {
+        let entry = entry?;
+        let path = entry.path();
+        let path = path.strip_prefix(".").unwrap_or(&path);
+        let file_type = entry.file_type()?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        if let Some(ignore) = ignore.as_deref_mut() {
+            if ignore.is_ignored(path, file_type.is_dir())? {
+                continue;
+            }
+        }
+        // a nested repository is listed as a whole, like a file
+        if file_type.is_dir() && !path.join(".git").exists() {
+            collect_files(ignore.as_deref_mut(), path, files)?;
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(line: &str) -> Pattern {
+        Pattern::parse(line, "", ".gitignore", 1).expect("a pattern")
+    }
+
+    #[test]
+    fn wildmatch_follows_gitignore_globs() {
+        for (glob, text) in [
+            ("*.o", "main.o"),
+            ("a?c", "abc"),
+            ("**/build", "build"),
+            ("**/build", "x/y/build"),
+            ("src/**", "src/a/b.rs"),
+            ("a/**/b", "a/b"),
+            ("a/**/b", "a/x/y/b"),
+            ("[a-c]x", "bx"),
+            ("[!a-c]x", "dx"),
+            ("[]]", "]"),
+            ("\\*", "*"),
+            ("[unterminated", "[unterminated"),
+        ] {
+            assert!(wildmatch(glob.as_bytes(), text.as_bytes()), "{glob} {text}");
+        }
+        for (glob, text) in [
+            ("*.o", "dir/main.o"),
+            ("a?c", "a/c"),
+            ("[a-c]x", "dx"),
+            ("[!a-c]x", "ax"),
+            ("\\*", "x"),
+            ("src/**", "other/a"),
+        ] {
+            assert!(
+                !wildmatch(glob.as_bytes(), text.as_bytes()),
+                "{glob} {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn pattern_lines_are_parsed() {
+        assert!(Pattern::parse("# comment", "", ".gitignore", 1).is_none());
+        assert!(Pattern::parse("   ", "", ".gitignore", 1).is_none());
+        assert!(Pattern::parse("/", "", ".gitignore", 1).is_none());
+
+        let negated = pattern("!keep.log");
+        assert!(negated.negated && negated.matches("logs/keep.log", false));
+        let escaped = pattern("\\#hash");
+        assert!(!escaped.negated && escaped.matches("#hash", false));
+        assert!(pattern("\\!bang").matches("!bang", false));
+        assert!(pattern("trailing\\ ").matches("trailing ", false));
+        assert!(pattern("spaces  ").matches("spaces", false));
+
+        let directory = pattern("target/");
+        assert!(directory.matches("target", true) && directory.matches("a/target", true));
+        assert!(!directory.matches("target", false));
+        let anchored = pattern("/root.txt");
+        assert!(anchored.matches("root.txt", false) && !anchored.matches("a/root.txt", false));
+
+        let nested = Pattern::parse("*.tmp", "sub/", "sub/.gitignore", 3).unwrap();
+        assert!(nested.matches("sub/x/a.tmp", false) && !nested.matches("a.tmp", false));
+        let found = Match::from(&nested);
+        assert_eq!((found.source.as_str(), found.line), ("sub/.gitignore", 3));
+        assert_eq!(found.pattern, "*.tmp");
+    }
+}
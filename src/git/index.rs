@@ -0,0 +1,263 @@
+use super::{hash::HashAlgo, platform, repository, ObjectId, TreeFiles};
+
+const INDEX: &str = "index";
+use anyhow::{anyhow, bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SIGNATURE: &[u8] = b"DIRC";
+const VERSION: u32 = 2;
+/// The version with extended flags, written only when an entry needs them.
+const EXTENDED_VERSION: u32 = 3;
+/// Size of the ten 32-bit stat fields preceding the object hash.
+const STAT_SIZE: usize = 10 * 4;
+const STAGE_SHIFT: u16 = 12;
+const STAGE_MASK: u16 = 0x3000;
+const EXTENDED_FLAG: u16 = 0x4000;
+const NAME_MASK: u16 = 0xfff;
+const SKIP_WORKTREE_FLAG: u16 = 0x4000;
+
+/// A path in the index. Stage 0 is a merged entry; stages 1 to 3 hold the
+/// base, ours and theirs versions of a conflicted path.
+#[derive(Clone, Default)]
+pub struct Entry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash: ObjectId,
+    pub stage: u16,
+    /// Left out of the working tree by sparse checkout.
+    pub skip_worktree: bool,
+    pub path: String,
+}
+
+impl Entry {
+    /// An entry carrying the stat data of a file in the working tree, so that
+    /// unchanged files can be recognised without hashing them.
+    pub fn from_file(path: &Path, mode: u32, hash: ObjectId) -> Result<Self> {
+        let meta = fs::symlink_metadata(path)?;
+        let stat = platform::stat(&meta);
+        Ok(Self {
+            ctime: stat.ctime,
+            mtime: stat.mtime,
+            dev: stat.dev,
+            ino: stat.ino,
+            mode,
+            uid: stat.uid,
+            gid: stat.gid,
+            size: meta.len() as u32,
+            hash,
+            stage: 0,
+            skip_worktree: false,
+            path: path_name(path)?,
+        })
+    }
+
+    /// A conflict stage entry, which has no file behind it.
+    pub fn staged(path: &Path, mode: u32, hash: ObjectId, stage: u16) -> Result<Self> {
+        Ok(Self {
+            mode,
+            hash,
+            stage,
+            path: path_name(path)?,
+            ..Default::default()
+        })
+    }
+}
+
+/// The staging area, `.git/index`, with entries sorted by path and stage.
+#[derive(Default)]
+pub struct Index {
+    pub entries: Vec<Entry>,
+}
+
+impl Index {
+    pub fn exists() -> bool {
+        repository::path(INDEX).is_file()
+    }
+
+    /// Reads the index; a repository without one has an empty index.
+    pub fn read() -> Result<Self> {
+        if !Self::exists() {
+            return Ok(Self::default());
+        }
+        Self::parse(&fs::read(repository::path(INDEX))?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let algo = HashAlgo::current();
+        let checksum_offset = data
+            .len()
+            .checked_sub(algo.size())
+            .ok_or_else(|| anyhow!("Index is truncated"))?;
+        if *algo.digest(&data[..checksum_offset]) != data[checksum_offset..] {
+            bail!("Index checksum mismatch");
+        }
+        if data.get(..4) != Some(SIGNATURE) {
+            bail!("Bad index signature");
+        }
+        let version = read_u32(data, 4)?;
+        if version != VERSION && version != EXTENDED_VERSION {
+            bail!("Unsupported index version {version}");
+        }
+        let count = read_u32(data, 8)? as usize;
+        let mut offset = 12;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let field = |i: usize| read_u32(data, offset + 4 * i);
+            let flags_offset = offset + STAT_SIZE + algo.size();
+            let flags = u16::from_be_bytes([data[flags_offset], data[flags_offset + 1]]);
+            let extended_flags = if flags & EXTENDED_FLAG != 0 {
+                if version != EXTENDED_VERSION {
+                    bail!("Extended index entry in index version {version}");
+                }
+                u16::from_be_bytes([data[flags_offset + 2], data[flags_offset + 3]])
+            } else {
+                0
+            };
+            let fixed_size =
+                STAT_SIZE + algo.size() + if flags & EXTENDED_FLAG != 0 { 4 } else { 2 };
+            let name_start = offset + fixed_size;
+            let name_length = data[name_start..checksum_offset]
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| anyhow!("Unterminated index entry name"))?;
+            entries.push(Entry {
+                ctime: (field(0)?, field(1)?),
+                mtime: (field(2)?, field(3)?),
+                dev: field(4)?,
+                ino: field(5)?,
+                mode: field(6)?,
+                uid: field(7)?,
+                gid: field(8)?,
+                size: field(9)?,
+                hash: ObjectId::from_bytes(&data[offset + STAT_SIZE..flags_offset])?,
+                stage: (flags & STAGE_MASK) >> STAGE_SHIFT,
+                skip_worktree: extended_flags & SKIP_WORKTREE_FLAG != 0,
+                path: String::from_utf8(data[name_start..name_start + name_length].to_vec())?,
+            });
+            offset += entry_size(fixed_size, name_length);
+        }
+        // extensions such as the cache tree are dropped; they are only caches
+        Ok(Self { entries })
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| (a.path.as_bytes(), a.stage).cmp(&(b.path.as_bytes(), b.stage)));
+        let extended = entries.iter().any(|e| e.skip_worktree);
+        let mut data = SIGNATURE.to_vec();
+        data.extend(if extended { EXTENDED_VERSION } else { VERSION }.to_be_bytes());
+        data.extend((entries.len() as u32).to_be_bytes());
+        for entry in entries {
+            let start = data.len();
+            for field in [
+                entry.ctime.0,
+                entry.ctime.1,
+                entry.mtime.0,
+                entry.mtime.1,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                data.extend(field.to_be_bytes());
+            }
+            data.extend_from_slice(&entry.hash);
+            let name_length = entry.path.len().min(NAME_MASK as usize) as u16;
+            let mut flags = (entry.stage << STAGE_SHIFT) | name_length;
+            let mut fixed_size = STAT_SIZE + entry.hash.len() + 2;
+            if entry.skip_worktree {
+                flags |= EXTENDED_FLAG;
+                fixed_size += 2;
+            }
+            data.extend(flags.to_be_bytes());
+            if entry.skip_worktree {
+                data.extend(SKIP_WORKTREE_FLAG.to_be_bytes());
+            }
+            data.extend(entry.path.as_bytes());
+            // NUL-terminate and pad to a multiple of eight bytes
+            data.resize(start + entry_size(fixed_size, entry.path.len()), 0);
+        }
+        let checksum = HashAlgo::current().digest(&data);
+        data.extend_from_slice(&checksum);
+        fs::write(repository::path(INDEX), data)?;
+        Ok(())
+    }
+
+    /// An index holding the given files, with stat data for those present in
+    /// the working tree.
+    pub fn from_files(files: &TreeFiles) -> Result<Self> {
+        let mut index = Self::default();
+        for (path, (mode, hash)) in files {
+            let entry = Entry::from_file(path, *mode, *hash)
+                .or_else(|_| Entry::staged(path, *mode, *hash, 0))?;
+            index.entries.push(entry);
+        }
+        Ok(index)
+    }
+
+    /// Replaces all stages of a path with the given entry.
+    pub fn add(&mut self, entry: Entry) {
+        self.remove(&entry.path);
+        self.entries.push(entry);
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|e| e.path != path);
+    }
+
+    /// Paths that have unmerged (non-zero stage) entries.
+    pub fn conflicts(&self) -> Vec<&str> {
+        let mut paths = self
+            .entries
+            .iter()
+            .filter(|e| e.stage != 0)
+            .map(|e| e.path.as_str())
+            .collect::<Vec<_>>();
+        paths.dedup();
+        paths
+    }
+
+    /// The merged entries as files keyed by path, as used for trees.
+    pub fn files(&self) -> TreeFiles {
+        self.entries
+            .iter()
+            .filter(|e| e.stage == 0)
+            .map(|e| (PathBuf::from(&e.path), (e.mode, e.hash)))
+            .collect()
+    }
+}
+
+fn entry_size(fixed_size: usize, name_length: usize) -> usize {
+    (fixed_size + name_length + 8) & !7
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("Index is truncated"))
+}
+
+/// The index name of a path given on the command line, which may start
+/// with `./` or end with a slash.
+pub fn pathspec_name(path: &Path) -> Result<String> {
+    let name = path_name(path)?;
+    let name = name.strip_prefix("./").unwrap_or(&name);
+    Ok(name.trim_end_matches('/').to_owned())
+}
+
+fn path_name(path: &Path) -> Result<String> {
+    let name = path
+        .to_str()
+        .ok_or_else(|| anyhow!("Non UTF-8 path {}", path.display()))?;
+    // index paths use slashes whatever the platform separator
+    Ok(name.replace(std::path::MAIN_SEPARATOR, "/"))
+}
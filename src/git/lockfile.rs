@@ -0,0 +1,71 @@
+use anyhow::{anyhow, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Exclusive access to a file, held as `<file>.lock` like git does. New
+/// content is written to the lock file and renamed over the file on commit,
+/// so readers see either the old or the new content. Dropping the lock
+/// without committing leaves the file untouched.
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    /// Takes the lock, failing if someone else holds it.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|error| match error.kind() {
+                ErrorKind::AlreadyExists => anyhow!(
+                    "Unable to create '{}': File exists.\n\n\
+                     Another git process seems to be running in this repository.",
+                    lock_path.display()
+                ),
+                _ => anyhow!("Unable to create '{}': {error}", lock_path.display()),
+            })?;
+        Ok(Self {
+            path: path.to_owned(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    pub fn write(&mut self, content: &[u8]) -> Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .expect("the lock is held until committed");
+        file.write_all(content)?;
+        Ok(())
+    }
+
+    /// Replaces the file with what was written, releasing the lock.
+    pub fn commit(mut self) -> Result<()> {
+        // close the file before renaming it
+        drop(self.file.take());
+        if let Err(error) = fs::rename(&self.lock_path, &self.path) {
+            let _ = fs::remove_file(&self.lock_path);
+            return Err(error.into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use super::{
+    date, mailmap::Mailmap, notes::Notes, peel_to_commit, revparse, revwalk::RevWalk, short_hash,
+    Commit, Odb,
+};
+use anyhow::Result;
+use std::io::{self, stdout, Write};
+
+/// Prints the history reachable from `start` in reverse chronological order.
+pub fn print(start: &str) -> Result<()> {
+    let odb = Odb::open()?;
+    let notes = Notes::load(&odb)?;
+    let mailmap = Mailmap::load()?;
+    let mut walk = RevWalk::new(&odb);
+    walk.push(&peel_to_commit(&revparse::resolve(start)?)?)?;
+    let mut out = stdout().lock();
+    for (index, entry) in walk.enumerate() {
+        let (hash, mut commit) = entry?;
+        commit.author = mailmap.map_identity(&commit.author);
+        if index > 0 {
+            writeln!(out)?;
+        }
+        print_commit(&mut out, &hash, &commit, notes.get(&odb, &hash)?.as_deref())?;
+    }
+    Ok(())
+}
+
+/// Writes the header and indented message of a commit as `log` shows it,
+/// followed by its note if it has one.
+pub fn print_commit(
+    out: &mut impl Write,
+    hash: &str,
+    commit: &Commit,
+    note: Option<&str>,
+) -> io::Result<()> {
+    writeln!(out, "commit {hash}")?;
+    if commit.parents.len() > 1 {
+        let parents = commit
+            .parents
+            .iter()
+            .map(|p| short_hash(p))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(out, "Merge: {parents}")?;
+    }
+    let author = &commit.author;
+    writeln!(out, "Author: {}", author.person())?;
+    writeln!(
+        out,
+        "Date:   {}",
+        date::format(author.timestamp, &author.timezone)
+    )?;
+    writeln!(out)?;
+    for line in commit.message.lines() {
+        writeln!(out, "    {line}")?;
+    }
+    if let Some(note) = note {
+        writeln!(out)?;
+        writeln!(out, "Notes:")?;
+        for line in note.lines() {
+            writeln!(out, "    {line}")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use super::{
+    ignore::{self, Ignore},
+    index::Index,
+};
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::io::{stdout, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Default)]
+pub struct LsFilesOptions {
+    /// List the entries of the index (`--cached`), the default.
+    pub cached: bool,
+    /// Show the mode, hash and stage of index entries (`--stage`).
+    pub stage: bool,
+    /// List untracked files (`--others`).
+    pub others: bool,
+    /// Leave out ignored files from untracked ones (`--exclude-standard`).
+    pub exclude_standard: bool,
+    /// End each path with a NUL byte instead of a newline (`-z`).
+    pub nul_terminated: bool,
+}
+
+/// Lists untracked files, then the paths of the index, one per entry so
+/// conflicted paths appear once per stage. Nested repositories are listed
+/// as directories.
+pub fn ls_files(options: &LsFilesOptions) -> Result<()> {
+    let index = if Index::exists() {
+        Index::read()?
+    } else {
+        Index::default()
+    };
+    let end = if options.nul_terminated { '\0' } else { '\n' };
+    let mut out = BufWriter::new(stdout().lock());
+    if options.others {
+        let tracked = index
+            .entries
+            .iter()
+            .map(|entry| Path::new(&entry.path))
+            .collect::<BTreeSet<_>>();
+        let mut ignore = match options.exclude_standard {
+            true => Some(Ignore::new()?),
+            false => None,
+        };
+        for file in ignore::worktree_files(ignore.as_mut(), Path::new("."))? {
+            if tracked.contains(file.as_path()) {
+                continue;
+            }
+            let slash = if file.is_dir() { "/" } else { "" };
+            write!(out, "{}{slash}{end}", file.display())?;
+        }
+    }
+    if options.cached || options.stage || !options.others {
+        for entry in &index.entries {
+            if options.stage {
+                write!(out, "{:06o} {} {}\t", entry.mode, entry.hash, entry.stage)?;
+            }
+            write!(out, "{}{end}", entry.path)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use super::{EntryKind, Odb, ParsedObject, TreeEntry};
+use anyhow::{bail, Result};
+use std::io::{stdout, Write};
+
+#[derive(Default)]
+pub struct LsTreeOptions {
+    /// Descend into subtrees, listing their entries instead (`-r`).
+    pub recursive: bool,
+    /// Add the size of blobs (`-l`).
+    pub long: bool,
+    /// Print paths only (`--name-only`).
+    pub name_only: bool,
+    /// Leave out blobs and submodules (`-d`).
+    pub trees_only: bool,
+    /// Show the trees descended into as well (`-t`).
+    pub show_trees: bool,
+    /// Paths to limit the listing to, all if empty.
+    pub paths: Vec<String>,
+}
+
+impl LsTreeOptions {
+    /// Whether `path` is one of the listed paths or lies under one.
+    fn includes(&self, path: &str) -> bool {
+        self.paths.is_empty()
+            || self.paths.iter().any(|spec| {
+                let spec = spec.trim_end_matches('/');
+                path.strip_prefix(spec)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+    }
+
+    /// Whether `path` is a directory that a listed path lies in, or is given
+    /// with a trailing slash to list its content.
+    fn leads_to(&self, path: &str) -> bool {
+        self.paths.iter().any(|spec| {
+            spec.strip_prefix(path)
+                .and_then(|rest| rest.strip_prefix('/'))
+                .is_some()
+        })
+    }
+}
+
+/// Lists the entries of a tree as `<mode> <type> <hash>\t<path>`.
+pub fn ls_tree(tree: &str, options: &LsTreeOptions) -> Result<()> {
+    let odb = Odb::open()?;
+    list(&odb, tree, "", options, &mut stdout().lock())
+}
+
+fn list(
+    odb: &Odb,
+    tree: &str,
+    prefix: &str,
+    options: &LsTreeOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    let ParsedObject::Tree(entries) = odb.read(tree)?.parse()? else {
+        bail!("{tree} is not a tree");
+    };
+    for entry in entries {
+        let path = format!("{prefix}{}", entry.name);
+        let kind = entry.kind();
+        let included = options.includes(&path);
+        let leading = options.leads_to(&path);
+        if !included && !leading {
+            continue;
+        }
+        if kind == EntryKind::Tree && (options.recursive && included || leading) {
+            // listing only trees shows those descended into as well
+            if options.show_trees || options.trees_only && options.recursive {
+                show(odb, &entry, &path, options, out)?;
+            }
+            list(
+                odb,
+                &entry.hash.to_string(),
+                &format!("{path}/"),
+                options,
+                out,
+            )?;
+            continue;
+        }
+        if options.trees_only && kind != EntryKind::Tree {
+            continue;
+        }
+        show(odb, &entry, &path, options, out)?;
+    }
+    Ok(())
+}
+
+fn show(
+    odb: &Odb,
+    entry: &TreeEntry,
+    path: &str,
+    options: &LsTreeOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    if options.name_only {
+        writeln!(out, "{path}")?;
+        return Ok(());
+    }
+    let kind = entry.kind();
+    let kind_name = match kind {
+        EntryKind::Blob => "blob",
+        EntryKind::Tree => "tree",
+        EntryKind::Gitlink => "commit",
+    };
+    write!(out, "{:06o} {kind_name} {}", entry.mode, entry.hash)?;
+    if options.long {
+        // only blobs have a size worth showing
+        let size = match kind {
+            EntryKind::Blob => odb.stream(&entry.hash.to_string())?.size().to_string(),
+            _ => "-".to_owned(),
+        };
+        write!(out, " {size:>7}")?;
+    }
+    writeln!(out, "\t{path}")?;
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use super::{config::Config, Identity};
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::Path;
+
+const MAILMAP_FILE: &str = ".mailmap";
+
+/// One `.mailmap` line: the canonical name and/or email for commits made
+/// with an email, and optionally only with a given name.
+struct Entry {
+    name: Option<String>,
+    email: Option<String>,
+    commit_name: Option<String>,
+    commit_email: String,
+}
+
+/// Maps the names and emails found in commits to canonical ones, as listed
+/// in the `.mailmap` file at the top of the worktree and in `mailmap.file`.
+#[derive(Default)]
+pub struct Mailmap {
+    entries: Vec<Entry>,
+}
+
+impl Mailmap {
+    pub fn load() -> Result<Self> {
+        let mut mailmap = Self::default();
+        let config = Config::load()?;
+        let files = [Some(MAILMAP_FILE), config.get("mailmap.file")];
+        for file in files.into_iter().flatten() {
+            if let Ok(content) = fs::read_to_string(Path::new(file)) {
+                mailmap.parse(&content);
+            }
+        }
+        Ok(mailmap)
+    }
+
+    /// Adds the entries of a mailmap file. Lines it cannot make sense of
+    /// are skipped, as git does.
+    fn parse(&mut self, content: &str) {
+        for line in content.lines() {
+            let line = line.split_once('#').map_or(line, |(line, _)| line);
+            // names and the emails in angle brackets alternate
+            let mut names = vec![];
+            let mut emails = vec![];
+            let mut rest = line;
+            while let Some((name, after)) = rest.split_once('<') {
+                let Some((email, after)) = after.split_once('>') else {
+                    break;
+                };
+                let name = name.trim();
+                names.push((!name.is_empty()).then(|| name.to_owned()));
+                emails.push(email.to_owned());
+                rest = after;
+            }
+            let entry = match (names.as_slice(), emails.as_slice()) {
+                ([name], [email]) => Entry {
+                    name: name.clone(),
+                    email: None,
+                    commit_name: None,
+                    commit_email: email.clone(),
+                },
+                ([name, commit_name], [email, commit_email]) => Entry {
+                    name: name.clone(),
+                    email: Some(email.clone()),
+                    commit_name: commit_name.clone(),
+                    commit_email: commit_email.clone(),
+                },
+                _ => continue,
+            };
+            self.entries.push(entry);
+        }
+    }
+
+    /// The canonical name and email for a contact. Entries for the name as
+    /// well as the email win over those for the email alone, and later
+    /// entries over earlier ones. Matching ignores case.
+    pub fn map(&self, name: Option<&str>, email: &str) -> (Option<String>, String) {
+        let matching = |entry: &&Entry| entry.commit_email.eq_ignore_ascii_case(email);
+        let by_name = self.entries.iter().filter(matching).rev().find(|entry| {
+            entry
+                .commit_name
+                .as_deref()
+                .zip(name)
+                .is_some_and(|(commit_name, name)| commit_name.eq_ignore_ascii_case(name))
+        });
+        let entry = by_name.or_else(|| {
+            self.entries
+                .iter()
+                .filter(matching)
+                .rev()
+                .find(|entry| entry.commit_name.is_none())
+        });
+        let Some(entry) = entry else {
+            return (name.map(str::to_owned), email.to_owned());
+        };
+        (
+            entry.name.clone().or_else(|| name.map(str::to_owned)),
+            entry.email.clone().unwrap_or_else(|| email.to_owned()),
+        )
+    }
+
+    /// An identity with the canonical name and email.
+    pub fn map_identity(&self, identity: &Identity) -> Identity {
+        let (name, email) = self.map(Some(&identity.name), &identity.email);
+        Identity {
+            name: name.unwrap_or_default(),
+            email,
+            ..identity.clone()
+        }
+    }
+}
+
+/// Prints the canonical form of `Name <email>` or `<email>` contacts.
+pub fn check_mailmap(contacts: &[String]) -> Result<()> {
+    let mailmap = Mailmap::load()?;
+    for contact in contacts {
+        let parsed = contact
+            .trim()
+            .strip_suffix('>')
+            .and_then(|contact| contact.rsplit_once('<'));
+        let Some((name, email)) = parsed else {
+            bail!("unable to parse contact: {contact}");
+        };
+        let name = name.trim();
+        match mailmap.map((!name.is_empty()).then_some(name), email) {
+            (Some(name), email) => println!("{name} <{email}>"),
+            (None, email) => println!("<{email}>"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,472 @@
+use super::{
+    commit::{self, CommitOptions},
+    diff::{self, Edit},
+    fast_forward, flatten_tree,
+    index::{Entry, Index},
+    parse_hash, peel_to_commit, refs,
+    remote::Sha1,
+    repository, reset_hard, revparse, revwalk, short_hash, update_worktree, write_commit,
+    write_tree_from_files, ObjectId, Odb, ParsedObject, TreeFiles,
+};
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// The commit being merged while a merge waits for conflicts to be resolved.
+const MERGE_HEAD: &str = "MERGE_HEAD";
+/// The message prepared for the merge commit.
+const MERGE_MSG: &str = "MERGE_MSG";
+
+/// Merges a revision into the current branch: a fast-forward when HEAD is an
+/// ancestor of it, a three-way merge of the trees otherwise. Histories without a
+/// common ancestor are only merged if `allow_unrelated_histories` is set. The
+/// previous tip is kept in `ORIG_HEAD`.
+pub fn merge(revision: &str, allow_unrelated_histories: bool) -> Result<()> {
+    if merge_head()?.is_some() {
+        bail!(
+            "You have not concluded your merge (MERGE_HEAD exists).\n\
+             Please, commit your changes before you merge."
+        );
+    }
+    let target = peel_to_commit(&revparse::resolve(revision)?)?;
+    let head = refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD does not point to a commit"))?;
+    let odb = Odb::open()?;
+    if revwalk::is_ancestor(&odb, &target, &head)? {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    if revwalk::is_ancestor(&odb, &head, &target)? {
+        refs::write("ORIG_HEAD", &head)?;
+        println!("Updating {}..{}", short_hash(&head), short_hash(&target));
+        println!("Fast-forward");
+        return fast_forward(&target, &format!("merge {revision}: Fast-forward"));
+    }
+    let base = revwalk::merge_base(&odb, &head, &target)?;
+    if base.is_none() && !allow_unrelated_histories {
+        bail!("refusing to merge unrelated histories");
+    }
+    refs::write("ORIG_HEAD", &head)?;
+    three_way_merge(&head, &target, base.as_deref(), revision)
+}
+
+/// Concludes a merge stopped by conflicts once they are resolved, committing
+/// the index with the prepared message.
+pub fn continue_merge() -> Result<()> {
+    if merge_head()?.is_none() {
+        bail!("There is no merge in progress (MERGE_HEAD missing).");
+    }
+    commit::commit(&CommitOptions::default())?;
+    Ok(())
+}
+
+/// Gives up on a merge stopped by conflicts, going back to HEAD.
+pub fn abort() -> Result<()> {
+    if merge_head()?.is_none() {
+        bail!("There is no merge to abort (MERGE_HEAD missing).");
+    }
+    let head = refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD does not point to a commit"))?;
+    reset_hard(&Odb::open()?, &head)?;
+    clear_state()
+}
+
+/// The commit being merged, if a merge is waiting to be committed.
+pub fn merge_head() -> Result<Option<Sha1>> {
+    Ok(refs::peel(MERGE_HEAD)?)
+}
+
+/// The message prepared for the commit concluding a merge. Like
+/// `git commit --no-edit`, the comment lines listing conflicts are kept.
+pub fn merge_message() -> Result<Option<String>> {
+    match fs::read_to_string(repository::path(MERGE_MSG)) {
+        Ok(prepared) => Ok(Some(prepared.trim_end().to_owned())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Forgets about a merge in progress, once committed or given up on.
+pub fn clear_state() -> Result<()> {
+    refs::delete(MERGE_HEAD)?;
+    let message = repository::path(MERGE_MSG);
+    if message.is_file() {
+        fs::remove_file(message)?;
+    }
+    Ok(())
+}
+
+/// Prints the best common ancestor of two revisions, or all of them.
+/// Returns whether there is one.
+pub fn print_merge_base(one: &str, two: &str, all: bool) -> Result<bool> {
+    let one = peel_to_commit(&revparse::resolve(one)?)?;
+    let two = peel_to_commit(&revparse::resolve(two)?)?;
+    let bases = revwalk::merge_bases(&Odb::open()?, &one, &two)?;
+    let shown = if all { bases.len() } else { 1 };
+    for base in bases.iter().take(shown) {
+        println!("{base}");
+    }
+    Ok(!bases.is_empty())
+}
+
+/// Whether one revision is an ancestor of another, or the same commit.
+pub fn is_ancestor(ancestor: &str, descendant: &str) -> Result<bool> {
+    revwalk::is_ancestor(
+        &Odb::open()?,
+        &peel_to_commit(&revparse::resolve(ancestor)?)?,
+        &peel_to_commit(&revparse::resolve(descendant)?)?,
+    )
+}
+
+/// The outcome of merging one path.
+enum Resolution {
+    Clean(Option<(u32, ObjectId)>),
+    Conflict,
+}
+
+fn resolve(
+    base: Option<&(u32, ObjectId)>,
+    ours: Option<&(u32, ObjectId)>,
+    theirs: Option<&(u32, ObjectId)>,
+) -> Resolution {
+    if ours == theirs || base == theirs {
+        Resolution::Clean(ours.cloned())
+    } else if base == ours {
+        Resolution::Clean(theirs.cloned())
+    } else {
+        Resolution::Conflict
+    }
+}
+
+/// Merges the trees of two commits with their merge base, if any, and commits
+/// the result, or leaves the conflicts to be resolved.
+fn three_way_merge(head: &str, target: &str, base: Option<&str>, revision: &str) -> Result<()> {
+    let odb = Odb::open()?;
+    let tree_files = |commit: Option<&str>| match commit {
+        Some(commit) => flatten_tree(&odb, &odb.read_commit(commit)?.tree),
+        None => Ok(TreeFiles::new()),
+    };
+    let (merged, conflicts) = merge_trees(
+        &odb,
+        &tree_files(base)?,
+        &tree_files(Some(head))?,
+        &tree_files(Some(target))?,
+        revision,
+    )?;
+    let message = match refs::expand(revision)? {
+        Some(name) if name.starts_with("refs/heads/") => format!("Merge branch '{revision}'"),
+        _ => format!("Merge commit '{revision}'"),
+    };
+    if !conflicts.is_empty() {
+        // leave what committing the resolution needs
+        let mut prepared = format!("{message}\n\n# Conflicts:\n");
+        for path in &conflicts {
+            prepared.push_str(&format!("#\t{}\n", path.display()));
+        }
+        fs::write(repository::path(MERGE_MSG), prepared)?;
+        refs::write(MERGE_HEAD, target)?;
+        bail!("Automatic merge failed; fix conflicts and then commit the result.");
+    }
+
+    let tree = write_tree_from_files(&odb, &merged)?;
+    let commit = write_commit(&tree, &[parse_hash(head)?, parse_hash(target)?], &message)?;
+    refs::update(
+        "HEAD",
+        &hex::encode(commit),
+        &format!("merge {revision}: Merge made by a three-way merge."),
+    )?;
+    println!("Merge made by a three-way merge.");
+    Ok(())
+}
+
+/// Merges the files of two trees with a common base into the working tree,
+/// which must match `our_files`, and the index. Files changed on both sides
+/// are merged line by line; conflicting hunks are written between markers
+/// and the versions of the file recorded as index stages. `label` names
+/// their side. Returns the cleanly merged files and the conflicted paths.
+pub fn merge_trees(
+    odb: &Odb,
+    base_files: &TreeFiles,
+    our_files: &TreeFiles,
+    their_files: &TreeFiles,
+    label: &str,
+) -> Result<(TreeFiles, Vec<PathBuf>)> {
+    let mut merged = TreeFiles::new();
+    let mut conflicts = vec![];
+    // contents with conflict markers, written once the worktree is updated
+    let mut marked = vec![];
+    let paths = base_files
+        .keys()
+        .chain(our_files.keys())
+        .chain(their_files.keys())
+        .collect::<BTreeSet<_>>();
+    for path in paths {
+        let (base, ours, theirs) = (
+            base_files.get(path),
+            our_files.get(path),
+            their_files.get(path),
+        );
+        let (ours, theirs) = match resolve(base, ours, theirs) {
+            Resolution::Clean(Some(entry)) => {
+                merged.insert(path.clone(), entry);
+                continue;
+            }
+            Resolution::Clean(None) => continue,
+            Resolution::Conflict => match (ours, theirs) {
+                (Some(ours), Some(theirs)) => (ours, theirs),
+                (Some(_), None) => {
+                    println!(
+                        "CONFLICT (modify/delete): {0} deleted in {label} and modified in HEAD. \
+                         Version HEAD of {0} left in tree.",
+                        path.display()
+                    );
+                    conflicts.push(path.clone());
+                    continue;
+                }
+                _ => {
+                    println!(
+                        "CONFLICT (modify/delete): {0} deleted in HEAD and modified in {label}. \
+                         Version {label} of {0} left in tree.",
+                        path.display()
+                    );
+                    conflicts.push(path.clone());
+                    continue;
+                }
+            },
+        };
+
+        println!("Auto-merging {}", path.display());
+        let kind = if base.is_some() { "content" } else { "add/add" };
+        if !is_regular(ours.0) || !is_regular(theirs.0) {
+            // symlinks and submodules are never merged, HEAD's is kept
+            println!("CONFLICT ({kind}): Merge conflict in {}", path.display());
+            conflicts.push(path.clone());
+            continue;
+        }
+        let content = |entry: Option<&(u32, ObjectId)>| match entry {
+            Some((mode, hash)) if is_regular(*mode) => blob_content(odb, hash),
+            _ => Ok(vec![]),
+        };
+        let (base_content, our_content, their_content) =
+            (content(base)?, content(Some(ours))?, content(Some(theirs))?);
+        if [&base_content, &our_content, &their_content]
+            .iter()
+            .any(|content| diff::is_binary(content))
+        {
+            eprintln!(
+                "warning: Cannot merge binary files: {} (HEAD vs. {label})",
+                path.display()
+            );
+            println!("CONFLICT ({kind}): Merge conflict in {}", path.display());
+            conflicts.push(path.clone());
+        } else {
+            let (content, conflicted) =
+                merge_lines(&base_content, &our_content, &their_content, "HEAD", label);
+            if conflicted == 0 {
+                // a mode changed on one side only is taken from that side
+                let mode = match base {
+                    Some((mode, _)) if *mode == ours.0 => theirs.0,
+                    _ => ours.0,
+                };
+                let hash = odb.write(b"blob", &content)?;
+                merged.insert(path.clone(), (mode, hash));
+                continue;
+            }
+            println!("CONFLICT ({kind}): Merge conflict in {}", path.display());
+            conflicts.push(path.clone());
+            marked.push((path.clone(), content));
+        }
+    }
+
+    // the side still holding a conflicted path provides its file for now
+    let mut worktree = merged.clone();
+    for path in &conflicts {
+        if let Some(entry) = our_files.get(path).or(their_files.get(path)) {
+            worktree.insert(path.clone(), *entry);
+        }
+    }
+    update_worktree(odb, our_files, &worktree)?;
+    for (path, content) in marked {
+        fs::write(path, content)?;
+    }
+
+    let mut index = Index::from_files(&merged)?;
+    for path in &conflicts {
+        let versions = [base_files, our_files, their_files];
+        for (stage, files) in (1..).zip(versions) {
+            if let Some((mode, hash)) = files.get(path) {
+                index
+                    .entries
+                    .push(Entry::staged(path, *mode, *hash, stage)?);
+            }
+        }
+    }
+    index.write()?;
+    Ok((merged, conflicts))
+}
+
+/// Merges the lines of two versions of a file changed from `base`, diff3
+/// style: lines changed on one side only take that side, and hunks changed
+/// differently on both sides are written between conflict markers labelled
+/// with `our_label` and `their_label`. Returns the merged content and the
+/// number of conflicting hunks.
+pub fn merge_lines(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    our_label: &str,
+    their_label: &str,
+) -> (Vec<u8>, usize) {
+    let (base, ours, theirs) = (diff::lines(base), diff::lines(ours), diff::lines(theirs));
+    let (our_lines, their_lines) = (kept_lines(&base, &ours), kept_lines(&base, &theirs));
+    let mut content = vec![];
+    let mut conflicts = 0;
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    loop {
+        // lines both sides kept as they are
+        while i < base.len() && our_lines[i] == Some(j) && their_lines[i] == Some(k) {
+            content.extend(base[i]);
+            (i, j, k) = (i + 1, j + 1, k + 1);
+        }
+        if i == base.len() && j == ours.len() && k == theirs.len() {
+            break;
+        }
+        // a changed hunk runs up to the next base line kept by both sides
+        let (end, our_end, their_end) = (i..base.len())
+            .find_map(|n| Some((n, our_lines[n]?, their_lines[n]?)))
+            .unwrap_or((base.len(), ours.len(), theirs.len()));
+        let (base_hunk, our_hunk, their_hunk) =
+            (&base[i..end], &ours[j..our_end], &theirs[k..their_end]);
+        (i, j, k) = (end, our_end, their_end);
+        if our_hunk == their_hunk || their_hunk == base_hunk {
+            our_hunk.iter().for_each(|line| content.extend(*line));
+            continue;
+        }
+        if our_hunk == base_hunk {
+            their_hunk.iter().for_each(|line| content.extend(*line));
+            continue;
+        }
+
+        // lines both sides agree on stay outside of the markers
+        let prefix = our_hunk
+            .iter()
+            .zip(their_hunk)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = our_hunk[prefix..]
+            .iter()
+            .rev()
+            .zip(their_hunk[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        our_hunk[..prefix]
+            .iter()
+            .for_each(|line| content.extend(*line));
+        content.extend(format!("<<<<<<< {our_label}\n").into_bytes());
+        push_lines(&mut content, &our_hunk[prefix..our_hunk.len() - suffix]);
+        content.extend(b"=======\n");
+        push_lines(&mut content, &their_hunk[prefix..their_hunk.len() - suffix]);
+        content.extend(format!(">>>>>>> {their_label}\n").into_bytes());
+        our_hunk[our_hunk.len() - suffix..]
+            .iter()
+            .for_each(|line| content.extend(*line));
+        conflicts += 1;
+    }
+    (content, conflicts)
+}
+
+/// For each line of `base`, the line of `side` it is kept as, if unchanged.
+fn kept_lines(base: &[&[u8]], side: &[&[u8]]) -> Vec<Option<usize>> {
+    let mut kept = vec![None; base.len()];
+    for edit in diff::myers(base, side) {
+        if let Edit::Equal { old, new } = edit {
+            kept[old] = Some(new);
+        }
+    }
+    kept
+}
+
+/// Appends lines inside conflict markers, so that the next marker starts on
+/// its own line.
+fn push_lines(content: &mut Vec<u8>, lines: &[&[u8]]) {
+    lines.iter().for_each(|line| content.extend(*line));
+    if !content.ends_with(b"\n") {
+        content.push(b'\n');
+    }
+}
+
+fn is_regular(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+fn blob_content(odb: &Odb, hash: &ObjectId) -> Result<Vec<u8>> {
+    let ParsedObject::Blob(content) = odb.read(&hash.to_string())?.parse()? else {
+        bail!("{hash} is not a blob");
+    };
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+        let (content, conflicts) = merge_lines(
+            base.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            "ours",
+            "theirs",
+        );
+        (String::from_utf8(content).unwrap(), conflicts)
+    }
+
+    #[test]
+    fn changes_on_one_side_are_taken() {
+        let base = "a\nb\nc\nd\ne\n";
+        assert_eq!(
+            merge(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\n"),
+            ("A\nb\nc\nd\nE\n".to_owned(), 0)
+        );
+        assert_eq!(
+            merge(base, "a\nb\nc\nd\ne\nf\n", "a\nc\nd\ne\n"),
+            ("a\nc\nd\ne\nf\n".to_owned(), 0)
+        );
+        assert_eq!(
+            merge(base, "a\nB\nc\nd\ne\n", "a\nB\nc\nd\ne\n"),
+            ("a\nB\nc\nd\ne\n".to_owned(), 0)
+        );
+    }
+
+    #[test]
+    fn markers_only_surround_conflicting_hunks() {
+        let (content, conflicts) = merge(
+            "a\nb\nc\nd\ne\n",
+            "a\nours\nc\nd\nE\n",
+            "a\ntheirs\nc\nd\ne\n",
+        );
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            content,
+            "a\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nc\nd\nE\n"
+        );
+    }
+
+    #[test]
+    fn lines_both_sides_agree_on_stay_outside_markers() {
+        let (content, conflicts) = merge("", "same\nours\nend\n", "same\ntheirs\nend\n");
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            content,
+            "same\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nend\n"
+        );
+    }
+
+    #[test]
+    fn markers_start_on_their_own_line() {
+        let (content, conflicts) = merge("a\n", "ours", "theirs");
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            content,
+            "<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n"
+        );
+    }
+}
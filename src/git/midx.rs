@@ -0,0 +1,249 @@
+use super::{config::Config, hash::HashAlgo, pack, ObjectId};
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+pub const MULTI_PACK_INDEX: &str = "multi-pack-index";
+const SIGNATURE: &[u8; 4] = b"MIDX";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 12;
+const CHUNK_ENTRY_SIZE: usize = 12;
+const PACK_NAMES: &[u8; 4] = b"PNAM";
+const OID_FANOUT: &[u8; 4] = b"OIDF";
+const OID_LOOKUP: &[u8; 4] = b"OIDL";
+const OBJECT_OFFSETS: &[u8; 4] = b"OOFF";
+const LARGE_OFFSETS: &[u8; 4] = b"LOFF";
+const FANOUT_SIZE: usize = 256 * 4;
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+/// The `multi-pack-index` of a pack directory: one sorted list of the
+/// objects of several packs, so that finding an object takes a single
+/// binary search instead of one per `.idx`.
+pub struct MultiPackIndex {
+    data: Vec<u8>,
+    /// File names of the indexed packs' `.idx` files, sorted.
+    pub pack_names: Vec<String>,
+    count: usize,
+    fanout: usize,
+    lookup: usize,
+    offsets: usize,
+    large_offsets: Option<usize>,
+    hash_size: usize,
+}
+
+impl MultiPackIndex {
+    /// Loads the multi-pack-index of `directory`. It is ignored when missing,
+    /// disabled by `core.multiPackIndex`, unreadable or when it names packs
+    /// that no longer exist.
+    pub fn open(directory: &Path) -> Option<Self> {
+        let enabled = Config::load()
+            .ok()
+            .and_then(|config| config.get_bool("core.multiPackIndex"))
+            .unwrap_or(true);
+        if !enabled {
+            return None;
+        }
+        let data = fs::read(directory.join(MULTI_PACK_INDEX)).ok()?;
+        let index = Self::parse(data).ok()?;
+        let complete = index
+            .pack_names
+            .iter()
+            .all(|name| directory.join(name).with_extension("pack").is_file());
+        complete.then_some(index)
+    }
+
+    fn parse(data: Vec<u8>) -> Result<Self> {
+        let algo = HashAlgo::current();
+        let hash_size = algo.size();
+        if data.len() < HEADER_SIZE + CHUNK_ENTRY_SIZE + hash_size
+            || &data[..4] != SIGNATURE
+            || data[4] != VERSION
+            || data[5] != algo.format_version()
+        {
+            bail!("Unsupported multi-pack-index");
+        }
+        let pack_count = u32::from_be_bytes(data[8..12].try_into()?) as usize;
+        let mut chunks = HashMap::new();
+        for i in 0..=data[6] as usize {
+            let entry = HEADER_SIZE + i * CHUNK_ENTRY_SIZE;
+            let id: [u8; 4] = data[entry..entry + 4].try_into()?;
+            let offset = u64::from_be_bytes(data[entry + 4..entry + 12].try_into()?) as usize;
+            if offset > data.len() - hash_size {
+                bail!("Multi-pack-index chunk out of bounds");
+            }
+            chunks.insert(id, offset);
+        }
+        let chunk = |id: &[u8; 4]| {
+            chunks
+                .get(id)
+                .copied()
+                .ok_or_else(|| anyhow!("Multi-pack-index lacks {}", String::from_utf8_lossy(id)))
+        };
+        let names_start = chunk(PACK_NAMES)?;
+        let pack_names = data[names_start..]
+            .split(|&b| b == 0)
+            .take(pack_count)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect::<Vec<_>>();
+        let mut index = Self {
+            pack_names,
+            count: 0,
+            fanout: chunk(OID_FANOUT)?,
+            lookup: chunk(OID_LOOKUP)?,
+            offsets: chunk(OBJECT_OFFSETS)?,
+            large_offsets: chunk(LARGE_OFFSETS).ok(),
+            hash_size,
+            data,
+        };
+        if index.fanout + FANOUT_SIZE > index.data.len() {
+            bail!("Multi-pack-index is truncated");
+        }
+        index.count = index.fanout(u8::MAX);
+        if index.lookup + index.count * hash_size > index.data.len()
+            || index.offsets + index.count * 8 > index.data.len()
+        {
+            bail!("Multi-pack-index is truncated");
+        }
+        Ok(index)
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Number of objects whose hash starts with a byte not greater than `byte`.
+    fn fanout(&self, byte: u8) -> usize {
+        self.read_u32(self.fanout + byte as usize * 4) as usize
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn hash(&self, position: usize) -> &[u8] {
+        let start = self.lookup + position * self.hash_size;
+        &self.data[start..start + self.hash_size]
+    }
+
+    /// Positions of all hashes starting with the first byte of `hash`.
+    pub fn bucket(&self, hash: &[u8]) -> std::ops::Range<usize> {
+        let start = match hash[0] {
+            0 => 0,
+            byte => self.fanout(byte - 1),
+        };
+        start..self.fanout(hash[0])
+    }
+
+    /// The position in `pack_names` of the pack holding an object and the
+    /// object's offset in it.
+    pub fn find(&self, hash: &[u8]) -> Option<(usize, u64)> {
+        let bucket = self.bucket(hash);
+        let (mut low, mut high) = (bucket.start, bucket.end);
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.hash(middle).cmp(hash) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return self.location(middle),
+            }
+        }
+        None
+    }
+
+    fn location(&self, position: usize) -> Option<(usize, u64)> {
+        let entry = self.offsets + position * 8;
+        let pack = self.read_u32(entry) as usize;
+        let offset = self.read_u32(entry + 4);
+        if offset & LARGE_OFFSET_FLAG == 0 {
+            return Some((pack, offset as u64));
+        }
+        let start = self.large_offsets? + (offset & !LARGE_OFFSET_FLAG) as usize * 8;
+        let large = self.data.get(start..start + 8)?;
+        Some((pack, u64::from_be_bytes(large.try_into().ok()?)))
+    }
+}
+
+/// Writes a multi-pack-index covering every pack in `directory`. An object
+/// stored in several packs is taken from the most recently modified one.
+pub fn write(directory: &Path) -> Result<()> {
+    let mut pack_names = vec![];
+    if directory.is_dir() {
+        for entry in directory.read_dir()? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "idx") && path.with_extension("pack").is_file()
+            {
+                pack_names.push(path.file_name().unwrap().to_string_lossy().into_owned());
+            }
+        }
+    }
+    pack_names.sort();
+
+    // (hash, pack position, offset, pack modification time)
+    let mut objects: Vec<(ObjectId, u32, u64, std::time::SystemTime)> = vec![];
+    for (position, name) in pack_names.iter().enumerate() {
+        let path = directory.join(name);
+        let modified = fs::metadata(path.with_extension("pack"))?.modified()?;
+        for (hash, offset) in pack::read_index(&path)? {
+            objects.push((hash, position as u32, offset, modified));
+        }
+    }
+    objects.sort_by(|a, b| a.0.cmp(&b.0).then(b.3.cmp(&a.3)));
+    objects.dedup_by(|later, first| later.0 == first.0);
+
+    let mut names = vec![];
+    for name in &pack_names {
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+    }
+    names.resize(names.len().next_multiple_of(4), 0);
+    let mut fanout = vec![];
+    for byte in 0..=u8::MAX {
+        let count = objects.partition_point(|(hash, ..)| hash[0] <= byte);
+        fanout.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    let mut lookup = vec![];
+    let mut offsets = vec![];
+    let mut large_offsets = vec![];
+    for (hash, pack, offset, _) in &objects {
+        lookup.extend_from_slice(hash);
+        offsets.extend_from_slice(&pack.to_be_bytes());
+        let offset = if *offset < LARGE_OFFSET_FLAG as u64 {
+            *offset as u32
+        } else {
+            large_offsets.extend_from_slice(&offset.to_be_bytes());
+            LARGE_OFFSET_FLAG | (large_offsets.len() / 8 - 1) as u32
+        };
+        offsets.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    let mut chunks = vec![
+        (PACK_NAMES, names),
+        (OID_FANOUT, fanout),
+        (OID_LOOKUP, lookup),
+        (OBJECT_OFFSETS, offsets),
+    ];
+    if !large_offsets.is_empty() {
+        chunks.push((LARGE_OFFSETS, large_offsets));
+    }
+    let algo = HashAlgo::current();
+    let mut index = vec![];
+    index.extend_from_slice(SIGNATURE);
+    index.extend_from_slice(&[VERSION, algo.format_version(), chunks.len() as u8, 0]);
+    index.extend_from_slice(&(pack_names.len() as u32).to_be_bytes());
+    let mut offset = HEADER_SIZE + (chunks.len() + 1) * CHUNK_ENTRY_SIZE;
+    for (id, chunk) in &chunks {
+        index.extend_from_slice(*id);
+        index.extend_from_slice(&(offset as u64).to_be_bytes());
+        offset += chunk.len();
+    }
+    index.extend_from_slice(&[0; 4]);
+    index.extend_from_slice(&(offset as u64).to_be_bytes());
+    for (_, chunk) in chunks {
+        index.extend(chunk);
+    }
+    let checksum = algo.digest(&index);
+    index.extend_from_slice(&checksum);
+    fs::write(directory.join(MULTI_PACK_INDEX), index)?;
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use super::index::{self, Index};
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Moves or renames tracked files or directories in the working tree and the
+/// index. With several sources, `destination` must be a directory to move
+/// them into. An existing destination file is only replaced when forced.
+pub fn mv(sources: &[PathBuf], destination: &Path, force: bool) -> Result<()> {
+    let into_directory = destination.is_dir();
+    if sources.len() > 1 && !into_directory {
+        bail!("destination '{}' is not a directory", destination.display());
+    }
+    let mut index = Index::read()?;
+    for source in sources {
+        let target = match (into_directory, source.file_name()) {
+            (true, Some(name)) => destination.join(name),
+            _ => destination.to_owned(),
+        };
+        let context = format!(
+            "source={}, destination={}",
+            source.display(),
+            target.display()
+        );
+        let name = index::pathspec_name(source)?;
+        let prefix = format!("{name}/");
+        let moved = index
+            .entries
+            .iter()
+            .filter(|entry| entry.path == name || entry.path.starts_with(&prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        if fs::symlink_metadata(source).is_err() {
+            bail!("bad source, {context}");
+        }
+        if moved.is_empty() {
+            bail!("not under version control, {context}");
+        }
+        if moved.iter().any(|entry| entry.stage != 0) {
+            bail!("conflicted, {context}");
+        }
+        if let Ok(meta) = fs::symlink_metadata(&target) {
+            if !force || meta.is_dir() || source.is_dir() {
+                bail!("destination exists, {context}");
+            }
+        }
+        if target
+            .parent()
+            .is_some_and(|parent| !parent.as_os_str().is_empty() && !parent.is_dir())
+        {
+            bail!("destination directory does not exist, {context}");
+        }
+
+        fs::rename(source, &target)?;
+        let target_name = index::pathspec_name(&target)?;
+        index.remove(&target_name);
+        for mut entry in moved {
+            index.remove(&entry.path);
+            entry.path = format!("{target_name}{}", &entry.path[name.len()..]);
+            index.add(entry);
+        }
+    }
+    index.write()
+}
@@ -0,0 +1,118 @@
+use super::{
+    flatten_tree, parse_hash, refs, remote::Sha1, revparse, write_commit, write_tree_from_files,
+    ObjectId, Odb, ParsedObject, TreeFiles,
+};
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const NOTES_REF: &str = "refs/notes/commits";
+const ADD_MESSAGE: &str = "Notes added by 'git notes add'";
+
+/// The notes recorded in `refs/notes/commits`: a tree of blobs named after
+/// the objects they annotate, possibly fanned out as `ab/cdef...`.
+pub struct Notes {
+    commit: Option<Sha1>,
+    notes: BTreeMap<Sha1, ObjectId>,
+}
+
+impl Notes {
+    pub fn load(odb: &Odb) -> Result<Self> {
+        let Some(commit) = refs::peel(NOTES_REF)? else {
+            return Ok(Self {
+                commit: None,
+                notes: BTreeMap::new(),
+            });
+        };
+        let mut notes = BTreeMap::new();
+        for (path, (_, hash)) in flatten_tree(odb, &odb.read_commit(&commit)?.tree)? {
+            let object = path.to_string_lossy().replace('/', "");
+            // anything else in the tree is not a note
+            if parse_hash(&object).is_ok() {
+                notes.insert(object, hash);
+            }
+        }
+        Ok(Self {
+            commit: Some(commit),
+            notes,
+        })
+    }
+
+    /// The note attached to an object, if any.
+    pub fn get(&self, odb: &Odb, object: &str) -> Result<Option<String>> {
+        let Some(hash) = self.notes.get(object) else {
+            return Ok(None);
+        };
+        let ParsedObject::Blob(content) = odb.read(&hash.to_string())?.parse()? else {
+            bail!("note {hash} is not a blob");
+        };
+        Ok(Some(String::from_utf8_lossy(&content).into_owned()))
+    }
+}
+
+/// Attaches a note to an object, replacing an existing one only if `force`.
+pub fn add(revision: &str, message: &str, force: bool) -> Result<()> {
+    let message = message.trim_end();
+    if message.trim().is_empty() {
+        bail!("Aborting: the note is empty");
+    }
+    let object = revparse::resolve(revision)?;
+    let odb = Odb::open()?;
+    let mut notes = Notes::load(&odb)?;
+    if notes.notes.contains_key(&object) {
+        if !force {
+            bail!("Cannot add notes. Found existing notes for object {object}. Use '-f' to overwrite existing notes");
+        }
+        eprintln!("Overwriting existing notes for object {object}");
+    }
+    let note = odb.write(b"blob", format!("{message}\n").as_bytes())?;
+    notes.notes.insert(object, note);
+
+    // notes are written flat, whatever fanout they were read with
+    let files = notes
+        .notes
+        .iter()
+        .map(|(object, hash)| (PathBuf::from(object), (0o100644, *hash)))
+        .collect::<TreeFiles>();
+    let tree = write_tree_from_files(&odb, &files)?;
+    let parents = notes
+        .commit
+        .iter()
+        .map(|commit| parse_hash(commit))
+        .collect::<Result<Vec<_>>>()?;
+    let commit = write_commit(&tree, &parents, ADD_MESSAGE)?;
+    Ok(refs::update(
+        NOTES_REF,
+        &commit.to_string(),
+        &format!("notes: {ADD_MESSAGE}"),
+    )?)
+}
+
+/// Prints the note attached to an object.
+pub fn show(revision: &str) -> Result<()> {
+    let object = revparse::resolve(revision)?;
+    let odb = Odb::open()?;
+    let note = Notes::load(&odb)?
+        .get(&odb, &object)?
+        .ok_or_else(|| anyhow!("no note found for object {object}."))?;
+    print!("{note}");
+    Ok(())
+}
+
+/// Lists notes as `<note> <annotated object>`, or just the note of one object.
+pub fn list(revision: Option<&str>) -> Result<()> {
+    let notes = Notes::load(&Odb::open()?)?;
+    let Some(revision) = revision else {
+        for (object, note) in &notes.notes {
+            println!("{note} {object}");
+        }
+        return Ok(());
+    };
+    let object = revparse::resolve(revision)?;
+    let note = notes
+        .notes
+        .get(&object)
+        .ok_or_else(|| anyhow!("no note found for object {object}."))?;
+    println!("{note}");
+    Ok(())
+}
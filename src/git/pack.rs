@@ -1,17 +1,39 @@
-use super::{Object, HASH_HEX_SIZE};
-use anyhow::{anyhow, bail, Ok, Result};
+use super::{
+    hash::HashAlgo, midx::MultiPackIndex, remote, repository, Backend, Object, ObjectId, Odb,
+};
+use anyhow::{anyhow, bail, Context, Ok, Result};
 use bytes::{Buf, Bytes};
-use flate2::read::ZlibDecoder;
-use sha1::{Digest, Sha1};
-use std::{collections::HashMap, io::Read};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
 const SIGNATURE_SIZE: usize = 4;
 const SIGNATURE: &[u8; SIGNATURE_SIZE] = b"PACK";
 const VERSION: u32 = 2;
-const HASH_SIZE: usize = HASH_HEX_SIZE / 2;
-const PACK_FRAME_SIZE: usize = SIGNATURE_SIZE + std::mem::size_of::<u32>() * 2 + HASH_SIZE;
+const INDEX_SIGNATURE: &[u8; 4] = b"\xfftOc";
+const INDEX_VERSION: u32 = 2;
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+const PACK_HEADER_SIZE: usize = SIGNATURE_SIZE + std::mem::size_of::<u32>() * 2;
+const FANOUT_SIZE: usize = 256;
+const INDEX_HEADER_SIZE: usize = SIGNATURE_SIZE + std::mem::size_of::<u32>();
+// enough for the type and size varint plus a delta base reference
+const MAX_OBJECT_HEADER_SIZE: u64 = 48;
+/// How many preceding objects are tried as delta bases.
+const DELTA_WINDOW: usize = 10;
+const MAX_DELTA_DEPTH: usize = 50;
+/// Granularity at which delta bases are indexed for matches.
+const DELTA_BLOCK_SIZE: usize = 16;
+const MAX_COPY_SIZE: usize = 0x10000;
+const MAX_INSERT_SIZE: usize = 0x7f;
 
 #[repr(u8)]
+#[derive(Clone, Copy, PartialEq)]
 enum ObjectTypeId {
     Commit = 1,
     Tree = 2,
@@ -21,16 +43,17 @@ enum ObjectTypeId {
     ReferenceDelta = 7,
 }
 
-impl ToString for ObjectTypeId {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Commit => "commit".to_owned(),
-            Self::Tree => "tree".to_owned(),
-            Self::Blob => "blob".to_owned(),
-            Self::Tag => "tag".to_owned(),
-            Self::OffsetDelta => "ofs_delta".to_owned(),
-            Self::ReferenceDelta => "ref_delta".to_owned(),
-        }
+impl std::fmt::Display for ObjectTypeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Commit => "commit",
+            Self::Tree => "tree",
+            Self::Blob => "blob",
+            Self::Tag => "tag",
+            Self::OffsetDelta => "ofs_delta",
+            Self::ReferenceDelta => "ref_delta",
+        };
+        f.write_str(name)
     }
 }
 
@@ -50,59 +73,917 @@ impl TryFrom<usize> for ObjectTypeId {
     }
 }
 
+impl TryFrom<&[u8]> for ObjectTypeId {
+    type Error = anyhow::Error;
+
+    fn try_from(kind: &[u8]) -> Result<Self, Self::Error> {
+        match kind {
+            b"commit" => Ok(ObjectTypeId::Commit),
+            b"tree" => Ok(ObjectTypeId::Tree),
+            b"blob" => Ok(ObjectTypeId::Blob),
+            b"tag" => Ok(ObjectTypeId::Tag),
+            _ => Err(anyhow!(
+                "Unsupported object kind {}",
+                String::from_utf8_lossy(kind)
+            )),
+        }
+    }
+}
+
+/// An object read from a pack along with where and how it was stored.
+pub struct PackEntry {
+    pub offset: usize,
+    pub crc32: u32,
+    pub object: Object,
+    /// Size given in the entry header: of the delta for deltified objects.
+    pub size: usize,
+    /// Bytes the entry takes in the pack, header included.
+    pub packed_size: usize,
+    /// Length of the delta chain leading to the object, 0 if stored whole.
+    pub depth: usize,
+    pub base: Option<ObjectId>,
+}
+
 pub fn parse(pack_buffer: Vec<u8>) -> Result<Vec<Object>> {
-    let mut parser = Bytes::from(pack_buffer);
+    let entries = parse_entries(Bytes::from(pack_buffer))?;
+    Ok(entries.into_iter().map(|entry| entry.object).collect())
+}
+
+fn parse_entries(pack: Bytes) -> Result<Vec<PackEntry>> {
+    parse_entries_with(pack, &|_| Ok(None), true)
+}
+
+/// Where the base of a deltified entry comes from.
+enum DeltaBase {
+    Entry(usize),
+    /// An object outside the pack, as thin packs refer to.
+    External(Object),
+    /// An entry not parsed yet, with the error to report if it never is.
+    Later(BaseKey, String),
+    Missing(String),
+}
+
+enum BaseKey {
+    Hash(ObjectId),
+    Offset(usize),
+}
+
+/// An entry read from the pack, its content still a delta for deltified
+/// objects.
+struct RawEntry {
+    offset: usize,
+    end: usize,
+    id: ObjectTypeId,
+    size: usize,
+    content: Vec<u8>,
+}
+
+/// The entries parsed so far, found by hash or offset as delta bases.
+#[derive(Default)]
+struct ParsedEntries {
+    entries: Vec<PackEntry>,
+    by_hash: HashMap<ObjectId, usize>,
+    by_offset: HashMap<usize, usize>,
+}
+
+impl ParsedEntries {
+    fn find(&self, key: &BaseKey) -> Option<usize> {
+        match key {
+            BaseKey::Hash(hash) => self.by_hash.get(hash),
+            BaseKey::Offset(offset) => self.by_offset.get(offset),
+        }
+        .copied()
+    }
+
+    /// Resolves an entry against its base and adds it. Unless `strict`,
+    /// an entry that cannot be resolved is reported and left out.
+    fn add(
+        &mut self,
+        pack: &Bytes,
+        raw: RawEntry,
+        base: Option<DeltaBase>,
+        strict: bool,
+    ) -> Result<()> {
+        let RawEntry {
+            offset,
+            end,
+            id,
+            size,
+            content,
+        } = raw;
+        let resolved = match base {
+            Some(DeltaBase::Entry(index)) => {
+                let base = &self.entries[index];
+                patch_object(&base.object, content)
+                    .map(|object| (object, base.depth + 1, Some(base.object.hash())))
+            }
+            Some(DeltaBase::External(base)) => {
+                patch_object(&base, content).map(|object| (object, 1, Some(base.hash())))
+            }
+            Some(DeltaBase::Later(_, message) | DeltaBase::Missing(message)) => {
+                Err(anyhow!(message))
+            }
+            None => Ok((Object::new(id.to_string().as_bytes(), &content), 0, None)),
+        };
+        let (object, depth, base) = match resolved {
+            Err(error) if !strict => {
+                eprintln!("error: skipping entry at offset {offset}: {error:#}");
+                return Ok(());
+            }
+            resolved => resolved?,
+        };
+        let mut crc = Crc::new();
+        crc.update(&pack[offset..end]);
+        self.by_hash.insert(object.hash(), self.entries.len());
+        self.by_offset.insert(offset, self.entries.len());
+        self.entries.push(PackEntry {
+            offset,
+            crc32: crc.sum(),
+            object,
+            size,
+            packed_size: end - offset,
+            depth,
+            base,
+        });
+        Ok(())
+    }
+}
+
+/// Parses all entries of a pack. Reference deltas against objects outside
+/// the pack are resolved with `external`. Deltas whose base comes later in
+/// the pack are resolved after the other entries, like index-pack does.
+/// Unless `strict`, entries whose delta cannot be applied are reported and
+/// left out.
+fn parse_entries_with(
+    pack: Bytes,
+    external: &dyn Fn(&[u8]) -> Result<Option<Object>>,
+    strict: bool,
+) -> Result<Vec<PackEntry>> {
+    let mut parser = pack.clone();
     verify_pack(&mut parser)?;
+    let hash_size = HashAlgo::current().size();
+    let data_end = pack.len() - hash_size;
     let object_number = parser.get_u32();
-    println!("Object number: {object_number}");
-    let mut objects = Vec::with_capacity(object_number as usize);
-    let mut ref_to_index = HashMap::new();
+    let mut parsed = ParsedEntries::default();
+    let mut deferred = vec![];
     for _ in 0..object_number {
+        let offset = data_end - parser.remaining();
         let (id, size) = parse_object_header(&mut parser)?;
-        println!("{} {size}", id.to_string());
         use ObjectTypeId::*;
-        match id {
-            Commit | Tree | Blob | Tag => {
-                let content = unpack_content(size, &mut parser)?;
+        let base = match id {
+            Commit | Tree | Blob | Tag => None,
+            ReferenceDelta => {
+                if parser.remaining() < hash_size {
+                    bail!("Truncated delta reference");
+                }
+                let reference = ObjectId::from_bytes(&parser.copy_to_bytes(hash_size))?;
+                let key = BaseKey::Hash(reference);
+                Some(match parsed.find(&key) {
+                    Some(index) => DeltaBase::Entry(index),
+                    None => match external(&reference)? {
+                        Some(object) => DeltaBase::External(object),
+                        None => {
+                            DeltaBase::Later(key, format!("Unknown object reference {reference}"))
+                        }
+                    },
+                })
+            }
+            OffsetDelta => {
+                let distance = parse_offset(&mut parser)?;
+                let message = format!("Wrong delta base offset {distance} at {offset}");
+                Some(match offset.checked_sub(distance) {
+                    Some(base) => {
+                        let key = BaseKey::Offset(base);
+                        match parsed.find(&key) {
+                            Some(index) => DeltaBase::Entry(index),
+                            // the base may itself be a delta waiting for its base
+                            None => DeltaBase::Later(key, message),
+                        }
+                    }
+                    None => DeltaBase::Missing(message),
+                })
+            }
+        };
+        let content = unpack_content(size, &mut parser)?;
+        let raw = RawEntry {
+            offset,
+            end: data_end - parser.remaining(),
+            id,
+            size,
+            content,
+        };
+        match base {
+            Some(DeltaBase::Later(key, message)) => deferred.push((raw, key, message)),
+            base => parsed.add(&pack, raw, base, strict)?,
+        }
+    }
+    // every pass resolves the deltas whose bases the previous one added
+    while !deferred.is_empty() {
+        let waiting = deferred.len();
+        let mut unresolved = vec![];
+        for (raw, key, message) in deferred {
+            match parsed.find(&key) {
+                Some(index) => parsed.add(&pack, raw, Some(DeltaBase::Entry(index)), strict)?,
+                None => unresolved.push((raw, key, message)),
+            }
+        }
+        deferred = unresolved;
+        if deferred.len() == waiting {
+            for (raw, key, message) in deferred.drain(..) {
+                parsed.add(&pack, raw, Some(DeltaBase::Later(key, message)), strict)?;
+            }
+        }
+    }
+    let mut entries = parsed.entries;
+    entries.sort_by_key(|entry| entry.offset);
+    Ok(entries)
+}
+
+/// Writes the objects of a pack as loose objects, resolving deltas against
+/// objects already in the repository. Broken deltas abort only when
+/// `strict`; a dry run parses the pack without writing anything.
+pub fn unpack_objects(input: &mut dyn Read, dry_run: bool, strict: bool) -> Result<()> {
+    let mut buffer = vec![];
+    input.read_to_end(&mut buffer)?;
+    let odb = Odb::open()?;
+    let external = |hash: &[u8]| {
+        let hash = hex::encode(hash);
+        match odb.exists(&hash)? {
+            true => Ok(Some(odb.read(&hash)?)),
+            false => Ok(None),
+        }
+    };
+    let entries = parse_entries_with(Bytes::from(buffer), &external, strict)?;
+    if !dry_run {
+        for entry in &entries {
+            odb.write(entry.object.kind()?, &entry.object.content)?;
+        }
+    }
+    Ok(())
+}
+
+/// Stores a pack under `.git/objects/pack` next to a generated version 2 `.idx`.
+/// Returns the pack checksum, which also names both files.
+pub fn index_pack(pack_buffer: Vec<u8>) -> Result<String> {
+    let pack = Bytes::from(pack_buffer);
+    let entries = parse_entries(pack.clone())?;
+    store(&pack, build_index(&pack, entries))
+}
+
+/// Writes the `.idx` of the pack at `path` next to it, reading the pack from
+/// `input` and saving it to `path` first if given. Without a path the pack is
+/// stored in the repository like a fetched one. Returns the pack checksum.
+pub fn index_pack_file(path: Option<&Path>, input: Option<&mut dyn Read>) -> Result<String> {
+    let pack = match (path, input) {
+        (_, Some(input)) => {
+            let mut buffer = vec![];
+            input.read_to_end(&mut buffer)?;
+            Bytes::from(buffer)
+        }
+        (Some(path), None) => Bytes::from(fs::read(path)?),
+        (None, None) => bail!("A pack file or input is required"),
+    };
+    let entries = parse_entries(pack.clone())?;
+    let index = build_index(&pack, entries);
+    let Some(path) = path else {
+        return store(&pack, index);
+    };
+    if !path.exists() {
+        fs::write(path, &pack)?;
+    }
+    fs::write(path.with_extension("idx"), index)?;
+    Ok(hex::encode(checksum(&pack)))
+}
+
+/// The trailing checksum of a pack, which also names it.
+fn checksum(pack: &[u8]) -> &[u8] {
+    &pack[pack.len() - HashAlgo::current().size()..]
+}
+
+fn store(pack: &Bytes, index: Vec<u8>) -> Result<String> {
+    let name = hex::encode(checksum(pack));
+    let directory = repository::path("objects/pack");
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join(format!("pack-{name}.pack")), pack)?;
+    fs::write(directory.join(format!("pack-{name}.idx")), index)?;
+    Ok(name)
+}
+
+/// Builds the version 2 `.idx` of a parsed pack.
+fn build_index(pack: &Bytes, mut entries: Vec<PackEntry>) -> Vec<u8> {
+    let checksum = checksum(pack);
+    entries.sort_by_cached_key(|entry| entry.object.hash());
+
+    let mut index = vec![];
+    index.extend_from_slice(INDEX_SIGNATURE);
+    index.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+    let hashes = entries
+        .iter()
+        .map(|entry| entry.object.hash())
+        .collect::<Vec<_>>();
+    for first_byte in 0..=u8::MAX {
+        let count = hashes.partition_point(|hash| hash[0] <= first_byte);
+        index.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    for hash in &hashes {
+        index.extend_from_slice(hash);
+    }
+    for entry in &entries {
+        index.extend_from_slice(&entry.crc32.to_be_bytes());
+    }
+    let mut large_offsets = vec![];
+    for entry in &entries {
+        let offset = if entry.offset < LARGE_OFFSET_FLAG as usize {
+            entry.offset as u32
+        } else {
+            large_offsets.push(entry.offset as u64);
+            LARGE_OFFSET_FLAG | (large_offsets.len() - 1) as u32
+        };
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+    for offset in large_offsets {
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+    index.extend_from_slice(checksum);
+    let index_checksum = HashAlgo::current().digest(&index);
+    index.extend_from_slice(&index_checksum);
+    index
+}
+
+/// Checks a pack against its trailing checksum and its `.idx`, given the path
+/// of either. Verbose output lists every object with its type, size, size in
+/// the pack, offset and delta chain, like `git verify-pack -v`.
+pub fn verify(path: &Path, verbose: bool) -> Result<()> {
+    let pack_path = path.with_extension("pack");
+    let pack = Bytes::from(fs::read(&pack_path)?);
+    let entries =
+        parse_entries(pack.clone()).with_context(|| format!("Bad pack {}", pack_path.display()))?;
+    let index_path = path.with_extension("idx");
+    PackIndex::open(&index_path)?
+        .verify(checksum(&pack), &entries)
+        .with_context(|| format!("Bad pack index {}", index_path.display()))?;
+    if !verbose {
+        return Ok(());
+    }
+    let mut chains = std::collections::BTreeMap::<usize, usize>::new();
+    for entry in &entries {
+        let kind = String::from_utf8_lossy(entry.object.kind()?).into_owned();
+        let hash = hex::encode(entry.object.hash());
+        let line = format!(
+            "{hash} {kind:<6} {} {} {}",
+            entry.size, entry.packed_size, entry.offset
+        );
+        match &entry.base {
+            Some(base) => println!("{line} {} {}", entry.depth, hex::encode(base)),
+            None => println!("{line}"),
+        }
+        *chains.entry(entry.depth).or_default() += 1;
+    }
+    for (depth, count) in chains {
+        match depth {
+            0 => println!("non delta: {count} objects"),
+            depth => println!("chain length = {depth}: {count} objects"),
+        }
+    }
+    println!("{}: ok", pack_path.display());
+    Ok(())
+}
+
+/// Builds a version 2 pack from objects paired with the path they were found at
+/// (empty for commits and tags). Objects of the same type are sorted by file name
+/// and size so that similar ones fall into the same window and can be stored as
+/// OFS deltas against each other.
+pub fn write_pack(objects: &[(Object, String)]) -> Result<Vec<u8>> {
+    let kinds = objects
+        .iter()
+        .map(|(object, _)| ObjectTypeId::try_from(object.kind()?))
+        .collect::<Result<Vec<_>>>()?;
+    let mut order = (0..objects.len()).collect::<Vec<_>>();
+    order.sort_by_cached_key(|&i| {
+        let (object, path) = &objects[i];
+        let name = path.rsplit('/').next().unwrap_or_default();
+        (
+            kinds[i] as u8,
+            name.to_owned(),
+            std::cmp::Reverse(object.content.len()),
+        )
+    });
+
+    let mut pack = vec![];
+    pack.extend_from_slice(SIGNATURE);
+    pack.extend_from_slice(&VERSION.to_be_bytes());
+    pack.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+    // offset and delta chain length of every written object, in write order
+    let mut written: Vec<(usize, usize)> = Vec::with_capacity(objects.len());
+    for (position, &i) in order.iter().enumerate() {
+        let object = &objects[i].0;
+        let offset = pack.len();
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for base_position in position.saturating_sub(DELTA_WINDOW)..position {
+            let base_index = order[base_position];
+            if kinds[base_index] != kinds[i] || written[base_position].1 >= MAX_DELTA_DEPTH {
+                continue;
+            }
+            let max_size = match &best {
+                Some((_, delta)) => delta.len(),
+                None => object.content.len() / 2,
+            };
+            if let Some(delta) =
+                create_delta(&objects[base_index].0.content, &object.content, max_size)
+            {
+                best = Some((base_position, delta));
+            }
+        }
+        let (data, depth) = match best {
+            Some((base_position, delta)) => {
+                let (base_offset, base_depth) = written[base_position];
+                write_object_header(&mut pack, ObjectTypeId::OffsetDelta, delta.len());
+                pack.extend(encode_offset(offset - base_offset));
+                (delta, base_depth + 1)
+            }
+            None => {
+                write_object_header(&mut pack, kinds[i], object.content.len());
+                (object.content.clone(), 0)
+            }
+        };
+        let mut encoder = ZlibEncoder::new(&mut pack, Compression::default());
+        encoder.write_all(&data)?;
+        encoder.finish()?;
+        written.push((offset, depth));
+    }
+    let checksum = HashAlgo::current().digest(&pack);
+    pack.extend_from_slice(&checksum);
+    Ok(pack)
+}
+
+fn write_object_header(pack: &mut Vec<u8>, id: ObjectTypeId, size: usize) {
+    let mut byte = ((id as u8) << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size != 0 {
+        pack.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    pack.push(byte);
+}
+
+/// Inverse of `parse_offset`.
+fn encode_offset(mut distance: usize) -> Vec<u8> {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance != 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+fn write_multibyte_number(delta: &mut Vec<u8>, mut number: usize) {
+    while number >= 0x80 {
+        delta.push(0x80 | (number & 0x7f) as u8);
+        number >>= 7;
+    }
+    delta.push(number as u8);
+}
+
+/// Encodes `target` as copy and insert instructions against `source`. Gives up
+/// once the delta would grow beyond `max_size`.
+fn create_delta(source: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    if source.len() < DELTA_BLOCK_SIZE || target.len() < DELTA_BLOCK_SIZE {
+        return None;
+    }
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for start in (0..=source.len() - DELTA_BLOCK_SIZE).step_by(DELTA_BLOCK_SIZE) {
+        blocks
+            .entry(&source[start..start + DELTA_BLOCK_SIZE])
+            .or_insert(start);
+    }
+    let mut delta = vec![];
+    write_multibyte_number(&mut delta, source.len());
+    write_multibyte_number(&mut delta, target.len());
+    let mut pending_insert = 0;
+    let mut position = 0;
+    while position < target.len() {
+        let matched = target
+            .get(position..position + DELTA_BLOCK_SIZE)
+            .and_then(|block| blocks.get(block));
+        let Some(&source_start) = matched else {
+            position += 1;
+            pending_insert += 1;
+            continue;
+        };
+        let mut length = DELTA_BLOCK_SIZE;
+        while source_start + length < source.len()
+            && position + length < target.len()
+            && source[source_start + length] == target[position + length]
+        {
+            length += 1;
+        }
+        push_insert(&mut delta, &target[position - pending_insert..position]);
+        pending_insert = 0;
+        push_copy(&mut delta, source_start, length);
+        position += length;
+        if delta.len() > max_size {
+            return None;
+        }
+    }
+    push_insert(&mut delta, &target[position - pending_insert..position]);
+    (delta.len() <= max_size).then_some(delta)
+}
+
+fn push_insert(delta: &mut Vec<u8>, mut data: &[u8]) {
+    while !data.is_empty() {
+        let size = data.len().min(MAX_INSERT_SIZE);
+        delta.push(size as u8);
+        delta.extend_from_slice(&data[..size]);
+        data = &data[size..];
+    }
+}
+
+fn push_copy(delta: &mut Vec<u8>, mut offset: usize, mut length: usize) {
+    while length > 0 {
+        let size = length.min(MAX_COPY_SIZE);
+        let mut header = 0x80;
+        let mut arguments = vec![];
+        for byte_index in 0..4 {
+            let byte = (offset >> (8 * byte_index)) as u8;
+            if byte != 0 {
+                header |= 1 << byte_index;
+                arguments.push(byte);
+            }
+        }
+        // a size of 0x10000 is encoded as zero
+        let encoded_size = size % MAX_COPY_SIZE;
+        for byte_index in 0..3 {
+            let byte = (encoded_size >> (8 * byte_index)) as u8;
+            if byte != 0 {
+                header |= 1 << (4 + byte_index);
+                arguments.push(byte);
+            }
+        }
+        delta.push(header);
+        delta.extend(arguments);
+        offset += size;
+        length -= size;
+    }
+}
 
-                let object = Object::new(id.to_string().as_bytes(), &content);
-                ref_to_index.insert(object.hash(), objects.len());
-                println!("hash {}", hex::encode(object.hash()));
-                objects.push(object);
+/// A version 2 `.idx` file loaded into memory together with the pack it describes.
+struct PackIndex {
+    pack_path: PathBuf,
+    data: Bytes,
+    count: usize,
+    hash_size: usize,
+}
+
+impl PackIndex {
+    fn open(index_path: &Path) -> Result<Self> {
+        Self::parse(
+            Bytes::from(fs::read(index_path)?),
+            index_path.with_extension("pack"),
+        )
+        .with_context(|| format!("Bad pack index {}", index_path.display()))
+    }
+
+    fn parse(data: Bytes, pack_path: PathBuf) -> Result<Self> {
+        let hash_size = HashAlgo::current().size();
+        if data.len() < INDEX_HEADER_SIZE + FANOUT_SIZE * 4 + hash_size * 2 {
+            bail!("Pack index too short");
+        }
+        if &data[..SIGNATURE_SIZE] != INDEX_SIGNATURE {
+            bail!("Wrong pack index signature");
+        }
+        let version = (&data[SIGNATURE_SIZE..]).get_u32();
+        if version != INDEX_VERSION {
+            bail!("Unsupported pack index version {version}");
+        }
+        let mut index = Self {
+            pack_path,
+            data,
+            count: 0,
+            hash_size,
+        };
+        for byte in 1..=u8::MAX {
+            if index.fanout(byte) < index.fanout(byte - 1) {
+                bail!("Non-monotonic fanout at {byte:#04x}");
             }
+        }
+        index.count = index.fanout(u8::MAX);
+        // hashes, CRCs, offsets and the two checksums, then 8 bytes per
+        // offset too large for the 31 bits of the offset table
+        let min_size =
+            INDEX_HEADER_SIZE + FANOUT_SIZE * 4 + index.count * (hash_size + 8) + hash_size * 2;
+        if index.data.len() < min_size {
+            bail!("Pack index too short for {} objects", index.count);
+        }
+        let large_table_size = index.data.len() - min_size;
+        if !large_table_size.is_multiple_of(8) {
+            bail!("Wrong size of the large offset table");
+        }
+        let large_offsets = large_table_size / 8;
+        for position in 0..index.count {
+            let offset = index.read_u32(index.offsets_start() + position * 4);
+            if offset & LARGE_OFFSET_FLAG != 0
+                && (offset & !LARGE_OFFSET_FLAG) as usize >= large_offsets
+            {
+                bail!("Large offset {} out of range", offset & !LARGE_OFFSET_FLAG);
+            }
+        }
+        Ok(index)
+    }
+
+    fn offsets_start(&self) -> usize {
+        INDEX_HEADER_SIZE + FANOUT_SIZE * 4 + self.count * (self.hash_size + 4)
+    }
+
+    /// Number of objects whose hash starts with a byte not greater than `byte`.
+    fn fanout(&self, byte: u8) -> usize {
+        self.read_u32(INDEX_HEADER_SIZE + byte as usize * 4) as usize
+    }
+
+    fn hash(&self, position: usize) -> &[u8] {
+        let start = INDEX_HEADER_SIZE + FANOUT_SIZE * 4 + position * self.hash_size;
+        &self.data[start..start + self.hash_size]
+    }
+
+    fn offset(&self, position: usize) -> u64 {
+        let offsets_start = self.offsets_start();
+        let offset = self.read_u32(offsets_start + position * 4);
+        if offset & LARGE_OFFSET_FLAG == 0 {
+            return offset as u64;
+        }
+        let large_start = offsets_start + self.count * 4;
+        let position = (offset & !LARGE_OFFSET_FLAG) as usize;
+        (&self.data[large_start + position * 8..]).get_u64()
+    }
+
+    /// Positions of all hashes starting with the first byte of `hash`.
+    fn bucket(&self, hash: &[u8]) -> Range<usize> {
+        let first_byte = hash[0];
+        let start = match first_byte {
+            0 => 0,
+            byte => self.fanout(byte - 1),
+        };
+        start..self.fanout(first_byte)
+    }
+
+    fn find(&self, hash: &[u8]) -> Option<u64> {
+        let Range { mut start, mut end } = self.bucket(hash);
+        while start < end {
+            let middle = start + (end - start) / 2;
+            match self.hash(middle).cmp(hash) {
+                Ordering::Less => start = middle + 1,
+                Ordering::Greater => end = middle,
+                Ordering::Equal => return Some(self.offset(middle)),
+            }
+        }
+        None
+    }
+
+    fn crc32(&self, position: usize) -> u32 {
+        let crcs_start = INDEX_HEADER_SIZE + FANOUT_SIZE * 4 + self.count * self.hash_size;
+        self.read_u32(crcs_start + position * 4)
+    }
+
+    /// Checks the index checksum and that it lists exactly the entries of the
+    /// pack with the given checksum, at their offsets and with their CRCs.
+    fn verify(&self, pack_checksum: &[u8], entries: &[PackEntry]) -> Result<()> {
+        let checksum_offset = self.data.len() - self.hash_size;
+        if *HashAlgo::current().digest(&self.data[..checksum_offset])
+            != self.data[checksum_offset..]
+        {
+            bail!("Index checksum mismatch");
+        }
+        if &self.data[checksum_offset - self.hash_size..checksum_offset] != pack_checksum {
+            bail!("Index does not belong to the pack");
+        }
+        if self.count != entries.len() {
+            bail!(
+                "Index lists {} objects, the pack has {}",
+                self.count,
+                entries.len()
+            );
+        }
+        let mut sorted = entries
+            .iter()
+            .map(|entry| (entry.object.hash(), entry))
+            .collect::<Vec<_>>();
+        sorted.sort_by_key(|a| a.0);
+        for (position, (hash, entry)) in sorted.iter().enumerate() {
+            if self.hash(position) != hash.as_bytes() {
+                bail!(
+                    "Index lists {} instead of {}",
+                    hex::encode(self.hash(position)),
+                    hex::encode(hash)
+                );
+            }
+            if self.offset(position) != entry.offset as u64 || self.crc32(position) != entry.crc32 {
+                bail!("Wrong offset or CRC for {}", hex::encode(hash));
+            }
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<(ObjectId, u64)>> {
+        (0..self.count)
+            .map(|position| {
+                Ok((
+                    ObjectId::from_bytes(self.hash(position))?,
+                    self.offset(position),
+                ))
+            })
+            .collect()
+    }
+
+    fn read_u32(&self, start: usize) -> u32 {
+        (&self.data[start..]).get_u32()
+    }
+}
+
+/// Every object listed in a downloaded `.idx` with its offset in the pack.
+pub fn parse_index(index: Vec<u8>) -> Result<Vec<(ObjectId, u64)>> {
+    PackIndex::parse(Bytes::from(index), PathBuf::new())?.entries()
+}
+
+/// Every object listed in a `.idx` file with its offset in the pack.
+pub fn read_index(path: &Path) -> Result<Vec<(ObjectId, u64)>> {
+    PackIndex::open(path)?.entries()
+}
+
+/// Object store backed by the packs in `.git/objects/pack`, indexes loaded up front.
+/// Packs covered by a multi-pack-index are looked up through it alone.
+pub struct Packs {
+    indexes: Vec<PackIndex>,
+    /// The multi-pack-index with the paths of its packs, in its order.
+    multi_pack: Option<(MultiPackIndex, Vec<PathBuf>)>,
+}
+
+impl Packs {
+    pub fn open(directory: &Path) -> Result<Self> {
+        let multi_pack = MultiPackIndex::open(directory).map(|index| {
+            let paths = index
+                .pack_names
+                .iter()
+                .map(|name| directory.join(name).with_extension("pack"))
+                .collect::<Vec<_>>();
+            (index, paths)
+        });
+        let covered = multi_pack
+            .as_ref()
+            .map(|(index, _)| index.pack_names.clone())
+            .unwrap_or_default();
+        let mut indexes = vec![];
+        if directory.is_dir() {
+            for entry in directory.read_dir()? {
+                let path = entry?.path();
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned());
+                if path.extension().is_some_and(|extension| extension == "idx")
+                    && !name.is_some_and(|name| covered.contains(&name))
+                {
+                    indexes.push(PackIndex::open(&path)?);
+                }
+            }
+        }
+        Ok(Self {
+            indexes,
+            multi_pack,
+        })
+    }
+
+    fn read_packed(&self, pack: &mut fs::File, offset: u64) -> Result<Object> {
+        pack.seek(SeekFrom::Start(offset))?;
+        let mut header = vec![];
+        (&mut *pack)
+            .take(MAX_OBJECT_HEADER_SIZE)
+            .read_to_end(&mut header)?;
+        let mut parser = Bytes::from(header);
+        let header_size = parser.len();
+        let (id, size) = parse_object_header(&mut parser)?;
+        use ObjectTypeId::*;
+        let base = match id {
+            Commit | Tree | Blob | Tag => None,
             ReferenceDelta => {
-                let reference = parser.copy_to_bytes(HASH_SIZE).to_vec();
-                if let Some(index) = ref_to_index.get(&reference) {
-                    let source_object = &objects[*index];
-                    let object = patch_object(source_object, size, &mut parser)?;
-                    ref_to_index.insert(object.hash(), objects.len());
-                    objects.push(object);
-                } else {
-                    bail!("Unknown object reference {}", hex::encode(reference));
+                let hash_size = HashAlgo::current().size();
+                if parser.remaining() < hash_size {
+                    bail!("Truncated delta reference");
                 }
+                let reference = parser.copy_to_bytes(hash_size);
+                let base = self.read(&reference)?.ok_or_else(|| {
+                    anyhow!("Unknown object reference {}", hex::encode(&reference))
+                })?;
+                Some(base)
             }
             OffsetDelta => {
-                let offset = parse_multibyte_number(&mut parser)?;
-                if offset > objects.len() {
-                    bail!(
-                        "Wrong object offset {offset}, current idx {}",
-                        objects.len()
-                    );
+                let distance = parse_offset(&mut parser)? as u64;
+                let base_offset = offset
+                    .checked_sub(distance)
+                    .ok_or_else(|| anyhow!("Wrong delta base offset {distance} at {offset}"))?;
+                Some(self.read_packed(pack, base_offset)?)
+            }
+        };
+        // reading the base moved the file cursor
+        pack.seek(SeekFrom::Start(
+            offset + (header_size - parser.remaining()) as u64,
+        ))?;
+        let mut content = Vec::with_capacity(size);
+        ZlibDecoder::new(BufReader::new(&mut *pack)).read_to_end(&mut content)?;
+        match base {
+            Some(base) => patch_object(&base, content),
+            None => Ok(Object::new(id.to_string().as_bytes(), &content)),
+        }
+    }
+}
+
+impl Backend for Packs {
+    fn read(&self, hash: &[u8]) -> Result<Option<Object>> {
+        if let Some((multi_pack, paths)) = &self.multi_pack {
+            if let Some((pack, offset)) = multi_pack.find(hash) {
+                let path = paths
+                    .get(pack)
+                    .ok_or_else(|| anyhow!("Bad pack reference in multi-pack-index"))?;
+                let mut pack = fs::File::open(path)?;
+                return self.read_packed(&mut pack, offset).map(Some);
+            }
+        }
+        for index in &self.indexes {
+            if let Some(offset) = index.find(hash) {
+                let mut pack = fs::File::open(&index.pack_path)?;
+                return self.read_packed(&mut pack, offset).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn exists(&self, hash: &[u8]) -> Result<bool> {
+        let in_multi_pack = self
+            .multi_pack
+            .as_ref()
+            .is_some_and(|(index, _)| index.find(hash).is_some());
+        Ok(in_multi_pack || self.indexes.iter().any(|index| index.find(hash).is_some()))
+    }
+
+    fn find_abbreviated(&self, prefix: &str) -> Result<Vec<remote::Sha1>> {
+        let mut matches = vec![];
+        // pad an odd prefix so that its first byte can still select a fanout bucket
+        let first_byte = hex::decode(format!("{:0<2}", &prefix[..prefix.len().min(2)]))?;
+        if let Some((index, _)) = &self.multi_pack {
+            for position in index.bucket(&first_byte) {
+                let hash = hex::encode(index.hash(position));
+                if hash.starts_with(prefix) {
+                    matches.push(hash);
                 }
-                let index = objects.len() - offset;
-                let source_object = &mut objects[index];
-                let object = patch_object(source_object, size, &mut parser)?;
-                ref_to_index.insert(object.hash(), objects.len());
-                objects.push(object);
             }
         }
+        for index in &self.indexes {
+            for position in index.bucket(&first_byte) {
+                let hash = hex::encode(index.hash(position));
+                if hash.starts_with(prefix) {
+                    matches.push(hash);
+                }
+            }
+        }
+        Ok(matches)
+    }
+
+    fn list(&self) -> Result<Vec<remote::Sha1>> {
+        let mut hashes = vec![];
+        if let Some((index, _)) = &self.multi_pack {
+            hashes.extend((0..index.count()).map(|position| hex::encode(index.hash(position))));
+        }
+        hashes.extend(
+            self.indexes.iter().flat_map(|index| {
+                (0..index.count).map(|position| hex::encode(index.hash(position)))
+            }),
+        );
+        Ok(hashes)
+    }
+}
+
+/// Reads the negative offset of an OFS_DELTA base, which unlike sizes adds one
+/// for every continuation byte.
+fn parse_offset(parser: &mut Bytes) -> Result<usize> {
+    let mut byte = next_byte(parser)?;
+    let mut offset = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = next_byte(parser)?;
+        offset = ((offset + 1) << 7) | (byte & 0x7f) as usize;
     }
-    println!("Parsed {} objects", objects.len());
-    Ok(objects)
+    Ok(offset)
 }
 
-fn patch_object(object: &Object, delta_size: usize, parser: &mut Bytes) -> Result<Object> {
-    let mut delta_instructions = Bytes::from(unpack_content(delta_size, parser)?);
+fn next_byte(parser: &mut Bytes) -> Result<u8> {
+    if !parser.has_remaining() {
+        bail!("Unexpected end of pack");
+    }
+    Ok(parser.get_u8())
+}
+
+fn patch_object(object: &Object, delta: Vec<u8>) -> Result<Object> {
+    let mut delta_instructions = Bytes::from(delta);
     let _source_size = parse_multibyte_number(&mut delta_instructions)?;
     let target_size = parse_multibyte_number(&mut delta_instructions)?;
     let patched_content = patch_content(delta_instructions, target_size, &object.content)?;
@@ -114,18 +995,14 @@ fn patch_object(object: &Object, delta_size: usize, parser: &mut Bytes) -> Resul
 }
 
 fn verify_pack(parser: &mut Bytes) -> Result<()> {
-    if parser.len() <= PACK_FRAME_SIZE {
+    let algo = HashAlgo::current();
+    if parser.len() <= PACK_HEADER_SIZE + algo.size() {
         bail!("Pack too short: {}", parser.len());
     }
-    println!("pack length {}", parser.len());
-    let expected_hash = parser.split_off(parser.len() - HASH_SIZE);
+    let expected_hash = parser.split_off(parser.len() - algo.size());
 
-    let real_hash = Sha1::new()
-        .chain_update(&parser[..])
-        .finalize()
-        .into_iter()
-        .collect::<Vec<_>>();
-    if real_hash != expected_hash {
+    let real_hash = algo.digest(&parser[..]);
+    if *real_hash != expected_hash {
         bail!(
             "Corrupted pack: expected {}, got {}",
             hex::encode(expected_hash),
@@ -207,8 +1084,11 @@ fn patch_content(mut delta: Bytes, target_size: usize, object: &[u8]) -> Result<
         if header & COPY_BIT != 0 {
             let offset = build_number(header, 4, &mut delta)?;
             let header = header >> 4;
-            let size = build_number(header, 3, &mut delta)?;
-            println!("Copy from {offset} size {size}");
+            // a zero size stands for 0x10000
+            let size = match build_number(header, 3, &mut delta)? {
+                0 => 0x10000,
+                size => size,
+            };
             new_content.extend_from_slice(
                 object
                     .get(offset..offset + size)
@@ -216,13 +1096,12 @@ fn patch_content(mut delta: Bytes, target_size: usize, object: &[u8]) -> Result<
             );
         } else {
             let size = header as usize;
-            println!("Insert {size} bytes");
             let remaining = delta.remaining();
             if remaining < size {
                 bail!("Wrong delta");
             }
             let patch = delta.copy_to_bytes(size);
-            new_content.extend(patch.into_iter());
+            new_content.extend(patch);
         }
     }
     if new_content.len() != target_size {
@@ -251,3 +1130,158 @@ fn build_number(mask: u8, bit_width: u32, data: &mut Bytes) -> Result<usize> {
     data.advance(bytes_read);
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pack of blobs with the given contents and its `.idx`.
+    fn pack_of(contents: &[&str]) -> (Bytes, Vec<u8>) {
+        let objects = contents
+            .iter()
+            .map(|content| (Object::new(b"blob", content.as_bytes()), String::new()))
+            .collect::<Vec<_>>();
+        let pack = Bytes::from(write_pack(&objects).unwrap());
+        let index = build_index(&pack, parse_entries(pack.clone()).unwrap());
+        (pack, index)
+    }
+
+    fn parse_error(index: Vec<u8>) -> String {
+        let error = PackIndex::parse(Bytes::from(index), PathBuf::new()).err();
+        format!("{:#}", error.expect("the index is rejected"))
+    }
+
+    /// Appends a zlib-compressed entry to a pack being built.
+    fn push_entry(pack: &mut Vec<u8>, id: ObjectTypeId, base: &[u8], data: &[u8]) {
+        write_object_header(pack, id, data.len());
+        pack.extend_from_slice(base);
+        let mut encoder = ZlibEncoder::new(pack, Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    /// A delta appending `suffix` to a source of `source_size` bytes.
+    fn append_delta(source_size: usize, suffix: &[u8]) -> Vec<u8> {
+        let mut delta = vec![];
+        write_multibyte_number(&mut delta, source_size);
+        write_multibyte_number(&mut delta, source_size + suffix.len());
+        push_copy(&mut delta, 0, source_size);
+        push_insert(&mut delta, suffix);
+        delta
+    }
+
+    #[test]
+    fn deltas_may_come_before_their_base() {
+        let base = Object::new(b"blob", b"base\n");
+        let mut pack = SIGNATURE.to_vec();
+        pack.extend_from_slice(&VERSION.to_be_bytes());
+        pack.extend_from_slice(&3u32.to_be_bytes());
+        let ref_delta = pack.len();
+        let delta = append_delta(5, b"more\n");
+        push_entry(
+            &mut pack,
+            ObjectTypeId::ReferenceDelta,
+            &base.hash(),
+            &delta,
+        );
+        // a delta against the one still waiting for its base
+        let offset = encode_offset(pack.len() - ref_delta);
+        let delta = append_delta(10, b"again\n");
+        push_entry(&mut pack, ObjectTypeId::OffsetDelta, &offset, &delta);
+        push_entry(&mut pack, ObjectTypeId::Blob, &[], &base.content);
+        let checksum = HashAlgo::current().digest(&pack);
+        pack.extend_from_slice(&checksum);
+
+        let entries = parse_entries(Bytes::from(pack)).unwrap();
+        let contents = entries
+            .iter()
+            .map(|entry| (entry.object.content.as_slice(), entry.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            [
+                (&b"base\nmore\n"[..], 1),
+                (&b"base\nmore\nagain\n"[..], 2),
+                (&b"base\n"[..], 0),
+            ]
+        );
+        assert_eq!(entries[0].base, Some(base.hash()));
+    }
+
+    #[test]
+    fn index_size_is_checked_against_the_fanout() {
+        let (_, index) = pack_of(&["one\n", "two\n"]);
+        assert!(PackIndex::parse(Bytes::from(index.clone()), PathBuf::new()).is_ok());
+
+        let truncated = index[..index.len() - 1].to_vec();
+        assert_eq!(parse_error(truncated), "Pack index too short for 2 objects");
+
+        let mut inflated = index.clone();
+        let last = INDEX_HEADER_SIZE + 255 * 4;
+        inflated[last..last + 4].copy_from_slice(&1000u32.to_be_bytes());
+        assert_eq!(
+            parse_error(inflated),
+            "Pack index too short for 1000 objects"
+        );
+
+        let mut unsorted = index.clone();
+        unsorted[INDEX_HEADER_SIZE..INDEX_HEADER_SIZE + 4].copy_from_slice(&3u32.to_be_bytes());
+        assert_eq!(parse_error(unsorted), "Non-monotonic fanout at 0x01");
+    }
+
+    #[test]
+    fn large_offsets_must_be_in_the_table() {
+        let (_, mut index) = pack_of(&["one\n"]);
+        let hash_size = HashAlgo::current().size();
+        let offsets_start = INDEX_HEADER_SIZE + FANOUT_SIZE * 4 + hash_size + 4;
+        index[offsets_start..offsets_start + 4].copy_from_slice(&LARGE_OFFSET_FLAG.to_be_bytes());
+        assert_eq!(parse_error(index.clone()), "Large offset 0 out of range");
+
+        let checksums = index.split_off(index.len() - 2 * hash_size);
+        index.extend_from_slice(&12u64.to_be_bytes());
+        index.extend_from_slice(&checksums);
+        let index = PackIndex::parse(Bytes::from(index), PathBuf::new()).unwrap();
+        assert_eq!(index.offset(0), 12);
+    }
+
+    #[test]
+    fn pack_and_index_round_trip() {
+        let base = "a line repeated to make a delta worthwhile\n".repeat(20);
+        let contents = [
+            base.clone(),
+            format!("{base}one more\n"),
+            "small\n".to_owned(),
+        ];
+        let (pack, index_data) = pack_of(&contents.iter().map(String::as_str).collect::<Vec<_>>());
+        let entries = parse_entries(pack.clone()).unwrap();
+        let index = PackIndex::parse(Bytes::from(index_data.clone()), PathBuf::new()).unwrap();
+        assert_eq!(index.count, contents.len());
+        index.verify(checksum(&pack), &entries).unwrap();
+        for entry in &entries {
+            let hash = entry.object.hash();
+            assert_eq!(index.find(&hash), Some(entry.offset as u64));
+        }
+        assert_eq!(index.find(&[0xff; 20]), None);
+
+        let mut listed = parse_index(index_data)
+            .unwrap()
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect::<Vec<_>>();
+        let mut written = contents
+            .iter()
+            .map(|content| Object::new(b"blob", content.as_bytes()).hash())
+            .collect::<Vec<_>>();
+        written.sort();
+        listed.sort();
+        assert_eq!(listed, written);
+        assert!(entries.iter().any(|entry| entry.depth > 0));
+        let mut parsed = parse(pack.to_vec())
+            .unwrap()
+            .iter()
+            .map(Object::hash)
+            .collect::<Vec<_>>();
+        parsed.sort();
+        assert_eq!(parsed, written);
+    }
+}
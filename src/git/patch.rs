@@ -0,0 +1,303 @@
+use super::{
+    apply::{self, ApplyOptions},
+    date, diff, head_files,
+    index::Index,
+    parse_hash, peel_to_commit, refs, revparse,
+    revwalk::RevWalk,
+    write_commit_as, write_tree_from_files, Odb,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The fixed date of the `From` line that starts each message of an mbox.
+const MBOX_DATE: &str = "Mon Sep 17 00:00:00 2001";
+/// Longest subject part of a patch file name.
+const MAX_NAME_LENGTH: usize = 52;
+
+/// Writes one mbox-formatted patch file per commit of `range`, oldest first.
+/// `A..B` selects the commits reachable from B but not from A; a single
+/// revision is taken as the start of a range ending at HEAD.
+pub fn format_patch(range: &str, output_directory: &Path) -> Result<()> {
+    let (since, until) = match range.split_once("..") {
+        Some((since, until)) => (since, if until.is_empty() { "HEAD" } else { until }),
+        None => (range, "HEAD"),
+    };
+    let odb = Odb::open()?;
+    let mut walk = RevWalk::new(&odb);
+    walk.push(&peel_to_commit(&revparse::resolve(until)?)?)?;
+    walk.hide(&peel_to_commit(&revparse::resolve(since)?)?)?;
+    let mut commits = walk.collect::<Result<Vec<_>>>()?;
+    // merges have no single diff to send
+    commits.retain(|(_, commit)| commit.parents.len() <= 1);
+    commits.reverse();
+
+    fs::create_dir_all(output_directory)?;
+    let total = commits.len();
+    for (number, (hash, commit)) in (1..).zip(commits) {
+        let (subject, body) = split_message(&commit.message);
+        let prefix = match total {
+            1 => "[PATCH]".to_owned(),
+            _ => format!("[PATCH {number}/{total}]"),
+        };
+        let mut mail = format!("From {hash} {MBOX_DATE}\n");
+        let author = &commit.author;
+        mail.push_str(&format!(
+            "From: {}\nDate: {}\n",
+            author.person(),
+            date::format_rfc2822(author.timestamp, &author.timezone)
+        ));
+        mail.push_str(&format!("Subject: {prefix} {subject}\n\n"));
+        if !body.is_empty() {
+            mail.push_str(&format!("{body}\n"));
+        }
+        mail.push_str("---\n");
+        let mut content = mail.into_bytes();
+        let parent_tree = match commit.parents.first() {
+            Some(parent) => Some(parse_hash(&odb.read_commit(parent)?.tree)?),
+            None => None,
+        };
+        let tree = parse_hash(&commit.tree)?;
+        content.extend(diff::tree_patch(&odb, parent_tree.as_ref(), Some(&tree))?);
+        content.extend(format!("-- \n{}\n\n", env!("CARGO_PKG_VERSION")).into_bytes());
+
+        let path = output_directory.join(format!("{number:04}-{}.patch", file_name(subject)));
+        fs::write(&path, content)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// The subject line and the body of a commit message.
+fn split_message(message: &str) -> (&str, &str) {
+    let message = message.trim();
+    match message.split_once('\n') {
+        Some((subject, body)) => (subject.trim(), body.trim()),
+        None => (message, ""),
+    }
+}
+
+/// A subject reduced to letters, digits, dots and underscores joined by dashes.
+fn file_name(subject: &str) -> String {
+    let mut name = String::new();
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let mut name = name.trim_end_matches(['-', '.']).to_owned();
+    name.truncate(MAX_NAME_LENGTH);
+    name.trim_end_matches(['-', '.']).to_owned()
+}
+
+/// A commit recovered from an email.
+struct Mail {
+    author: String,
+    message: String,
+    patch: Vec<u8>,
+}
+
+/// Applies the patches of mbox files one after another, committing each with
+/// the author, date and message of its email.
+pub fn am(mailboxes: &[PathBuf]) -> Result<()> {
+    let odb = Odb::open()?;
+    if !Index::exists() {
+        Index::from_files(&head_files(&odb)?)?.write()?;
+    }
+    for mailbox in mailboxes {
+        let content = fs::read(mailbox)
+            .with_context(|| format!("Cannot read mailbox {}", mailbox.display()))?;
+        for (number, mail) in (1..).zip(split_mailbox(&content)) {
+            let mail = parse_mail(mail)?;
+            let subject = mail.message.lines().next().unwrap_or_default().to_owned();
+            println!("Applying: {subject}");
+            let patches = apply::parse(&mail.patch)?;
+            if patches.is_empty() {
+                bail!("Patch is empty.");
+            }
+            let options = ApplyOptions {
+                index: true,
+                ..Default::default()
+            };
+            apply::apply_patches(&patches, &options)
+                .with_context(|| format!("Patch failed at {number:04} {subject}"))?;
+            let tree = write_tree_from_files(&odb, &Index::read()?.files())?;
+            let parents = match refs::peel("HEAD")? {
+                Some(head) => vec![parse_hash(&head)?],
+                None => vec![],
+            };
+            let commit = write_commit_as(&tree, &parents, &mail.author, &mail.message)?;
+            refs::update("HEAD", &hex::encode(commit), &format!("am: {subject}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits an mbox at the `From <hash> <date>` lines that start each message.
+fn split_mailbox(content: &[u8]) -> Vec<&[u8]> {
+    let mut starts = vec![];
+    let mut offset = 0;
+    for line in diff::lines(content) {
+        if is_mbox_separator(line) {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    if starts.first() != Some(&0) {
+        // a single message without an mbox separator
+        starts.insert(0, 0);
+    }
+    starts.push(content.len());
+    starts.windows(2).map(|w| &content[w[0]..w[1]]).collect()
+}
+
+fn is_mbox_separator(line: &[u8]) -> bool {
+    line.starts_with(b"From ")
+        && line
+            .get(5..45)
+            .is_some_and(|hash| hash.iter().all(u8::is_ascii_hexdigit))
+}
+
+fn parse_mail(mail: &[u8]) -> Result<Mail> {
+    let (headers, rest) = match mail.windows(2).position(|w| w == b"\n\n") {
+        Some(end) => (&mail[..end], &mail[end + 2..]),
+        None => (mail, &[][..]),
+    };
+    let headers = String::from_utf8_lossy(headers);
+    let (mut from, mut date, mut subject) = (None, None, String::new());
+    let mut unfolded: Vec<String> = vec![];
+    for line in headers.lines() {
+        match unfolded.last_mut() {
+            // folded header lines continue the previous one
+            Some(previous) if line.starts_with([' ', '\t']) => {
+                previous.push(' ');
+                previous.push_str(line.trim());
+            }
+            _ => unfolded.push(line.to_owned()),
+        }
+    }
+    for header in &unfolded {
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "from" => from = Some(value.trim().to_owned()),
+            "date" => date = Some(value.trim().to_owned()),
+            "subject" => subject = clean_subject(value),
+            _ => {}
+        }
+    }
+    let from = from.ok_or_else(|| anyhow!("Patch does not have a From header"))?;
+    let (timestamp, timezone) = match date {
+        Some(date) => date::parse(&date)?,
+        None => bail!("Patch does not have a Date header"),
+    };
+
+    // the message ends at the "---" separator or where the diff begins
+    let patch_start = diff::lines(rest)
+        .iter()
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some((start, *line))
+        })
+        .find(|(_, line)| {
+            *line == b"---\n" || line.starts_with(b"diff --git ") || line.starts_with(b"Index: ")
+        })
+        .map_or(rest.len(), |(start, _)| start);
+    let body = String::from_utf8_lossy(&rest[..patch_start]);
+    let body = body.trim();
+    let message = match body {
+        "" => subject,
+        body => format!("{subject}\n\n{body}"),
+    };
+    Ok(Mail {
+        author: format!("{from} {timestamp} {timezone}"),
+        message,
+        patch: rest[patch_start..].to_vec(),
+    })
+}
+
+/// Drops `Re:` and bracketed prefixes such as `[PATCH 1/2]` from a subject.
+fn clean_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        if let Some(rest) = subject
+            .strip_prefix("Re:")
+            .or_else(|| subject.strip_prefix("re:"))
+        {
+            subject = rest.trim_start();
+        } else if let Some(end) = subject
+            .starts_with('[')
+            .then(|| subject.find(']'))
+            .flatten()
+        {
+            subject = subject[end + 1..].trim_start();
+        } else {
+            return subject.to_owned();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "\
+diff --git a/f b/f
+index 0000000..1111111 100644
+--- a/f
++++ b/f
+@@ -1 +1 @@
+-old
++new
+";
+
+    fn mail(hash: char, subject: &str, body: &str) -> String {
+        format!(
+            "From {} {MBOX_DATE}\nFrom: A U Thor <author@example.com>\nDate: {}\n\
+             Subject: {subject}\n\n{body}---\n{DIFF}-- \n2.0\n\n",
+            hash.to_string().repeat(40),
+            date::format_rfc2822(1_700_000_000, "+0100"),
+        )
+    }
+
+    #[test]
+    fn mails_round_trip() {
+        let mbox = mail('a', "[PATCH 1/2] First change", "")
+            + &mail(
+                'b',
+                "Re: [PATCH 2/2] Second\n change",
+                "Why it is needed.\n\n",
+            );
+        let mails = split_mailbox(mbox.as_bytes());
+        assert_eq!(mails.len(), 2);
+
+        let first = parse_mail(mails[0]).unwrap();
+        assert_eq!(
+            first.author,
+            "A U Thor <author@example.com> 1700000000 +0100"
+        );
+        assert_eq!(first.message, "First change");
+        assert!(first.patch.starts_with(b"---\ndiff --git a/f b/f\n"));
+        let patches = apply::parse(&first.patch).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(
+            apply::apply_hunks("f", b"old\n", &patches[0].hunks).unwrap(),
+            b"new\n"
+        );
+
+        let second = parse_mail(mails[1]).unwrap();
+        assert_eq!(second.message, "Second change\n\nWhy it is needed.");
+    }
+
+    #[test]
+    fn subjects_become_file_names() {
+        let (subject, body) = split_message("Fix: the *parser*  \n\nDetails.\n");
+        assert_eq!((subject, body), ("Fix: the *parser*", "Details."));
+        assert_eq!(file_name(subject), "Fix-the-parser");
+        assert_eq!(file_name(&"x".repeat(80)).len(), MAX_NAME_LENGTH);
+    }
+}
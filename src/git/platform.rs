@@ -0,0 +1,110 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Stat data the index keeps to notice changed files without hashing them,
+/// truncated to 32 bits like the index stores it.
+#[derive(Default)]
+pub struct Stat {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+#[cfg(unix)]
+pub fn stat(meta: &fs::Metadata) -> Stat {
+    use std::os::unix::fs::MetadataExt;
+    Stat {
+        ctime: (meta.ctime() as u32, meta.ctime_nsec() as u32),
+        mtime: (meta.mtime() as u32, meta.mtime_nsec() as u32),
+        dev: meta.dev() as u32,
+        ino: meta.ino() as u32,
+        uid: meta.uid(),
+        gid: meta.gid(),
+    }
+}
+
+/// Without inode data only the times are known; creation stands in for the
+/// change time.
+#[cfg(not(unix))]
+pub fn stat(meta: &fs::Metadata) -> Stat {
+    let time = |time: io::Result<std::time::SystemTime>| {
+        time.ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or((0, 0), |d| (d.as_secs() as u32, d.subsec_nanos()))
+    };
+    let mtime = time(meta.modified());
+    Stat {
+        ctime: meta.created().map_or(mtime, |created| time(Ok(created))),
+        mtime,
+        ..Default::default()
+    }
+}
+
+/// Whether the owner may execute a file, `None` on file systems without
+/// execute bits, where the mode recorded in the index or tree is kept.
+#[cfg(unix)]
+pub fn is_executable(meta: &fs::Metadata) -> Option<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode() & 0o100 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(_meta: &fs::Metadata) -> Option<bool> {
+    None
+}
+
+/// Gives a file the permission bits of a tree entry mode.
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Creates a symlink pointing at `target`.
+#[cfg(unix)]
+pub fn symlink(target: &[u8], link: &Path) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), link)
+}
+
+/// Symlinks need privileges on Windows, so like git with `core.symlinks`
+/// off the link becomes a plain file holding its target.
+#[cfg(not(unix))]
+pub fn symlink(target: &[u8], link: &Path) -> io::Result<()> {
+    fs::write(link, target)
+}
+
+/// The bytes git records for a file name or a symlink target.
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+/// Windows paths are recorded as UTF-8 with slashes for separators.
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+/// Disk space taken by a file, counted in blocks of 512 bytes where the file
+/// system reports them.
+#[cfg(unix)]
+pub fn allocated_size(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.blocks() * 512
+}
+
+#[cfg(not(unix))]
+pub fn allocated_size(meta: &fs::Metadata) -> u64 {
+    meta.len()
+}
@@ -0,0 +1,57 @@
+use super::{fsck, remote::Sha1, Odb};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Deletes the loose objects that cannot be reached from refs, the index or
+/// reflogs. A dry run only prints them with their type.
+pub fn prune(dry_run: bool) -> Result<()> {
+    let odb = Odb::open()?;
+    let reachable = fsck::reachable(&odb)?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect::<HashSet<_>>();
+    for hash in unreachable_loose(&reachable, i64::MAX)? {
+        if dry_run {
+            let kind = match odb.stream(&hash) {
+                Ok(object) => String::from_utf8_lossy(object.kind()).into_owned(),
+                Err(_) => "unknown".to_owned(),
+            };
+            println!("{hash} {kind}");
+        } else {
+            remove_loose(&hash)?;
+        }
+    }
+    Ok(())
+}
+
+/// Loose objects outside `reachable` last modified before `expire`.
+pub fn unreachable_loose(reachable: &HashSet<Sha1>, expire: i64) -> Result<Vec<Sha1>> {
+    let mut unreachable = vec![];
+    for hash in Odb::open()?.list_loose()? {
+        if !reachable.contains(&hash) && modified(&Odb::loose_path(&hash))? < expire {
+            unreachable.push(hash);
+        }
+    }
+    unreachable.sort();
+    Ok(unreachable)
+}
+
+fn modified(path: &Path) -> Result<i64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64))
+}
+
+/// Deletes a loose object along with its fan-out directory once empty.
+pub fn remove_loose(hash: &str) -> Result<()> {
+    let path = Odb::loose_path(hash);
+    fs::remove_file(&path)?;
+    if let Some(directory) = path.parent() {
+        let _ = fs::remove_dir(directory); // fails while other objects remain
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use super::{config::Config, fast_forward, fetch, refs, revwalk, short_hash, Odb};
+use anyhow::{anyhow, bail, Result};
+
+/// Fetches from a remote, by default the upstream of the current branch, and
+/// fast-forwards the current branch to the fetched commit marked for merge.
+pub fn pull(remote: Option<&str>) -> Result<()> {
+    let remote = match remote {
+        Some(remote) => remote.to_owned(),
+        None => upstream_remote()?,
+    };
+    fetch::fetch(&remote)?;
+    let target = fetch::merge_head()?
+        .ok_or_else(|| anyhow!("There is no tracking information for the current branch"))?;
+    let head = refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD does not point to a commit"))?;
+    let odb = Odb::open()?;
+    if revwalk::is_ancestor(&odb, &target, &head)? {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    if !revwalk::is_ancestor(&odb, &head, &target)? {
+        bail!("Not possible to fast-forward, aborting.");
+    }
+    println!("Updating {}..{}", short_hash(&head), short_hash(&target));
+    println!("Fast-forward");
+    refs::write("ORIG_HEAD", &head)?;
+    fast_forward(&target, &format!("pull {remote}: Fast-forward"))
+}
+
+fn upstream_remote() -> Result<String> {
+    let config = Config::load()?;
+    Ok(refs::current_branch()?
+        .and_then(|branch| config.get(&format!("branch.{branch}.remote")))
+        .unwrap_or("origin")
+        .to_owned())
+}
@@ -0,0 +1,163 @@
+use super::{
+    flatten_tree,
+    index::{Entry, Index},
+    revparse, ObjectId, Odb, TreeFiles,
+};
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// A version of a path in a tree or in the index.
+type Version = Option<(u32, ObjectId)>;
+
+/// The outcome of merging one path into the index.
+enum Resolution {
+    /// A single version, or none to leave the path out.
+    Take(Version),
+    /// The base, our and their versions, left as stages 1 to 3.
+    Unmerged([Version; 3]),
+}
+
+/// Reads trees into the index without touching the working tree. One tree
+/// replaces the index. With `merge`, two trees move the index from the
+/// first to the second while keeping staged changes, and three trees merge
+/// the last two with the first as their base, leaving the paths changed on
+/// both sides unmerged.
+pub fn read_tree(tree_ishes: &[String], merge: bool) -> Result<()> {
+    let odb = Odb::open()?;
+    let trees = tree_ishes
+        .iter()
+        .map(|tree_ish| revparse::peel(&revparse::resolve(tree_ish)?, "tree"))
+        .collect::<Result<Vec<_>>>()?;
+    match (trees.as_slice(), merge) {
+        ([tree], _) => return load(&odb, tree),
+        (_, false) => bail!("reading several trees requires -m"),
+        (trees, true) if trees.len() > 3 => bail!("read-tree -m takes at most three trees"),
+        _ => {}
+    }
+
+    let index = if Index::exists() {
+        Index::read()?
+    } else {
+        Index::default()
+    };
+    if !index.conflicts().is_empty() {
+        bail!("you need to resolve your current index first");
+    }
+    let current = index.files();
+    let files = trees
+        .iter()
+        .map(|tree| flatten_tree(&odb, tree))
+        .collect::<Result<Vec<_>>>()?;
+    let paths = current
+        .keys()
+        .chain(files.iter().flat_map(TreeFiles::keys))
+        .collect::<BTreeSet<_>>();
+    let mut merged = Index::default();
+    for path in paths {
+        let version = |files: &TreeFiles| files.get(path).copied();
+        let staged = version(&current);
+        let resolution = match files.as_slice() {
+            [old, new] => two_way(path, staged, version(old), version(new))?,
+            [base, ours, theirs] => {
+                three_way(path, staged, version(base), version(ours), version(theirs))?
+            }
+            _ => unreachable!("one tree is read without merging"),
+        };
+        match resolution {
+            Resolution::Take(Some((mode, hash))) => {
+                merged.entries.push(entry(&index, path, mode, hash)?);
+            }
+            Resolution::Take(None) => {}
+            Resolution::Unmerged(versions) => {
+                for (stage, version) in (1..).zip(versions) {
+                    if let Some((mode, hash)) = version {
+                        merged.entries.push(Entry::staged(path, mode, hash, stage)?);
+                    }
+                }
+            }
+        }
+    }
+    merged.write()
+}
+
+/// Replaces the index with the files of a tree, keeping the stat data of
+/// entries that stay the same.
+pub fn load(odb: &Odb, tree: &str) -> Result<()> {
+    let old = if Index::exists() {
+        Index::read()?
+    } else {
+        Index::default()
+    };
+    let mut index = Index::default();
+    for (path, (mode, hash)) in flatten_tree(odb, tree)? {
+        index.entries.push(entry(&old, &path, mode, hash)?);
+    }
+    index.write()
+}
+
+/// An index entry for a path, reusing the one already in `index` when it has
+/// the same content so that its stat data is kept.
+fn entry(index: &Index, path: &Path, mode: u32, hash: ObjectId) -> Result<Entry> {
+    let unchanged = index.entries.iter().find(|entry| {
+        entry.stage == 0
+            && Path::new(&entry.path) == path
+            && (entry.mode, entry.hash) == (mode, hash)
+    });
+    match unchanged {
+        Some(entry) => Ok(entry.clone()),
+        None => Entry::staged(path, mode, hash, 0),
+    }
+}
+
+/// Moves a path from the `old` tree to the `new` one, as git's two-way merge
+/// does: staged changes are kept unless the new tree changes the path too.
+fn two_way(path: &Path, staged: Version, old: Version, new: Version) -> Result<Resolution> {
+    if staged == new || old == new {
+        Ok(Resolution::Take(staged))
+    } else if staged == old {
+        Ok(Resolution::Take(new))
+    } else {
+        bail!(
+            "Entry '{}' would be overwritten by merge. Cannot merge.",
+            path.display()
+        )
+    }
+}
+
+/// Resolves the trivial cases of a three-way merge as git does without
+/// `--aggressive`: a path changed on one side only, or the same way on both.
+/// Deletions are left unmerged. The index must match our side, or already
+/// hold the result.
+fn three_way(
+    path: &Path,
+    staged: Version,
+    base: Version,
+    ours: Version,
+    theirs: Version,
+) -> Result<Resolution> {
+    let reject = || {
+        bail!(
+            "Entry '{}' would be overwritten by merge. Cannot merge.",
+            path.display()
+        )
+    };
+    let ours_unchanged = base == ours;
+    let theirs_unchanged = base == theirs;
+    if theirs.is_some() && ours_unchanged && !theirs_unchanged {
+        if staged.is_some() && staged != theirs && staged != ours {
+            return reject();
+        }
+        return Ok(Resolution::Take(theirs));
+    }
+    if staged.is_some() && staged != ours {
+        return reject();
+    }
+    if ours.is_some() && (ours == theirs || (theirs_unchanged && !ours_unchanged)) {
+        return Ok(Resolution::Take(ours));
+    }
+    if (ours, theirs, base) == (None, None, None) {
+        return Ok(Resolution::Take(None));
+    }
+    Ok(Resolution::Unmerged([base, ours, theirs]))
+}
@@ -0,0 +1,207 @@
+use super::{
+    cherry_pick::{self, Change, Outcome},
+    detach_head,
+    index::Index,
+    parse_hash, peel_to_commit, reflog, refs, repository, reset_hard, revparse,
+    revwalk::{self, RevWalk},
+    short_hash, write_commit_as, write_tree_from_files, Odb,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+/// The state of a rebase in progress, named after git's merge backend.
+const REBASE_DIR: &str = "rebase-merge";
+
+/// Replays the commits of the current branch that `upstream` does not have
+/// on top of it, one by one, then moves the branch to the result. Merges
+/// are left out, and commits whose change upstream already has are dropped.
+pub fn rebase(upstream: &str) -> Result<()> {
+    if is_rebasing() {
+        bail!("It seems that there is already a rebase-merge directory. Use \"git rebase --continue\" or \"git rebase --abort\".");
+    }
+    let head = refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD does not point to a commit"))?;
+    let onto = peel_to_commit(&revparse::resolve(upstream)?)?;
+    let head_name = match refs::current_branch()? {
+        Some(branch) => format!("refs/heads/{branch}"),
+        None => "detached HEAD".to_owned(),
+    };
+    let odb = Odb::open()?;
+    if revwalk::is_ancestor(&odb, &onto, &head)? {
+        match refs::current_branch()? {
+            Some(branch) => println!("Current branch {branch} is up to date."),
+            None => println!("HEAD is up to date."),
+        }
+        return Ok(());
+    }
+
+    let mut walk = RevWalk::from_tip(&odb, &head)?;
+    walk.hide(&onto)?;
+    let mut todo = vec![];
+    for hash in walk.hashes() {
+        let hash = hash?;
+        let commit = odb.read_commit(&hash)?;
+        if commit.parents.len() <= 1 {
+            todo.push(format!("pick {hash} {}\n", commit.subject()));
+        }
+    }
+    todo.reverse();
+
+    fs::create_dir_all(state_path(""))?;
+    fs::write(state_path("head-name"), format!("{head_name}\n"))?;
+    fs::write(state_path("onto"), format!("{onto}\n"))?;
+    fs::write(state_path("orig-head"), format!("{head}\n"))?;
+    fs::write(state_path("git-rebase-todo"), todo.concat())?;
+    fs::write(state_path("done"), "")?;
+    refs::write("ORIG_HEAD", &head)?;
+    detach_head(&onto, &format!("rebase (start): checkout {upstream}"))?;
+    run()
+}
+
+/// Commits the resolution of the commit the rebase stopped at, if anything
+/// is left of it, and goes on with the remaining ones.
+pub fn continue_rebase() -> Result<()> {
+    check_rebasing()?;
+    let index = Index::read()?;
+    if !index.conflicts().is_empty() {
+        bail!("You must edit all merge conflicts and then mark them as resolved using git add");
+    }
+    if let Ok(stopped) = fs::read_to_string(state_path("stopped-sha")) {
+        let odb = Odb::open()?;
+        let stopped = odb.read_commit(stopped.trim_end())?;
+        let head = refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD is unborn"))?;
+        let tree = write_tree_from_files(&odb, &index.files())?;
+        if tree.to_string() != odb.read_commit(&head)?.tree {
+            let message = stopped.message.trim_end();
+            let hash = write_commit_as(
+                &tree,
+                &[parse_hash(&head)?],
+                &stopped.author.to_string(),
+                message,
+            )?;
+            refs::update_from(
+                "HEAD",
+                Some(&head),
+                &hash.to_string(),
+                &format!("rebase (continue): {}", stopped.subject()),
+            )?;
+        }
+        fs::remove_file(state_path("stopped-sha"))?;
+    }
+    run()
+}
+
+/// Gives up on the rebase, going back to the branch and commit it started
+/// from.
+pub fn abort() -> Result<()> {
+    check_rebasing()?;
+    let head_name = read_state("head-name")?;
+    let orig_head = read_state("orig-head")?;
+    let odb = Odb::open()?;
+    reset_hard(&odb, &orig_head)?;
+    let old_head = refs::peel("HEAD")?;
+    if head_name.starts_with("refs/") {
+        refs::write_symbolic("HEAD", &head_name)?;
+    } else {
+        refs::write("HEAD", &orig_head)?;
+    }
+    reflog::append(
+        "HEAD",
+        old_head.as_deref(),
+        &orig_head,
+        &format!("rebase (abort): returning to {head_name}"),
+    )?;
+    fs::remove_dir_all(state_path(""))?;
+    Ok(())
+}
+
+fn is_rebasing() -> bool {
+    state_path("").is_dir()
+}
+
+fn check_rebasing() -> Result<()> {
+    if !is_rebasing() {
+        bail!("No rebase in progress?");
+    }
+    Ok(())
+}
+
+fn state_path(name: &str) -> PathBuf {
+    repository::path(REBASE_DIR).join(name)
+}
+
+fn read_state(name: &str) -> Result<String> {
+    let value = fs::read_to_string(state_path(name))
+        .with_context(|| format!("could not read {REBASE_DIR}/{name}"))?;
+    Ok(value.trim_end().to_owned())
+}
+
+/// Picks the commits left in the todo list, stopping at the first conflict.
+fn run() -> Result<()> {
+    let odb = Odb::open()?;
+    loop {
+        let todo = fs::read_to_string(state_path("git-rebase-todo"))?;
+        let Some((line, rest)) = todo.split_once('\n') else {
+            break;
+        };
+        fs::write(state_path("git-rebase-todo"), rest)?;
+        let mut done = fs::read_to_string(state_path("done"))?;
+        done.push_str(line);
+        done.push('\n');
+        fs::write(state_path("done"), done)?;
+
+        let hash = line
+            .strip_prefix("pick ")
+            .and_then(|pick| pick.split(' ').next())
+            .ok_or_else(|| anyhow!("invalid line in the todo list: {line}"))?;
+        let commit = odb.read_commit(hash)?;
+        let subject = commit.subject();
+        let change = Change {
+            base: commit.parents.first().map(String::as_str),
+            target: Some(hash),
+            label: format!("{} ({subject})", short_hash(hash)),
+        };
+        let outcome = cherry_pick::apply(
+            &odb,
+            &change,
+            &commit.author.to_string(),
+            commit.message.trim_end(),
+            "rebase (pick)",
+        )?;
+        if let Outcome::Conflicted = outcome {
+            fs::write(state_path("stopped-sha"), format!("{hash}\n"))?;
+            bail!(
+                "could not apply {}... {subject}\n\
+                 Resolve all conflicts manually, mark them as resolved with \"git add\", \
+                 then run \"git rebase --continue\".\n\
+                 To abort and get back to the state before \"git rebase\", run \"git rebase --abort\".",
+                short_hash(hash)
+            );
+        }
+    }
+    finish()
+}
+
+/// Moves the rebased branch to the last replayed commit and HEAD back to it.
+fn finish() -> Result<()> {
+    let head_name = read_state("head-name")?;
+    let onto = read_state("onto")?;
+    let head = refs::peel("HEAD")?.ok_or_else(|| anyhow!("HEAD is unborn"))?;
+    if head_name.starts_with("refs/") {
+        refs::update(
+            &head_name,
+            &head,
+            &format!("rebase (finish): {head_name} onto {onto}"),
+        )?;
+        refs::write_symbolic("HEAD", &head_name)?;
+        reflog::append(
+            "HEAD",
+            Some(&head),
+            &head,
+            &format!("rebase (finish): returning to {head_name}"),
+        )?;
+    }
+    fs::remove_dir_all(state_path(""))?;
+    println!("Successfully rebased and updated {head_name}.");
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use super::{
+    hash::HashAlgo, refs, remote::Sha1, repository, revwalk::RevWalk, short_hash, signature,
+    split_identity, Odb, Role,
+};
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub struct Entry {
+    pub old: Sha1,
+    pub new: Sha1,
+    pub identity: String,
+    pub message: String,
+}
+
+fn log_path(name: &str) -> PathBuf {
+    repository::path(&format!("logs/{name}"))
+}
+
+/// Appends a `<old> <new> <identity>\t<message>` line to the reflog of `name`.
+pub fn append(name: &str, old: Option<&str>, new: &str, message: &str) -> Result<()> {
+    let filepath = log_path(name);
+    fs::create_dir_all(filepath.parent().unwrap())?;
+    let identity = signature(Role::Committer)?;
+    // reflog messages are single-line
+    let message = message.lines().next().unwrap_or_default();
+    let null = HashAlgo::current().null_hex();
+    let old = old.unwrap_or(&null);
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(filepath)?
+        .write_all(format!("{old} {new} {identity}\t{message}\n").as_bytes())?;
+    Ok(())
+}
+
+/// Reads the reflog of `name`, oldest entry first.
+pub fn read(name: &str) -> Result<Vec<Entry>> {
+    let filepath = log_path(name);
+    if !filepath.is_file() {
+        return Ok(vec![]);
+    }
+    fs::read_to_string(filepath)?
+        .lines()
+        .map(|line| {
+            let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+            let mut fields = head.splitn(3, ' ');
+            let mut next_field = || {
+                fields
+                    .next()
+                    .ok_or_else(|| anyhow!("Bad reflog line: {line}"))
+            };
+            Ok(Entry {
+                old: next_field()?.to_owned(),
+                new: next_field()?.to_owned(),
+                identity: next_field()?.to_owned(),
+                message: message.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Replaces the reflog of `name`, e.g. after dropping an entry.
+pub fn write(name: &str, entries: &[Entry]) -> Result<()> {
+    let content = entries
+        .iter()
+        .map(|e| format!("{} {} {}\t{}\n", e.old, e.new, e.identity, e.message))
+        .collect::<String>();
+    let filepath = log_path(name);
+    fs::create_dir_all(filepath.parent().unwrap())?;
+    fs::write(filepath, content)?;
+    Ok(())
+}
+
+/// Names of all refs that have a reflog, such as `HEAD` and `refs/heads/master`.
+pub fn list() -> Result<Vec<String>> {
+    let mut names = vec![];
+    collect_names(&repository::path("logs/HEAD"), "HEAD", &mut names);
+    collect_names(&repository::path("logs/refs"), "refs", &mut names);
+    Ok(names)
+}
+
+fn collect_names(path: &Path, name: &str, names: &mut Vec<String>) {
+    match path.read_dir() {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let child = format!("{name}/{}", entry.file_name().to_string_lossy());
+                collect_names(&entry.path(), &child, names);
+            }
+        }
+        Err(_) if path.is_file() => names.push(name.to_owned()),
+        Err(_) => {}
+    }
+}
+
+/// Drops entries of the reflog of `name` older than `expire`, and those
+/// older than `expire_unreachable` whose commit is no longer reachable from
+/// the ref. Returns the number of dropped entries.
+pub fn expire(name: &str, expire: Option<i64>, expire_unreachable: Option<i64>) -> Result<usize> {
+    let entries = read(name)?;
+    let mut reachable = HashSet::new();
+    if let Some(tip) = refs::peel(name)? {
+        for hash in RevWalk::from_tip(&Odb::open()?, &tip)?.hashes() {
+            reachable.insert(hash?);
+        }
+    }
+    let is_expired = |time: i64, cutoff: Option<i64>| cutoff.is_some_and(|cutoff| time < cutoff);
+    let count = entries.len();
+    let kept = entries
+        .into_iter()
+        .filter(|entry| {
+            !is_expired(entry.time(), expire)
+                && (reachable.contains(&entry.new) || !is_expired(entry.time(), expire_unreachable))
+        })
+        .collect::<Vec<_>>();
+    let dropped = count - kept.len();
+    if dropped > 0 {
+        write(name, &kept)?;
+    }
+    Ok(dropped)
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    let filepath = log_path(name);
+    if filepath.is_file() {
+        fs::remove_file(filepath)?;
+    }
+    Ok(())
+}
+
+/// Prints the reflog of `name` newest first, like `git reflog show`.
+pub fn print(name: &str) -> Result<()> {
+    let short_name = name.strip_prefix("refs/heads/").unwrap_or(name);
+    for (index, entry) in read(name)?.iter().rev().enumerate() {
+        println!(
+            "{} {short_name}@{{{index}}}: {}",
+            short_hash(&entry.new),
+            entry.message
+        );
+    }
+    Ok(())
+}
+
+impl Entry {
+    pub fn time(&self) -> i64 {
+        split_identity(&self.identity)
+            .map(|(_, timestamp, _)| timestamp)
+            .unwrap_or_default()
+    }
+}
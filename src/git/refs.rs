@@ -0,0 +1,485 @@
+use super::{
+    error::Result, hash::HashAlgo, lockfile::LockFile, reflog, remote::Sha1, repository, Error,
+};
+use anyhow::anyhow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PACKED_REFS: &str = "packed-refs";
+const PACKED_HEADER: &str = "# pack-refs with: ";
+/// Traits of `packed-refs` that still hold once refs are removed from it.
+const KEPT_TRAITS: [&str; 3] = ["peeled", "fully-peeled", "sorted"];
+const SYMBOLIC_PREFIX: &str = "ref: ";
+
+/// The value stored in a ref: either an object hash or the name of another ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Direct(Sha1),
+    Symbolic(String),
+}
+
+struct PackedRef {
+    name: String,
+    hash: Sha1,
+    peeled: Option<Sha1>,
+}
+
+/// Where a ref is stored as a loose ref. Only valid names have one, so that
+/// no name can lead outside of the refs.
+fn ref_path(name: &str) -> Result<PathBuf> {
+    check_name(name)?;
+    Ok(repository::path(name))
+}
+
+/// Whether `name` is a valid full ref name by the rules of
+/// `git check-ref-format`: components under `refs/` separated by single
+/// slashes, none of them starting with a dot or ending with `.lock`, and no
+/// `..`, `@{`, control characters, spaces or any of `~^:?*[\`. One-level
+/// names are only allowed for pseudo refs like `HEAD` or `ORIG_HEAD`.
+pub fn is_valid_name(name: &str) -> bool {
+    let forbidden = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    if name.contains("..") || name.contains("@{") || name.ends_with('.') || name.contains(forbidden)
+    {
+        return false;
+    }
+    match name.strip_prefix("refs/") {
+        Some(rest) => rest.split('/').all(|component| {
+            !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+        }),
+        None => !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_'),
+    }
+}
+
+/// Fails for a ref name that `is_valid_name` refuses.
+pub fn check_name(name: &str) -> Result<()> {
+    if !is_valid_name(name) {
+        return Err(anyhow!("'{name}' is not a valid ref name").into());
+    }
+    Ok(())
+}
+
+fn is_hash(name: &str) -> bool {
+    name.len() == HashAlgo::current().hex_size() && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Reads a ref by its full name (e.g. `refs/heads/master`), checking loose refs
+/// before falling back to `.git/packed-refs`.
+pub fn read(name: &str) -> Result<Option<Target>> {
+    // nothing can be stored under an invalid name
+    if !is_valid_name(name) {
+        return Ok(None);
+    }
+    let filepath = ref_path(name)?;
+    if filepath.is_file() {
+        let content = fs::read_to_string(filepath)?;
+        let content = content.trim_end();
+        return Ok(Some(match content.strip_prefix(SYMBOLIC_PREFIX) {
+            Some(target) => Target::Symbolic(target.to_owned()),
+            // FETCH_HEAD holds more than a hash; its first one counts
+            None => Target::Direct(content.split_whitespace().next().unwrap_or("").to_owned()),
+        }));
+    }
+    Ok(read_packed()?
+        .into_iter()
+        .find(|r| r.name == name)
+        .map(|r| Target::Direct(r.hash)))
+}
+
+/// Follows symbolic refs until reaching an object hash.
+pub fn peel(name: &str) -> Result<Option<Sha1>> {
+    let mut name = name.to_owned();
+    // guard against symbolic ref loops
+    for _ in 0..5 {
+        match read(&name)? {
+            Some(Target::Direct(hash)) => return Ok(Some(hash)),
+            Some(Target::Symbolic(target)) => name = target,
+            None => return Ok(None),
+        }
+    }
+    Err(anyhow!("Too many levels of symbolic refs at {name}").into())
+}
+
+/// Expands a short ref name the way git does, returning the first full name that exists.
+pub fn expand(name: &str) -> Result<Option<String>> {
+    let candidates = [
+        name.to_owned(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ];
+    for candidate in candidates {
+        if read(&candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Resolves `HEAD`, a branch, tag or remote-tracking ref name, or a full hash to an object hash.
+pub fn resolve(name: &str) -> Result<Sha1> {
+    if is_hash(name) {
+        return Ok(name.to_owned());
+    }
+    match expand(name)? {
+        Some(full_name) => {
+            peel(&full_name)?.ok_or_else(|| anyhow!("Reference {full_name} is unborn").into())
+        }
+        None => Err(Error::RefNotFound(name.to_owned())),
+    }
+}
+
+/// Returns the branch name HEAD points at, or `None` when HEAD is not a symbolic ref.
+pub fn current_branch() -> Result<Option<String>> {
+    Ok(match read("HEAD")? {
+        Some(Target::Symbolic(target)) => target.strip_prefix("refs/heads/").map(str::to_owned),
+        _ => None,
+    })
+}
+
+/// Lists refs under a prefix such as `refs/heads/`, merging loose and packed refs.
+/// Names are returned in full and sorted.
+pub fn list(prefix: &str) -> Result<Vec<(String, Sha1)>> {
+    let mut refs = vec![];
+    collect_loose(&repository::path(prefix), prefix, &mut refs)?;
+    for packed in read_packed()? {
+        if packed.name.starts_with(prefix) && !refs.iter().any(|(name, _)| *name == packed.name) {
+            refs.push((packed.name, packed.hash));
+        }
+    }
+    refs.sort();
+    Ok(refs)
+}
+
+fn collect_loose(directory: &Path, prefix: &str, refs: &mut Vec<(String, Sha1)>) -> Result<()> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+    for entry in directory.read_dir()? {
+        let entry = entry?;
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_loose(&entry.path(), &format!("{name}/"), refs)?;
+        } else if let Some(hash) = peel(&name)? {
+            refs.push((name, hash));
+        }
+    }
+    Ok(())
+}
+
+/// Points a ref at an object hash, creating it as a loose ref.
+pub fn write(name: &str, hash: &str) -> Result<()> {
+    let mut transaction = Transaction::new();
+    transaction.update(name, Target::Direct(hash.to_owned()), None, None);
+    transaction.commit()
+}
+
+/// Moves a ref to a new hash and records the change in its reflog. Updating a
+/// symbolic ref (e.g. `HEAD`) updates the ref it points at, and updating the
+/// branch HEAD points at is logged for `HEAD` as well.
+pub fn update(name: &str, hash: &str, message: &str) -> Result<()> {
+    let mut transaction = Transaction::new();
+    transaction.update(
+        &dereference(name)?,
+        Target::Direct(hash.to_owned()),
+        None,
+        Some(message),
+    );
+    transaction.commit()
+}
+
+/// Like `update`, but fails if the ref no longer points at `old`, or already
+/// exists when `old` is `None`, so that a concurrent update is not lost.
+pub fn update_from(name: &str, old: Option<&str>, hash: &str, message: &str) -> Result<()> {
+    let mut transaction = Transaction::new();
+    transaction.update(
+        &dereference(name)?,
+        Target::Direct(hash.to_owned()),
+        Some(old),
+        Some(message),
+    );
+    transaction.commit()
+}
+
+/// Makes a ref (usually `HEAD`) point at another ref.
+pub fn write_symbolic(name: &str, target: &str) -> Result<()> {
+    let mut transaction = Transaction::new();
+    transaction.update(name, Target::Symbolic(target.to_owned()), None, None);
+    transaction.commit()
+}
+
+/// Prints the ref a symbolic ref points at or, given a target, points it there.
+pub fn symbolic_ref(name: &str, target: Option<&str>) -> Result<()> {
+    match (target, read(name)?) {
+        (Some(target), _) if !target.starts_with("refs/") => {
+            Err(anyhow!("Refusing to point {name} outside of refs/").into())
+        }
+        (Some(target), _) => write_symbolic(name, target),
+        (None, Some(Target::Symbolic(target))) => {
+            println!("{target}");
+            Ok(())
+        }
+        (None, _) => Err(anyhow!("ref {name} is not a symbolic ref").into()),
+    }
+}
+
+/// Safely moves the ref `name`, or the one it refers to, to `new`. An `old`
+/// hash must still be the ref's value, while an empty or all-zero one means
+/// the ref must not exist yet.
+pub fn update_ref(name: &str, new: &str, old: Option<&str>, message: &str) -> Result<()> {
+    let old = old.map(|old| (!old.bytes().all(|b| b == b'0')).then_some(old));
+    let mut transaction = Transaction::new();
+    transaction.update(
+        &dereference(name)?,
+        Target::Direct(new.to_owned()),
+        old,
+        Some(message),
+    );
+    transaction.commit()
+}
+
+/// Follows symbolic refs to the name of the ref holding a hash, or about to.
+fn dereference(name: &str) -> Result<String> {
+    let mut name = name.to_owned();
+    for _ in 0..5 {
+        match read(&name)? {
+            Some(Target::Symbolic(target)) => name = target,
+            _ => return Ok(name),
+        }
+    }
+    Err(anyhow!("Too many levels of symbolic refs at {name}").into())
+}
+
+struct RefUpdate {
+    name: String,
+    new: Target,
+    /// The hash the ref must still have, or `Some(None)` if it must not exist.
+    old: Option<Option<Sha1>>,
+    /// Logged in the reflog when set.
+    message: Option<String>,
+}
+
+/// A set of ref updates applied together. Every ref is locked by creating
+/// `<ref>.lock` before anything is checked or written, and the new values
+/// are only renamed into place once all of them could be written, so a
+/// failed check or write leaves every ref as it was. The renames happen one
+/// at a time: if one of them fails, the refs renamed before it keep their
+/// new values.
+#[derive(Default)]
+pub struct Transaction {
+    updates: Vec<RefUpdate>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues setting `name` to `new`, without following symbolic refs.
+    /// With `old`, the update fails unless the ref still points at that hash
+    /// or, for `Some(None)`, does not exist yet.
+    pub fn update(
+        &mut self,
+        name: &str,
+        new: Target,
+        old: Option<Option<&str>>,
+        message: Option<&str>,
+    ) -> &mut Self {
+        self.updates.push(RefUpdate {
+            name: name.to_owned(),
+            new,
+            old: old.map(|old| old.map(str::to_owned)),
+            message: message.map(str::to_owned),
+        });
+        self
+    }
+
+    pub fn commit(self) -> Result<()> {
+        let mut locks = vec![];
+        for update in &self.updates {
+            if let Target::Symbolic(target) = &update.new {
+                check_name(target)?;
+            }
+            locks.push(LockFile::acquire(&ref_path(&update.name)?)?);
+        }
+        let mut previous = vec![];
+        for (update, lock) in self.updates.iter().zip(&mut locks) {
+            let current = peel(&update.name)?;
+            if let Some(old) = &update.old {
+                if current != *old {
+                    return Err(anyhow!(
+                        "cannot lock ref '{}': {}",
+                        update.name,
+                        match (&current, old) {
+                            (Some(current), Some(old)) =>
+                                format!("is at {current} but expected {old}"),
+                            (Some(_), None) => "reference already exists".to_owned(),
+                            (None, _) => "unable to resolve reference".to_owned(),
+                        }
+                    )
+                    .into());
+                }
+            }
+            let content = match &update.new {
+                Target::Direct(hash) => format!("{hash}\n"),
+                Target::Symbolic(target) => format!("{SYMBOLIC_PREFIX}{target}\n"),
+            };
+            lock.write(content.as_bytes())?;
+            previous.push(current);
+        }
+        for lock in locks {
+            lock.commit()?;
+        }
+
+        for (update, old) in self.updates.iter().zip(previous) {
+            let (Some(message), Target::Direct(hash)) = (&update.message, &update.new) else {
+                continue;
+            };
+            let name = &update.name;
+            reflog::append(name, old.as_deref(), hash, message)?;
+            if name != "HEAD" && read("HEAD")? == Some(Target::Symbolic(name.to_owned())) {
+                reflog::append("HEAD", old.as_deref(), hash, message)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes a ref from both the loose and packed storage, holding its lock
+/// like a transaction does meanwhile.
+pub fn delete(name: &str) -> Result<()> {
+    let filepath = ref_path(name)?;
+    let lock = LockFile::acquire(&filepath)?;
+    if filepath.is_file() {
+        fs::remove_file(&filepath)?;
+    }
+    reflog::delete(name)?;
+    let packed = read_packed()?;
+    if packed.iter().any(|r| r.name == name) {
+        write_packed(
+            &packed_traits()?,
+            packed.into_iter().filter(|r| r.name != name),
+        )?;
+    }
+    drop(lock);
+    // drop directories left empty by nested names like refs/heads/feature/x
+    let refs_root = repository::path("refs");
+    let mut parent = filepath.parent();
+    while let Some(directory) = parent.filter(|d| d.starts_with(&refs_root) && *d != refs_root) {
+        if fs::remove_dir(directory).is_err() {
+            break;
+        }
+        parent = directory.parent();
+    }
+    Ok(())
+}
+
+fn read_packed() -> Result<Vec<PackedRef>> {
+    let filepath = repository::path(PACKED_REFS);
+    if !filepath.is_file() {
+        return Ok(vec![]);
+    }
+    let mut refs: Vec<PackedRef> = vec![];
+    for line in fs::read_to_string(filepath)?.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            let last = refs
+                .last_mut()
+                .ok_or_else(|| anyhow!("Peeled line without a ref in packed-refs"))?;
+            last.peeled = Some(peeled.to_owned());
+            continue;
+        }
+        let (hash, name) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Bad packed-refs line: {line}"))?;
+        refs.push(PackedRef {
+            name: name.to_owned(),
+            hash: hash.to_owned(),
+            peeled: None,
+        });
+    }
+    Ok(refs)
+}
+
+/// The traits the `packed-refs` header declares, e.g. `fully-peeled` when
+/// every annotated tag is followed by the object it peels to.
+fn packed_traits() -> Result<Vec<String>> {
+    let content = fs::read_to_string(repository::path(PACKED_REFS))?;
+    Ok(content
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix(PACKED_HEADER))
+        .map(|traits| traits.split_whitespace().map(str::to_owned).collect())
+        .unwrap_or_default())
+}
+
+/// Rewrites `packed-refs` with refs taken from it, declaring only the traits
+/// of the original file that removing refs keeps true.
+fn write_packed(traits: &[String], refs: impl Iterator<Item = PackedRef>) -> Result<()> {
+    let mut content = PACKED_HEADER.to_owned();
+    for name in KEPT_TRAITS {
+        if traits.iter().any(|t| t == name) {
+            content += &format!("{name} ");
+        }
+    }
+    content.push('\n');
+    for packed in refs {
+        content += &format!("{} {}\n", packed.hash, packed.name);
+        if let Some(peeled) = packed.peeled {
+            content += &format!("^{peeled}\n");
+        }
+    }
+    let mut lock = LockFile::acquire(&repository::path(PACKED_REFS))?;
+    lock.write(content.as_bytes())?;
+    Ok(lock.commit()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ref_names_follow_check_ref_format() {
+        for name in [
+            "HEAD",
+            "ORIG_HEAD",
+            "FETCH_HEAD",
+            "refs/heads/master",
+            "refs/heads/feature/x",
+            "refs/tags/v1.0",
+            "refs/remotes/origin/HEAD",
+        ] {
+            assert!(is_valid_name(name), "{name:?} is refused");
+        }
+        for name in [
+            "",
+            "../../config",
+            "refs/heads/../../config",
+            "refs/heads/a b",
+            "refs/heads/a..b",
+            "refs/heads/x.lock",
+            "refs/heads/.hidden",
+            "refs/heads/x.",
+            "refs/heads//x",
+            "refs/heads/",
+            "refs/heads/a@{1}",
+            "refs/heads/a~1",
+            "refs/heads/a:b",
+            "refs/heads/a\\b",
+            "refs/heads/a\nb",
+            "config",
+            "hooks/x",
+        ] {
+            assert!(!is_valid_name(name), "{name:?} is accepted");
+        }
+    }
+
+    #[test]
+    fn invalid_names_have_no_path() {
+        assert!(ref_path("../../config").is_err());
+        assert!(ref_path("refs/heads/master").is_ok());
+    }
+}
@@ -0,0 +1,39 @@
+use anyhow::{bail, Result};
+
+/// A `[+]<source>:<destination>` mapping from remote to local ref names. Both
+/// sides may contain a single `*` matching any part of a name.
+pub struct Refspec {
+    pub force: bool,
+    pub source: String,
+    pub destination: String,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (source, destination) = spec.split_once(':').unwrap_or((spec, ""));
+        let wildcards = source.matches('*').count();
+        if wildcards > 1 || wildcards != destination.matches('*').count() {
+            bail!("Invalid refspec {spec}");
+        }
+        Ok(Self {
+            force,
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+        })
+    }
+
+    /// Maps a remote ref name to its local destination when it matches the source.
+    pub fn map(&self, name: &str) -> Option<String> {
+        match self.source.split_once('*') {
+            Some((prefix, suffix)) => {
+                let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                Some(self.destination.replacen('*', matched, 1))
+            }
+            None => (name == self.source).then(|| self.destination.clone()),
+        }
+    }
+}
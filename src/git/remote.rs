@@ -1,18 +1,177 @@
-use std::io::Read;
+use std::io::{Read, Write};
+use std::net::TcpStream;
 
-use anyhow::{anyhow, bail, Result};
-use reqwest::{blocking::Client, header, StatusCode, Url};
+use super::{
+    config::Config, credential, hash::HashAlgo, pack, refs, refspec::Refspec, revparse, revwalk,
+    short_hash, Error, Object, ObjectReader, Odb, ParsedObject,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header, StatusCode, Url,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
 
 pub type Sha1 = String;
 pub type ReferenceName = String;
 pub type Reference = (Sha1, ReferenceName);
 
 const LENGTH_SIZE: usize = 4;
+const UPLOAD_PACK: &str = "git-upload-pack";
+const RECEIVE_PACK: &str = "git-receive-pack";
+const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+const PROTOCOL_HEADER: &str = "Git-Protocol";
+const FLUSH_PKT: &str = "0000";
+const DELIMITER_PKT: &str = "0001";
+const REF_PREFIXES: [&str; 2] = ["refs/heads/", "refs/tags/"];
+const DAEMON_PORT: u16 = 9418;
 
-pub fn discover_references(git_url: &Url) -> Result<Vec<Reference>> {
-    let url = git_url.join("info/refs?service=git-upload-pack")?;
-    println!("Discover refs: {url}");
-    let response = reqwest::blocking::get(url)?;
+/// Parses a repository URL, making sure it ends with a slash so that endpoints
+/// can be joined to it.
+pub fn parse_url(url: &str) -> Result<Url> {
+    if url.ends_with('/') {
+        Ok(Url::parse(url)?)
+    } else {
+        Ok(Url::parse(&format!("{url}/"))?)
+    }
+}
+
+/// Looks up `remote.<name>.url`, treating anything that is not a configured remote as a URL.
+pub fn remote_url(name: &str) -> Result<Url> {
+    let config = Config::load()?;
+    let url = config
+        .get(&format!("remote.{name}.url"))
+        .unwrap_or(name)
+        .to_owned();
+    parse_url(&url).with_context(|| format!("'{name}' is neither a remote nor a URL"))
+}
+
+/// Wire protocol version negotiated during discovery.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    V0,
+    V2,
+    /// Plain file downloads from a server without git support.
+    Dumb,
+}
+
+/// The result of ref discovery: the advertised refs, the server capabilities
+/// and the protocol to keep talking with.
+pub struct Advertisement {
+    pub refs: Vec<Reference>,
+    pub capabilities: Vec<String>,
+    pub protocol: Protocol,
+}
+
+impl Advertisement {
+    fn supports(&self, capability: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|c| c == capability || c.starts_with(&format!("{capability}=")))
+    }
+
+    /// Whether upload-pack accepts a fetch feature such as `shallow` or `filter`:
+    /// a capability in v0, a value of the `fetch` capability in v2.
+    fn supports_fetch(&self, feature: &str) -> bool {
+        match self.protocol {
+            Protocol::V0 => self.supports(feature),
+            Protocol::V2 => self
+                .capabilities
+                .iter()
+                .filter_map(|c| c.strip_prefix("fetch="))
+                .any(|features| features.split(' ').any(|f| f == feature)),
+            Protocol::Dumb => false,
+        }
+    }
+}
+
+/// Knobs for limiting what `fetch_pack` downloads.
+#[derive(Clone, Default)]
+pub struct FetchOptions {
+    /// Number of commits of history to fetch from each tip (`--depth`).
+    pub depth: Option<usize>,
+    /// Objects the server may leave out (`--filter`), see `check_filter`.
+    pub filter: Option<String>,
+    /// Local commits offered so that the server can leave out their history.
+    pub haves: Vec<Sha1>,
+    /// The boundary of a shallow repository, so that the server knows which
+    /// parents are missing locally.
+    pub shallow: Vec<Sha1>,
+}
+
+/// Accepts the supported partial clone filters: `blob:none` and
+/// `blob:limit=<n>[kmg]`.
+pub fn check_filter(spec: &str) -> Result<()> {
+    if spec == "blob:none" {
+        return Ok(());
+    }
+    if let Some(limit) = spec.strip_prefix("blob:limit=") {
+        let digits = limit.trim_end_matches(['k', 'm', 'g', 'K', 'M', 'G']);
+        if limit.len() - digits.len() <= 1 && digits.parse::<u64>().is_ok() {
+            return Ok(());
+        }
+    }
+    bail!("Unsupported object filter {spec}")
+}
+
+/// A fetched pack along with the commits whose parents were left out of it.
+pub struct Fetched {
+    pub pack: Vec<u8>,
+    pub shallow: Vec<Sha1>,
+}
+
+/// Fails in repositories whose objects are not named by SHA-1, the only
+/// object format the protocol is spoken with here.
+pub fn check_object_format() -> Result<()> {
+    let algo = HashAlgo::current();
+    if algo != HashAlgo::Sha1 {
+        bail!(
+            "Fetching and pushing are not supported in {} repositories",
+            algo.name()
+        );
+    }
+    Ok(())
+}
+
+/// Discovers the refs of an upload-pack service, preferring protocol v2 and
+/// falling back to v0 when the server ignores the request for it.
+pub fn discover_references(git_url: &Url) -> Result<Advertisement> {
+    let advertisement = match git_url.scheme() {
+        "git" => parse_advertisement(connect_daemon(git_url)?.1)?,
+        _ => discover(git_url, UPLOAD_PACK)?,
+    };
+    Ok(advertisement)
+}
+
+/// Checks that a v0 server will serve every wanted hash: advertised ref tips
+/// always are, anything else only with `allow-*-sha1-in-want`.
+fn check_wants(advertisement: &Advertisement, wants: &[Reference]) -> Result<()> {
+    let unadvertised = wants
+        .iter()
+        .any(|(hash, _)| !advertisement.refs.iter().any(|(tip, _)| tip == hash));
+    if unadvertised
+        && !(advertisement.supports("allow-tip-sha1-in-want")
+            || advertisement.supports("allow-reachable-sha1-in-want"))
+    {
+        bail!(Error::Protocol(
+            "Missing git server capabilities".to_owned()
+        ));
+    }
+    Ok(())
+}
+
+fn discover(git_url: &Url, service: &str) -> Result<Advertisement> {
+    let url = git_url.join(&format!("info/refs?service={service}"))?;
+    let response = send(&url, |client, url| {
+        let request = client.get(url);
+        // receive-pack only speaks v0
+        if service == UPLOAD_PACK {
+            request.header(PROTOCOL_HEADER, "version=2")
+        } else {
+            request
+        }
+    })?;
 
     if response.status() != StatusCode::OK && response.status() != StatusCode::NOT_MODIFIED {
         bail!(
@@ -20,109 +179,797 @@ pub fn discover_references(git_url: &Url) -> Result<Vec<Reference>> {
             response.status()
         );
     }
-    if let Some(content_type) = response.headers().get(header::CONTENT_TYPE) {
-        if content_type != "application/x-git-upload-pack-advertisement" {
-            bail!("Wrong response content type {}", content_type.to_str()?);
-        }
-    }
-    let content = response.text()?;
-    let mut pkt_lines = content.lines();
-    let first_line = parse_pkt_line(
-        pkt_lines
-            .next()
-            .ok_or_else(|| anyhow!("Empty discovery response"))?,
-    )?;
-    if first_line != "# service=git-upload-pack" {
-        bail!("Unexpected first discovery response line {first_line}")
-    }
-    let mut pkt_lines = pkt_lines.rev();
-    let last_line = parse_pkt_line(
-        pkt_lines
-            .next()
-            .ok_or_else(|| anyhow!("Discovery response with no terminator line"))?,
-    )?;
-    if last_line != "" {
-        bail!("Unexpected last line {last_line}");
-    }
-    let mut refs = pkt_lines
-        .rev()
-        .enumerate()
-        .map(|(i, p)| {
-            let line = if i == 0 {
-                p.get(4..)
-                    .ok_or_else(|| anyhow!("First ref line in wrong format"))?
-            } else {
-                p
-            };
-            Ok(parse_pkt_line(line)?
-                .split_once(' ')
-                .map(|(hash, reference)| (hash.to_owned(), reference.to_owned()))
-                .ok_or_else(|| anyhow!("Ref line in wrong format"))?)
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|content_type| content_type.to_str().unwrap_or_default().to_owned());
+    let content = response.bytes()?;
+    if content_type.as_deref() != Some(&format!("application/x-{service}-advertisement")) {
+        if service == UPLOAD_PACK && is_dumb_listing(content_type.as_deref(), &content) {
+            eprintln!("Falling back to the dumb HTTP protocol");
+            return discover_dumb(git_url, &String::from_utf8_lossy(&content));
+        }
+        bail!(Error::Protocol(format!(
+            "Wrong response content type {content_type:?}"
+        )));
+    }
+    let mut pkt_lines = split_pkt_lines(&content)?.into_iter().peekable();
+    // v2 servers answer without the service announcement
+    let service_line = format!("# service={service}\n");
+    if pkt_lines.peek() == Some(&Some(service_line.as_bytes())) {
+        pkt_lines.next();
+        if pkt_lines.next() != Some(None) {
+            bail!(Error::Protocol(
+                "Missing flush after the service line".to_owned()
+            ));
+        }
+    }
+    let lines = pkt_lines
+        .map_while(|line| line)
+        .map(|line| String::from_utf8_lossy(line).trim_end().to_owned())
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        bail!(Error::Protocol("Empty discovery response".to_owned()));
+    }
+    if lines.first().map(String::as_str) == Some("version 2") {
+        return Ok(Advertisement {
+            refs: list_references(git_url)?,
+            capabilities: lines[1..].to_vec(),
+            protocol: Protocol::V2,
+        });
+    }
+    parse_advertisement(lines)
+}
+
+/// Parses the v0 ref advertisement, whose first line also carries the capabilities.
+fn parse_advertisement(lines: Vec<String>) -> Result<Advertisement> {
+    let mut refs = vec![];
+    let mut capabilities = vec![];
+    for (i, line) in lines.into_iter().enumerate() {
+        let line = if i == 0 {
+            let (reference, announced) = line.split_once('\0').ok_or_else(|| {
+                Error::Protocol("Discovery response without capabilities".to_owned())
+            })?;
+            capabilities = announced.split(' ').map(str::to_owned).collect();
+            reference.to_owned()
+        } else {
+            line
+        };
+        let (hash, name) = line
+            .split_once(' ')
+            .ok_or_else(|| Error::Protocol("Ref line in wrong format".to_owned()))?;
+        // peeled tags are not refs of their own, and an empty repository
+        // advertises its capabilities on a `capabilities^{}` placeholder
+        if !name.ends_with("^{}") {
+            refs.push((hash.to_owned(), name.to_owned()));
+        }
+    }
+    Ok(Advertisement {
+        refs,
+        capabilities,
+        protocol: Protocol::V0,
+    })
+}
+
+/// Connects to a git daemon, asks for upload-pack and reads the ref advertisement.
+/// The connection is left open right after the advertisement's flush packet.
+fn connect_daemon(git_url: &Url) -> Result<(TcpStream, Vec<String>)> {
+    let host = git_url
+        .host_str()
+        .ok_or_else(|| anyhow!("No host in {git_url}"))?;
+    let port = git_url.port().unwrap_or(DAEMON_PORT);
+    let path = git_url.path().trim_end_matches('/');
+    let mut stream = TcpStream::connect((host, port))
+        .with_context(|| format!("Failed to connect to {host}:{port}"))?;
+    stream.write_all(pkt_line(&format!("{UPLOAD_PACK} {path}\0host={host}\0")).as_bytes())?;
+    let mut lines = vec![];
+    while let Some(line) = read_pkt_line(&mut stream)? {
+        let line = String::from_utf8_lossy(&line).trim_end().to_owned();
+        if let Some(message) = line.strip_prefix("ERR ") {
+            bail!("Remote error: {message}");
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        bail!(Error::Protocol("Empty discovery response".to_owned()));
+    }
+    Ok((stream, lines))
+}
+
+/// Reads a single pkt-line from a stream; a flush packet yields `None`.
+fn read_pkt_line(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; LENGTH_SIZE];
+    reader.read_exact(&mut length)?;
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+        .ok_or_else(|| Error::Protocol("Bad PKT length".to_owned()))?;
+    if length < LENGTH_SIZE {
+        return Ok(None);
+    }
+    let mut line = vec![0; length - LENGTH_SIZE];
+    reader.read_exact(&mut line)?;
+    Ok(Some(line))
+}
+
+/// Whether an answer to the smart `info/refs` request is the static file of a
+/// dumb server, `<hash>\t<name>` lines, rather than an error or login page.
+fn is_dumb_listing(content_type: Option<&str>, content: &[u8]) -> bool {
+    if content_type.is_some_and(|content_type| content_type.starts_with("text/html")) {
+        return false;
+    }
+    let Ok(listing) = std::str::from_utf8(content) else {
+        return false;
+    };
+    listing.lines().all(|line| {
+        line.split_once('\t').is_some_and(|(hash, name)| {
+            matches!(hash.len(), 40 | 64)
+                && hash.bytes().all(|b| b.is_ascii_hexdigit())
+                && refs::is_valid_name(name.trim_end_matches("^{}"))
         })
-        .collect::<Result<Vec<_>>>()?;
-    let first_pkt_line = refs
-        .get_mut(0)
-        .ok_or_else(|| anyhow!("Discovery response without capabilities line"))?;
-    let capabilities_start = first_pkt_line
-        .1
-        .find('\0')
-        .ok_or_else(|| anyhow!("Discovery response without capabilities"))?;
-    let capabilities = first_pkt_line.1.split_off(capabilities_start);
-    if !(capabilities.contains("allow-tip-sha1-in-want")
-        || capabilities.contains("allow-reachable-sha1-in-want"))
+    })
+}
+
+/// Parses a static `info/refs` file (`<hash>\t<name>` lines) and resolves the
+/// remote `HEAD` file against it.
+fn discover_dumb(git_url: &Url, info_refs: &str) -> Result<Advertisement> {
+    let mut refs = vec![];
+    for line in info_refs.lines() {
+        let (hash, name) = line
+            .split_once('\t')
+            .ok_or_else(|| Error::Protocol(format!("Bad info/refs line: {line}")))?;
+        if !name.ends_with("^{}") {
+            refs.push((hash.to_owned(), name.to_owned()));
+        }
+    }
+    let head =
+        String::from_utf8(download(git_url, "HEAD")?.ok_or_else(|| anyhow!("No remote HEAD"))?)?;
+    let head = head.trim_end();
+    let head_hash = match head.strip_prefix("ref: ") {
+        Some(target) => refs
+            .iter()
+            .find(|(_, name)| name == target)
+            .map(|(hash, _)| hash.clone())
+            .ok_or_else(|| anyhow!("Remote HEAD points at missing {target}"))?,
+        None => head.to_owned(),
+    };
+    refs.insert(0, (head_hash, "HEAD".to_owned()));
+    Ok(Advertisement {
+        refs,
+        capabilities: vec![],
+        protocol: Protocol::Dumb,
+    })
+}
+
+/// GETs a file relative to the repository URL; a missing file yields `None`.
+fn download(git_url: &Url, path: &str) -> Result<Option<Vec<u8>>> {
+    let mut response = send(&git_url.join(path)?, |client, url| client.get(url))?;
+    match response.status() {
+        StatusCode::OK => {
+            let mut body = vec![];
+            response.read_to_end(&mut body)?;
+            Ok(Some(body))
+        }
+        StatusCode::NOT_FOUND => Ok(None),
+        status => bail!("Failed to download {path}: unexpected status {status}"),
+    }
+}
+
+/// Walks the object graph from the wanted refs over plain GETs, trying loose
+/// objects first and downloading packs that contain missing ones. The objects
+/// collected are returned as a freshly written pack.
+fn fetch_dumb(git_url: &Url, refs: &[Reference], haves: &[Sha1]) -> Result<Vec<u8>> {
+    let mut objects: HashMap<Sha1, Object> = HashMap::new();
+    let mut packs: Option<DumbPacks> = None;
+    let mut visited = HashSet::new();
+    if !haves.is_empty() {
+        // everything reachable from local commits is already here
+        let local = revwalk::reachable_objects(&Odb::open()?, haves, &[])?;
+        visited.extend(local.into_iter().map(|(hash, _)| hash));
+    }
+    let mut queue = refs
+        .iter()
+        .map(|(hash, _)| hash.clone())
+        .collect::<Vec<_>>();
+    while let Some(hash) = queue.pop() {
+        if !visited.insert(hash.clone()) {
+            continue;
+        }
+        if !objects.contains_key(&hash) {
+            let (subdir, filename) = hash.split_at(2);
+            match download(git_url, &format!("objects/{subdir}/{filename}"))? {
+                Some(data) => {
+                    let object =
+                        ObjectReader::from_loose(std::io::Cursor::new(data))?.into_object()?;
+                    objects.insert(hash.clone(), object);
+                }
+                None => {
+                    let packs = match &mut packs {
+                        Some(packs) => packs,
+                        None => packs.insert(list_dumb_packs(git_url)?),
+                    };
+                    download_pack_with(git_url, packs, &hash, &mut objects)?;
+                }
+            }
+        }
+        match objects[&hash].parse()? {
+            ParsedObject::Commit(commit) => {
+                queue.push(commit.tree);
+                queue.extend(commit.parents);
+            }
+            ParsedObject::Tag(tag) => queue.push(tag.object),
+            ParsedObject::Tree(entries) => {
+                queue.extend(entries.into_iter().map(|entry| hex::encode(entry.hash)))
+            }
+            ParsedObject::Blob(_) => {}
+        }
+    }
+    println!("Downloaded {} objects", objects.len());
+    let objects = objects
+        .into_values()
+        .map(|object| (object, String::new()))
+        .collect::<Vec<_>>();
+    pack::write_pack(&objects)
+}
+
+/// The packs of a dumb server not downloaded yet, each with the objects its
+/// index lists.
+type DumbPacks = Vec<(String, HashSet<Sha1>)>;
+
+/// Lists the packs in `objects/info/packs` and downloads their indexes, once
+/// per fetch.
+fn list_dumb_packs(git_url: &Url) -> Result<DumbPacks> {
+    let Some(listing) = download(git_url, "objects/info/packs")? else {
+        return Ok(vec![]);
+    };
+    let mut packs = vec![];
+    for name in String::from_utf8_lossy(&listing)
+        .lines()
+        .filter_map(|line| line.strip_prefix("P "))
     {
-        bail!("Missing git server capabilities");
+        let index_name = name.replace(".pack", ".idx");
+        let index = download(git_url, &format!("objects/pack/{index_name}"))?
+            .ok_or_else(|| anyhow!("Missing pack index {index_name}"))?;
+        let hashes = pack::parse_index(index)
+            .with_context(|| format!("Bad pack index {index_name}"))?
+            .into_iter()
+            .map(|(hash, _)| hash.to_string())
+            .collect();
+        packs.push((name.to_owned(), hashes));
+    }
+    Ok(packs)
+}
+
+/// Downloads the pack whose index lists `hash` and adds all of its objects.
+/// Packs are dropped from the list once downloaded.
+fn download_pack_with(
+    git_url: &Url,
+    packs: &mut DumbPacks,
+    hash: &str,
+    objects: &mut HashMap<Sha1, Object>,
+) -> Result<()> {
+    let Some(position) = packs.iter().position(|(_, hashes)| hashes.contains(hash)) else {
+        bail!("Object {hash} is missing on the remote");
+    };
+    let (name, _) = packs.remove(position);
+    let data = download(git_url, &format!("objects/pack/{name}"))?
+        .ok_or_else(|| anyhow!("Missing pack {name}"))?;
+    for object in pack::parse(data)? {
+        objects.insert(hex::encode(object.hash()), object);
     }
+    Ok(())
+}
+
+/// Runs the v2 `ls-refs` command for HEAD, branches and tags. HEAD comes first.
+fn list_references(git_url: &Url) -> Result<Vec<Reference>> {
+    let arguments = ["symrefs", "ref-prefix HEAD"]
+        .into_iter()
+        .map(str::to_owned)
+        .chain(
+            REF_PREFIXES
+                .iter()
+                .map(|prefix| format!("ref-prefix {prefix}")),
+        )
+        .collect::<Vec<_>>();
+    let response = command_v2(git_url, "ls-refs", &arguments)?;
+    let mut refs = split_pkt_lines(&response)?
+        .into_iter()
+        .map_while(|line| line)
+        .map(|line| {
+            let line = String::from_utf8_lossy(line);
+            let mut fields = line.trim_end().split(' ');
+            match (fields.next(), fields.next()) {
+                (Some(hash), Some(name)) => Ok((hash.to_owned(), name.to_owned())),
+                _ => bail!(Error::Protocol(format!("Ref line in wrong format: {line}"))),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    refs.sort_by_key(|(_, name)| name != "HEAD");
     Ok(refs)
 }
 
-fn parse_pkt_line(data: &str) -> Result<String> {
-    let expected_length = usize::from_str_radix(
-        data.get(..4)
-            .ok_or_else(|| anyhow!("Bad PKT length: {data}"))?,
-        16,
-    )?;
-    // add 1 to account for the deleted newline character
-    // handle special case for empty lines
-    if (data.len() + 1 != expected_length) && (expected_length == 0 && data.len() != LENGTH_SIZE) {
-        bail!(
-            "Wrong encoded PKT length: expected {expected_length}, got {}",
-            data.len()
-        );
+/// Sends a protocol v2 command with its arguments and returns the raw response.
+fn command_v2(git_url: &Url, command: &str, arguments: &[String]) -> Result<Vec<u8>> {
+    let mut request = pkt_line(&format!("command={command}\n"));
+    request.push_str(DELIMITER_PKT);
+    for argument in arguments {
+        request.push_str(&pkt_line(&format!("{argument}\n")));
+    }
+    request.push_str(FLUSH_PKT);
+    let mut response = send(&git_url.join(UPLOAD_PACK)?, |client, url| {
+        client
+            .post(url)
+            .header(PROTOCOL_HEADER, "version=2")
+            .header(
+                header::CONTENT_TYPE,
+                "application/x-git-upload-pack-request",
+            )
+            .body(request.clone())
+    })?;
+    if response.status() != StatusCode::OK {
+        bail!("{command} failed: unexpected status {}", response.status());
+    }
+    let mut body = vec![];
+    response.read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Credentials that succeeded earlier in this process, by the origin they
+/// were accepted by, reused there without waiting for a 401.
+static SESSION_CREDENTIALS: Mutex<BTreeMap<String, credential::Credentials>> =
+    Mutex::new(BTreeMap::new());
+
+/// The scheme, host and port of `url`: credentials are only sent back where
+/// they came from.
+fn origin(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+/// Sends the request built by `request` for `url`, authenticating with the
+/// credentials in the URL if any. When the server answers 401 without them,
+/// the request is retried once with credentials found by `credential::lookup`,
+/// which the credential helpers are then told to store or erase.
+fn send(url: &Url, request: impl Fn(&Client, Url) -> RequestBuilder) -> Result<Response> {
+    let client = Client::new();
+    let mut plain_url = url.clone();
+    let _ = plain_url.set_username("");
+    let _ = plain_url.set_password(None);
+    let attempt = |credentials: Option<&credential::Credentials>| {
+        let builder = request(&client, plain_url.clone());
+        let builder = match credentials {
+            Some(credentials) => credentials.apply(builder),
+            None => builder,
+        };
+        builder.send()
+    };
+    let url_credentials = credential::from_url(url);
+    let session_credentials = SESSION_CREDENTIALS
+        .lock()
+        .unwrap()
+        .get(&origin(url))
+        .cloned();
+    let response = attempt(url_credentials.as_ref().or(session_credentials.as_ref()))?;
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+    if url_credentials.is_none() {
+        if let Some(credentials) = credential::lookup(url)? {
+            let response = attempt(Some(&credentials))?;
+            if response.status() != StatusCode::UNAUTHORIZED {
+                credential::approve(&plain_url, &credentials)?;
+                SESSION_CREDENTIALS
+                    .lock()
+                    .unwrap()
+                    .insert(origin(url), credentials);
+                return Ok(response);
+            }
+            credential::reject(&plain_url, &credentials)?;
+        }
+    }
+    bail!(
+        "Authentication failed for {plain_url}: pass credentials in the URL, \
+         set GIT_TOKEN or GIT_USERNAME/GIT_PASSWORD, add the host to ~/.netrc, \
+         or configure a credential.helper"
+    )
+}
+
+fn pkt_line(line: &str) -> String {
+    format!("{:04x}{line}", line.len() + LENGTH_SIZE)
+}
+
+/// Splits a pkt-line stream into payloads; flush, delimiter and response-end
+/// packets become `None`.
+fn split_pkt_lines(mut data: &[u8]) -> Result<Vec<Option<&[u8]>>> {
+    let mut lines = vec![];
+    while !data.is_empty() {
+        let length = data
+            .get(..LENGTH_SIZE)
+            .and_then(|length| std::str::from_utf8(length).ok())
+            .and_then(|length| usize::from_str_radix(length, 16).ok())
+            .ok_or_else(|| Error::Protocol("Bad PKT length".to_owned()))?;
+        if length < LENGTH_SIZE - 1 {
+            lines.push(None);
+            data = &data[LENGTH_SIZE..];
+            continue;
+        }
+        if length < LENGTH_SIZE || length > data.len() {
+            bail!(Error::Protocol(format!(
+                "Wrong encoded PKT length {length}"
+            )));
+        }
+        lines.push(Some(&data[LENGTH_SIZE..length]));
+        data = &data[length..];
+    }
+    Ok(lines)
+}
+
+/// Updates a ref of `remote`, a configured remote or a URL, according to
+/// `[+]<source>[:<destination>]`. An empty source deletes the destination;
+/// `+` allows non-fast-forward updates. The remote-tracking ref of the
+/// destination follows once the server accepted the update.
+pub fn push(remote: &str, refspec: &str) -> Result<()> {
+    check_object_format()?;
+    let git_url = &remote_url(remote)?;
+    let (force, refspec) = match refspec.strip_prefix('+') {
+        Some(refspec) => (true, refspec),
+        None => (false, refspec),
+    };
+    let (source, destination) = refspec.split_once(':').unwrap_or((refspec, refspec));
+    let source_ref = refs::expand(source)?;
+    let destination = if destination.starts_with("refs/") {
+        destination.to_owned()
+    } else if source_ref
+        .as_deref()
+        .is_some_and(|name| name.starts_with("refs/tags/"))
+    {
+        format!("refs/tags/{destination}")
+    } else {
+        format!("refs/heads/{destination}")
+    };
+    let new = if source.is_empty() {
+        ZERO_HASH.to_owned()
+    } else {
+        revparse::resolve(source)?
+    };
+
+    let advertisement = discover(git_url, RECEIVE_PACK)?;
+    let old = advertisement
+        .refs
+        .iter()
+        .find(|(_, name)| *name == destination)
+        .map_or(ZERO_HASH, |(hash, _)| hash.as_str())
+        .to_owned();
+    if old == new {
+        println!("Everything up-to-date");
+        return update_tracking_ref(remote, &destination, &new);
+    }
+    let odb = Odb::open()?;
+    if !force
+        && old != ZERO_HASH
+        && new != ZERO_HASH
+        && !(odb.exists(&old)? && revwalk::is_ancestor(&odb, &old, &new)?)
+    {
+        bail!("Updates to {destination} were rejected (non-fast-forward)");
+    }
+    if new == ZERO_HASH && !advertisement.supports("delete-refs") {
+        bail!("The remote does not support deleting refs");
+    }
+
+    let mut body = pkt_line(&format!("{old} {new} {destination}\0report-status\n"));
+    body.push_str(FLUSH_PKT);
+    let mut body = body.into_bytes();
+    if new != ZERO_HASH {
+        let exclude = advertisement
+            .refs
+            .iter()
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<_>>();
+        let objects = revwalk::reachable_objects(&odb, std::slice::from_ref(&new), &exclude)?
+            .into_iter()
+            .map(|(hash, path)| Ok((odb.read(&hash)?, path)))
+            .collect::<Result<Vec<_>>>()?;
+        println!("Sending {} objects", objects.len());
+        body.extend(pack::write_pack(&objects)?);
+    }
+    let url = git_url.join(RECEIVE_PACK)?;
+    let response = send(&url, |client, url| {
+        client
+            .post(url)
+            .header(
+                header::CONTENT_TYPE,
+                "application/x-git-receive-pack-request",
+            )
+            .body(body.clone())
+    })?;
+    if response.status() != StatusCode::OK {
+        bail!("Push failed: unexpected status {}", response.status());
+    }
+    parse_report_status(&response.bytes()?)?;
+    if new == ZERO_HASH {
+        println!(" - [deleted] {destination}");
+    } else {
+        println!("{} -> {destination}", short_hash(&new));
+    }
+    update_tracking_ref(remote, &destination, &new)
+}
+
+/// Moves the ref that fetching `destination` from `remote` would update to
+/// what was pushed. A URL has no fetch refspecs, so nothing tracks it.
+fn update_tracking_ref(remote: &str, destination: &str, new: &str) -> Result<()> {
+    let config = Config::load()?;
+    for refspec in config.get_all(&format!("remote.{remote}.fetch")) {
+        let Some(tracking) = Refspec::parse(refspec)?.map(destination) else {
+            continue;
+        };
+        if new == ZERO_HASH {
+            if refs::peel(&tracking)?.is_some() {
+                refs::delete(&tracking)?;
+            }
+        } else {
+            refs::update(&tracking, new, "update by push")?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_report_status(report: &[u8]) -> Result<()> {
+    let mut lines = split_pkt_lines(report)?
+        .into_iter()
+        .flatten()
+        .map(|line| String::from_utf8_lossy(line).trim_end().to_owned());
+    let unpack = lines
+        .next()
+        .ok_or_else(|| Error::Protocol("Empty push report".to_owned()))?;
+    if unpack != "unpack ok" {
+        bail!("Remote failed to unpack: {unpack}");
+    }
+    for line in lines {
+        if let Some(rejection) = line.strip_prefix("ng ") {
+            bail!("Remote rejected {rejection}");
+        }
+    }
+    Ok(())
+}
+
+pub fn fetch_pack(
+    git_url: &Url,
+    advertisement: &Advertisement,
+    options: &FetchOptions,
+) -> Result<Fetched> {
+    if options.depth.is_some() && !advertisement.supports_fetch("shallow") {
+        bail!("The server does not support shallow clones");
+    }
+    let mut options = options.clone();
+    if options.filter.is_some() && !advertisement.supports_fetch("filter") {
+        eprintln!("warning: filtering not recognized by server, ignoring");
+        options.filter = None;
+    }
+    match advertisement.protocol {
+        Protocol::V2 => return fetch_pack_v2(git_url, &advertisement.refs, &options),
+        Protocol::Dumb => {
+            return Ok(Fetched {
+                pack: fetch_dumb(git_url, &advertisement.refs, &options.haves)?,
+                shallow: vec![],
+            })
+        }
+        Protocol::V0 => {}
+    }
+    let sideband = advertisement.supports("side-band-64k");
+    let request = want_request(advertisement, &options);
+    let mut body: Vec<u8> = Vec::new();
+    if git_url.scheme() == "git" {
+        // the daemon connection is stateful, so start over; the refs may have
+        // moved since they were first listed
+        let (mut stream, lines) = connect_daemon(git_url)?;
+        check_wants(&parse_advertisement(lines)?, &advertisement.refs)?;
+        stream.write_all(request.as_bytes())?;
+        stream.read_to_end(&mut body)?;
+    } else {
+        let url = git_url.join("git-upload-pack")?;
+        let mut response = send(&url, |client, url| {
+            client
+                .post(url)
+                .header(
+                    header::CONTENT_TYPE,
+                    "application/x-git-upload-pack-request",
+                )
+                .body(request.clone())
+        })?;
+        response.read_to_end(&mut body)?;
+    }
+    parse_fetch_response(&body, sideband)
+}
+
+/// Splits a v0 upload-pack response into the shallow boundary, sent first when
+/// deepening, and the pack following the final NAK, demultiplexing it when
+/// side-band was negotiated.
+fn parse_fetch_response(mut body: &[u8], sideband: bool) -> Result<Fetched> {
+    let mut shallow = vec![];
+    loop {
+        let Some(line) = read_pkt_line(&mut body).context("Unexpected fetch response")? else {
+            continue;
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        if let Some(hash) = line.strip_prefix("shallow ") {
+            shallow.push(hash.to_owned());
+        } else if line == "NAK" || (line.starts_with("ACK ") && line.split(' ').count() == 2) {
+            // "ACK <hash> continue" lines of multi_ack negotiation precede the final one
+            let pack = if sideband {
+                let mut pack = vec![];
+                while let Some(packet) = read_pkt_line(&mut body)? {
+                    demultiplex(&packet, &mut pack)?;
+                }
+                pack
+            } else {
+                body.to_vec()
+            };
+            return Ok(Fetched { pack, shallow });
+        } else if let Some(message) = line.strip_prefix("ERR ") {
+            bail!("Remote error: {message}");
+        }
     }
-    Ok(data[4..].to_owned())
 }
 
-pub fn fetch_pack(git_url: &Url, refs: &[Reference]) -> Result<Vec<u8>> {
-    let request = refs
+fn want_request(advertisement: &Advertisement, options: &FetchOptions) -> String {
+    let mut capabilities = " multi_ack".to_owned();
+    for capability in ["side-band-64k", "include-tag"] {
+        if advertisement.supports(capability) {
+            capabilities.push_str(&format!(" {capability}"));
+        }
+    }
+    if options.depth.is_some() || !options.shallow.is_empty() {
+        capabilities.push_str(" shallow");
+    }
+    if options.filter.is_some() {
+        capabilities.push_str(" filter");
+    }
+    let mut request = advertisement
+        .refs
         .iter()
         .enumerate()
         .map(|(i, (sha, _))| {
             let want = if i == 0 {
-                format!("want {sha} multi_ack\n")
+                format!("want {sha}{capabilities}\n")
             } else {
                 format!("want {sha}\n")
             };
             format!("{:04x}{}", want.len() + LENGTH_SIZE, want)
         })
-        .chain(std::iter::once("0000".to_owned()))
-        .chain(std::iter::once("0009done\n".to_owned()))
         // join
         .fold(String::new(), |result, line| result + line.as_str());
-    let url = git_url.join("git-upload-pack")?;
-    let mut response = Client::new()
-        .post(url)
-        .header(
-            header::CONTENT_TYPE,
-            "application/x-git-upload-pack-request",
+    for hash in &options.shallow {
+        request.push_str(&pkt_line(&format!("shallow {hash}\n")));
+    }
+    if let Some(depth) = options.depth {
+        request.push_str(&pkt_line(&format!("deepen {depth}\n")));
+    }
+    if let Some(filter) = &options.filter {
+        request.push_str(&pkt_line(&format!("filter {filter}\n")));
+    }
+    request.push_str(FLUSH_PKT);
+    for hash in &options.haves {
+        request.push_str(&pkt_line(&format!("have {hash}\n")));
+    }
+    request + "0009done\n"
+}
+
+fn fetch_pack_v2(git_url: &Url, refs: &[Reference], options: &FetchOptions) -> Result<Fetched> {
+    let mut wants = refs.iter().map(|(hash, _)| hash).collect::<Vec<_>>();
+    wants.sort();
+    wants.dedup();
+    let arguments = ["ofs-delta".to_owned(), "include-tag".to_owned()]
+        .into_iter()
+        .chain(wants.into_iter().map(|hash| format!("want {hash}")))
+        .chain(options.haves.iter().map(|hash| format!("have {hash}")))
+        .chain(options.shallow.iter().map(|hash| format!("shallow {hash}")))
+        .chain(options.depth.map(|depth| format!("deepen {depth}")))
+        .chain(
+            options
+                .filter
+                .as_ref()
+                .map(|filter| format!("filter {filter}")),
         )
-        .body(request.to_owned())
-        .send()?;
-    let mut body: Vec<u8> = Vec::new();
-    response.read_to_end(&mut body)?;
-    const PACK_OFFSET: usize = 8;
-    Ok(body
-        .get(8..)
-        .ok_or_else(|| anyhow!("Unexpected fetch response"))?
-        .to_vec())
+        .chain(["done".to_owned()])
+        .collect::<Vec<_>>();
+    let response = command_v2(git_url, "fetch", &arguments)?;
+    let mut lines = split_pkt_lines(&response)?.into_iter();
+    // collect the shallow-info section and skip anything else until the pack arrives
+    let mut shallow = vec![];
+    for line in lines.by_ref().flatten() {
+        if line == b"packfile\n" {
+            break;
+        }
+        if let Some(hash) = line.strip_prefix(b"shallow ") {
+            shallow.push(String::from_utf8_lossy(hash).trim_end().to_owned());
+        }
+    }
+    let mut pack = vec![];
+    for line in lines.map_while(|line| line) {
+        demultiplex(line, &mut pack)?;
+    }
+    if pack.is_empty() {
+        bail!(Error::Protocol("Fetch response without a pack".to_owned()));
+    }
+    Ok(Fetched { pack, shallow })
+}
+
+/// Handles one side-band packet: channel 1 carries pack data, channel 2
+/// progress messages for the user and channel 3 a fatal error.
+fn demultiplex(packet: &[u8], pack: &mut Vec<u8>) -> Result<()> {
+    match packet.split_first() {
+        Some((1, data)) => pack.extend_from_slice(data),
+        // messages may be split across packets, so pass them through as is
+        Some((2, progress)) => std::io::stderr().write_all(progress)?,
+        Some((3, message)) => bail!("Remote error: {}", String::from_utf8_lossy(message)),
+        _ => bail!(Error::Protocol("Unexpected side-band packet".to_owned())),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_are_cached_by_scheme_host_and_port() {
+        let key = |url: &str| origin(&Url::parse(url).unwrap());
+        assert_eq!(
+            key("https://example.com/a.git"),
+            key("https://user@example.com:443/b.git")
+        );
+        assert_ne!(
+            key("https://example.com/a.git"),
+            key("http://example.com/a.git")
+        );
+        assert_ne!(
+            key("https://example.com/a.git"),
+            key("https://example.com:8443/a.git")
+        );
+        assert_ne!(
+            key("https://example.com/a.git"),
+            key("https://example.org/a.git")
+        );
+    }
+
+    #[test]
+    fn only_ref_listings_fall_back_to_the_dumb_protocol() {
+        let hash = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let listing = format!("{hash}\trefs/heads/main\n{hash}\trefs/tags/v1^{{}}\n");
+        assert!(is_dumb_listing(Some("text/plain"), listing.as_bytes()));
+        assert!(is_dumb_listing(None, listing.as_bytes()));
+        assert!(!is_dumb_listing(Some("text/html"), listing.as_bytes()));
+        assert!(!is_dumb_listing(
+            Some("text/plain"),
+            b"<html><body>Please log in</body></html>\n"
+        ));
+        assert!(!is_dumb_listing(None, b"Service unavailable\n"));
+        assert!(!is_dumb_listing(
+            None,
+            format!("{hash}\trefs/heads/a..b\n").as_bytes()
+        ));
+    }
+
+    #[test]
+    fn pkt_lines_round_trip() {
+        let stream = format!("{}0000{}0001", pkt_line("command=ls-refs\n"), pkt_line("x"));
+        assert_eq!(
+            split_pkt_lines(stream.as_bytes()).unwrap(),
+            [Some(&b"command=ls-refs\n"[..]), None, Some(b"x"), None]
+        );
+        let mut reader = stream.as_bytes();
+        assert_eq!(
+            read_pkt_line(&mut reader).unwrap().as_deref(),
+            Some(&b"command=ls-refs\n"[..])
+        );
+        assert_eq!(read_pkt_line(&mut reader).unwrap(), None);
+        assert_eq!(
+            read_pkt_line(&mut reader).unwrap().as_deref(),
+            Some(&b"x"[..])
+        );
+
+        assert!(split_pkt_lines(b"00zzabc").is_err());
+        assert!(split_pkt_lines(b"0010short").is_err());
+        assert!(split_pkt_lines(b"0003").is_err());
+    }
 }
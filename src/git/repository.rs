@@ -0,0 +1,190 @@
+use super::{error, hash::HashAlgo, init_git_dir, parse_hash, platform, refs, ObjectId, Odb};
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+const DOT_GIT: &str = ".git";
+const GITDIR_PREFIX: &str = "gitdir: ";
+
+/// Files and directories of a git directory that are not shared between
+/// linked worktrees; everything else lives in the common directory.
+const PER_WORKTREE: &[&str] = &["HEAD", "index", "info/sparse-checkout", "rebase-merge"];
+
+thread_local! {
+    /// The git directory operations act on while `with_git_dir` runs, in
+    /// place of the current directory's.
+    static SCOPE: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// The git directory of a working tree: its `.git` directory, or for a
+/// linked worktree or a submodule the directory a `.git` file points at.
+pub fn resolve(worktree: &Path) -> Result<PathBuf> {
+    let dot_git = worktree.join(DOT_GIT);
+    if !dot_git.is_file() {
+        return Ok(dot_git);
+    }
+    let content = fs::read_to_string(&dot_git)?;
+    let target = content
+        .trim_end()
+        .strip_prefix(GITDIR_PREFIX)
+        .ok_or_else(|| anyhow!("Invalid gitfile format: {}", dot_git.display()))?;
+    Ok(worktree.join(target))
+}
+
+/// The top of the working tree holding `start`: the nearest directory from
+/// `start` up with a `.git` directory or gitdir file. The search neither
+/// crosses into another file system nor goes up into a directory listed in
+/// `GIT_CEILING_DIRECTORIES`.
+pub fn discover(start: &Path) -> Result<Option<PathBuf>> {
+    let start = fs::canonicalize(start)?;
+    let ceilings = env::var_os("GIT_CEILING_DIRECTORIES")
+        .map(|directories| {
+            env::split_paths(&directories)
+                .filter(|directory| directory.is_absolute())
+                .map(|directory| fs::canonicalize(&directory).unwrap_or(directory))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let device = |path: &Path| fs::metadata(path).map(|meta| platform::stat(&meta).dev);
+    let start_device = device(&start)?;
+    let mut directory = start.as_path();
+    loop {
+        if directory.join(DOT_GIT).exists() {
+            return Ok(Some(directory.to_owned()));
+        }
+        let Some(parent) = directory.parent() else {
+            return Ok(None);
+        };
+        if ceilings.iter().any(|ceiling| ceiling == parent) || device(parent)? != start_device {
+            return Ok(None);
+        }
+        directory = parent;
+    }
+}
+
+/// Moves to the top of the working tree holding the current directory, as
+/// git does before running a command. Outside of any, nothing changes.
+pub fn enter_worktree() -> Result<()> {
+    if let Some(top) = discover(Path::new("."))? {
+        env::set_current_dir(top)?;
+    }
+    Ok(())
+}
+
+/// Where the current directory is inside its working tree: empty at the top
+/// or outside of any working tree.
+pub fn prefix() -> Result<PathBuf> {
+    let current = fs::canonicalize(".")?;
+    Ok(match discover(&current)? {
+        Some(top) => current.strip_prefix(top)?.to_owned(),
+        None => PathBuf::new(),
+    })
+}
+
+/// The git directory of the current working tree, or the one `with_git_dir`
+/// is running with.
+pub fn git_dir() -> PathBuf {
+    if let Some(git_dir) = SCOPE.with(|scope| scope.borrow().clone()) {
+        return git_dir;
+    }
+    resolve(Path::new("")).unwrap_or_else(|_| PathBuf::from(DOT_GIT))
+}
+
+/// Runs `f` with the repository files — objects, refs, config and the like —
+/// looked up in `git_dir` rather than in the current directory's repository.
+pub fn with_git_dir<T>(git_dir: &Path, f: impl FnOnce() -> T) -> T {
+    /// Puts the enclosing scope back, even when `f` panics.
+    struct Restore(Option<PathBuf>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SCOPE.with(|scope| *scope.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(SCOPE.with(|scope| scope.replace(Some(git_dir.to_owned()))));
+    f()
+}
+
+/// The directory holding what all worktrees of the repository share, such
+/// as objects, refs and the configuration. A linked worktree's git directory
+/// names it in its `commondir` file.
+pub fn common_dir() -> PathBuf {
+    let git_dir = git_dir();
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => git_dir.join(common.trim_end()),
+        Err(_) => git_dir,
+    }
+}
+
+/// Where a file of the repository such as `index`, `HEAD`, `refs/heads/x`
+/// or `objects` lives, given by its path inside a git directory. Pseudo refs
+/// like `ORIG_HEAD` belong to the worktree, like `HEAD`.
+pub fn path(name: &str) -> PathBuf {
+    let per_worktree = PER_WORKTREE.contains(&name)
+        || (!name.contains('/') && name.ends_with("_HEAD"))
+        || name.starts_with("logs/") && !name.starts_with("logs/refs/");
+    if per_worktree {
+        git_dir().join(name)
+    } else {
+        common_dir().join(name)
+    }
+}
+
+/// A repository opened through its working tree. Its methods act on it
+/// wherever the current directory is; `run` lets the library's functions
+/// do so too.
+pub struct Repository {
+    worktree: PathBuf,
+    git_dir: PathBuf,
+}
+
+impl Repository {
+    /// Opens the repository whose working tree holds `path`.
+    pub fn open(path: impl AsRef<Path>) -> error::Result<Self> {
+        let path = path.as_ref();
+        let not_found = || anyhow!("Not a git repository: {}", path.display());
+        let worktree = discover(path)?.ok_or_else(not_found)?;
+        let git_dir = resolve(&worktree)?;
+        if !git_dir.is_dir() {
+            return Err(not_found().into());
+        }
+        Ok(Self { worktree, git_dir })
+    }
+
+    /// Creates a repository at `path`, hashing objects with `algo`.
+    pub fn init(path: impl AsRef<Path>, algo: HashAlgo) -> error::Result<Self> {
+        init_git_dir(path.as_ref(), algo)?;
+        Self::open(path)
+    }
+
+    pub fn worktree(&self) -> &Path {
+        &self.worktree
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// Runs `f` with the library's functions reading and writing this
+    /// repository instead of the current directory's. Paths inside the
+    /// working tree are still taken relative to the current directory.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        with_git_dir(&self.git_dir, f)
+    }
+
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.run(HashAlgo::current)
+    }
+
+    pub fn odb(&self) -> error::Result<Odb> {
+        self.run(Odb::open)
+    }
+
+    /// The commit HEAD points at, `None` on an unborn branch.
+    pub fn head(&self) -> error::Result<Option<ObjectId>> {
+        let head = self.run(|| refs::peel("HEAD"))?;
+        Ok(head.map(|hash| parse_hash(&hash)).transpose()?)
+    }
+}
@@ -0,0 +1,66 @@
+use super::{
+    attributes::Conversion, diff::Version, index::Index, merge, peel_to_commit, read_tree, refs,
+    reset_hard, revparse, short_hash, Odb,
+};
+use anyhow::Result;
+
+/// How much of the repository `reset` takes back to the target commit.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Only the branch, leaving changes staged.
+    Soft,
+    /// The branch and the index, leaving changes in the working tree.
+    #[default]
+    Mixed,
+    /// The branch, the index and the working tree, discarding changes.
+    Hard,
+}
+
+/// Moves the current branch, or detached HEAD, to a commit and resets the
+/// index and the working tree as far as `mode` says. The old tip is kept in
+/// `ORIG_HEAD` and the move is logged, so that it can be found again; a
+/// merge in progress is forgotten.
+pub fn reset(revision: &str, mode: ResetMode) -> Result<()> {
+    let target = peel_to_commit(&revparse::resolve(revision)?)?;
+    let odb = Odb::open()?;
+    let commit = odb.read_commit(&target)?;
+    match mode {
+        ResetMode::Soft => {}
+        ResetMode::Mixed => read_tree::load(&odb, &commit.tree)?,
+        ResetMode::Hard => reset_hard(&odb, &target)?,
+    }
+    if let Some(head) = refs::peel("HEAD")? {
+        refs::write("ORIG_HEAD", &head)?;
+    }
+    refs::update("HEAD", &target, &format!("reset: moving to {revision}"))?;
+    merge::clear_state()?;
+    match mode {
+        ResetMode::Hard => println!(
+            "HEAD is now at {} {}",
+            short_hash(&target),
+            commit.subject()
+        ),
+        ResetMode::Mixed => print_unstaged()?,
+        ResetMode::Soft => {}
+    }
+    Ok(())
+}
+
+/// Lists the tracked files whose working tree version differs from the index.
+fn print_unstaged() -> Result<()> {
+    let mut conversion = Conversion::new()?;
+    let mut header = false;
+    for (path, (mode, hash)) in Index::read()?.files() {
+        let state = match Version::from_file(&path, Some(mode), &mut conversion)? {
+            Some(version) if (version.mode, version.hash) == (mode, hash) => continue,
+            Some(_) => 'M',
+            None => 'D',
+        };
+        if !header {
+            println!("Unstaged changes after reset:");
+            header = true;
+        }
+        println!("{state}\t{}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use super::{
+    peel_to_commit, remote::Sha1, revparse, revwalk, revwalk::RevWalk, EntryKind, Odb, ParsedObject,
+};
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::io::{stdout, BufWriter, Write};
+
+#[derive(Default)]
+pub struct RevListOptions {
+    /// List the trees and blobs of the listed commits too (`--objects`).
+    pub objects: bool,
+}
+
+/// Lists the commits reachable from the given revisions but not from those
+/// excluded with `^<rev>` or the left side of `<from>..<to>`, newest first.
+pub fn rev_list(revisions: &[String], options: &RevListOptions) -> Result<()> {
+    let odb = Odb::open()?;
+    let (walk, hidden) = walk(&odb, revisions)?;
+
+    let mut out = BufWriter::new(stdout().lock());
+    let mut trees = vec![];
+    for entry in walk {
+        let (hash, commit) = entry?;
+        writeln!(out, "{hash}")?;
+        trees.push(commit.tree);
+    }
+    if options.objects {
+        // objects of excluded history are already on the other side
+        let mut seen = revwalk::reachable_objects(&odb, &hidden, &[])?
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect::<HashSet<_>>();
+        for tree in trees {
+            list_tree(&odb, &tree, "", &mut seen, &mut out)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Sets up a walk of the commits the revisions select, also returning the
+/// excluded commits.
+pub fn walk<'a>(odb: &'a Odb, revisions: &[String]) -> Result<(RevWalk<'a>, Vec<Sha1>)> {
+    let mut walk = RevWalk::new(odb);
+    let mut hidden = vec![];
+    let mut included = false;
+    for revision in revisions {
+        if let Some(excluded) = revision.strip_prefix('^') {
+            hidden.push(commit(excluded)?);
+        } else if let Some((from, to)) = revision.split_once("..") {
+            hidden.push(commit(from)?);
+            walk.push(&commit(to)?)?;
+            included = true;
+        } else {
+            walk.push(&commit(revision)?)?;
+            included = true;
+        }
+    }
+    if !included {
+        bail!("No revision to list");
+    }
+    for hash in &hidden {
+        walk.hide(hash)?;
+    }
+    Ok((walk, hidden))
+}
+
+/// A revision as the commit it resolves to, `HEAD` if empty as in `..<to>`.
+fn commit(revision: &str) -> Result<Sha1> {
+    let revision = if revision.is_empty() {
+        "HEAD"
+    } else {
+        revision
+    };
+    peel_to_commit(&revparse::resolve(revision)?)
+}
+
+/// Prints a tree and the objects below it not seen yet, each with its path.
+fn list_tree(
+    odb: &Odb,
+    tree: &str,
+    path: &str,
+    seen: &mut HashSet<Sha1>,
+    out: &mut impl Write,
+) -> Result<()> {
+    if !seen.insert(tree.to_owned()) {
+        return Ok(());
+    }
+    writeln!(out, "{tree} {path}")?;
+    let ParsedObject::Tree(entries) = odb.read(tree)?.parse()? else {
+        bail!("{tree} is not a tree");
+    };
+    for entry in entries {
+        let hash = entry.hash.to_string();
+        let entry_path = if path.is_empty() {
+            entry.name.clone()
+        } else {
+            format!("{path}/{}", entry.name)
+        };
+        match entry.kind() {
+            EntryKind::Tree => list_tree(odb, &hash, &entry_path, seen, out)?,
+            // submodule commits belong to another repository
+            EntryKind::Gitlink => {}
+            EntryKind::Blob => {
+                if seen.insert(hash.clone()) {
+                    writeln!(out, "{hash} {entry_path}")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use super::{
+    cherry_pick::{self, Change},
+    peel_to_commit, revparse, short_hash, signature, Odb, Role,
+};
+use anyhow::{bail, Result};
+
+/// Commits the inverse of a commit's change on top of HEAD: a three-way
+/// merge from the commit to its parent.
+pub fn revert(revision: &str) -> Result<()> {
+    let hash = peel_to_commit(&revparse::resolve(revision)?)?;
+    let odb = Odb::open()?;
+    let commit = odb.read_commit(&hash)?;
+    if commit.parents.len() > 1 {
+        bail!("commit {hash} is a merge but no -m option was given.");
+    }
+    let subject = commit.subject();
+    let change = Change {
+        base: Some(&hash),
+        target: commit.parents.first().map(String::as_str),
+        label: format!("parent of {} ({subject})", short_hash(&hash)),
+    };
+    let message = format!("Revert \"{subject}\"\n\nThis reverts commit {hash}.");
+    let outcome = cherry_pick::apply(&odb, &change, &signature(Role::Author)?, &message, "revert")?;
+    cherry_pick::report(
+        outcome,
+        &message,
+        &format!("could not revert {}... {subject}", short_hash(&hash)),
+    )
+}
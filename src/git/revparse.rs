@@ -0,0 +1,115 @@
+use super::{hash::HashAlgo, refs, remote::Sha1, Error, Odb, ParsedObject, MIN_ABBREVIATION};
+use anyhow::{anyhow, bail, Result};
+
+/// Resolves a revision expression to an object hash. Supports `HEAD`/`@`,
+/// ref names, full and abbreviated hashes, the `~<n>`, `^<n>` and `^{<type>}`
+/// suffixes and `<rev>:<path>` tree lookups.
+pub fn resolve(revision: &str) -> Result<Sha1> {
+    if let Some((tree_ish, path)) = revision.split_once(':') {
+        if tree_ish.is_empty() {
+            bail!("Index lookups are not supported: {revision}");
+        }
+        let tree = peel(&resolve(tree_ish)?, "tree")?;
+        return lookup_path(&tree, path);
+    }
+    let base_end = revision.find(['~', '^']).unwrap_or(revision.len());
+    let (base, mut suffixes) = revision.split_at(base_end);
+    let mut hash = resolve_base(base)?;
+    while let Some(operator) = suffixes.chars().next() {
+        suffixes = &suffixes[1..];
+        if operator == '^' && suffixes.starts_with('{') {
+            let end = suffixes
+                .find('}')
+                .ok_or_else(|| anyhow!("Unterminated ^{{ in {revision}"))?;
+            hash = peel(&hash, &suffixes[1..end])?;
+            suffixes = &suffixes[end + 1..];
+            continue;
+        }
+        let digits_end = suffixes
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(suffixes.len());
+        let count = match &suffixes[..digits_end] {
+            "" => 1,
+            digits => digits.parse()?,
+        };
+        suffixes = &suffixes[digits_end..];
+        hash = match operator {
+            '~' => ancestor(&hash, count)?,
+            _ => parent(&hash, count)?,
+        };
+    }
+    Ok(hash)
+}
+
+fn resolve_base(base: &str) -> Result<Sha1> {
+    let base = if base == "@" || base.is_empty() {
+        "HEAD"
+    } else {
+        base
+    };
+    let hex_size = HashAlgo::current().hex_size();
+    if base.len() == hex_size && base.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(base.to_ascii_lowercase());
+    }
+    if let Some(name) = refs::expand(base)? {
+        return refs::peel(&name)?.ok_or_else(|| anyhow!("Reference {name} is unborn"));
+    }
+    if base.len() >= MIN_ABBREVIATION
+        && base.len() <= hex_size
+        && base.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Ok(Odb::open()?.expand(base)?);
+    }
+    bail!(Error::RefNotFound(base.to_owned()))
+}
+
+/// Returns the `n`-th parent of a commit; `^0` is the commit itself.
+fn parent(hash: &str, n: usize) -> Result<Sha1> {
+    let hash = peel(hash, "commit")?;
+    if n == 0 {
+        return Ok(hash);
+    }
+    Odb::open()?
+        .read_commit(&hash)?
+        .parents
+        .get(n - 1)
+        .cloned()
+        .ok_or_else(|| anyhow!("{hash} has no parent {n}"))
+}
+
+/// Follows first parents `n` generations back.
+fn ancestor(hash: &str, n: usize) -> Result<Sha1> {
+    (0..n).try_fold(peel(hash, "commit")?, |hash, _| parent(&hash, 1))
+}
+
+/// Dereferences tags (and commits, for `tree`) until an object of `kind` is reached.
+/// An empty kind peels tags only.
+pub fn peel(hash: &str, kind: &str) -> Result<Sha1> {
+    match (Odb::open()?.read(hash)?.parse()?, kind) {
+        (ParsedObject::Tag(tag), _) if kind != "tag" => peel(&tag.object, kind),
+        (ParsedObject::Commit(commit), "tree") => Ok(commit.tree),
+        (_, "") | (_, "object") => Ok(hash.to_owned()),
+        (ParsedObject::Blob(_), "blob")
+        | (ParsedObject::Commit(_), "commit")
+        | (ParsedObject::Tag(_), "tag")
+        | (ParsedObject::Tree(_), "tree") => Ok(hash.to_owned()),
+        _ => bail!("{hash} cannot be peeled to a {kind}"),
+    }
+}
+
+/// Looks up a slash-separated path inside a tree.
+fn lookup_path(tree: &str, path: &str) -> Result<Sha1> {
+    let odb = Odb::open()?;
+    path.split('/')
+        .filter(|component| !component.is_empty())
+        .try_fold(tree.to_owned(), |hash, component| {
+            let ParsedObject::Tree(entries) = odb.read(&hash)?.parse()? else {
+                bail!("{hash} is not a tree");
+            };
+            entries
+                .into_iter()
+                .find(|entry| entry.name == component)
+                .map(|entry| hex::encode(entry.hash))
+                .ok_or_else(|| anyhow!("Path '{path}' does not exist"))
+        })
+}
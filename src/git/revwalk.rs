@@ -0,0 +1,293 @@
+use super::{
+    commit_graph::CommitGraph, remote::Sha1, repository, Commit, EntryKind, Odb, ParsedObject,
+};
+use anyhow::Result;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+
+/// Commits of a shallow repository whose parents are missing.
+const SHALLOW: &str = "shallow";
+
+/// Walks the commit graph from a set of tips, newest committer date first.
+/// The walk stops at the boundary of a shallow repository. Commits found in
+/// the commit-graph file are walked without reading their objects.
+pub struct RevWalk<'a> {
+    odb: &'a Odb,
+    queue: BinaryHeap<(i64, Sha1)>,
+    seen: HashSet<Sha1>,
+    shallow: HashSet<Sha1>,
+    graph: Option<CommitGraph>,
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn new(odb: &'a Odb) -> Self {
+        Self {
+            odb,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            shallow: read_shallow(),
+            graph: CommitGraph::load(),
+        }
+    }
+
+    pub fn push(&mut self, hash: &str) -> Result<()> {
+        if self.seen.insert(hash.to_owned()) {
+            let (time, _) = commit_links(self.odb, self.graph.as_ref(), hash)?;
+            self.queue.push((time, hash.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Excludes a commit and all its ancestors from the walk.
+    pub fn hide(&mut self, hash: &str) -> Result<()> {
+        let mut hidden = HashSet::new();
+        for hash in RevWalk::from_tip(self.odb, hash)?.hashes() {
+            hidden.insert(hash?);
+        }
+        self.queue.retain(|(_, hash)| !hidden.contains(hash));
+        self.seen.extend(hidden);
+        Ok(())
+    }
+
+    /// A walk of a commit and all its ancestors.
+    pub fn from_tip(odb: &'a Odb, hash: &str) -> Result<Self> {
+        let mut walk = Self::new(odb);
+        walk.push(hash)?;
+        Ok(walk)
+    }
+
+    /// Takes the next commit and queues its parents. The commit object comes
+    /// along only if it had to be read for that.
+    fn next_commit(&mut self) -> Result<Option<(Sha1, Option<Commit>)>> {
+        let Some((_, hash)) = self.queue.pop() else {
+            return Ok(None);
+        };
+        let (parents, commit) = match self.graph.as_ref().and_then(|graph| graph.get(&hash)) {
+            Some(graph_commit) => (graph_commit.parents, None),
+            None => {
+                let commit = self.odb.read_commit(&hash)?;
+                (commit.parents.clone(), Some(commit))
+            }
+        };
+        if !self.shallow.contains(&hash) {
+            for parent in &parents {
+                self.push(parent)?;
+            }
+        }
+        Ok(Some((hash, commit)))
+    }
+
+    /// The hashes of the walked commits, without reading the commits the
+    /// commit graph knows.
+    pub fn hashes(mut self) -> impl Iterator<Item = Result<Sha1>> + 'a {
+        std::iter::from_fn(move || {
+            self.next_commit()
+                .map(|entry| entry.map(|(hash, _)| hash))
+                .transpose()
+        })
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = Result<(Sha1, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = match self.next_commit() {
+            Ok(entry) => entry?,
+            Err(error) => return Some(Err(error)),
+        };
+        Some(match entry {
+            (hash, Some(commit)) => Ok((hash, commit)),
+            (hash, None) => self
+                .odb
+                .read_commit(&hash)
+                .map(|commit| (hash, commit))
+                .map_err(Into::into),
+        })
+    }
+}
+
+/// The committer date and parents of a commit, from the commit graph when it
+/// knows the commit.
+fn commit_links(odb: &Odb, graph: Option<&CommitGraph>, hash: &str) -> Result<(i64, Vec<Sha1>)> {
+    if let Some(commit) = graph.and_then(|graph| graph.get(hash)) {
+        return Ok((commit.time, commit.parents));
+    }
+    let commit = odb.read_commit(hash)?;
+    Ok((commit.time(), commit.parents))
+}
+
+/// Whether `ancestor` is reachable from `descendant` (a commit is its own ancestor).
+pub fn is_ancestor(odb: &Odb, ancestor: &str, descendant: &str) -> Result<bool> {
+    let known = CommitGraph::load().and_then(|graph| graph.is_ancestor(ancestor, descendant));
+    if let Some(known) = known {
+        return Ok(known);
+    }
+    for hash in RevWalk::from_tip(odb, descendant)?.hashes() {
+        if hash? == ancestor {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Finds the best common ancestor of two commits, the newest by committer
+/// date if there are several.
+pub fn merge_base(odb: &Odb, one: &str, two: &str) -> Result<Option<Sha1>> {
+    Ok(merge_bases(odb, one, two)?.into_iter().next())
+}
+
+/// Reached from the first commit given to `merge_bases`.
+const FROM_ONE: u8 = 1;
+/// Reached from the second commit.
+const FROM_TWO: u8 = 2;
+/// Behind a common ancestor, so that no best one can be found there.
+const STALE: u8 = 4;
+
+/// Finds the best common ancestors of two commits: those reachable from both
+/// that are not ancestors of another such commit, newest first. Like git,
+/// both histories are walked together by date, only until everything left
+/// to walk is behind a common ancestor.
+pub fn merge_bases(odb: &Odb, one: &str, two: &str) -> Result<Vec<Sha1>> {
+    if one == two {
+        return Ok(vec![one.to_owned()]);
+    }
+    let graph = CommitGraph::load();
+    let shallow = read_shallow();
+    let mut flags = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for (hash, flag) in [(one, FROM_ONE), (two, FROM_TWO)] {
+        let (time, parents) = commit_links(odb, graph.as_ref(), hash)?;
+        flags.insert(hash.to_owned(), flag);
+        queue.push((time, hash.to_owned(), parents));
+    }
+    let mut common = vec![];
+    let is_stale = |flags: &HashMap<Sha1, u8>, hash: &Sha1| flags[hash] & STALE != 0;
+    while queue.iter().any(|(_, hash, _)| !is_stale(&flags, hash)) {
+        let Some((_, hash, parents)) = queue.pop() else {
+            break;
+        };
+        let mut flag = flags[&hash];
+        if flag & (FROM_ONE | FROM_TWO | STALE) == FROM_ONE | FROM_TWO {
+            if !common.contains(&hash) {
+                common.push(hash.clone());
+            }
+            flag |= STALE;
+        }
+        if shallow.contains(&hash) {
+            continue;
+        }
+        for parent in parents {
+            let parent_flag = flags.entry(parent.clone()).or_insert(0);
+            if *parent_flag & flag == flag {
+                continue;
+            }
+            *parent_flag |= flag;
+            let (time, grandparents) = commit_links(odb, graph.as_ref(), &parent)?;
+            queue.push((time, parent, grandparents));
+        }
+    }
+    common.retain(|hash| !is_stale(&flags, hash));
+    // with criss-cross merges a base can still be behind another one
+    let mut bases: Vec<Sha1> = vec![];
+    for hash in &common {
+        let mut redundant = false;
+        for other in &common {
+            if other != hash && is_ancestor(odb, hash, other)? {
+                redundant = true;
+                break;
+            }
+        }
+        if !redundant {
+            bases.push(hash.clone());
+        }
+    }
+    Ok(bases)
+}
+
+/// Lists every object reachable from `tips` but not from any of the `exclude`
+/// tips present locally. Each hash comes with the path it was reached through,
+/// empty for commits and tags.
+pub fn reachable_objects(
+    odb: &Odb,
+    tips: &[Sha1],
+    exclude: &[Sha1],
+) -> Result<Vec<(Sha1, String)>> {
+    let mut seen = HashSet::new();
+    let shallow = read_shallow();
+    let mut uninteresting = vec![];
+    for hash in exclude {
+        if odb.exists(hash)? {
+            collect_objects(odb, hash, &shallow, &mut seen, &mut uninteresting)?;
+        }
+    }
+    let mut objects = vec![];
+    for hash in tips {
+        collect_objects(odb, hash, &shallow, &mut seen, &mut objects)?;
+    }
+    Ok(objects)
+}
+
+fn collect_objects(
+    odb: &Odb,
+    tip: &str,
+    shallow: &HashSet<Sha1>,
+    seen: &mut HashSet<Sha1>,
+    objects: &mut Vec<(Sha1, String)>,
+) -> Result<()> {
+    let mut stack = vec![(tip.to_owned(), String::new())];
+    while let Some((hash, path)) = stack.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        match odb.read(&hash)?.parse()? {
+            ParsedObject::Commit(commit) => {
+                if !shallow.contains(&hash) {
+                    stack.extend(commit.parents.into_iter().map(|p| (p, String::new())));
+                }
+                stack.push((commit.tree, String::new()));
+            }
+            ParsedObject::Tag(tag) => stack.push((tag.object, String::new())),
+            ParsedObject::Tree(entries) => {
+                // submodule commits belong to another repository
+                for entry in entries
+                    .into_iter()
+                    .rev()
+                    .filter(|e| e.kind() != EntryKind::Gitlink)
+                {
+                    let entry_path = if path.is_empty() {
+                        entry.name
+                    } else {
+                        format!("{path}/{}", entry.name)
+                    };
+                    stack.push((hex::encode(entry.hash), entry_path));
+                }
+            }
+            ParsedObject::Blob(_) => {}
+        }
+        objects.push((hash, path));
+    }
+    Ok(())
+}
+
+/// Reads the shallow boundary; a complete repository has none.
+pub fn read_shallow() -> HashSet<Sha1> {
+    fs::read_to_string(repository::path(SHALLOW))
+        .map(|content| content.lines().map(str::to_owned).collect())
+        .unwrap_or_default()
+}
+
+/// Records the shallow boundary, removing the file when history is complete.
+pub fn write_shallow(commits: &[Sha1]) -> Result<()> {
+    if commits.is_empty() {
+        if fs::metadata(repository::path(SHALLOW)).is_ok() {
+            fs::remove_file(repository::path(SHALLOW))?;
+        }
+        return Ok(());
+    }
+    let mut commits = commits.to_vec();
+    commits.sort();
+    commits.dedup();
+    fs::write(repository::path(SHALLOW), commits.join("\n") + "\n")?;
+    Ok(())
+}
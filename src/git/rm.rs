@@ -0,0 +1,112 @@
+use super::{
+    attributes::Conversion,
+    diff::Version,
+    head_files,
+    index::{self, Index},
+    remove_empty_parents, Odb,
+};
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+pub struct RmOptions {
+    /// Only remove the files from the index, keeping them in the working tree (`--cached`).
+    pub cached: bool,
+    /// Remove files even when changes to them would be lost (`-f`).
+    pub force: bool,
+    /// Remove the files under directories given (`-r`).
+    pub recursive: bool,
+}
+
+/// Removes tracked files from the index and the working tree. Unless forced,
+/// files whose changes exist nowhere else are kept and reported: staged
+/// changes, or local modifications when the file itself is removed.
+pub fn rm(paths: &[PathBuf], options: &RmOptions) -> Result<()> {
+    let mut index = Index::read()?;
+    let mut removed = BTreeSet::new();
+    for path in paths {
+        let name = index::pathspec_name(path)?;
+        let prefix = format!("{name}/");
+        let matches = index
+            .entries
+            .iter()
+            .filter(|entry| entry.path == name || entry.path.starts_with(&prefix))
+            .map(|entry| entry.path.clone())
+            .collect::<BTreeSet<_>>();
+        if matches.is_empty() {
+            bail!("pathspec '{}' did not match any files", path.display());
+        }
+        if !options.recursive && !matches.contains(&name) {
+            bail!("not removing '{name}' recursively without -r");
+        }
+        removed.extend(matches);
+    }
+    if !options.force {
+        check_changes(&index, &removed, options.cached)?;
+    }
+
+    for name in &removed {
+        println!("rm '{name}'");
+        index.remove(name);
+        let path = Path::new(name);
+        if !options.cached && fs::symlink_metadata(path).is_ok() {
+            fs::remove_file(path)?;
+            remove_empty_parents(path);
+        }
+    }
+    index.write()
+}
+
+/// Fails for files whose changes removing them would lose, as git does.
+fn check_changes(index: &Index, names: &BTreeSet<String>, cached: bool) -> Result<()> {
+    let head = head_files(&Odb::open()?)?;
+    let mut conversion = Conversion::new()?;
+    let (mut both, mut staged, mut local) = (vec![], vec![], vec![]);
+    // conflicted paths have no stage 0 entry and are removed as they are
+    for entry in index.entries.iter().filter(|entry| entry.stage == 0) {
+        if !names.contains(&entry.path) {
+            continue;
+        }
+        let path = Path::new(&entry.path);
+        let recorded = (entry.mode, entry.hash);
+        let Some(version) = Version::from_file(path, Some(entry.mode), &mut conversion)? else {
+            continue;
+        };
+        let has_staged = head.get(path) != Some(&recorded);
+        let has_local = (version.mode, version.hash) != recorded;
+        match (has_staged, has_local) {
+            (true, true) => both.push(&entry.path),
+            (true, false) if !cached => staged.push(&entry.path),
+            (false, true) if !cached => local.push(&entry.path),
+            _ => {}
+        }
+    }
+    let failure = |files: &[&String], problem: &str, hint: &str| {
+        let (noun, verb) = match files.len() {
+            1 => ("file", "has"),
+            _ => ("files", "have"),
+        };
+        let mut message = format!("the following {noun} {verb} {problem}:");
+        for file in files {
+            message.push_str(&format!("\n    {file}"));
+        }
+        message + hint
+    };
+    const HINT: &str = "\n(use --cached to keep the file, or -f to force removal)";
+    if !both.is_empty() {
+        bail!(failure(
+            &both,
+            "staged content different from both the\nfile and the HEAD",
+            "\n(use -f to force removal)"
+        ));
+    }
+    if !staged.is_empty() {
+        bail!(failure(&staged, "changes staged in the index", HINT));
+    }
+    if !local.is_empty() {
+        bail!(failure(&local, "local modifications", HINT));
+    }
+    Ok(())
+}
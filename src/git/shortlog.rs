@@ -0,0 +1,49 @@
+use super::{mailmap::Mailmap, rev_list, Odb};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+pub struct ShortlogOptions {
+    /// Print only the number of commits of each author (`-s`).
+    pub summary: bool,
+    /// Sort authors by their number of commits rather than by name (`-n`).
+    pub numbered: bool,
+    /// Show the email of each author (`-e`).
+    pub email: bool,
+}
+
+/// Summarizes the commits the revisions select by author, listing the
+/// subjects of each author's commits oldest first.
+pub fn shortlog(revisions: &[String], options: &ShortlogOptions) -> Result<()> {
+    let odb = Odb::open()?;
+    let (walk, _) = rev_list::walk(&odb, revisions)?;
+    let mailmap = Mailmap::load()?;
+    let mut authors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in walk {
+        let (_, commit) = entry?;
+        let identity = mailmap.map_identity(&commit.author);
+        let author = if options.email {
+            identity.person()
+        } else {
+            identity.name
+        };
+        authors.entry(author).or_default().push(commit.subject());
+    }
+    let mut authors = authors.into_iter().collect::<Vec<_>>();
+    if options.numbered {
+        // the sort is stable, so equal counts stay sorted by name
+        authors.sort_by_key(|(_, subjects)| std::cmp::Reverse(subjects.len()));
+    }
+    for (author, subjects) in authors {
+        if options.summary {
+            println!("{:6}\t{author}", subjects.len());
+            continue;
+        }
+        println!("{author} ({}):", subjects.len());
+        for subject in subjects.iter().rev() {
+            println!("      {subject}");
+        }
+        println!();
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use super::{
+    date, diff, log, mailmap::Mailmap, notes::Notes, parse_hash, revparse, EntryKind, Identity,
+    Odb, ParsedObject,
+};
+use anyhow::Result;
+use std::io::{stdout, Write};
+
+/// Shows an object: a commit with its log entry and its changes to its first
+/// parent, an annotated tag with its message followed by what it points at,
+/// a tree as the names in it and a blob as its content.
+pub fn show(revision: &str) -> Result<()> {
+    let odb = Odb::open()?;
+    show_object(&odb, &revparse::resolve(revision)?, revision)
+}
+
+fn show_object(odb: &Odb, hash: &str, name: &str) -> Result<()> {
+    match odb.read(hash)?.parse()? {
+        ParsedObject::Commit(mut commit) => {
+            commit.author = Mailmap::load()?.map_identity(&commit.author);
+            let note = Notes::load(odb)?.get(odb, hash)?;
+            log::print_commit(&mut stdout(), hash, &commit, note.as_deref())?;
+            // like git without --cc, merges are shown without a diff
+            if commit.parents.len() > 1 {
+                println!();
+                return Ok(());
+            }
+            let parent_tree = match commit.parents.first() {
+                Some(parent) => Some(parse_hash(&odb.read_commit(parent)?.tree)?),
+                None => None,
+            };
+            let tree = parse_hash(&commit.tree)?;
+            let patch = diff::tree_patch(odb, parent_tree.as_ref(), Some(&tree))?;
+            if !patch.is_empty() {
+                println!();
+                stdout().write_all(&patch)?;
+            }
+        }
+        ParsedObject::Tag(tag) => {
+            println!("tag {}", tag.name);
+            if let Some(tagger) = tag.tagger.as_deref().and_then(Identity::parse) {
+                println!("Tagger: {}", tagger.person());
+                println!(
+                    "Date:   {}",
+                    date::format(tagger.timestamp, &tagger.timezone)
+                );
+            }
+            println!();
+            print!("{}", tag.message);
+            println!();
+            show_object(odb, &tag.object, &tag.object)?;
+        }
+        ParsedObject::Tree(entries) => {
+            println!("tree {name}");
+            println!();
+            for entry in entries {
+                let slash = if entry.kind() == EntryKind::Tree {
+                    "/"
+                } else {
+                    ""
+                };
+                println!("{}{slash}", entry.name);
+            }
+        }
+        ParsedObject::Blob(content) => stdout().write_all(&content)?,
+    }
+    Ok(())
+}
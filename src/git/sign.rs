@@ -0,0 +1,186 @@
+use super::{config::Config, revparse, Odb};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const SSH_SIGNATURE_START: &str = "-----BEGIN SSH SIGNATURE-----";
+const SIGNATURE_STARTS: [&str; 2] = ["-----BEGIN PGP SIGNATURE-----", SSH_SIGNATURE_START];
+
+/// Signs `payload` with the key configured as `user.signingKey`, returning
+/// the armored signature. `gpg.format=ssh` signs with `ssh-keygen`, which
+/// needs the key file; gpg falls back to its default key.
+pub fn sign(payload: &[u8]) -> Result<String> {
+    let config = Config::load()?;
+    let key = config.get("user.signingKey");
+    let format = config.get("gpg.format").unwrap_or("openpgp");
+    let mut command = match format {
+        "openpgp" => {
+            let mut command = Command::new(config.get("gpg.program").unwrap_or("gpg"));
+            command.args(["--status-fd=2", "-bsa"]);
+            if let Some(key) = key {
+                command.args(["-u", key]);
+            }
+            command
+        }
+        "ssh" => {
+            let Some(key) = key else {
+                bail!("user.signingKey needs to be set for ssh signing");
+            };
+            let mut command = Command::new(config.get("gpg.ssh.program").unwrap_or("ssh-keygen"));
+            command.args(["-Y", "sign", "-n", "git", "-f", key]);
+            command
+        }
+        _ => bail!("Unsupported signature format '{format}'"),
+    };
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {:?}", command.get_program()))?;
+    child.stdin.take().unwrap().write_all(payload)?;
+    let output = child.wait_with_output()?;
+    let signature = String::from_utf8(output.stdout)?;
+    if !output.status.success() || signature.is_empty() {
+        bail!(
+            "Signing failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(signature)
+}
+
+/// Signs a commit, adding the signature as a `gpgsig` header whose
+/// continuation lines start with a space.
+pub fn sign_commit(content: &str) -> Result<String> {
+    let signature = sign(content.as_bytes())?;
+    let (headers, message) = content
+        .split_once("\n\n")
+        .context("commit has no message separator")?;
+    let header = signature.trim_end().replace('\n', "\n ");
+    Ok(format!("{headers}\ngpgsig {header}\n\n{message}"))
+}
+
+/// Signs a tag, whose signature follows its message.
+pub fn sign_tag(content: &str) -> Result<String> {
+    Ok(format!("{content}{}", sign(content.as_bytes())?))
+}
+
+/// Checks the signature of a commit, printing what the verifier reports.
+pub fn verify_commit(revision: &str) -> Result<()> {
+    let content = signed_object(revision, b"commit")?;
+    let mut payload = String::new();
+    let mut signature = String::new();
+    let mut in_signature = false;
+    let (headers, message) = content
+        .split_once("\n\n")
+        .context("commit has no message separator")?;
+    for line in headers.lines() {
+        if let Some(first) = line.strip_prefix("gpgsig ") {
+            signature = format!("{first}\n");
+            in_signature = true;
+        } else if let (true, Some(continued)) = (in_signature, line.strip_prefix(' ')) {
+            signature.push_str(&format!("{continued}\n"));
+        } else {
+            in_signature = false;
+            payload.push_str(&format!("{line}\n"));
+        }
+    }
+    if signature.is_empty() {
+        bail!("no signature found");
+    }
+    verify(&format!("{payload}\n{message}"), &signature)
+}
+
+/// Checks the signature following the message of a tag.
+pub fn verify_tag(name: &str) -> Result<()> {
+    let content = signed_object(name, b"tag")?;
+    let start = SIGNATURE_STARTS
+        .iter()
+        .filter_map(|start| content.find(&format!("\n{start}")))
+        .min()
+        .context("no signature found")?;
+    let (payload, signature) = content.split_at(start + 1);
+    verify(payload, signature)
+}
+
+fn signed_object(revision: &str, kind: &[u8]) -> Result<String> {
+    let object = Odb::open()?.read(&revparse::resolve(revision)?)?;
+    if object.kind()? != kind {
+        bail!(
+            "{revision}: cannot verify a non-{} object",
+            String::from_utf8_lossy(kind)
+        );
+    }
+    Ok(String::from_utf8(object.content)?)
+}
+
+/// Verifies `signature` over `payload` with the program matching its format.
+/// Verifier messages go to stderr; a bad signature is an error.
+fn verify(payload: &str, signature: &str) -> Result<()> {
+    let config = Config::load()?;
+    let signature_file = std::env::temp_dir().join(format!("git-signature-{}", std::process::id()));
+    fs::write(&signature_file, signature)?;
+    let result = if signature.starts_with(SSH_SIGNATURE_START) {
+        verify_ssh(&config, payload, &signature_file)
+    } else {
+        let program = config.get("gpg.program").unwrap_or("gpg");
+        let mut command = Command::new(program);
+        command.args(["--status-fd=1", "--verify"]);
+        command.arg(&signature_file).arg("-");
+        run_verifier(command, payload).map(|(good, status)| good && status.contains("GOODSIG"))
+    };
+    let _ = fs::remove_file(&signature_file);
+    if !result? {
+        bail!("bad signature");
+    }
+    Ok(())
+}
+
+/// Verifies an ssh signature against `gpg.ssh.allowedSignersFile`, as made
+/// by any of the principals listed there for its key.
+fn verify_ssh(config: &Config, payload: &str, signature_file: &Path) -> Result<bool> {
+    let allowed = config
+        .get("gpg.ssh.allowedSignersFile")
+        .context("gpg.ssh.allowedSignersFile needs to be configured to verify ssh signatures")?;
+    let program = config.get("gpg.ssh.program").unwrap_or("ssh-keygen");
+    let principals = Command::new(program)
+        .args(["-Y", "find-principals", "-f", allowed, "-s"])
+        .arg(signature_file)
+        .output()?;
+    let principals = String::from_utf8_lossy(&principals.stdout);
+    if principals.trim().is_empty() {
+        eprintln!("No principal matched the signing key");
+    }
+    for principal in principals.lines() {
+        let mut command = Command::new(program);
+        command.args([
+            "-Y", "verify", "-n", "git", "-f", allowed, "-I", principal, "-s",
+        ]);
+        command.arg(signature_file);
+        let (good, output) = run_verifier(command, payload)?;
+        eprint!("{output}");
+        if good {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Runs a verifier on `payload`, returning whether it succeeded and its
+/// standard output. Its own messages are passed through to stderr.
+fn run_verifier(mut command: Command, payload: &str) -> Result<(bool, String)> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {:?}", command.get_program()))?;
+    child.stdin.take().unwrap().write_all(payload.as_bytes())?;
+    let output = child.wait_with_output()?;
+    Ok((
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    ))
+}
@@ -0,0 +1,204 @@
+use super::{
+    attributes::Conversion,
+    config::Config,
+    hash_file, head_files,
+    index::{Entry, Index},
+    remove_empty_parents, repository, write_file, Odb,
+};
+use anyhow::Result;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const SPARSE_CHECKOUT: &str = "info/sparse-checkout";
+/// Patterns of a cone that holds only the files at the top level.
+const ROOT_PATTERNS: &str = "/*\n!/*/\n";
+
+/// The paths a sparse checkout keeps in the working tree, in cone mode: the
+/// files at the top level, everything below the chosen directories and the
+/// files directly inside the directories leading to them.
+pub struct Sparse {
+    root: PathBuf,
+    /// Directories kept with all their contents.
+    recursive: Vec<String>,
+    /// Directories of which only the files are kept.
+    parents: Vec<String>,
+}
+
+impl Sparse {
+    /// The sparse checkout of the repository, `None` when it is not enabled.
+    pub fn load() -> Result<Option<Self>> {
+        if Config::load()?.get_bool("core.sparseCheckout") != Some(true) {
+            return Ok(None);
+        }
+        let patterns = fs::read_to_string(repository::path(SPARSE_CHECKOUT)).unwrap_or_default();
+        Ok(Some(Self::parse(&patterns)?))
+    }
+
+    /// Reads cone patterns: `/dir/` includes a directory and `!/dir/*/`
+    /// excludes its subdirectories again, leaving just its files.
+    fn parse(patterns: &str) -> Result<Self> {
+        let mut included = vec![];
+        let mut parents = vec![];
+        for line in patterns.lines().map(str::trim) {
+            if let Some(directory) = line.strip_prefix("!/").and_then(|l| l.strip_suffix("/*/")) {
+                parents.push(directory.to_owned());
+            } else if let Some(directory) = line.strip_prefix('/').and_then(|l| l.strip_suffix('/'))
+            {
+                included.push(directory.to_owned());
+            }
+        }
+        let recursive = included
+            .into_iter()
+            .filter(|directory| !parents.contains(directory))
+            .collect();
+        Ok(Self {
+            root: env::current_dir()?,
+            recursive,
+            parents,
+        })
+    }
+
+    /// Whether a file, relative to the repository root or absolute within
+    /// it, belongs in the working tree.
+    pub fn includes(&self, path: &Path) -> bool {
+        let path = self.relative(path);
+        let directory = path.rsplit_once('/').map_or("", |(directory, _)| directory);
+        directory.is_empty()
+            || self.parents.iter().any(|parent| parent == directory)
+            || self.recursive.iter().any(|d| is_within(&path, d))
+    }
+
+    /// Whether a directory holds anything that belongs in the working tree.
+    pub fn includes_directory(&self, path: &Path) -> bool {
+        let path = self.relative(path);
+        path.is_empty()
+            || self
+                .recursive
+                .iter()
+                .any(|d| is_within(&path, d) || is_within(d, &path))
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Whether `path` is `directory` or lies below it.
+fn is_within(path: &str, directory: &str) -> bool {
+    path.strip_prefix(directory)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Enables sparse checkout, keeping only the top-level files unless
+/// patterns were set before.
+pub fn init() -> Result<()> {
+    let patterns = match fs::read_to_string(repository::path(SPARSE_CHECKOUT)) {
+        Ok(patterns) => patterns,
+        Err(_) => ROOT_PATTERNS.to_owned(),
+    };
+    enable(&patterns)
+}
+
+/// Enables sparse checkout of the given directories and their contents.
+pub fn set(directories: &[String]) -> Result<()> {
+    let mut directories = directories
+        .iter()
+        .map(|d| d.trim_matches('/').to_owned())
+        .filter(|d| !d.is_empty())
+        .collect::<Vec<_>>();
+    directories.sort();
+    directories.dedup();
+    let mut parents = directories
+        .iter()
+        .flat_map(|d| d.match_indices('/').map(|(i, _)| d[..i].to_owned()))
+        .collect::<Vec<_>>();
+    parents.sort();
+    parents.dedup();
+    let mut patterns = ROOT_PATTERNS.to_owned();
+    for parent in &parents {
+        patterns.push_str(&format!("/{parent}/\n!/{parent}/*/\n"));
+    }
+    for directory in &directories {
+        patterns.push_str(&format!("/{directory}/\n"));
+    }
+    enable(&patterns)
+}
+
+fn enable(patterns: &str) -> Result<()> {
+    write_patterns(patterns)?;
+    reapply(&Sparse::parse(patterns)?)
+}
+
+/// Enables sparse checkout of the top-level files for a checkout yet to
+/// come, as `clone --sparse` does.
+pub fn enable_for_checkout() -> Result<()> {
+    write_patterns(ROOT_PATTERNS)
+}
+
+fn write_patterns(patterns: &str) -> Result<()> {
+    let file = repository::path(SPARSE_CHECKOUT);
+    fs::create_dir_all(file.parent().unwrap())?;
+    fs::write(file, patterns)?;
+    let mut config = Config::local()?;
+    config.set("core.sparseCheckout", "true")?;
+    config.set("core.sparseCheckoutCone", "true")?;
+    config.save()
+}
+
+/// Brings the working tree in line with the sparse patterns: files now
+/// included are written and flagged as present in the index, files now
+/// excluded are removed and flagged with skip-worktree. Excluded files with
+/// local changes are left in place.
+fn reapply(sparse: &Sparse) -> Result<()> {
+    let odb = Odb::open()?;
+    let mut index = if Index::exists() {
+        Index::read()?
+    } else {
+        Index::from_files(&head_files(&odb)?)?
+    };
+    let mut conversion = Conversion::new()?;
+    let mut kept = vec![];
+    for entry in index.entries.iter_mut().filter(|e| e.stage == 0) {
+        let path = PathBuf::from(&entry.path);
+        match (sparse.includes(&path), entry.skip_worktree) {
+            (true, true) => {
+                if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)?;
+                }
+                write_file(&odb, &path, entry.mode, &entry.hash, &mut conversion)?;
+                *entry = Entry::from_file(&path, entry.mode, entry.hash)?;
+            }
+            (false, false) => {
+                match hash_file(&path, &mut conversion)? {
+                    Some(hash) if hash != entry.hash => {
+                        kept.push(entry.path.clone());
+                        continue;
+                    }
+                    Some(_) => {
+                        fs::remove_file(&path)?;
+                        remove_empty_parents(&path);
+                    }
+                    None => {}
+                }
+                entry.skip_worktree = true;
+            }
+            _ => {}
+        }
+    }
+    if !kept.is_empty() {
+        eprintln!(
+            "warning: The following paths are not up to date and were left despite sparse patterns:\n\t{}",
+            kept.join("\n\t")
+        );
+    }
+    index.write()
+}
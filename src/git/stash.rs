@@ -0,0 +1,149 @@
+use super::{
+    attributes::Conversion, diff::Version, flatten_tree, head_files, index::Index, merge,
+    parse_hash, reflog, refs, short_hash, update_worktree, write_commit, write_tree_from_files,
+    Odb, TreeFiles,
+};
+use anyhow::{anyhow, bail, Result};
+
+const STASH: &str = "refs/stash";
+
+/// Saves local changes as a stash entry and resets the working tree and the
+/// index to HEAD. An entry is a commit of the working tree whose parents are
+/// HEAD and a commit of the index, as in git.
+pub fn push(message: Option<&str>) -> Result<()> {
+    let odb = Odb::open()?;
+    let head =
+        refs::peel("HEAD")?.ok_or_else(|| anyhow!("You do not have the initial commit yet"))?;
+    let head_tree = flatten_tree(&odb, &odb.read_commit(&head)?.tree)?;
+    let index_files = if Index::exists() {
+        Index::read()?.files()
+    } else {
+        head_tree.clone()
+    };
+    let mut conversion = Conversion::new()?;
+    let mut worktree_files = TreeFiles::new();
+    for (path, (mode, _)) in &index_files {
+        if let Some(version) = Version::from_file(path, Some(*mode), &mut conversion)? {
+            odb.write(b"blob", &version.content)?;
+            worktree_files.insert(path.clone(), (version.mode, version.hash));
+        }
+    }
+    if index_files == head_tree && worktree_files == head_tree {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let commit = odb.read_commit(&head)?;
+    let subject = commit.message.lines().next().unwrap_or_default();
+    let branch = refs::current_branch()?.unwrap_or_else(|| "(no branch)".to_owned());
+    let head_line = format!("{}: {} {subject}", branch, short_hash(&head));
+    let head_hash = parse_hash(&head)?;
+    let index_commit = write_commit(
+        &write_tree_from_files(&odb, &index_files)?,
+        std::slice::from_ref(&head_hash),
+        &format!("index on {head_line}"),
+    )?;
+    let message = match message {
+        Some(message) => format!("On {branch}: {message}"),
+        None => format!("WIP on {head_line}"),
+    };
+    let stash_commit = write_commit(
+        &write_tree_from_files(&odb, &worktree_files)?,
+        &[head_hash, index_commit],
+        &message,
+    )?;
+    refs::update(STASH, &hex::encode(stash_commit), &message)?;
+
+    update_worktree(&odb, &worktree_files, &head_tree)?;
+    Index::from_files(&head_tree)?.write()?;
+    println!("Saved working directory and index state {message}");
+    Ok(())
+}
+
+/// Lists stash entries, newest first.
+pub fn list() -> Result<()> {
+    for (number, entry) in reflog::read(STASH)?.iter().rev().enumerate() {
+        println!("stash@{{{number}}}: {}", entry.message);
+    }
+    Ok(())
+}
+
+/// Restores the changes of a stash entry on top of HEAD with a three-way
+/// merge whose base is the commit the entry was made on. Staged changes come
+/// back as unstaged ones, except for new files.
+pub fn apply(stash: Option<&str>) -> Result<()> {
+    let (_, hash) = find_entry(stash)?;
+    let odb = Odb::open()?;
+    let commit = odb.read_commit(&hash)?;
+    let base = commit
+        .parents
+        .first()
+        .ok_or_else(|| anyhow!("{hash} is not a stash commit"))?;
+    let base_files = flatten_tree(&odb, &odb.read_commit(base)?.tree)?;
+    let our_files = head_files(&odb)?;
+    let (merged, conflicts) = merge::merge_trees(
+        &odb,
+        &base_files,
+        &our_files,
+        &flatten_tree(&odb, &commit.tree)?,
+        "Stashed changes",
+    )?;
+    if !conflicts.is_empty() {
+        bail!("Conflicts in index. The stash entry is kept in case you need it again.");
+    }
+    let mut index_files = our_files.clone();
+    for (path, entry) in merged {
+        if !our_files.contains_key(&path) {
+            index_files.insert(path, entry);
+        }
+    }
+    Index::from_files(&index_files)?.write()?;
+    Ok(())
+}
+
+/// Applies a stash entry and drops it if that succeeded.
+pub fn pop(stash: Option<&str>) -> Result<()> {
+    apply(stash)?;
+    drop(stash)
+}
+
+/// Removes a stash entry, `stash@{0}` by default.
+pub fn drop(stash: Option<&str>) -> Result<()> {
+    let (position, hash) = find_entry(stash)?;
+    let mut entries = reflog::read(STASH)?;
+    entries.remove(position);
+    match entries.last() {
+        Some(newest) => {
+            refs::write(STASH, &newest.new)?;
+            reflog::write(STASH, &entries)?;
+        }
+        None => {
+            refs::delete(STASH)?;
+            reflog::delete(STASH)?;
+        }
+    }
+    let number = entries.len() - position;
+    println!("Dropped refs/stash@{{{number}}} ({hash})");
+    Ok(())
+}
+
+/// Finds `stash@{<n>}` (or just `<n>`) among the reflog entries of the stash,
+/// returning its position in the reflog and its commit.
+fn find_entry(stash: Option<&str>) -> Result<(usize, String)> {
+    let name = stash.unwrap_or("stash@{0}");
+    let number = name
+        .strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(name)
+        .parse::<usize>()
+        .map_err(|_| anyhow!("{name} is not a valid reference"))?;
+    let entries = reflog::read(STASH)?;
+    if entries.is_empty() {
+        bail!("No stash entries found.");
+    }
+    let position = entries
+        .len()
+        .checked_sub(number + 1)
+        .ok_or_else(|| anyhow!("{name} is not a valid reference"))?;
+    Ok((position, entries[position].new.clone()))
+}
@@ -0,0 +1,117 @@
+use super::{
+    attributes::Conversion,
+    diff::Version,
+    head_files,
+    ignore::{self, Ignore},
+    index::Index,
+    is_unpopulated_submodule, Odb,
+};
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Prints the state of the working tree in git's short format: `XY path`
+/// where X compares the index with HEAD and Y the working tree with the
+/// index, followed by untracked paths as `?? path`. Untracked directories
+/// are listed once, and ignored files not at all.
+pub fn print() -> Result<()> {
+    let odb = Odb::open()?;
+    let head = head_files(&odb)?;
+    let index = if Index::exists() {
+        Index::read()?
+    } else {
+        Index::from_files(&head)?
+    };
+    let staged = index.files();
+    // files left out by sparse checkout are not missing
+    let skipped = index
+        .entries
+        .iter()
+        .filter(|e| e.skip_worktree)
+        .map(|e| PathBuf::from(&e.path))
+        .collect::<BTreeSet<_>>();
+
+    let mut conversion = Conversion::new()?;
+    let mut lines = BTreeMap::new();
+    for path in index.conflicts() {
+        let stages = index
+            .entries
+            .iter()
+            .filter(|e| e.path == path)
+            .map(|e| e.stage)
+            .collect::<Vec<_>>();
+        let code = match stages[..] {
+            [1, 2, 3] => "UU",
+            [2, 3] => "AA",
+            [1, 2] => "UD",
+            [1, 3] => "DU",
+            [2] => "AU",
+            [3] => "UA",
+            _ => "DD",
+        };
+        lines.insert(PathBuf::from(path), code.to_owned());
+    }
+    for path in head.keys().chain(staged.keys()).collect::<BTreeSet<_>>() {
+        if lines.contains_key(path) {
+            continue;
+        }
+        let index_state = match (head.get(path), staged.get(path)) {
+            (None, Some(_)) => 'A',
+            (Some(_), None) => 'D',
+            (old, new) if old != new => 'M',
+            _ => ' ',
+        };
+        let worktree_state = match staged.get(path) {
+            Some(_) if skipped.contains(path) => ' ',
+            Some((mode, _)) if is_unpopulated_submodule(path, *mode) => ' ',
+            Some((mode, hash)) => match Version::from_file(path, Some(*mode), &mut conversion)? {
+                None => 'D',
+                Some(file) if file.mode != *mode || file.hash != *hash => 'M',
+                Some(_) => ' ',
+            },
+            None => ' ',
+        };
+        if index_state != ' ' || worktree_state != ' ' {
+            lines.insert(path.clone(), format!("{index_state}{worktree_state}"));
+        }
+    }
+    for (path, code) in &lines {
+        println!("{code} {}", path.display());
+    }
+
+    let tracked = index
+        .entries
+        .iter()
+        .map(|e| PathBuf::from(&e.path))
+        .collect::<BTreeSet<_>>();
+    let mut untracked = BTreeSet::new();
+    for file in ignore::worktree_files(Some(&mut Ignore::new()?), Path::new("."))? {
+        if !tracked.contains(&file) {
+            untracked.insert(untracked_name(&file, &tracked));
+        }
+    }
+    for name in untracked {
+        println!("?? {name}");
+    }
+    Ok(())
+}
+
+/// The outermost directory of an untracked file holding nothing tracked,
+/// or the file itself.
+pub fn untracked_name(file: &Path, tracked: &BTreeSet<PathBuf>) -> String {
+    for directory in file
+        .ancestors()
+        .skip(1)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        if directory.as_os_str().is_empty() {
+            continue;
+        }
+        if !tracked.iter().any(|path| path.starts_with(directory)) {
+            return format!("{}/", directory.display());
+        }
+    }
+    file.display().to_string()
+}
@@ -0,0 +1,105 @@
+use super::{refs, remote::Sha1, revparse, short_hash, sign, signature, Odb, Role};
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+
+const TAGS_PREFIX: &str = "refs/tags/";
+
+/// An annotated tag object.
+pub struct Tag {
+    pub object: Sha1,
+    pub kind: String,
+    pub name: String,
+    pub tagger: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "object {}", self.object)?;
+        writeln!(f, "type {}", self.kind)?;
+        writeln!(f, "tag {}", self.name)?;
+        if let Some(tagger) = &self.tagger {
+            writeln!(f, "tagger {tagger}")?;
+        }
+        write!(f, "\n{}", self.message)
+    }
+}
+
+pub fn parse(content: &[u8]) -> Result<Tag> {
+    let content = String::from_utf8_lossy(content);
+    let (headers, message) = content.split_once("\n\n").unwrap_or((&content, ""));
+    let mut object = None;
+    let mut kind = None;
+    let mut name = None;
+    let mut tagger = None;
+    for line in headers.lines() {
+        match line.split_once(' ') {
+            Some(("object", hash)) => object = Some(hash.to_owned()),
+            Some(("type", value)) => kind = Some(value.to_owned()),
+            Some(("tag", value)) => name = Some(value.to_owned()),
+            Some(("tagger", identity)) => tagger = Some(identity.to_owned()),
+            _ => {}
+        }
+    }
+    Ok(Tag {
+        object: object.ok_or_else(|| anyhow!("tag does not have an object line"))?,
+        kind: kind.ok_or_else(|| anyhow!("tag does not have a type line"))?,
+        name: name.ok_or_else(|| anyhow!("tag does not have a tag line"))?,
+        tagger,
+        message: message.to_owned(),
+    })
+}
+
+pub fn list() -> Result<()> {
+    for (name, _) in refs::list(TAGS_PREFIX)? {
+        println!("{}", &name[TAGS_PREFIX.len()..]);
+    }
+    Ok(())
+}
+
+/// Creates a lightweight tag, or an annotated tag object when a message is
+/// given, signed if `sign` is set.
+pub fn create(name: &str, target: &str, message: Option<&str>, sign: bool) -> Result<()> {
+    verify_name(name)?;
+    let full_name = format!("{TAGS_PREFIX}{name}");
+    if refs::read(&full_name)?.is_some() {
+        bail!("tag '{name}' already exists");
+    }
+    let object = revparse::resolve(target)?;
+    let hash = match message {
+        Some(message) => {
+            let odb = Odb::open()?;
+            let kind = String::from_utf8(odb.read(&object)?.kind()?.to_vec())?;
+            let tag = Tag {
+                object,
+                kind,
+                name: name.to_owned(),
+                tagger: Some(signature(Role::Committer)?),
+                message: format!("{}\n", message.trim_end()),
+            };
+            let mut content = tag.to_string();
+            if sign {
+                content = sign::sign_tag(&content)?;
+            }
+            hex::encode(odb.write(b"tag", content.as_bytes())?)
+        }
+        None => object,
+    };
+    Ok(refs::write(&full_name, &hash)?)
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    verify_name(name)?;
+    let full_name = format!("{TAGS_PREFIX}{name}");
+    let hash = refs::peel(&full_name)?.ok_or_else(|| anyhow!("tag '{name}' not found."))?;
+    refs::delete(&full_name)?;
+    println!("Deleted tag '{name}' (was {})", short_hash(&hash));
+    Ok(())
+}
+
+fn verify_name(name: &str) -> Result<()> {
+    if name.starts_with('-') || !refs::is_valid_name(&format!("{TAGS_PREFIX}{name}")) {
+        bail!("'{name}' is not a valid tag name.");
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use super::{
+    attributes::Conversion,
+    diff::Version,
+    index::{self, Entry, Index},
+    parse_hash, verify_path, ObjectId, Odb, GITLINK_MODE,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+pub struct UpdateIndexOptions {
+    /// Add files that are not in the index yet (`--add`).
+    pub add: bool,
+    /// Remove entries whose files no longer exist (`--remove`).
+    pub remove: bool,
+    /// Refresh the stat data of entries whose files are unchanged (`--refresh`).
+    pub refresh: bool,
+    /// Entries recorded as given, as `<mode>,<hash>,<path>` (`--cacheinfo`).
+    pub cacheinfo: Vec<String>,
+}
+
+/// Records entries given as `--cacheinfo`, then the current content of the
+/// given files, then refreshes the index. Returns whether the refresh found
+/// every entry up to date.
+pub fn update_index(paths: &[PathBuf], options: &UpdateIndexOptions) -> Result<bool> {
+    let mut index = if Index::exists() {
+        Index::read()?
+    } else {
+        Index::default()
+    };
+    for info in &options.cacheinfo {
+        let (mode, hash, path) = parse_cacheinfo(info)?;
+        let name = index::pathspec_name(&path)?;
+        verify_path(&name).with_context(|| format!("--cacheinfo cannot add {name}"))?;
+        if !options.add && !index.entries.iter().any(|entry| entry.path == name) {
+            bail!("{name}: cannot add to the index - missing --add option?");
+        }
+        index.add(Entry::staged(Path::new(&name), mode, hash, 0)?);
+    }
+
+    let odb = Odb::open()?;
+    let mut conversion = Conversion::new()?;
+    for path in paths {
+        let name = index::pathspec_name(path)?;
+        let recorded = index.entries.iter().find(|entry| entry.path == name);
+        if path.is_dir() && !path.join(".git").exists() {
+            bail!("{name}: is a directory - add files inside instead");
+        }
+        let Some(version) =
+            Version::from_file(path, recorded.map(|entry| entry.mode), &mut conversion)?
+        else {
+            if !options.remove {
+                bail!("{name}: does not exist and --remove not passed");
+            }
+            index.remove(&name);
+            continue;
+        };
+        if recorded.is_none() && !options.add {
+            bail!("{name}: cannot add to the index - missing --add option?");
+        }
+        // a submodule is recorded as the commit it has checked out
+        if version.mode != GITLINK_MODE {
+            odb.write(b"blob", &version.content)?;
+        }
+        index.add(Entry::from_file(
+            Path::new(&name),
+            version.mode,
+            version.hash,
+        )?);
+    }
+
+    let mut up_to_date = true;
+    if options.refresh {
+        up_to_date = refresh(&mut index, &mut conversion)?;
+    }
+    index.write()?;
+    Ok(up_to_date)
+}
+
+/// Updates the stat data of entries whose files still have the recorded
+/// content, and reports the others. Returns whether there were none.
+fn refresh(index: &mut Index, conversion: &mut Conversion) -> Result<bool> {
+    let mut up_to_date = true;
+    let mut reported = None;
+    for entry in &mut index.entries {
+        if entry.skip_worktree {
+            continue;
+        }
+        if entry.stage != 0 {
+            if reported.as_ref() != Some(&entry.path) {
+                println!("{}: needs merge", entry.path);
+                reported = Some(entry.path.clone());
+            }
+            up_to_date = false;
+            continue;
+        }
+        let path = Path::new(&entry.path);
+        match Version::from_file(path, Some(entry.mode), conversion)? {
+            Some(version) if (version.mode, version.hash) == (entry.mode, entry.hash) => {
+                let refreshed = Entry::from_file(path, entry.mode, entry.hash)?;
+                *entry = Entry {
+                    skip_worktree: entry.skip_worktree,
+                    ..refreshed
+                };
+            }
+            _ => {
+                println!("{}: needs update", entry.path);
+                up_to_date = false;
+            }
+        }
+    }
+    Ok(up_to_date)
+}
+
+/// Parses `<mode>,<hash>,<path>` with an octal mode.
+fn parse_cacheinfo(info: &str) -> Result<(u32, ObjectId, PathBuf)> {
+    let mut fields = info.splitn(3, ',');
+    let (Some(mode), Some(hash), Some(path)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(anyhow!("option 'cacheinfo' expects <mode>,<sha1>,<path>"));
+    };
+    let mode = u32::from_str_radix(mode, 8)
+        .with_context(|| format!("invalid mode '{mode}' in --cacheinfo"))?;
+    Ok((mode, parse_hash(hash)?, PathBuf::from(path)))
+}
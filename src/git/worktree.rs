@@ -0,0 +1,279 @@
+use super::{
+    attributes::Conversion,
+    checkout, hash_file,
+    ignore::{self, Ignore},
+    index::Index,
+    peel_to_commit,
+    refs::{self, Target},
+    repository, revparse, short_hash, Odb,
+};
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const WORKTREES: &str = "worktrees";
+const HEADS_PREFIX: &str = "refs/heads/";
+
+/// A working tree of the repository: the main one or a linked one.
+struct Worktree {
+    path: PathBuf,
+    /// The directory holding its HEAD and index.
+    git_dir: PathBuf,
+    head: Option<Target>,
+}
+
+impl Worktree {
+    fn branch(&self) -> Option<&str> {
+        match &self.head {
+            Some(Target::Symbolic(name)) => name.strip_prefix(HEADS_PREFIX),
+            _ => None,
+        }
+    }
+}
+
+/// The main worktree followed by the linked ones, found through the
+/// `gitdir` files under `worktrees/` in the common directory.
+fn worktrees() -> Result<Vec<Worktree>> {
+    let common = fs::canonicalize(repository::common_dir())?;
+    let main = Worktree {
+        path: common
+            .parent()
+            .ok_or_else(|| anyhow!("Bare repositories have no main worktree"))?
+            .to_path_buf(),
+        head: read_head(&common)?,
+        git_dir: common.clone(),
+    };
+    let mut linked = vec![];
+    let directory = common.join(WORKTREES);
+    if directory.is_dir() {
+        for entry in directory.read_dir()? {
+            let git_dir = entry?.path();
+            let Ok(gitdir) = fs::read_to_string(git_dir.join("gitdir")) else {
+                continue;
+            };
+            let dot_git = PathBuf::from(gitdir.trim_end());
+            linked.push(Worktree {
+                path: dot_git.parent().unwrap_or(&dot_git).to_path_buf(),
+                head: read_head(&git_dir)?,
+                git_dir,
+            });
+        }
+    }
+    linked.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(std::iter::once(main).chain(linked).collect())
+}
+
+/// The git directories of all worktrees, each holding its own HEAD and index.
+pub fn git_dirs() -> Result<Vec<PathBuf>> {
+    Ok(worktrees()?.into_iter().map(|w| w.git_dir).collect())
+}
+
+pub fn read_head(git_dir: &Path) -> Result<Option<Target>> {
+    let Ok(content) = fs::read_to_string(git_dir.join("HEAD")) else {
+        return Ok(None);
+    };
+    let content = content.trim_end();
+    Ok(Some(match content.strip_prefix("ref: ") {
+        Some(name) => Target::Symbolic(name.to_owned()),
+        None => Target::Direct(content.to_owned()),
+    }))
+}
+
+/// Prints each worktree with the commit and branch it has checked out.
+pub fn list() -> Result<()> {
+    let worktrees = worktrees()?;
+    let width = worktrees
+        .iter()
+        .map(|w| w.path.display().to_string().len())
+        .max()
+        .unwrap_or_default();
+    for worktree in &worktrees {
+        let hash = match &worktree.head {
+            Some(Target::Direct(hash)) => Some(hash.clone()),
+            Some(Target::Symbolic(name)) => refs::peel(name)?,
+            None => None,
+        };
+        let short = hash.as_deref().map_or("0000000", short_hash);
+        let branch = match worktree.branch() {
+            Some(branch) => format!("[{branch}]"),
+            None => "(detached HEAD)".to_owned(),
+        };
+        println!(
+            "{:<width$} {short} {branch}",
+            worktree.path.display(),
+            width = width + 1
+        );
+    }
+    Ok(())
+}
+
+/// What `worktree add` checks out.
+pub struct AddOptions {
+    /// Create this branch at the commit (`-b`).
+    pub new_branch: Option<String>,
+    /// Check the commit out with a detached HEAD (`--detach`).
+    pub detach: bool,
+}
+
+/// Creates a linked worktree at `path`. It gets its own HEAD and index in
+/// `worktrees/<name>` of the common directory, which a `.git` file in the
+/// worktree points at. Without a commit, a branch named after the
+/// directory is checked out, created from HEAD if it does not exist.
+pub fn add(path: &Path, commit: Option<&str>, options: &AddOptions) -> Result<()> {
+    if path.exists() && path.read_dir()?.next().is_some() {
+        bail!("'{}' already exists", path.display());
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid worktree path {}", path.display()))?
+        .to_string_lossy()
+        .into_owned();
+
+    // decide on the branch, if any, and the commit to check out
+    let default_branch = (commit.is_none() && !options.detach).then(|| name.clone());
+    let (branch, hash, created) = match (&options.new_branch, commit) {
+        (Some(branch), _) => (
+            Some(branch.clone()),
+            peel_to_commit(&revparse::resolve(commit.unwrap_or("HEAD"))?)?,
+            true,
+        ),
+        (None, Some(commit))
+            if !options.detach && refs::read(&format!("{HEADS_PREFIX}{commit}"))?.is_some() =>
+        {
+            let hash = refs::resolve(&format!("{HEADS_PREFIX}{commit}"))?;
+            (Some(commit.to_owned()), hash, false)
+        }
+        (None, Some(commit)) => (None, peel_to_commit(&revparse::resolve(commit)?)?, false),
+        (None, None) => match default_branch {
+            Some(branch) => match refs::peel(&format!("{HEADS_PREFIX}{branch}"))? {
+                Some(hash) => (Some(branch), hash, false),
+                None => (
+                    Some(branch),
+                    peel_to_commit(&revparse::resolve("HEAD")?)?,
+                    true,
+                ),
+            },
+            None => (None, peel_to_commit(&revparse::resolve("HEAD")?)?, false),
+        },
+    };
+    match (&branch, created) {
+        (Some(branch), true) => {
+            if refs::read(&format!("{HEADS_PREFIX}{branch}"))?.is_some() {
+                bail!("A branch named '{branch}' already exists");
+            }
+            println!("Preparing worktree (new branch '{branch}')");
+        }
+        (Some(branch), false) => {
+            if let Some(other) = worktrees()?
+                .iter()
+                .find(|w| w.branch() == Some(branch.as_str()))
+            {
+                bail!(
+                    "'{branch}' is already checked out at '{}'",
+                    other.path.display()
+                );
+            }
+            println!("Preparing worktree (checking out '{branch}')");
+        }
+        (None, _) => println!("Preparing worktree (detached HEAD {})", short_hash(&hash)),
+    }
+
+    let common = fs::canonicalize(repository::common_dir())?;
+    let worktrees_dir = common.join(WORKTREES);
+    let mut admin_name = name.clone();
+    let mut suffix = 1;
+    while worktrees_dir.join(&admin_name).exists() {
+        admin_name = format!("{name}{suffix}");
+        suffix += 1;
+    }
+    let git_dir = worktrees_dir.join(&admin_name);
+    fs::create_dir_all(path)?;
+    let path = fs::canonicalize(path)?;
+    fs::create_dir_all(&git_dir)?;
+    fs::write(
+        git_dir.join("gitdir"),
+        format!("{}\n", path.join(".git").display()),
+    )?;
+    fs::write(git_dir.join("commondir"), "../..\n")?;
+    fs::write(
+        path.join(".git"),
+        format!("gitdir: {}\n", git_dir.display()),
+    )?;
+    if let (Some(branch), true) = (&branch, created) {
+        refs::update(
+            &format!("{HEADS_PREFIX}{branch}"),
+            &hash,
+            &format!("branch: Created from {}", commit.unwrap_or("HEAD")),
+        )?;
+    }
+
+    let main_path = env::current_dir()?;
+    env::set_current_dir(&path)?;
+    let result = match &branch {
+        Some(branch) => refs::write_symbolic("HEAD", &format!("{HEADS_PREFIX}{branch}")),
+        None => refs::write("HEAD", &hash),
+    }
+    .and_then(|_| Ok(checkout(&hash)?));
+    env::set_current_dir(main_path)?;
+    result?;
+    let subject = Odb::open()?
+        .read_commit(&hash)?
+        .message
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    println!("HEAD is now at {} {subject}", short_hash(&hash));
+    Ok(())
+}
+
+/// Deletes a linked worktree and its administrative files. Unless forced,
+/// a worktree with modified or untracked files is kept.
+pub fn remove(path: &Path, force: bool) -> Result<()> {
+    let target = fs::canonicalize(path)
+        .map_err(|_| anyhow!("'{}' is not a working tree", path.display()))?;
+    let worktrees = worktrees()?;
+    let Some(position) = worktrees.iter().position(|w| w.path == target) else {
+        bail!("'{}' is not a working tree", path.display());
+    };
+    if position == 0 {
+        bail!("'{}' is a main working tree", path.display());
+    }
+    let worktree = &worktrees[position];
+    if !force {
+        let main_path = env::current_dir()?;
+        env::set_current_dir(&worktree.path)?;
+        let dirty = has_changes();
+        env::set_current_dir(main_path)?;
+        if dirty? {
+            bail!(
+                "'{}' contains modified or untracked files, use --force to delete it",
+                path.display()
+            );
+        }
+    }
+    fs::remove_dir_all(&worktree.path)?;
+    fs::remove_dir_all(&worktree.git_dir)?;
+    Ok(())
+}
+
+/// Whether the current worktree has files differing from its index or
+/// untracked files that are not ignored.
+fn has_changes() -> Result<bool> {
+    let index = Index::read()?;
+    let mut conversion = Conversion::new()?;
+    for entry in index.entries.iter().filter(|e| !e.skip_worktree) {
+        if hash_file(Path::new(&entry.path), &mut conversion)?.as_ref() != Some(&entry.hash) {
+            return Ok(true);
+        }
+    }
+    let tracked = index
+        .entries
+        .iter()
+        .map(|e| PathBuf::from(&e.path))
+        .collect::<BTreeSet<_>>();
+    let files = ignore::worktree_files(Some(&mut Ignore::new()?), Path::new("."))?;
+    Ok(files.iter().any(|file| !tracked.contains(file)))
+}
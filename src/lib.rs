@@ -0,0 +1,37 @@
+//! A git implementation: the object database with loose objects and packs,
+//! refs and reflogs, history walks, the index and working tree, merges and
+//! the fetch and push protocols. The `git-starter-rust` binary is a thin
+//! command line over it.
+//!
+//! Operations act on the repository of the current directory, as git
+//! commands do, or on an opened [`Repository`] within [`Repository::run`];
+//! opening one leaves the current directory alone.
+//! [`Repository`], [`Odb`] and the `refs` functions fail with an [`Error`]
+//! telling a missing object, an unknown revision or an I/O error apart; the
+//! rest of the library returns `anyhow` errors.
+//!
+//! ```no_run
+//! use git_starter_rust::{Repository, RevWalk};
+//!
+//! let repository = Repository::open("path/to/worktree")?;
+//! let odb = repository.odb()?;
+//! if let Some(head) = repository.head()? {
+//!     repository.run(|| {
+//!         for entry in RevWalk::from_tip(&odb, &head.to_string())? {
+//!             let (_, commit) = entry?;
+//!             println!("{}", commit.message.lines().next().unwrap_or_default());
+//!         }
+//!         Ok::<(), anyhow::Error>(())
+//!     })?;
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod git;
+
+pub use git::{
+    hash::{HashAlgo, ObjectId},
+    repository::Repository,
+    revwalk::RevWalk,
+    Commit, EntryKind, Error, Identity, Object, ObjectReader, Odb, ParsedObject, TreeEntry,
+};
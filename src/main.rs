@@ -1,10 +1,8 @@
-mod git;
-
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use clap::{Args, Parser, Subcommand};
-use reqwest::Url;
-use std::path::PathBuf;
-use std::str::FromStr;
+use git_starter_rust::git;
+use std::io::{BufRead, Read};
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -15,103 +13,1287 @@ struct CommandLine {
 
 #[derive(Subcommand, Debug)]
 enum Command {
-    Init,
+    Init(Init),
     CatFile(CatFile),
     HashObject(HashObject),
     LsTree(LsTree),
-    WriteTree,
+    WriteTree(WriteTree),
     CommitTree(CommitTree),
     Clone(CloneRepo),
+    Log(Log),
+    Branch(Branch),
+    Checkout(Checkout),
+    Tag(Tag),
+    Reflog(Reflog),
+    RevParse(RevParse),
+    Push(Push),
+    Fetch(Fetch),
+    Pull(Pull),
+    Merge(Merge),
+    Diff(Diff),
+    Apply(Apply),
+    FormatPatch(FormatPatch),
+    Am(Am),
+    Stash(Stash),
+    Status,
+    SparseCheckout(SparseCheckout),
+    Worktree(Worktree),
+    Fsck,
+    Gc,
+    Prune(Prune),
+    CountObjects(CountObjects),
+    VerifyPack(VerifyPack),
+    IndexPack(IndexPack),
+    UnpackObjects(UnpackObjects),
+    CommitGraph(CommitGraph),
+    MultiPackIndex(MultiPackIndex),
+    Commit(Commit),
+    VerifyCommit(VerifyCommit),
+    VerifyTag(VerifyTag),
+    Show(Show),
+    Blame(Blame),
+    Grep(Grep),
+    Archive(Archive),
+    Bundle(Bundle),
+    RevList(RevList),
+    MergeBase(MergeBase),
+    Describe(Describe),
+    Shortlog(Shortlog),
+    Notes(Notes),
+    CheckMailmap(CheckMailmap),
+    Bisect(Bisect),
+    CherryPick(CherryPick),
+    Revert(Revert),
+    Rebase(Rebase),
+    Reset(Reset),
+    Rm(Rm),
+    Mv(Mv),
+    Clean(Clean),
+    CheckIgnore(CheckIgnore),
+    LsFiles(LsFiles),
+    UpdateIndex(UpdateIndex),
+    ReadTree(ReadTree),
+    CheckoutIndex(CheckoutIndex),
+    SymbolicRef(SymbolicRef),
+    UpdateRef(UpdateRef),
+}
+
+#[derive(Args, Debug)]
+struct Init {
+    /// Hash algorithm naming the objects: sha1 or sha256
+    #[arg(long, default_value = "sha1")]
+    object_format: String,
 }
 
 #[derive(Args, Debug)]
 struct CatFile {
     #[arg(short)]
     pretty: bool,
-    hash: String,
+    #[arg(short)]
+    size: bool,
+    #[arg(short = 't')]
+    kind: bool,
+    /// Print `<hash> <type> <size>` and the content of each object named on stdin
+    #[arg(long, conflicts_with = "batch_check")]
+    batch: bool,
+    /// Print `<hash> <type> <size>` of each object named on stdin
+    #[arg(long)]
+    batch_check: bool,
+    #[arg(required_unless_present_any = ["batch", "batch_check"])]
+    hash: Option<String>,
 }
 
 #[derive(Args, Debug)]
 struct HashObject {
     #[arg(short)]
     write: bool,
-    path: PathBuf,
+    #[arg(short = 't', default_value = "blob")]
+    kind: String,
+    /// Read the content from stdin instead of a file
+    #[arg(long)]
+    stdin: bool,
+    /// Read the paths to hash from stdin, separated by newlines or NULs
+    #[arg(long, conflicts_with_all = ["stdin", "paths"])]
+    stdin_paths: bool,
+    #[arg(required_unless_present_any = ["stdin", "stdin_paths"], value_parser = file_arg)]
+    paths: Vec<PathBuf>,
 }
 
 #[derive(Args, Debug)]
 struct LsTree {
+    #[arg(short)]
+    recursive: bool,
+    #[arg(short)]
+    long: bool,
+    /// Show only trees
+    #[arg(short = 'd')]
+    trees_only: bool,
+    /// Show trees even when recursing into them
+    #[arg(short = 't')]
+    show_trees: bool,
     #[arg(long)]
     name_only: bool,
     hash: String,
+    paths: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct WriteTree {
+    /// Snapshot the working directory instead of the index
+    #[arg(long)]
+    from_worktree: bool,
 }
 
 #[derive(Args, Debug)]
 struct CommitTree {
+    #[arg(short = 'p')]
+    parents: Vec<String>,
+    /// The commit message, read from stdin unless given; several are
+    /// joined as paragraphs
     #[arg(short)]
-    parent_hash: String,
-    #[arg(short)]
-    message: String,
+    message: Vec<String>,
+    /// Read the commit message from a file, `-` for stdin
+    #[arg(short = 'F', conflicts_with = "message", value_parser = file_arg)]
+    file: Option<PathBuf>,
     tree_hash: String,
 }
 
 #[derive(Args, Debug)]
 struct CloneRepo {
+    #[arg(long)]
+    depth: Option<usize>,
+    #[arg(long)]
+    filter: Option<String>,
+    #[arg(short, long)]
+    branch: Option<String>,
+    #[arg(long)]
+    single_branch: bool,
+    #[arg(long)]
+    recurse_submodules: bool,
+    #[arg(long)]
+    sparse: bool,
     url: String,
     path: PathBuf,
 }
 
+#[derive(Args, Debug)]
+struct Log {
+    #[arg(default_value = "HEAD")]
+    revision: String,
+}
+
+#[derive(Args, Debug)]
+struct Branch {
+    #[arg(short)]
+    delete: bool,
+    name: Option<String>,
+    #[arg(default_value = "HEAD")]
+    start: String,
+}
+
+#[derive(Args, Debug)]
+struct Checkout {
+    /// Detach HEAD even when given a branch
+    #[arg(long)]
+    detach: bool,
+    /// The branch to switch to, or the commit to detach HEAD at
+    branch: String,
+}
+
+#[derive(Args, Debug)]
+struct Tag {
+    #[arg(short)]
+    annotate: bool,
+    /// Make a signed annotated tag
+    #[arg(short)]
+    sign: bool,
+    #[arg(short)]
+    message: Option<String>,
+    #[arg(short)]
+    delete: bool,
+    name: Option<String>,
+    #[arg(default_value = "HEAD")]
+    object: String,
+}
+
+#[derive(Args, Debug)]
+struct Reflog {
+    #[arg(default_value = "HEAD")]
+    reference: String,
+}
+
+#[derive(Args, Debug)]
+struct RevParse {
+    #[arg(required = true)]
+    revisions: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Fetch {
+    #[arg(default_value = "origin")]
+    remote: String,
+}
+
+#[derive(Args, Debug)]
+struct Pull {
+    remote: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct Merge {
+    #[arg(required_unless_present_any = ["abort", "continue_"])]
+    revision: Option<String>,
+    /// Give up on the merge stopped by conflicts
+    #[arg(long, conflicts_with_all = ["revision", "continue_"])]
+    abort: bool,
+    /// Commit the merge once conflicts are resolved
+    #[arg(long = "continue", conflicts_with = "revision")]
+    continue_: bool,
+    /// Merge histories without a common ancestor
+    #[arg(long)]
+    allow_unrelated_histories: bool,
+}
+
+#[derive(Args, Debug)]
+struct Diff {
+    /// Compare the working tree with one commit, or two commits (also given
+    /// as `<commit>..<commit>`) with each other
+    #[arg(num_args = 0..=2)]
+    commits: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Apply {
+    /// Apply the patch to the index without touching the working tree
+    #[arg(long)]
+    cached: bool,
+    /// Apply the patch to both the working tree and the index
+    #[arg(long, conflicts_with = "cached")]
+    index: bool,
+    /// Only check whether the patch applies
+    #[arg(long)]
+    check: bool,
+    #[arg(value_parser = file_arg)]
+    patch: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct FormatPatch {
+    /// Directory to write the patch files to
+    #[arg(short, long, default_value = ".", value_parser = file_arg)]
+    output_directory: PathBuf,
+    /// `<since>..<until>`, or `<since>` for the commits since it up to HEAD
+    range: String,
+}
+
+#[derive(Args, Debug)]
+struct Am {
+    #[arg(required = true, value_parser = file_arg)]
+    mailboxes: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Stash {
+    /// Defaults to `push`
+    #[command(subcommand)]
+    action: Option<StashAction>,
+}
+
+#[derive(Subcommand, Debug)]
+enum StashAction {
+    Push {
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+    List,
+    Apply {
+        stash: Option<String>,
+    },
+    Pop {
+        stash: Option<String>,
+    },
+    Drop {
+        stash: Option<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct SparseCheckout {
+    #[command(subcommand)]
+    action: SparseCheckoutAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum SparseCheckoutAction {
+    Init {
+        /// Cone mode is the only one supported
+        #[arg(long)]
+        cone: bool,
+    },
+    Set {
+        #[arg(long)]
+        cone: bool,
+        #[arg(required = true)]
+        directories: Vec<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct Worktree {
+    #[command(subcommand)]
+    action: WorktreeAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum WorktreeAction {
+    Add {
+        /// Create a new branch for the worktree
+        #[arg(short = 'b')]
+        new_branch: Option<String>,
+        #[arg(long, conflicts_with = "new_branch")]
+        detach: bool,
+        #[arg(value_parser = file_arg)]
+        path: PathBuf,
+        commit: Option<String>,
+    },
+    List,
+    Remove {
+        #[arg(short, long)]
+        force: bool,
+        #[arg(value_parser = file_arg)]
+        path: PathBuf,
+    },
+}
+
+#[derive(Args, Debug)]
+struct Push {
+    remote: String,
+    refspec: String,
+}
+
+#[derive(Args, Debug)]
+struct Prune {
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct CountObjects {
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(Args, Debug)]
+struct VerifyPack {
+    #[arg(short, long)]
+    verbose: bool,
+    #[arg(required = true, value_parser = file_arg)]
+    packs: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct IndexPack {
+    #[arg(long)]
+    stdin: bool,
+    #[arg(required_unless_present = "stdin", value_parser = file_arg)]
+    pack: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct UnpackObjects {
+    #[arg(short = 'n')]
+    dry_run: bool,
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(Args, Debug)]
+struct CommitGraph {
+    #[command(subcommand)]
+    action: CommitGraphAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum CommitGraphAction {
+    /// Write the commit graph of all commits reachable from refs
+    Write {
+        #[arg(long)]
+        reachable: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+struct MultiPackIndex {
+    #[command(subcommand)]
+    action: MultiPackIndexAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum MultiPackIndexAction {
+    /// Write a multi-pack-index covering all packs
+    Write,
+}
+
+#[derive(Args, Debug)]
+struct Commit {
+    /// The commit message; several are joined as paragraphs
+    #[arg(short, required_unless_present_any = ["amend", "file"])]
+    message: Vec<String>,
+    /// Read the commit message from a file, `-` for stdin
+    #[arg(short = 'F', conflicts_with = "message", value_parser = file_arg)]
+    file: Option<PathBuf>,
+    /// Replace the tip commit, keeping its message unless one is given
+    #[arg(long)]
+    amend: bool,
+    /// Sign the commit with gpg, or ssh-keygen for `gpg.format=ssh`
+    #[arg(short = 'S')]
+    gpg_sign: bool,
+}
+
+#[derive(Args, Debug)]
+struct VerifyCommit {
+    #[arg(required = true)]
+    commits: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct VerifyTag {
+    #[arg(required = true)]
+    tags: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Show {
+    #[arg(default_value = "HEAD")]
+    objects: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Blame {
+    /// `[<revision>] <path>`, the revision defaulting to HEAD
+    #[arg(required = true, num_args = 1..=2)]
+    args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Grep {
+    /// Prefix matching lines with their line number
+    #[arg(short = 'n')]
+    line_number: bool,
+    /// Print only the names of files with matches
+    #[arg(short = 'l')]
+    files_with_matches: bool,
+    /// A basic regular expression
+    pattern: String,
+    /// Search this tree instead of the index
+    tree_ish: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct Archive {
+    /// Archive format: tar or zip
+    #[arg(long, default_value = "tar")]
+    format: String,
+    /// Prepend this to every path in the archive
+    #[arg(long, default_value = "")]
+    prefix: String,
+    tree_ish: String,
+}
+
+#[derive(Args, Debug)]
+struct Bundle {
+    #[command(subcommand)]
+    action: BundleAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum BundleAction {
+    Create {
+        #[arg(value_parser = file_arg)]
+        file: PathBuf,
+        /// Refs to bundle, `--all`, or ranges and `^<rev>` to leave out history
+        #[arg(required = true, allow_hyphen_values = true)]
+        revisions: Vec<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct RevList {
+    /// Also list the trees and blobs of the listed commits
+    #[arg(long)]
+    objects: bool,
+    /// Commits to list the history of; `^<rev>` and `<from>..<to>` exclude history
+    #[arg(required = true, allow_hyphen_values = true)]
+    revisions: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct MergeBase {
+    /// Print every best common ancestor, not just one
+    #[arg(long, conflicts_with = "is_ancestor")]
+    all: bool,
+    /// Only check whether the first commit is an ancestor of the second
+    #[arg(long)]
+    is_ancestor: bool,
+    one: String,
+    two: String,
+}
+
+#[derive(Args, Debug)]
+struct Describe {
+    /// Use lightweight tags as well as annotated ones
+    #[arg(long)]
+    tags: bool,
+    #[arg(default_value = "HEAD")]
+    commit: String,
+}
+
+#[derive(Args, Debug)]
+struct Shortlog {
+    /// Print only the number of commits of each author
+    #[arg(short, long)]
+    summary: bool,
+    /// Sort authors by their number of commits
+    #[arg(short, long)]
+    numbered: bool,
+    /// Show the email of each author
+    #[arg(short, long)]
+    email: bool,
+    #[arg(default_value = "HEAD", allow_hyphen_values = true)]
+    revisions: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Notes {
+    #[command(subcommand)]
+    action: NotesAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum NotesAction {
+    Add {
+        /// The note; several are joined as paragraphs
+        #[arg(short, required_unless_present = "file")]
+        message: Vec<String>,
+        /// Read the note from a file, `-` for stdin
+        #[arg(short = 'F', conflicts_with = "message", value_parser = file_arg)]
+        file: Option<PathBuf>,
+        /// Replace an existing note
+        #[arg(short, long)]
+        force: bool,
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    Show {
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    List {
+        object: Option<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct CheckMailmap {
+    /// `Name <email>` or `<email>`
+    #[arg(required = true)]
+    contacts: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Bisect {
+    #[command(subcommand)]
+    action: BisectAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum BisectAction {
+    Start {
+        bad: Option<String>,
+        good: Vec<String>,
+    },
+    Bad {
+        #[arg(default_value = "HEAD")]
+        revision: String,
+    },
+    Good {
+        #[arg(default_value = "HEAD")]
+        revisions: Vec<String>,
+    },
+    Reset,
+}
+
+#[derive(Args, Debug)]
+struct CherryPick {
+    commit: String,
+}
+
+#[derive(Args, Debug)]
+struct Revert {
+    commit: String,
+}
+
+#[derive(Args, Debug)]
+struct Rebase {
+    #[arg(required_unless_present_any = ["abort", "continue_"])]
+    upstream: Option<String>,
+    /// Give up on the rebase in progress
+    #[arg(long, conflicts_with_all = ["upstream", "continue_"])]
+    abort: bool,
+    /// Go on with the rebase in progress once conflicts are resolved
+    #[arg(long = "continue", conflicts_with = "upstream")]
+    continue_: bool,
+}
+
+#[derive(Args, Debug)]
+struct Reset {
+    /// Only move the branch, keeping the index and the working tree
+    #[arg(long, conflicts_with_all = ["mixed", "hard"])]
+    soft: bool,
+    /// Also reset the index (the default)
+    #[arg(long, conflicts_with = "hard")]
+    mixed: bool,
+    /// Also reset the working tree, discarding changes to tracked files
+    #[arg(long)]
+    hard: bool,
+    #[arg(default_value = "HEAD")]
+    commit: String,
+}
+
+#[derive(Args, Debug)]
+struct Rm {
+    /// Only remove the files from the index
+    #[arg(long)]
+    cached: bool,
+    /// Remove files even when their changes would be lost
+    #[arg(short, long)]
+    force: bool,
+    /// Remove the files under directories given
+    #[arg(short)]
+    recursive: bool,
+    #[arg(required = true, value_parser = pathspec)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Mv {
+    /// Replace an existing destination file
+    #[arg(short, long)]
+    force: bool,
+    /// The sources followed by the destination
+    #[arg(num_args = 2.., required = true, value_parser = pathspec)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Clean {
+    /// Only show what would be removed
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// Remove untracked files
+    #[arg(short, long)]
+    force: bool,
+    /// Remove untracked directories too
+    #[arg(short)]
+    directories: bool,
+}
+
+#[derive(Args, Debug)]
+struct CheckIgnore {
+    /// Show the pattern matching each path and where it comes from
+    #[arg(short, long)]
+    verbose: bool,
+    #[arg(required = true, value_parser = pathspec)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct LsFiles {
+    /// List the entries of the index (the default)
+    #[arg(short, long)]
+    cached: bool,
+    /// Show the mode, hash and stage of index entries
+    #[arg(short, long)]
+    stage: bool,
+    /// List untracked files
+    #[arg(short, long)]
+    others: bool,
+    /// Leave out ignored files
+    #[arg(long)]
+    exclude_standard: bool,
+    /// Terminate paths with NUL
+    #[arg(short)]
+    z: bool,
+}
+
+#[derive(Args, Debug)]
+struct UpdateIndex {
+    /// Add files that are not in the index yet
+    #[arg(long)]
+    add: bool,
+    /// Remove entries whose files no longer exist
+    #[arg(long)]
+    remove: bool,
+    /// Refresh the stat data of unchanged entries
+    #[arg(long)]
+    refresh: bool,
+    /// Record an entry as `<mode>,<hash>,<path>`
+    #[arg(long, value_name = "MODE,HASH,PATH")]
+    cacheinfo: Vec<String>,
+    #[arg(value_parser = pathspec)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ReadTree {
+    /// Merge two or three trees into the index
+    #[arg(short = 'm')]
+    merge: bool,
+    #[arg(required = true, num_args = 1..=3)]
+    trees: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct CheckoutIndex {
+    /// Check out every file of the index
+    #[arg(short, long)]
+    all: bool,
+    /// Replace files that already exist
+    #[arg(short, long)]
+    force: bool,
+    /// Write the files below this prefix, e.g. `export/`
+    #[arg(long, default_value = "")]
+    prefix: String,
+    #[arg(value_parser = pathspec)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct SymbolicRef {
+    name: String,
+    /// The ref to point `name` at, e.g. `refs/heads/main`
+    target: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct UpdateRef {
+    /// The reflog message
+    #[arg(short)]
+    message: Option<String>,
+    name: String,
+    new: String,
+    /// The value the ref must still have, empty if it must not exist
+    old: Option<String>,
+}
+
 impl Command {
     fn run(&self) -> Result<()> {
+        // like git, commands run from the top of the working tree, except
+        // those creating a repository where they are told to
+        if !matches!(self, Self::Init(_) | Self::Clone(_)) {
+            git::repository::enter_worktree()?;
+        }
         match self {
-            Self::Init => {
-                git::init(".")
+            Self::Init(ref command) => {
+                git::init(".", git::hash::HashAlgo::from_name(&command.object_format)?)
+            }
+            Self::CatFile(ref command) => {
+                if command.batch || command.batch_check {
+                    return git::cat_file::batch(std::io::stdin().lock(), command.batch);
+                }
+                let hash = command.hash.as_deref().unwrap_or_default();
+                let object = git::Odb::open()?.stream(&git::revparse::resolve(hash)?)?;
+                if command.size {
+                    println!("{}", object.size());
+                } else if command.kind {
+                    println!("{}", String::from_utf8_lossy(object.kind()));
+                } else {
+                    object.print()?;
+                }
+                Ok(())
             }
-            Self::CatFile(ref command) => git::Object::from_hash(&command.hash)?.print(),
             Self::HashObject(ref command) => {
-                let hash = git::blobify(&command.path)?;
-                println!("{}", hex::encode(&hash));
+                let kind = &command.kind;
+                if command.stdin {
+                    let mut content = vec![];
+                    std::io::stdin().read_to_end(&mut content)?;
+                    println!("{}", git::hash_object(kind, content, None, command.write)?);
+                }
+                let hash_file = |path: &std::path::Path| -> Result<()> {
+                    let content = std::fs::read(path)?;
+                    let hash = git::hash_object(kind, content, Some(path), command.write)?;
+                    println!("{hash}");
+                    Ok(())
+                };
+                for path in &command.paths {
+                    hash_file(path)?;
+                }
+                if command.stdin_paths {
+                    for path in std::io::stdin().lock().split(b'\n') {
+                        for path in path?.split(|&b| b == 0).filter(|p| !p.is_empty()) {
+                            hash_file(std::path::Path::new(std::str::from_utf8(path)?))?;
+                        }
+                    }
+                }
                 Ok(())
             }
-            Self::LsTree(ref command) => git::Object::from_hash(&command.hash)?
-                .parse()?
-                .print_tree_names(),
-            Self::WriteTree => {
-                let hash = git::write_tree(&PathBuf::from("."))?;
-                println!("{}", hex::encode(&hash));
+            Self::LsTree(ref command) => {
+                let tree = git::revparse::peel(&git::revparse::resolve(&command.hash)?, "tree")?;
+                git::ls_tree::ls_tree(
+                    &tree,
+                    &git::ls_tree::LsTreeOptions {
+                        recursive: command.recursive,
+                        long: command.long,
+                        name_only: command.name_only,
+                        trees_only: command.trees_only,
+                        show_trees: command.show_trees,
+                        paths: command.paths.clone(),
+                    },
+                )
+            }
+            Self::WriteTree(ref command) => {
+                let hash = git::write_tree(&PathBuf::from("."), command.from_worktree)?;
+                println!("{}", hex::encode(hash));
                 Ok(())
             }
             Self::CommitTree(ref command) => {
-                let hash = git::commit(
-                    &git::parse_hash(&command.tree_hash)?,
-                    &git::parse_hash(&command.parent_hash)?,
-                    &command.message,
-                )?;
-                println!("{}", hex::encode(&hash));
+                let parents = command
+                    .parents
+                    .iter()
+                    .map(|parent| git::parse_hash(parent))
+                    .collect::<Result<Vec<_>>>()?;
+                let message = match read_message(&command.message, command.file.as_deref())? {
+                    Some(message) => message,
+                    None => read_message(&[], Some(Path::new("-")))?.unwrap_or_default(),
+                };
+                // plumbing: the commit is only written, no ref moves to it
+                let tree = git::parse_hash(&command.tree_hash)?;
+                let hash = git::write_commit(&tree, &parents, &message)?;
+                println!("{}", hex::encode(hash));
                 Ok(())
             }
             Self::Clone(ref command) => {
-                let remote_url = if command.url.ends_with('/') {
-                    Url::from_str(&command.url)?
+                let options = git::clone::CloneOptions {
+                    fetch: git::remote::FetchOptions {
+                        depth: command.depth,
+                        filter: command.filter.clone(),
+                        ..Default::default()
+                    },
+                    branch: command.branch.clone(),
+                    single_branch: command.single_branch,
+                    recurse_submodules: command.recurse_submodules,
+                    sparse: command.sparse,
+                };
+                git::clone::clone(&command.url, &command.path, &options)
+            }
+            Self::Push(ref command) => git::remote::push(&command.remote, &command.refspec),
+            Self::Fetch(ref command) => git::fetch::fetch(&command.remote),
+            Self::Pull(ref command) => git::pull::pull(command.remote.as_deref()),
+            Self::Merge(ref command) => match command.revision {
+                _ if command.abort => git::merge::abort(),
+                _ if command.continue_ => git::merge::continue_merge(),
+                Some(ref revision) => {
+                    git::merge::merge(revision, command.allow_unrelated_histories)
+                }
+                None => unreachable!("clap requires a revision"),
+            },
+            Self::Diff(ref command) => match command.commits.as_slice() {
+                [] => git::diff::print_worktree_diff(None),
+                [range] => match range.split_once("..") {
+                    Some((old, new)) => git::diff::print_tree_diff(
+                        if old.is_empty() { "HEAD" } else { old },
+                        if new.is_empty() { "HEAD" } else { new },
+                    ),
+                    None => git::diff::print_worktree_diff(Some(range)),
+                },
+                [old, new] => git::diff::print_tree_diff(old, new),
+                _ => unreachable!(),
+            },
+            Self::Apply(ref command) => git::apply::apply(
+                &command.patch,
+                &git::apply::ApplyOptions {
+                    cached: command.cached,
+                    index: command.index,
+                    check: command.check,
+                },
+            ),
+            Self::FormatPatch(ref command) => {
+                git::patch::format_patch(&command.range, &command.output_directory)
+            }
+            Self::Am(ref command) => git::patch::am(&command.mailboxes),
+            Self::Stash(ref command) => match &command.action {
+                None => git::stash::push(None),
+                Some(StashAction::Push { message }) => git::stash::push(message.as_deref()),
+                Some(StashAction::List) => git::stash::list(),
+                Some(StashAction::Apply { stash }) => git::stash::apply(stash.as_deref()),
+                Some(StashAction::Pop { stash }) => git::stash::pop(stash.as_deref()),
+                Some(StashAction::Drop { stash }) => git::stash::drop(stash.as_deref()),
+            },
+            Self::Status => git::status::print(),
+            Self::SparseCheckout(ref command) => match &command.action {
+                SparseCheckoutAction::Init { .. } => git::sparse::init(),
+                SparseCheckoutAction::Set { directories, .. } => git::sparse::set(directories),
+            },
+            Self::Worktree(ref command) => match &command.action {
+                WorktreeAction::Add {
+                    new_branch,
+                    detach,
+                    path,
+                    commit,
+                } => git::worktree::add(
+                    path,
+                    commit.as_deref(),
+                    &git::worktree::AddOptions {
+                        new_branch: new_branch.clone(),
+                        detach: *detach,
+                    },
+                ),
+                WorktreeAction::List => git::worktree::list(),
+                WorktreeAction::Remove { force, path } => git::worktree::remove(path, *force),
+            },
+            Self::Fsck => git::fsck::fsck(),
+            Self::Gc => git::gc::gc(),
+            Self::Prune(ref command) => git::prune::prune(command.dry_run),
+            Self::CountObjects(ref command) => git::count_objects::count_objects(command.verbose),
+            Self::IndexPack(ref command) => {
+                let mut stdin = std::io::stdin();
+                let input = command
+                    .stdin
+                    .then_some(&mut stdin as &mut dyn std::io::Read);
+                let checksum = git::pack::index_pack_file(command.pack.as_deref(), input)?;
+                if command.stdin {
+                    println!("pack\t{checksum}");
+                } else {
+                    println!("{checksum}");
+                }
+                Ok(())
+            }
+            Self::UnpackObjects(ref command) => {
+                git::pack::unpack_objects(&mut std::io::stdin(), command.dry_run, command.strict)
+            }
+            Self::CommitGraph(ref command) => match command.action {
+                CommitGraphAction::Write { .. } => git::commit_graph::write(),
+            },
+            Self::MultiPackIndex(ref command) => match command.action {
+                MultiPackIndexAction::Write => {
+                    git::midx::write(&git::repository::path("objects/pack"))
+                }
+            },
+            Self::VerifyPack(ref command) => {
+                for pack in &command.packs {
+                    git::pack::verify(pack, command.verbose)?;
+                }
+                Ok(())
+            }
+            Self::Log(ref command) => git::log::print(&command.revision),
+            Self::Branch(ref command) => match (&command.name, command.delete) {
+                (None, false) => git::branch::list(),
+                (None, true) => bail!("branch name required"),
+                (Some(name), true) => git::branch::delete(name),
+                (Some(name), false) => git::branch::create(name, &command.start),
+            },
+            Self::Checkout(ref command) => git::checkout_revision(&command.branch, command.detach),
+            Self::Reflog(ref command) => {
+                let name = git::refs::expand(&command.reference)?
+                    .ok_or_else(|| anyhow!("Unknown reference {}", command.reference))?;
+                git::reflog::print(&name)
+            }
+            Self::RevParse(ref command) => {
+                for revision in &command.revisions {
+                    println!("{}", git::revparse::resolve(revision)?);
+                }
+                Ok(())
+            }
+            Self::Tag(ref command) => match (&command.name, command.delete) {
+                (None, false) => git::tag::list(),
+                (None, true) => bail!("tag name required"),
+                (Some(name), true) => git::tag::delete(name),
+                (Some(name), false) => {
+                    if (command.annotate || command.sign) && command.message.is_none() {
+                        bail!("annotated tags require a message (-m)");
+                    }
+                    git::tag::create(
+                        name,
+                        &command.object,
+                        command.message.as_deref(),
+                        command.sign,
+                    )
+                }
+            },
+            Self::Commit(ref command) => {
+                let options = git::commit::CommitOptions {
+                    message: read_message(&command.message, command.file.as_deref())?,
+                    amend: command.amend,
+                    sign: command.gpg_sign,
+                };
+                git::commit::commit(&options)?;
+                Ok(())
+            }
+            Self::VerifyCommit(ref command) => {
+                for commit in &command.commits {
+                    git::sign::verify_commit(commit)?;
+                }
+                Ok(())
+            }
+            Self::VerifyTag(ref command) => {
+                for tag in &command.tags {
+                    git::sign::verify_tag(tag)?;
+                }
+                Ok(())
+            }
+            Self::Show(ref command) => {
+                for object in &command.objects {
+                    git::show::show(object)?;
+                }
+                Ok(())
+            }
+            Self::Blame(ref command) => match command.args.as_slice() {
+                [path] => git::blame::blame("HEAD", path),
+                [revision, path] => git::blame::blame(revision, path),
+                _ => unreachable!(),
+            },
+            Self::Grep(ref command) => {
+                let options = git::grep::GrepOptions {
+                    line_numbers: command.line_number,
+                    files_with_matches: command.files_with_matches,
+                };
+                // like grep, finding nothing is a failure without a message
+                if !git::grep::grep(&command.pattern, command.tree_ish.as_deref(), &options)? {
+                    std::process::exit(1);
+                }
+                Ok(())
+            }
+            Self::Archive(ref command) => git::archive::archive(
+                &command.tree_ish,
+                git::archive::ArchiveFormat::from_name(&command.format)?,
+                &command.prefix,
+            ),
+            Self::Bundle(ref command) => match command.action {
+                BundleAction::Create {
+                    ref file,
+                    ref revisions,
+                } => git::bundle::create(file, revisions),
+            },
+            Self::RevList(ref command) => git::rev_list::rev_list(
+                &command.revisions,
+                &git::rev_list::RevListOptions {
+                    objects: command.objects,
+                },
+            ),
+            Self::MergeBase(ref command) => {
+                let found = if command.is_ancestor {
+                    git::merge::is_ancestor(&command.one, &command.two)?
                 } else {
-                    let url = command.url.clone() + "/";
-                    Url::from_str(&url)?
+                    git::merge::print_merge_base(&command.one, &command.two, command.all)?
+                };
+                // the answer is in the exit code, as with grep
+                if !found {
+                    std::process::exit(1);
+                }
+                Ok(())
+            }
+            Self::Describe(ref command) => {
+                let options = git::describe::DescribeOptions { tags: command.tags };
+                println!("{}", git::describe::describe(&command.commit, &options)?);
+                Ok(())
+            }
+            Self::Shortlog(ref command) => git::shortlog::shortlog(
+                &command.revisions,
+                &git::shortlog::ShortlogOptions {
+                    summary: command.summary,
+                    numbered: command.numbered,
+                    email: command.email,
+                },
+            ),
+            Self::Notes(ref command) => match command.action {
+                NotesAction::Add {
+                    ref message,
+                    ref file,
+                    force,
+                    ref object,
+                } => {
+                    let message = read_message(message, file.as_deref())?.unwrap_or_default();
+                    git::notes::add(object, &message, force)
+                }
+                NotesAction::Show { ref object } => git::notes::show(object),
+                NotesAction::List { ref object } => git::notes::list(object.as_deref()),
+            },
+            Self::CheckMailmap(ref command) => git::mailmap::check_mailmap(&command.contacts),
+            Self::Bisect(ref command) => match command.action {
+                BisectAction::Start { ref bad, ref good } => {
+                    git::bisect::start(bad.as_deref(), good)
+                }
+                BisectAction::Bad { ref revision } => git::bisect::bad(revision),
+                BisectAction::Good { ref revisions } => git::bisect::good(revisions),
+                BisectAction::Reset => git::bisect::reset(),
+            },
+            Self::CherryPick(ref command) => git::cherry_pick::cherry_pick(&command.commit),
+            Self::Revert(ref command) => git::revert::revert(&command.commit),
+            Self::Rebase(ref command) => match command.upstream {
+                _ if command.abort => git::rebase::abort(),
+                _ if command.continue_ => git::rebase::continue_rebase(),
+                Some(ref upstream) => git::rebase::rebase(upstream),
+                None => unreachable!("clap requires an upstream"),
+            },
+            Self::Reset(ref command) => {
+                let mode = match (command.soft, command.hard) {
+                    (true, _) => git::reset::ResetMode::Soft,
+                    (_, true) => git::reset::ResetMode::Hard,
+                    _ => git::reset::ResetMode::Mixed,
+                };
+                git::reset::reset(&command.commit, mode)
+            }
+            Self::Rm(ref command) => {
+                let options = git::rm::RmOptions {
+                    cached: command.cached,
+                    force: command.force,
+                    recursive: command.recursive,
+                };
+                git::rm::rm(&command.paths, &options)
+            }
+            Self::Mv(ref command) => {
+                let (destination, sources) = command
+                    .paths
+                    .split_last()
+                    .expect("clap requires a source and a destination");
+                git::mv::mv(sources, destination, command.force)
+            }
+            Self::Clean(ref command) => {
+                let options = git::clean::CleanOptions {
+                    dry_run: command.dry_run,
+                    force: command.force,
+                    directories: command.directories,
+                };
+                git::clean::clean(&options)
+            }
+            Self::CheckIgnore(ref command) => {
+                if !git::ignore::check_ignore(&command.paths, command.verbose)? {
+                    std::process::exit(1);
+                }
+                Ok(())
+            }
+            Self::LsFiles(ref command) => {
+                let options = git::ls_files::LsFilesOptions {
+                    cached: command.cached,
+                    stage: command.stage,
+                    others: command.others,
+                    exclude_standard: command.exclude_standard,
+                    nul_terminated: command.z,
+                };
+                git::ls_files::ls_files(&options)
+            }
+            Self::UpdateIndex(ref command) => {
+                let options = git::update_index::UpdateIndexOptions {
+                    add: command.add,
+                    remove: command.remove,
+                    refresh: command.refresh,
+                    cacheinfo: command.cacheinfo.clone(),
                 };
-                let refs = git::remote::discover_references(&remote_url)?;
-                let pack = git::remote::fetch_pack(&remote_url, &refs)?;
-                let objects = git::pack::parse(pack)?;
-                git::init(&command.path)?;
-                for object in objects {
-                    object.serialize()?;
+                if !git::update_index::update_index(&command.paths, &options)? {
+                    std::process::exit(1);
                 }
-                let head_hash = git::store_references(&refs)?;
-                git::checkout(&head_hash)
+                Ok(())
+            }
+            Self::ReadTree(ref command) => git::read_tree::read_tree(&command.trees, command.merge),
+            Self::CheckoutIndex(ref command) => {
+                let options = git::checkout_index::CheckoutIndexOptions {
+                    all: command.all,
+                    force: command.force,
+                    prefix: command.prefix.clone(),
+                };
+                git::checkout_index::checkout_index(&command.paths, &options)
+            }
+            Self::SymbolicRef(ref command) => Ok(git::refs::symbolic_ref(
+                &command.name,
+                command.target.as_deref(),
+            )?),
+            Self::UpdateRef(ref command) => {
+                let new = git::revparse::resolve(&command.new)?;
+                let old = match command.old.as_deref() {
+                    Some("") => Some(String::new()),
+                    Some(old) => Some(git::revparse::resolve(old)?),
+                    None => None,
+                };
+                let message = command.message.as_deref().unwrap_or_default();
+                Ok(git::refs::update_ref(
+                    &command.name,
+                    &new,
+                    old.as_deref(),
+                    message,
+                )?)
             }
         }
     }
 }
 
-fn main() -> Result<()> {
+/// A file named on the command line, made absolute when run below the top of
+/// the working tree, where commands move to. `-` stands for stdin.
+fn file_arg(arg: &str) -> Result<PathBuf> {
+    if arg == "-" || git::repository::prefix()?.as_os_str().is_empty() {
+        return Ok(PathBuf::from(arg));
+    }
+    Ok(std::path::absolute(arg)?)
+}
+
+/// A path in the working tree given relative to where the command runs,
+/// turned into one from the top of the working tree as the index names it.
+fn pathspec(arg: &str) -> Result<PathBuf> {
+    let prefix = git::repository::prefix()?;
+    if prefix.as_os_str().is_empty() {
+        return Ok(PathBuf::from(arg));
+    }
+    let mut path = PathBuf::new();
+    for component in prefix.join(arg).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => {}
+            Component::ParentDir if path.pop() => {}
+            _ => bail!("'{arg}' is outside repository"),
+        }
+    }
+    Ok(path)
+}
+
+/// The message given as `-m` paragraphs or in a `-F` file, `-` being stdin.
+fn read_message(paragraphs: &[String], file: Option<&Path>) -> Result<Option<String>> {
+    let Some(file) = file else {
+        return Ok((!paragraphs.is_empty()).then(|| paragraphs.join("\n\n")));
+    };
+    let message = if file == Path::new("-") {
+        let mut message = String::new();
+        std::io::stdin().read_to_string(&mut message)?;
+        message
+    } else {
+        std::fs::read_to_string(file)?
+    };
+    // the final newline is added back when the commit is written
+    Ok(Some(
+        message.strip_suffix('\n').unwrap_or(&message).to_owned(),
+    ))
+}
+
+fn main() {
     let args = CommandLine::parse();
-    args.command.run()?;
-    Ok(())
+    if let Err(error) = args.command.run() {
+        // a reader such as `head` closing the pipe early is not a failure
+        let broken_pipe = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|error| error.kind() == std::io::ErrorKind::BrokenPipe)
+        });
+        if broken_pipe {
+            return;
+        }
+        eprintln!("Error: {error:?}");
+        // like git, failing to find what the user named is fatal
+        let code = match error.downcast_ref::<git::Error>() {
+            Some(
+                git::Error::ObjectNotFound(_)
+                | git::Error::AmbiguousObject { .. }
+                | git::Error::RefNotFound(_),
+            ) => 128,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
 }
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{git, git_ok, Scratch};
+use std::fs;
+use std::path::Path;
+
+/// A patch creating `path`, in git's format.
+fn creation_patch(path: &str) -> String {
+    format!(
+        "diff --git a/{path} b/{path}\n\
+         new file mode 100644\n\
+         --- /dev/null\n\
+         +++ b/{path}\n\
+         @@ -0,0 +1 @@\n\
+         +pwned\n"
+    )
+}
+
+/// An mbox holding one mail with `patch`.
+fn mail(patch: &str) -> String {
+    format!(
+        "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001\n\
+         From: A U Thor <author@example.com>\n\
+         Date: Tue, 14 Nov 2023 22:13:20 +0000\n\
+         Subject: [PATCH] crafted\n\
+         \n\
+         ---\n\
+         {patch}\
+         -- \n\
+         2.39.5\n\n"
+    )
+}
+
+fn repository(name: &str) -> Scratch {
+    let base = Scratch::new(name);
+    fs::create_dir(base.join("repo")).unwrap();
+    git_ok(&base.join("repo"), &["init"]);
+    base
+}
+
+const UNSAFE_PATHS: [&str; 5] = [
+    "../evil",
+    "sub/../../evil",
+    ".git/evil",
+    "sub/.GIT/evil",
+    "link/evil",
+];
+
+/// Checks that nothing was written for any of `UNSAFE_PATHS`.
+fn assert_untouched(base: &Path) {
+    assert!(!base.join("evil").exists());
+    assert!(!base.join("repo/.git/evil").exists());
+    assert!(!base.join("repo/sub").exists());
+    assert!(!base.join("outside/evil").exists());
+}
+
+#[cfg(unix)]
+fn link_outside(base: &Path) {
+    fs::create_dir(base.join("outside")).unwrap();
+    std::os::unix::fs::symlink(base.join("outside"), base.join("repo/link")).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn apply_refuses_paths_leading_out_of_the_worktree() {
+    let base = repository("apply-paths");
+    link_outside(&base);
+    let repo = base.join("repo");
+    for path in UNSAFE_PATHS {
+        fs::write(base.join("patch"), creation_patch(path)).unwrap();
+        let output = git(&repo, &["apply", "../patch"]);
+        assert!(!output.status.success(), "{path} was applied");
+        assert_untouched(&base);
+    }
+    // `b//tmp/...` stands for an absolute path
+    let absolute = format!("{}/evil", base.display());
+    fs::write(base.join("patch"), creation_patch(&absolute)).unwrap();
+    let output = git(&repo, &["apply", "../patch"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid path"));
+    assert_untouched(&base);
+}
+
+#[cfg(unix)]
+#[test]
+fn am_refuses_paths_leading_out_of_the_worktree() {
+    let base = repository("am-paths");
+    link_outside(&base);
+    let repo = base.join("repo");
+    for path in UNSAFE_PATHS {
+        fs::write(base.join("mbox"), mail(&creation_patch(path))).unwrap();
+        let output = git(&repo, &["am", "../mbox"]);
+        assert!(!output.status.success(), "{path} was applied");
+        assert_untouched(&base);
+    }
+}
+
+#[test]
+fn apply_creates_files_in_subdirectories() {
+    let base = repository("apply-subdirectory");
+    let repo = base.join("repo");
+    fs::write(base.join("patch"), creation_patch("sub/dir/file")).unwrap();
+    git_ok(&repo, &["apply", "../patch"]);
+    assert_eq!(
+        fs::read_to_string(repo.join("sub/dir/file")).unwrap(),
+        "pwned\n"
+    );
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::{git, git_ok, RawRepo, Scratch};
+use std::fs;
+
+#[test]
+fn branch_names_cannot_leave_the_refs() {
+    let base = Scratch::new("branch-names");
+    let repo = RawRepo::create(&base.join("repo"));
+    let readme = repo.blob("hello\n");
+    let tree = repo.tree(&[("100644", "README", readme)]);
+    let head = repo.commit(tree);
+    let config = repo.path.join(".git/config");
+    fs::write(&config, "[core]\n\tbare = false\n").unwrap();
+
+    for name in ["../../config", "../HEAD", "a b", "bad..name", "x.lock"] {
+        let output = git(&repo.path, &["branch", "-d", name]);
+        assert!(!output.status.success(), "deleted {name:?}");
+        let output = git(&repo.path, &["branch", name]);
+        assert!(!output.status.success(), "created {name:?}");
+    }
+    let output = git(&repo.path, &["update-ref", "../../config", &head]);
+    assert!(!output.status.success());
+    assert_eq!(
+        fs::read_to_string(&config).unwrap(),
+        "[core]\n\tbare = false\n"
+    );
+    assert!(repo.path.join(".git/HEAD").is_file());
+
+    git_ok(&repo.path, &["branch", "feature/x"]);
+    let deleted = git_ok(&repo.path, &["branch", "-d", "feature/x"]);
+    assert_eq!(
+        deleted,
+        format!("Deleted branch feature/x (was {}).\n", &head[..7])
+    );
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{git, git_ok, RawRepo, Scratch};
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const PLANTED_CONFIG: &str = "[core]\n\tplanted = true\n";
+
+/// Clones a repository whose root tree has a directory named `name` holding
+/// a `config` file, next to a harmless file.
+fn clone_with_directory_named(name: &str) -> (Scratch, Output) {
+    let base = Scratch::new(&format!("crafted-{}", name.replace('.', "dot")));
+    let source = RawRepo::create(&base.join("source"));
+    let config = source.blob(PLANTED_CONFIG);
+    let directory = source.tree(&[("100644", "config", config)]);
+    let readme = source.blob("hello\n");
+    let root = source.tree(&[("40000", name, directory), ("100644", "README", readme)]);
+    source.commit(root);
+    let output = git(&base, &["clone", "source", "clone"]);
+    (base, output)
+}
+
+#[test]
+fn clone_refuses_tree_entries_leading_out_of_the_worktree() {
+    let (base, output) = clone_with_directory_named("..");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid path '..'"));
+    assert!(!base.join("config").exists());
+}
+
+#[test]
+fn clone_refuses_tree_entries_writing_into_the_repository() {
+    for name in [".git", ".GIT", "."] {
+        let (base, output) = clone_with_directory_named(name);
+        assert!(!output.status.success(), "{name} was checked out");
+        let config = fs::read_to_string(base.join("clone/.git/config")).unwrap();
+        assert!(!config.contains("planted"), "{name} replaced the config");
+    }
+}
+
+#[test]
+fn clone_refuses_empty_and_slashed_entry_names() {
+    for name in ["", "a/b"] {
+        let base = Scratch::new(&format!("crafted-name-{}", name.len()));
+        let source = RawRepo::create(&base.join("source"));
+        let blob = source.blob("x\n");
+        source.commit(source.tree(&[("100644", name, blob)]));
+        let output = git(&base, &["clone", "source", "clone"]);
+        assert!(!output.status.success(), "'{name}' was checked out");
+        assert!(!base.join("clone/a").exists());
+    }
+}
+
+/// A `git daemon` serving the repositories under a directory, killed when
+/// dropped.
+struct Daemon {
+    child: Child,
+    port: u16,
+}
+
+impl Daemon {
+    fn serve(base: &Path) -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        // `git daemon` runs `git-daemon` as a child it would leave behind
+        let exec_path = Command::new("git").arg("--exec-path").output().unwrap();
+        let exec_path = String::from_utf8(exec_path.stdout).unwrap();
+        let child = Command::new(Path::new(exec_path.trim()).join("git-daemon"))
+            .arg("--export-all")
+            .arg("--reuseaddr")
+            .arg("--listen=127.0.0.1")
+            .arg(format!("--port={port}"))
+            .arg(format!("--base-path={}", base.display()))
+            .arg(base)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        Self { child, port }
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn clone_from_a_stock_git_daemon() {
+    let base = Scratch::new("daemon");
+    let source = RawRepo::create(&base.join("source"));
+    let readme = source.blob("hello\n");
+    let head = source.commit(source.tree(&[("100644", "README", readme)]));
+    let daemon = Daemon::serve(&base);
+    let url = format!("git://127.0.0.1:{}/source", daemon.port);
+    git_ok(&base, &["clone", &url, "clone"]);
+    assert_eq!(
+        fs::read_to_string(base.join("clone/README")).unwrap(),
+        "hello\n"
+    );
+    let clone = base.join("clone");
+    assert_eq!(git_ok(&clone, &["rev-parse", "HEAD"]).trim(), head);
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::{git_ok, real_git, Scratch};
+use std::fs;
+
+#[test]
+fn written_commit_graph_verifies_and_answers_like_the_objects() {
+    let base = Scratch::new("commit-graph");
+    let repo = base.join("repo");
+    fs::create_dir(&repo).unwrap();
+    real_git(&repo, &["init", "-q", "-b", "master"]);
+    for i in 1..=3 {
+        fs::write(repo.join("file"), format!("{i}\n")).unwrap();
+        real_git(&repo, &["add", "file"]);
+        real_git(&repo, &["commit", "-qm", &format!("commit {i}")]);
+    }
+    real_git(&repo, &["checkout", "-qb", "side", "HEAD~1"]);
+    fs::write(repo.join("side"), "side\n").unwrap();
+    real_git(&repo, &["add", "side"]);
+    real_git(&repo, &["commit", "-qm", "side"]);
+    real_git(&repo, &["checkout", "-q", "master"]);
+    real_git(&repo, &["merge", "-q", "--no-edit", "side"]);
+    let log_before = git_ok(&repo, &["log"]);
+
+    git_ok(&repo, &["commit-graph", "write"]);
+    assert!(repo.join(".git/objects/info/commit-graph").is_file());
+    real_git(&repo, &["commit-graph", "verify"]);
+    assert_eq!(git_ok(&repo, &["log"]), log_before);
+    assert_eq!(
+        git_ok(&repo, &["merge-base", "master~1", "side"]),
+        real_git(&repo, &["merge-base", "master~1", "side"])
+    );
+}
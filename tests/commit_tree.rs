@@ -0,0 +1,25 @@
+mod common;
+
+use common::{git_ok, RawRepo, Scratch};
+use std::fs;
+
+#[test]
+fn commit_tree_moves_no_ref() {
+    let base = Scratch::new("commit-tree");
+    let repo = RawRepo::create(&base.join("repo"));
+    let readme = repo.blob("hello\n");
+    let tree = repo.tree(&[("100644", "README", readme)]);
+    let head = repo.commit(tree);
+
+    let tree = hex::encode(tree);
+    let orphan = git_ok(&repo.path, &["commit-tree", &tree, "-m", "orphan"]);
+    let child = git_ok(
+        &repo.path,
+        &["commit-tree", &tree, "-p", &head, "-m", "child"],
+    );
+    assert_ne!(orphan.trim(), head);
+    assert_ne!(child.trim(), head);
+    let master = fs::read_to_string(repo.path.join(".git/refs/heads/master")).unwrap();
+    assert_eq!(master.trim(), head);
+    assert!(!repo.path.join(".git/logs").exists());
+}
@@ -0,0 +1,157 @@
+//! Helpers for tests that run the command line on repositories built object
+//! by object, which lets them contain what git itself refuses to create.
+
+#![allow(dead_code)]
+
+use flate2::{write::ZlibEncoder, Compression};
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::io::Write;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A fresh directory under the system temporary directory, removed when
+/// dropped.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("git-starter-rust-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs the command line in `directory`.
+pub fn git(directory: &Path, args: &[&str]) -> Output {
+    git_env(directory, args, &[])
+}
+
+/// Like `git`, with additional environment variables.
+pub fn git_env(directory: &Path, args: &[&str], vars: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-starter-rust"))
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_AUTHOR_NAME", "A U Thor")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_COMMITTER_NAME", "C O Mitter")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+        .envs(vars.iter().copied())
+        .output()
+        .unwrap()
+}
+
+/// Like `git`, failing the test if the command does.
+pub fn git_ok(directory: &Path, args: &[&str]) -> String {
+    let output = git(directory, args);
+    assert!(
+        output.status.success(),
+        "{args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Runs the system git in `directory`, failing the test if it fails, to
+/// build repositories or check what the command line wrote.
+pub fn real_git(directory: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_AUTHOR_NAME", "A U Thor")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_COMMITTER_NAME", "C O Mitter")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// A repository written directly: a `.git` directory with loose objects and
+/// refs, without a working tree.
+pub struct RawRepo {
+    pub path: PathBuf,
+}
+
+impl RawRepo {
+    pub fn create(path: &Path) -> Self {
+        let git_dir = path.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        Self {
+            path: path.to_owned(),
+        }
+    }
+
+    /// Stores an object, returning its hash.
+    pub fn write_object(&self, kind: &str, content: &[u8]) -> [u8; 20] {
+        let mut object = format!("{kind} {}\0", content.len()).into_bytes();
+        object.extend(content);
+        let hash: [u8; 20] = Sha1::digest(&object).into();
+        let hex = hex::encode(hash);
+        let path = self
+            .path
+            .join(".git/objects")
+            .join(&hex[..2])
+            .join(&hex[2..]);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&object).unwrap();
+        fs::write(path, encoder.finish().unwrap()).unwrap();
+        hash
+    }
+
+    pub fn blob(&self, content: &str) -> [u8; 20] {
+        self.write_object("blob", content.as_bytes())
+    }
+
+    /// Stores a tree of `(mode, name, hash)` entries, as given: names are
+    /// neither checked nor sorted.
+    pub fn tree(&self, entries: &[(&str, &str, [u8; 20])]) -> [u8; 20] {
+        let mut content = vec![];
+        for (mode, name, hash) in entries {
+            content.extend(format!("{mode} {name}\0").into_bytes());
+            content.extend(hash);
+        }
+        self.write_object("tree", &content)
+    }
+
+    /// Stores a root commit of `tree` and points `master` at it.
+    pub fn commit(&self, tree: [u8; 20]) -> String {
+        let content = format!(
+            "tree {}\nauthor A <a@example.com> 1700000000 +0000\n\
+             committer A <a@example.com> 1700000000 +0000\n\ncrafted\n",
+            hex::encode(tree)
+        );
+        let hash = hex::encode(self.write_object("commit", content.as_bytes()));
+        fs::write(
+            self.path.join(".git/refs/heads/master"),
+            format!("{hash}\n"),
+        )
+        .unwrap();
+        hash
+    }
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{git_env, git_ok, RawRepo, Scratch};
+use std::fs;
+
+#[test]
+fn commands_find_the_repository_above() {
+    let base = Scratch::new("discover");
+    let repo = RawRepo::create(&base.join("repo"));
+    let tree = repo.tree(&[("100644", "README", repo.blob("hello\n"))]);
+    let head = repo.commit(tree);
+    git_ok(&repo.path, &["reset", "--hard", "master"]);
+    let deeper = repo.path.join("sub/deeper");
+    fs::create_dir_all(&deeper).unwrap();
+
+    assert_eq!(git_ok(&deeper, &["rev-parse", "HEAD"]).trim(), head);
+    assert_eq!(
+        git_ok(&deeper, &["cat-file", "-p", "HEAD:README"]),
+        "hello\n"
+    );
+
+    // paths are taken relative to where the command runs
+    fs::write(deeper.join("new.txt"), "new\n").unwrap();
+    git_ok(&deeper, &["update-index", "--add", "new.txt"]);
+    let files = git_ok(&repo.path, &["ls-files"]);
+    assert_eq!(files, "README\nsub/deeper/new.txt\n");
+}
+
+#[test]
+fn discovery_stops_at_ceiling_directories() {
+    let base = Scratch::new("discover-ceiling");
+    let repo = RawRepo::create(&base.join("repo"));
+    let tree = repo.tree(&[("100644", "README", repo.blob("hello\n"))]);
+    let head = repo.commit(tree);
+    let sub = repo.path.join("sub");
+    fs::create_dir_all(&sub).unwrap();
+
+    let ceiling = repo.path.to_str().unwrap();
+    let vars = [("GIT_CEILING_DIRECTORIES", ceiling)];
+    let output = git_env(&sub, &["rev-parse", "HEAD"], &vars);
+    assert!(!output.status.success());
+    // the ceiling itself is still searched
+    let output = git_env(&repo.path, &["rev-parse", "HEAD"], &vars);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), head);
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{git_ok, RawRepo, Scratch};
+use std::fs;
+use std::path::Path;
+
+/// Clones `origin` by `url` into `clone`, then fetches a commit made on it
+/// afterwards.
+fn clone_then_fetch(base: &Path, url: &str) {
+    let origin = RawRepo::create(&base.join("origin"));
+    let tree = origin.tree(&[("100644", "README", origin.blob("one\n"))]);
+    let first = origin.commit(tree);
+    git_ok(base, &["clone", url, "clone"]);
+    let clone = base.join("clone");
+    assert_eq!(fs::read_to_string(clone.join("README")).unwrap(), "one\n");
+
+    let tree = hex::encode(origin.tree(&[("100644", "README", origin.blob("two\n"))]));
+    let second = git_ok(
+        &origin.path,
+        &["commit-tree", &tree, "-p", &first, "-m", "second"],
+    );
+    let second = second.trim();
+    git_ok(&origin.path, &["update-ref", "refs/heads/master", second]);
+
+    let output = git_ok(&clone, &["fetch"]);
+    assert!(output.starts_with("From "), "{output}");
+    let tracking = git_ok(&clone, &["rev-parse", "refs/remotes/origin/master"]);
+    assert_eq!(tracking.trim(), second);
+    let content = git_ok(&clone, &["cat-file", "-p", &format!("{second}:README")]);
+    assert_eq!(content, "two\n");
+    // the worktree is left alone
+    assert_eq!(fs::read_to_string(clone.join("README")).unwrap(), "one\n");
+}
+
+#[test]
+fn fetch_from_a_local_clone_source() {
+    let base = Scratch::new("fetch-local-path");
+    clone_then_fetch(&base, "origin");
+}
+
+#[test]
+fn fetch_from_a_file_url() {
+    let base = Scratch::new("fetch-file-url");
+    let url = format!("file://{}", base.join("origin").display());
+    clone_then_fetch(&base, &url);
+}
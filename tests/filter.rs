@@ -0,0 +1,63 @@
+mod common;
+
+use common::{git, git_ok, RawRepo, Scratch};
+use std::fs;
+use std::path::Path;
+
+/// A `filter.x` driver whose commands leave `marker` behind when run.
+fn filter_config(marker: &Path) -> String {
+    format!(
+        "[filter \"x\"]\n\tclean = touch {0}; cat\n\tsmudge = touch {0}; cat\n",
+        marker.display()
+    )
+}
+
+#[test]
+fn checked_out_config_never_configures_filters() {
+    for dot_git in [".git", ".Git", ".GIT"] {
+        let base = Scratch::new(&format!("planted-{}", dot_git.to_ascii_lowercase()));
+        let marker = base.join("filter-ran");
+        let source = RawRepo::create(&base.join("source"));
+        let config = source.blob(&filter_config(&marker));
+        let planted = source.tree(&[("100644", "config", config)]);
+        let attributes = source.blob("* filter=x\n");
+        let readme = source.blob("hello\n");
+        source.commit(source.tree(&[
+            ("40000", dot_git, planted),
+            ("100644", ".gitattributes", attributes),
+            ("100644", "README", readme),
+        ]));
+
+        let output = git(&base, &["clone", "source", "clone"]);
+        assert!(!output.status.success(), "{dot_git} was checked out");
+        let clone = base.join("clone");
+        let config = fs::read_to_string(clone.join(".git/config")).unwrap();
+        assert!(!config.contains("filter"), "{dot_git} replaced the config");
+        // commands that run filters on the working tree
+        git(&clone, &["status"]);
+        git(&clone, &["checkout-index", "--all", "--force"]);
+        assert!(!marker.exists(), "a filter from {dot_git} ran");
+    }
+}
+
+#[test]
+fn filters_configured_in_the_repository_run() {
+    let base = Scratch::new("configured-filter");
+    let marker = base.join("filter-ran");
+    let source = RawRepo::create(&base.join("source"));
+    let attributes = source.blob("* filter=x\n");
+    let readme = source.blob("hello\n");
+    source.commit(source.tree(&[
+        ("100644", ".gitattributes", attributes),
+        ("100644", "README", readme),
+    ]));
+    git_ok(&base, &["clone", "source", "clone"]);
+    let clone = base.join("clone");
+    let mut config = fs::read_to_string(clone.join(".git/config")).unwrap();
+    config.push_str(&filter_config(&marker));
+    fs::write(clone.join(".git/config"), config).unwrap();
+
+    fs::write(clone.join("README"), "changed\n").unwrap();
+    git_ok(&clone, &["status"]);
+    assert!(marker.exists());
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{RawRepo, Scratch};
+use git_starter_rust::{ParsedObject, Repository, RevWalk};
+use std::env;
+
+#[test]
+fn repositories_are_used_without_moving_into_them() {
+    let base = Scratch::new("library-open");
+    let raw = RawRepo::create(&base.join("repo"));
+    let tree = raw.tree(&[("100644", "README", raw.blob("hello\n"))]);
+    let commit = raw.commit(tree);
+    let cwd = env::current_dir().unwrap();
+
+    let repository = Repository::open(&raw.path).unwrap();
+    assert_eq!(env::current_dir().unwrap(), cwd);
+    assert_eq!(
+        repository.git_dir(),
+        raw.path.canonicalize().unwrap().join(".git")
+    );
+    let head = repository.head().unwrap().expect("HEAD is born");
+    assert_eq!(head.to_string(), commit);
+    let odb = repository.odb().unwrap();
+    let ParsedObject::Commit(parsed) = odb.read(&commit).unwrap().parse().unwrap() else {
+        panic!("{commit} is not a commit");
+    };
+    assert_eq!(parsed.message, "crafted\n");
+
+    let walked = repository
+        .run(|| {
+            RevWalk::from_tip(&odb, &commit)?
+                .hashes()
+                .collect::<anyhow::Result<Vec<_>>>()
+        })
+        .unwrap();
+    assert_eq!(walked, [commit]);
+    assert_eq!(env::current_dir().unwrap(), cwd);
+}
+
+#[test]
+fn init_leaves_the_current_directory_alone() {
+    let base = Scratch::new("library-init");
+    let cwd = env::current_dir().unwrap();
+    let repository = Repository::init(base.join("new"), Default::default()).unwrap();
+    assert_eq!(env::current_dir().unwrap(), cwd);
+    assert!(repository.git_dir().join("HEAD").is_file());
+    assert_eq!(repository.head().unwrap(), None);
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{git, git_ok, RawRepo, Scratch};
+use std::fs;
+
+fn numbered(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("{line}\n")).collect()
+}
+
+/// Commits `content` as file `f` on top of `parent`.
+fn commit_file(repo: &RawRepo, content: &str, parent: &str, message: &str) -> String {
+    let tree = hex::encode(repo.tree(&[("100644", "f", repo.blob(content))]));
+    let hash = git_ok(
+        &repo.path,
+        &["commit-tree", &tree, "-p", parent, "-m", message],
+    );
+    hash.trim().to_owned()
+}
+
+#[test]
+fn merge_combines_changes_to_different_lines() {
+    let base = Scratch::new("merge-lines");
+    let repo = RawRepo::create(&base.join("repo"));
+    let lines = ["1", "2", "3", "4", "5", "6", "7", "8", "9"];
+    let tree = repo.tree(&[("100644", "f", repo.blob(&numbered(&lines)))]);
+    let root = repo.commit(tree);
+
+    let side = commit_file(
+        &repo,
+        &numbered(&["1", "two", "3", "4", "5", "6", "7", "8", "9"]),
+        &root,
+        "side",
+    );
+    git_ok(&repo.path, &["update-ref", "refs/heads/side", &side]);
+    let ours = commit_file(
+        &repo,
+        &numbered(&["1", "2", "3", "4", "5", "6", "7", "8", "nine"]),
+        &root,
+        "ours",
+    );
+    git_ok(&repo.path, &["reset", "--hard", &ours]);
+
+    let output = git_ok(&repo.path, &["merge", "side"]);
+    assert!(output.contains("Auto-merging f\n"), "{output}");
+    assert!(
+        output.contains("Merge made by a three-way merge."),
+        "{output}"
+    );
+    assert_eq!(
+        fs::read_to_string(repo.path.join("f")).unwrap(),
+        numbered(&["1", "two", "3", "4", "5", "6", "7", "8", "nine"])
+    );
+    assert!(git_ok(&repo.path, &["diff"]).is_empty());
+    let commit = git_ok(&repo.path, &["cat-file", "-p", "HEAD"]);
+    assert!(
+        commit.contains(&format!("parent {ours}\nparent {side}\n")),
+        "{commit}"
+    );
+}
+
+#[test]
+fn merge_refuses_unrelated_histories() {
+    let base = Scratch::new("merge-unrelated");
+    let repo = RawRepo::create(&base.join("repo"));
+    let tree = repo.tree(&[("100644", "f", repo.blob("ours\n"))]);
+    repo.commit(tree);
+    git_ok(&repo.path, &["reset", "--hard", "master"]);
+    let tree = hex::encode(repo.tree(&[("100644", "g", repo.blob("theirs\n"))]));
+    let other = git_ok(&repo.path, &["commit-tree", &tree, "-m", "other"]);
+    git_ok(
+        &repo.path,
+        &["update-ref", "refs/heads/other", other.trim()],
+    );
+
+    let output = git(&repo.path, &["merge", "other"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("refusing to merge unrelated histories"),
+        "{stderr}"
+    );
+    assert!(!repo.path.join("g").exists());
+
+    git_ok(
+        &repo.path,
+        &["merge", "--allow-unrelated-histories", "other"],
+    );
+    assert_eq!(fs::read_to_string(repo.path.join("g")).unwrap(), "theirs\n");
+}
+
+#[test]
+fn merge_fast_forwards_to_descendants() {
+    let base = Scratch::new("merge-fast-forward");
+    let repo = RawRepo::create(&base.join("repo"));
+    let tree = repo.tree(&[("100644", "f", repo.blob("1\n"))]);
+    let root = repo.commit(tree);
+    git_ok(&repo.path, &["reset", "--hard", "master"]);
+    let child = commit_file(&repo, "1\n2\n", &root, "child");
+    let grandchild = commit_file(&repo, "1\n2\n3\n", &child, "grandchild");
+    git_ok(&repo.path, &["update-ref", "refs/heads/side", &grandchild]);
+
+    let output = git_ok(&repo.path, &["merge", "side"]);
+    assert!(output.contains("Fast-forward"), "{output}");
+    let head = git_ok(&repo.path, &["rev-parse", "HEAD"]);
+    assert_eq!(head.trim(), grandchild);
+    assert_eq!(
+        fs::read_to_string(repo.path.join("f")).unwrap(),
+        "1\n2\n3\n"
+    );
+
+    let output = git_ok(&repo.path, &["merge", &child]);
+    assert_eq!(output, "Already up to date.\n");
+}
+
+#[test]
+fn merge_base_lists_all_bases_of_criss_cross_merges() {
+    let base = Scratch::new("merge-base-criss-cross");
+    let repo = RawRepo::create(&base.join("repo"));
+    let tree = repo.tree(&[("100644", "f", repo.blob("root\n"))]);
+    let root = repo.commit(tree);
+    let tree = hex::encode(tree);
+    let commit = |parents: &[&str], message: &str| {
+        let mut args = vec!["commit-tree", &tree, "-m", message];
+        for parent in parents {
+            args.extend(["-p", parent]);
+        }
+        git_ok(&repo.path, &args).trim().to_owned()
+    };
+    let one = commit(&[&root], "one");
+    let two = commit(&[&root], "two");
+    let left = commit(&[&commit(&[&one, &two], "left merge")], "left");
+    let right = commit(&[&commit(&[&two, &one], "right merge")], "right");
+
+    let output = git_ok(&repo.path, &["merge-base", "--all", &left, &right]);
+    let mut bases = output.lines().collect::<Vec<_>>();
+    bases.sort();
+    let mut expected = vec![one.as_str(), two.as_str()];
+    expected.sort();
+    assert_eq!(bases, expected);
+
+    let output = git_ok(&repo.path, &["merge-base", &one, &left]);
+    assert_eq!(output.trim(), one);
+    let output = git(&repo.path, &["merge-base", "--is-ancestor", &left, &right]);
+    assert!(!output.status.success());
+}
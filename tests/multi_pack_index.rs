@@ -0,0 +1,37 @@
+mod common;
+
+use common::{git_ok, real_git, Scratch};
+use std::fs;
+
+#[test]
+fn written_multi_pack_index_verifies_and_finds_every_object() {
+    let base = Scratch::new("multi-pack-index");
+    let repo = base.join("repo");
+    fs::create_dir(&repo).unwrap();
+    real_git(&repo, &["init", "-q"]);
+    // one pack per commit, so that objects are spread over several packs
+    for i in 1..=3 {
+        fs::write(repo.join(format!("file{i}")), format!("{i}\n")).unwrap();
+        real_git(&repo, &["add", "."]);
+        real_git(&repo, &["commit", "-qm", &format!("commit {i}")]);
+        real_git(&repo, &["repack", "-q"]);
+    }
+    let packs = fs::read_dir(repo.join(".git/objects/pack"))
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("pack".as_ref()))
+        .count();
+    assert_eq!(packs, 3);
+
+    git_ok(&repo, &["multi-pack-index", "write"]);
+    assert!(repo.join(".git/objects/pack/multi-pack-index").is_file());
+    real_git(&repo, &["multi-pack-index", "verify"]);
+    for line in real_git(&repo, &["rev-list", "--all", "--objects"]).lines() {
+        let hash = &line[..40];
+        for option in ["-t", "-s"] {
+            assert_eq!(
+                git_ok(&repo, &["cat-file", option, hash]),
+                real_git(&repo, &["cat-file", option, hash])
+            );
+        }
+    }
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use common::{git_ok, real_git, Scratch};
+use std::fs;
+
+#[test]
+fn sha256_repository_passes_fsck_before_and_after_packing() {
+    let base = Scratch::new("sha256");
+    let repo = base.join("repo");
+    fs::create_dir(&repo).unwrap();
+    git_ok(&repo, &["init", "--object-format=sha256"]);
+    assert_eq!(
+        real_git(&repo, &["rev-parse", "--show-object-format"]),
+        "sha256\n"
+    );
+    fs::create_dir(repo.join("dir")).unwrap();
+    fs::write(repo.join("dir/nested"), "nested\n").unwrap();
+    fs::write(repo.join("top"), "one\n").unwrap();
+    git_ok(&repo, &["update-index", "--add", "dir/nested", "top"]);
+    git_ok(&repo, &["commit", "-m", "one"]);
+    fs::write(repo.join("top"), "two\n").unwrap();
+    git_ok(&repo, &["update-index", "top"]);
+    git_ok(&repo, &["commit", "-m", "two"]);
+    git_ok(&repo, &["tag", "-a", "v1", "-m", "release"]);
+
+    let head = git_ok(&repo, &["rev-parse", "HEAD"]);
+    assert_eq!(head.trim().len(), 64);
+    assert_eq!(real_git(&repo, &["rev-parse", "HEAD"]), head);
+    real_git(&repo, &["fsck", "--strict"]);
+    assert_eq!(real_git(&repo, &["log", "--format=%s"]), "two\none\n");
+
+    git_ok(&repo, &["gc"]);
+    real_git(&repo, &["fsck", "--strict"]);
+    let indexes = fs::read_dir(repo.join(".git/objects/pack"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("idx".as_ref()))
+        .collect::<Vec<_>>();
+    assert!(!indexes.is_empty());
+    for index in &indexes {
+        real_git(&repo, &["verify-pack", index.to_str().unwrap()]);
+    }
+    assert_eq!(git_ok(&repo, &["rev-parse", "v1^{commit}"]), head);
+}
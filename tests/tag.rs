@@ -0,0 +1,82 @@
+mod common;
+
+use common::{git, git_ok, RawRepo, Scratch};
+use std::fs;
+
+#[test]
+fn tag_names_are_checked() {
+    let base = Scratch::new("tag-names");
+    let repo = RawRepo::create(&base.join("repo"));
+    let readme = repo.blob("hello\n");
+    let tree = repo.tree(&[("100644", "README", readme)]);
+    let head = repo.commit(tree);
+
+    for name in [
+        "../heads/evil",
+        "a b",
+        "bad name..x",
+        "x.lock",
+        ".hidden",
+        "v1/",
+    ] {
+        let output = git(&repo.path, &["tag", name]);
+        assert!(!output.status.success(), "created {name:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!("'{name}' is not a valid tag name.")),
+            "{stderr}"
+        );
+    }
+    assert!(!repo.path.join(".git/refs/heads/evil").exists());
+    let branches = git_ok(&repo.path, &["branch"]);
+    assert_eq!(branches, "* master\n");
+
+    git_ok(&repo.path, &["tag", "release/v1.0"]);
+    let tags = git_ok(&repo.path, &["tag"]);
+    assert_eq!(tags, "release/v1.0\n");
+    let deleted = git_ok(&repo.path, &["tag", "-d", "release/v1.0"]);
+    assert_eq!(
+        deleted,
+        format!("Deleted tag 'release/v1.0' (was {})\n", &head[..7])
+    );
+}
+
+#[test]
+fn deleting_a_packed_tag_keeps_the_peeling_the_file_had() {
+    let base = Scratch::new("tag-packed");
+    let repo = RawRepo::create(&base.join("repo"));
+    let tree = repo.tree(&[("100644", "README", repo.blob("hello\n"))]);
+    let head = repo.commit(tree);
+    let tag = hex::encode(
+        repo.write_object(
+            "tag",
+            format!("object {head}\ntype commit\ntag v1\ntagger T <t@example.com> 0 +0000\n\nv1\n")
+                .as_bytes(),
+        ),
+    );
+    let packed_refs = repo.path.join(".git/packed-refs");
+
+    let unpeeled = format!("{tag} refs/tags/v1\n");
+    fs::write(
+        &packed_refs,
+        format!("# pack-refs with: sorted \n{unpeeled}{head} refs/tags/v2\n"),
+    )
+    .unwrap();
+    git_ok(&repo.path, &["tag", "-d", "v2"]);
+    assert_eq!(
+        fs::read_to_string(&packed_refs).unwrap(),
+        format!("# pack-refs with: sorted \n{unpeeled}")
+    );
+
+    let peeled = format!("{tag} refs/tags/v1\n^{head}\n");
+    fs::write(
+        &packed_refs,
+        format!("# pack-refs with: peeled fully-peeled sorted \n{peeled}{head} refs/tags/v2\n"),
+    )
+    .unwrap();
+    git_ok(&repo.path, &["tag", "-d", "v2"]);
+    assert_eq!(
+        fs::read_to_string(&packed_refs).unwrap(),
+        format!("# pack-refs with: peeled fully-peeled sorted \n{peeled}")
+    );
+}